@@ -1,17 +1,19 @@
-extern crate pe_load;
+#![cfg_attr(not(windows), allow(dead_code))]
 
-use std::fs::File;
-use std::io::prelude::*;
+extern crate pe_load;
 
-const PATH: &'static str = "F:\\Programming\\C++\\call\\build\\Debug\\call.dll";
+const PATH: &str = "F:\\Programming\\C++\\call\\build\\Debug\\call.dll";
 
+#[cfg(windows)]
 fn main() {
-    let mut file = File::open(PATH).expect("couldn't open file");
-    let mut contents = vec![];
-    file.read_to_end(&mut contents).expect("couldn't read file");
-    let loader = pe_load::Loader::new(contents);
+    let loader = pe_load::Loader::from_path(PATH).expect("couldn't open file");
     let loaded = loader.load().expect("couldn't load .exe");
     let entry = loaded.entry_point.expect("entry point is empty");
     pe_load::wrapped_dll_main(entry)();
     println!("done");
 }
+
+#[cfg(not(windows))]
+fn main() {
+    println!("loading images is only supported on Windows");
+}
@@ -0,0 +1,48 @@
+// Helpers shared by the fuzz targets that load images.
+
+use pe_load::*;
+
+// Refuses allocations larger than `max_size`, images and objects can claim to be as large as they
+// want and the fuzzer shouldn't run out of memory on them.
+pub struct LimitedBackend {
+    backend: InMemoryBackend,
+    max_size: usize,
+}
+
+impl LimitedBackend {
+    pub fn new(base: u64, max_size: usize) -> Self {
+        LimitedBackend {
+            backend: InMemoryBackend::at(base),
+            max_size,
+        }
+    }
+}
+
+unsafe impl Backend for LimitedBackend {
+    fn allocate(&mut self, preferred_base: u64, size: usize) -> Result<Box<dyn Memory>, LoadError> {
+        if size > self.max_size {
+            return Err(LoadError::NoMemory);
+        }
+        self.backend.allocate(preferred_base, size)
+    }
+
+    fn page_size(&self) -> usize {
+        self.backend.page_size()
+    }
+}
+
+// Every module and every symbol exists.
+pub struct AnyResolver;
+
+impl ImportResolver for AnyResolver {
+    fn load_module(&mut self, name: &str) -> Result<u64, LoadError> {
+        Ok(0x1000 + name.len() as u64)
+    }
+
+    fn resolve(&mut self, module: u64, symbol: &ImportName) -> u64 {
+        match *symbol {
+            ImportName::Ordinal(ordinal) => module << 16 | ordinal as u64,
+            ImportName::Name { name, .. } => module << 16 | name.len() as u64,
+        }
+    }
+}
@@ -0,0 +1,45 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate pe_load;
+
+mod common;
+
+use common::{AnyResolver, LimitedBackend};
+use pe_load::*;
+
+// Anything larger than this is most likely a bogus size_of_image, don't let the fuzzer run out of
+// memory on it.
+const MAX_IMAGE_SIZE: usize = 0x100_0000;
+const MAX_DUMP_SIZE: usize = 0x1000_0000;
+
+// Mapped somewhere else than the usual image bases so the relocations are applied.
+const BASE: u64 = 0x7ff6_1234_0000;
+
+fuzz_target!(|data: &[u8]| {
+    let config = LoaderConfig::new()
+        .backend(LimitedBackend::new(BASE, MAX_IMAGE_SIZE))
+        .resolver(AnyResolver)
+        .ec_view(true);
+    let mut image = match Loader::with_config(data, config).load() {
+        Ok(image) => image,
+        Err(_) => return,
+    };
+
+    let slots = image.imports().map(|i| i.slot).collect::<Vec<_>>();
+    for slot in slots {
+        image.set_import_target(slot, 0).unwrap();
+    }
+    for export in image.exports() {
+        if let Some(ref name) = export.name {
+            image.export_address(name.as_str());
+        }
+    }
+
+    // Every section is dumped on its own, even if they overlap.
+    let sections = PEFile::parse(data).unwrap().num_sections();
+    if sections.saturating_mul(image.size()) <= MAX_DUMP_SIZE {
+        let options = DumpOptions::new().rebase(true).rebuild_imports(true);
+        let _ = image.dump_with_options(&options);
+    }
+});
@@ -0,0 +1,41 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate pe_load;
+
+mod common;
+
+use common::{AnyResolver, LimitedBackend};
+use pe_load::*;
+
+// Sections and common symbols can claim to be as large as they want.
+const MAX_OBJECT_SIZE: usize = 0x100_0000;
+
+const BASE: u64 = 0x7ff6_1234_0000;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file) = CoffFile::parse(data) {
+        if let Ok(table) = file.symbol_table() {
+            for _ in table.iter() {}
+        }
+        for section in file.sections() {
+            let _ = file.section_data(&section);
+            let _ = file.relocations(&section);
+        }
+    }
+
+    let config = LoaderConfig::new()
+        .backend(LimitedBackend::new(BASE, MAX_OBJECT_SIZE))
+        .resolver(AnyResolver);
+    let object = match ObjectLoader::with_config(data, config)
+        .search_module("kernel32")
+        .load()
+    {
+        Ok(object) => object,
+        Err(_) => return,
+    };
+
+    for symbol in object.symbols() {
+        assert!(object.symbol_address(&symbol.name).is_some());
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate pe_load;
+
+use std::io::Cursor;
+
+use pe_load::{Loader, PEFile};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file) = PEFile::parse(data) {
+        file.is_64_bit();
+        file.address_of_entry_point();
+        file.image_base();
+        file.size_of_image();
+        file.size_of_headers();
+        file.num_of_rva_and_sizes();
+    }
+
+    // Only reads what the headers point at, which may be anywhere.
+    let _ = Loader::from_reader(Cursor::new(data));
+});
@@ -0,0 +1,91 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate pe_load;
+
+use pe_load::{PEFile, DIRECTORIES};
+
+fuzz_target!(|data: &[u8]| {
+    let file = match PEFile::parse(data) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+
+    for section in file.sections() {
+        section.name_str();
+        let _ = file.section_name(&section);
+        file.rva_to_offset(section.virtual_address);
+        file.rva_to_offset(section.virtual_address.wrapping_add(section.mapped_size()));
+    }
+    for &entry in DIRECTORIES.iter() {
+        if let Ok(Some(directory)) = file.data_directory(entry) {
+            let _ = file.read_rva(directory.virtual_address, directory.size as usize);
+        }
+    }
+
+    if let Ok(imports) = file.imports() {
+        for module in imports.flatten() {
+            module.is_bound();
+            for _ in module.entries() {}
+        }
+    }
+
+    if let Ok(Some(exports)) = file.exports() {
+        if let Ok(iter) = exports.iter() {
+            for _ in iter {}
+        }
+        let _ = exports.by_name("DllMain");
+        let _ = exports.by_ordinal(exports.ordinal_base);
+        let _ = exports.by_ordinal(1);
+    }
+
+    if let Ok(blocks) = file.relocations() {
+        for block in blocks.flatten() {
+            for _ in block.relocations() {}
+        }
+    }
+    let _ = file.tls();
+    let _ = file.resources();
+    if let Ok(entries) = file.debug_directories() {
+        for entry in &entries {
+            let _ = file.debug_data(entry);
+        }
+        let _ = file.codeview();
+    }
+    let _ = file.dos_header();
+    if let Ok(Some(rich)) = file.rich_header() {
+        rich.hash();
+    }
+    let _ = file.imphash();
+    let _ = file.overlay();
+    let _ = file.clr_header();
+    let _ = file.clr_metadata();
+    let _ = file.load_config();
+    let _ = file.dynamic_relocations();
+    let _ = file.chpe_metadata();
+    let _ = file.code_ranges();
+    if let Ok(Some(view)) = file.ec_view() {
+        if let Ok(ec) = PEFile::parse(&view) {
+            let _ = ec.hybrid_machine();
+        }
+    }
+    file.validate();
+    if let Ok(Some(table)) = file.symbol_table() {
+        for symbol in table.iter().take(0x1000) {
+            if let Ok(symbol) = symbol {
+                let _ = symbol.aux_symbol();
+            }
+        }
+    }
+    file.section_fingerprints();
+    if let Ok(certificates) = file.certificates() {
+        for certificate in &certificates {
+            if let Ok(signed_data) = certificate.signed_data() {
+                let _ = signed_data.matches(&file);
+                for certificate in signed_data.signer_certificates() {
+                    let _ = certificate.subject_name();
+                }
+            }
+        }
+    }
+});
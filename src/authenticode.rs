@@ -0,0 +1,455 @@
+// Authenticode signatures in the certificate table.
+//
+// The security directory is the odd one out: its "RVA" is a file offset, since the certificates
+// are appended to the file and never mapped. Each WIN_CERTIFICATE holding a signature contains a
+// PKCS#7 SignedData whose content is a digest of the file, computed over everything but the
+// checksum, the security directory entry and the certificate table itself.
+//
+// Only as much DER is decoded as is needed to get at that digest and the certificates. Nothing in
+// here checks the signature over the digest or the certificate chain, only that the digest matches
+// the file.
+
+use std::ops::Range;
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::pe_file::{read_bytes, read_u16, read_u32, PEFile, ParseError};
+use crate::structs::DirectoryEntry;
+
+const CERTIFICATE_HEADER_SIZE: usize = 8;
+const CHECKSUM_OFFSET: usize = 64;
+
+pub const CERT_REVISION_1_0: u16 = 0x0100;
+pub const CERT_REVISION_2_0: u16 = 0x0200;
+pub const CERT_TYPE_X509: u16 = 1;
+pub const CERT_TYPE_PKCS_SIGNED_DATA: u16 = 2;
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_CONTEXT_0: u8 = 0xa0;
+const TAG_CONTEXT_1: u8 = 0xa1;
+const TAG_CONTEXT_3: u8 = 0xa3;
+// The IMPLICIT [0] subjectKeyIdentifier of a SignerInfo, which replaces the OCTET STRING tag.
+const TAG_CONTEXT_PRIMITIVE_0: u8 = 0x80;
+
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+const OID_SPC_INDIRECT_DATA: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x04];
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_SUBJECT_KEY_IDENTIFIER: &[u8] = &[0x55, 0x1d, 0x0e];
+
+/// The hash functions Authenticode signatures are made with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// The algorithm with the given DER encoded object identifier.
+    pub fn from_oid(oid: &[u8]) -> Option<Self> {
+        match oid {
+            [0x2b, 0x0e, 0x03, 0x02, 0x1a] => Some(DigestAlgorithm::Sha1),
+            [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01] => Some(DigestAlgorithm::Sha256),
+            [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02] => Some(DigestAlgorithm::Sha384),
+            [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03] => Some(DigestAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn hash<'b, I: Iterator<Item = &'b [u8]>>(self, parts: I) -> Vec<u8> {
+        fn hash<'b, D: Digest, I: Iterator<Item = &'b [u8]>>(parts: I) -> Vec<u8> {
+            let mut digest = D::new();
+            for part in parts {
+                digest.update(part);
+            }
+            digest.finalize().to_vec()
+        }
+
+        match self {
+            DigestAlgorithm::Sha1 => hash::<Sha1, _>(parts),
+            DigestAlgorithm::Sha256 => hash::<Sha256, _>(parts),
+            DigestAlgorithm::Sha384 => hash::<Sha384, _>(parts),
+            DigestAlgorithm::Sha512 => hash::<Sha512, _>(parts),
+        }
+    }
+}
+
+/// A WIN_CERTIFICATE in the certificate table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Certificate<'a> {
+    /// File offset of the entry.
+    pub offset: usize,
+    pub revision: u16,
+    /// One of the `CERT_TYPE_*` constants.
+    pub kind: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    /// Decodes the PKCS#7 SignedData of a `CERT_TYPE_PKCS_SIGNED_DATA` entry.
+    pub fn signed_data(&self) -> Result<SignedData<'a>, ParseError> {
+        if self.kind != CERT_TYPE_PKCS_SIGNED_DATA {
+            return Err(ParseError::InvalidSignature);
+        }
+        SignedData::parse(self.data)
+    }
+}
+
+/// An X.509 certificate, with the fields needed to tell which one signed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct X509Certificate<'a> {
+    /// The whole certificate in DER.
+    pub der: &'a [u8],
+    /// The contents of the serial number INTEGER.
+    pub serial_number: &'a [u8],
+    /// The issuer and subject Names in DER.
+    pub issuer: &'a [u8],
+    pub subject: &'a [u8],
+    /// The contents of the subject key identifier extension, if there is one.
+    pub subject_key_id: Option<&'a [u8]>,
+}
+
+impl<'a> X509Certificate<'a> {
+    fn parse(der: &'a [u8]) -> Result<Self, ParseError> {
+        let mut tbs = Der::new(der).read(TAG_SEQUENCE)?;
+        let mut tbs = tbs.read(TAG_SEQUENCE)?;
+        if tbs.peek() == Some(TAG_CONTEXT_0) {
+            tbs.read(TAG_CONTEXT_0)?;
+        }
+        let serial_number = tbs.read(TAG_INTEGER)?.rest;
+        tbs.read(TAG_SEQUENCE)?;
+        let issuer = tbs.read_element(TAG_SEQUENCE)?;
+        tbs.read(TAG_SEQUENCE)?;
+        let subject = tbs.read_element(TAG_SEQUENCE)?;
+        tbs.read(TAG_SEQUENCE)?;
+
+        // The unique identifiers and the extensions, all optional.
+        let mut subject_key_id = None;
+        while !tbs.rest.is_empty() {
+            let (tag, contents) = tbs.read_any()?;
+            if tag != TAG_CONTEXT_3 {
+                continue;
+            }
+            let mut extensions = Der::new(contents).read(TAG_SEQUENCE)?;
+            while !extensions.rest.is_empty() {
+                let mut extension = extensions.read(TAG_SEQUENCE)?;
+                let oid = extension.read(TAG_OID)?.rest;
+                if extension.peek() == Some(TAG_BOOLEAN) {
+                    extension.read(TAG_BOOLEAN)?;
+                }
+                let mut value = extension.read(TAG_OCTET_STRING)?;
+                if oid == OID_SUBJECT_KEY_IDENTIFIER {
+                    subject_key_id = Some(value.read(TAG_OCTET_STRING)?.rest);
+                }
+            }
+        }
+
+        Ok(Self {
+            der,
+            serial_number,
+            issuer,
+            subject,
+            subject_key_id,
+        })
+    }
+
+    /// The common name of the subject, if it has one that is a string.
+    pub fn subject_name(&self) -> Option<&'a str> {
+        common_name(self.subject)
+    }
+
+    pub fn issuer_name(&self) -> Option<&'a str> {
+        common_name(self.issuer)
+    }
+}
+
+/// Identifies the certificate of a signer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signer<'a> {
+    /// The issuer Name in DER and the contents of the serial number INTEGER, which version 1
+    /// SignerInfos use.
+    IssuerAndSerial {
+        issuer: &'a [u8],
+        serial_number: &'a [u8],
+    },
+    /// The subject key identifier of the certificate, which version 3 SignerInfos use.
+    SubjectKeyId(&'a [u8]),
+}
+
+impl<'a> Signer<'a> {
+    pub fn identifies(&self, certificate: &X509Certificate) -> bool {
+        match *self {
+            Signer::IssuerAndSerial {
+                issuer,
+                serial_number,
+            } => certificate.issuer == issuer && certificate.serial_number == serial_number,
+            Signer::SubjectKeyId(id) => certificate.subject_key_id == Some(id),
+        }
+    }
+}
+
+/// The parts of an Authenticode PKCS#7 SignedData needed to check it against a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedData<'a> {
+    /// The algorithm the file digest was computed with, `None` if it isn't supported.
+    pub digest_algorithm: Option<DigestAlgorithm>,
+    /// The Authenticode hash of the file at the time it was signed.
+    pub digest: &'a [u8],
+    /// Every certificate included, not only the ones of the signers.
+    pub certificates: Vec<X509Certificate<'a>>,
+    pub signers: Vec<Signer<'a>>,
+}
+
+impl<'a> SignedData<'a> {
+    /// Decodes a DER encoded ContentInfo holding an Authenticode SignedData.
+    pub fn parse(der: &'a [u8]) -> Result<Self, ParseError> {
+        let mut content_info = Der::new(der).read(TAG_SEQUENCE)?;
+        if content_info.read(TAG_OID)?.rest != OID_SIGNED_DATA {
+            return Err(ParseError::InvalidSignature);
+        }
+        let mut signed_data = content_info
+            .read(TAG_CONTEXT_0)?
+            .read(TAG_SEQUENCE)?;
+        signed_data.read(TAG_INTEGER)?;
+        signed_data.read(TAG_SET)?;
+
+        // SpcIndirectDataContent ::= SEQUENCE { data, messageDigest DigestInfo }
+        let mut content = signed_data.read(TAG_SEQUENCE)?;
+        if content.read(TAG_OID)?.rest != OID_SPC_INDIRECT_DATA {
+            return Err(ParseError::InvalidSignature);
+        }
+        let mut indirect_data = content.read(TAG_CONTEXT_0)?.read(TAG_SEQUENCE)?;
+        indirect_data.read(TAG_SEQUENCE)?;
+        let mut digest_info = indirect_data.read(TAG_SEQUENCE)?;
+        let digest_algorithm = digest_info.read(TAG_SEQUENCE)?.read(TAG_OID)?.rest;
+        let digest = digest_info.read(TAG_OCTET_STRING)?.rest;
+
+        let mut certificates = vec![];
+        if signed_data.peek() == Some(TAG_CONTEXT_0) {
+            let mut list = signed_data.read(TAG_CONTEXT_0)?;
+            while !list.rest.is_empty() {
+                certificates.push(X509Certificate::parse(list.read_element(TAG_SEQUENCE)?)?);
+            }
+        }
+        if signed_data.peek() == Some(TAG_CONTEXT_1) {
+            signed_data.read(TAG_CONTEXT_1)?;
+        }
+
+        let mut signers = vec![];
+        let mut signer_infos = signed_data.read(TAG_SET)?;
+        while !signer_infos.rest.is_empty() {
+            let mut signer_info = signer_infos.read(TAG_SEQUENCE)?;
+            signer_info.read(TAG_INTEGER)?;
+            signers.push(match signer_info.peek() {
+                Some(TAG_CONTEXT_PRIMITIVE_0) => {
+                    Signer::SubjectKeyId(signer_info.read(TAG_CONTEXT_PRIMITIVE_0)?.rest)
+                }
+                _ => {
+                    let mut issuer_and_serial = signer_info.read(TAG_SEQUENCE)?;
+                    Signer::IssuerAndSerial {
+                        issuer: issuer_and_serial.read_element(TAG_SEQUENCE)?,
+                        serial_number: issuer_and_serial.read(TAG_INTEGER)?.rest,
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            digest_algorithm: DigestAlgorithm::from_oid(digest_algorithm),
+            digest,
+            certificates,
+            signers,
+        })
+    }
+
+    /// The certificates of the signers, in the order of `signers`.
+    pub fn signer_certificates(&self) -> Vec<X509Certificate<'a>> {
+        self.signers
+            .iter()
+            .filter_map(|signer| {
+                self.certificates
+                    .iter()
+                    .find(|c| signer.identifies(c))
+                    .copied()
+            })
+            .collect()
+    }
+
+    /// Whether `digest` is the Authenticode hash of `file`. `false` if the digest algorithm isn't
+    /// supported.
+    pub fn matches(&self, file: &PEFile) -> Result<bool, ParseError> {
+        match self.digest_algorithm {
+            Some(algorithm) => Ok(file.authenticode_hash(algorithm)? == self.digest),
+            None => Ok(false),
+        }
+    }
+}
+
+impl<'a> PEFile<'a> {
+    /// The entries in the certificate table, empty if there is none.
+    pub fn certificates(&self) -> Result<Vec<Certificate<'a>>, ParseError> {
+        let table = match self.certificate_table()? {
+            Some(table) => table,
+            None => return Ok(vec![]),
+        };
+        let buffer = self.buffer();
+
+        let mut certificates = vec![];
+        let mut offset = table.start;
+        while offset < table.end {
+            let length = read_u32(buffer, offset)? as usize;
+            if length < CERTIFICATE_HEADER_SIZE || length > table.end - offset {
+                return Err(ParseError::InvalidSignature);
+            }
+            certificates.push(Certificate {
+                offset,
+                revision: read_u16(buffer, offset + 4)?,
+                kind: read_u16(buffer, offset + 6)?,
+                data: read_bytes(
+                    buffer,
+                    offset + CERTIFICATE_HEADER_SIZE,
+                    length - CERTIFICATE_HEADER_SIZE,
+                )?,
+            });
+            // Entries are aligned to 8 bytes.
+            offset += (length + 7) & !7;
+        }
+
+        Ok(certificates)
+    }
+
+    /// The file range of the certificate table.
+    pub fn certificate_table(&self) -> Result<Option<Range<usize>>, ParseError> {
+        let directory = match self.data_directory(DirectoryEntry::Security)? {
+            Some(directory) => directory,
+            None => return Ok(None),
+        };
+        let start = directory.virtual_address as usize;
+        read_bytes(self.buffer(), start, directory.size as usize)?;
+        Ok(Some(start..start + directory.size as usize))
+    }
+
+    /// The parts of the file covered by the Authenticode hash: all of it except the checksum, the
+    /// security directory entry and the certificate table.
+    pub fn authenticode_ranges(&self) -> Result<Vec<Range<usize>>, ParseError> {
+        let checksum = self.optional_header_offset() + CHECKSUM_OFFSET;
+        let mut excluded = vec![];
+        excluded.push(checksum..checksum + 4);
+        if (DirectoryEntry::Security as u32) < self.num_of_rva_and_sizes() {
+            let entry = self.data_directory_offset(DirectoryEntry::Security);
+            excluded.push(entry..entry + 8);
+        }
+        if let Some(table) = self.certificate_table()? {
+            excluded.push(table);
+        }
+        excluded.sort_by_key(|range| range.start);
+
+        let mut ranges = vec![];
+        let mut start = 0;
+        for range in excluded {
+            if range.start > start {
+                ranges.push(start..range.start);
+            }
+            start = start.max(range.end);
+        }
+        if start < self.buffer().len() {
+            ranges.push(start..self.buffer().len());
+        }
+        Ok(ranges)
+    }
+
+    /// The Authenticode hash of the file, to compare with `SignedData::digest`.
+    pub fn authenticode_hash(&self, algorithm: DigestAlgorithm) -> Result<Vec<u8>, ParseError> {
+        let buffer = self.buffer();
+        let ranges = self.authenticode_ranges()?;
+        Ok(algorithm.hash(ranges.into_iter().map(|range| &buffer[range])))
+    }
+}
+
+// The first common name in a DER encoded Name.
+fn common_name(name: &[u8]) -> Option<&str> {
+    let mut name = Der::new(name).read(TAG_SEQUENCE).ok()?;
+    while !name.rest.is_empty() {
+        let mut set = name.read(TAG_SET).ok()?;
+        while !set.rest.is_empty() {
+            let mut attribute = set.read(TAG_SEQUENCE).ok()?;
+            if attribute.read(TAG_OID).ok()?.rest == OID_COMMON_NAME {
+                // Any of the string types, they are all read as UTF-8.
+                let (_, value) = attribute.read_any().ok()?;
+                return ::std::str::from_utf8(value).ok();
+            }
+        }
+    }
+    None
+}
+
+// Reads DER elements one after the other. Only definite lengths are supported, which is all DER
+// allows.
+struct Der<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(der: &'a [u8]) -> Self {
+        Der { rest: der }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.rest.first().copied()
+    }
+
+    // The next element, tag and length included, along with its contents.
+    fn read_raw(&mut self) -> Result<(u8, &'a [u8], &'a [u8]), ParseError> {
+        let (&tag, rest) = self.rest.split_first().ok_or(ParseError::InvalidSignature)?;
+        let (&first, mut rest) = rest.split_first().ok_or(ParseError::InvalidSignature)?;
+        let length = if first < 0x80 {
+            first as usize
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || count > rest.len() {
+                return Err(ParseError::InvalidSignature);
+            }
+            let length = rest[..count]
+                .iter()
+                .fold(0usize, |length, &b| length << 8 | b as usize);
+            rest = &rest[count..];
+            length
+        };
+        if length > rest.len() {
+            return Err(ParseError::InvalidSignature);
+        }
+
+        let header = self.rest.len() - rest.len();
+        let element = &self.rest[..header + length];
+        self.rest = &rest[length..];
+        Ok((tag, element, &rest[..length]))
+    }
+
+    fn read_any(&mut self) -> Result<(u8, &'a [u8]), ParseError> {
+        let (tag, _, contents) = self.read_raw()?;
+        Ok((tag, contents))
+    }
+
+    // The contents of the next element, which has to have the tag `tag`.
+    fn read(&mut self, tag: u8) -> Result<Der<'a>, ParseError> {
+        match self.read_raw()? {
+            (t, _, contents) if t == tag => Ok(Der::new(contents)),
+            _ => Err(ParseError::InvalidSignature),
+        }
+    }
+
+    // Like `read`, but the whole element.
+    fn read_element(&mut self, tag: u8) -> Result<&'a [u8], ParseError> {
+        match self.read_raw()? {
+            (t, element, _) if t == tag => Ok(element),
+            _ => Err(ParseError::InvalidSignature),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// Where the loader maps images to.
+//
+// `WindowsBackend` maps images into the current process so they can be run, `InMemoryBackend`
+// maps them into a plain heap buffer at an arbitrary address, which works on any host and is what
+// analysis tools and tests use.
+
+#[cfg(windows)]
+use std::mem;
+use std::slice;
+
+#[cfg(windows)]
+use kernel32::{GetNativeSystemInfo, VirtualAlloc, VirtualFree, VirtualProtect};
+#[cfg(windows)]
+use winapi::{
+    MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
+    PAGE_EXECUTE_WRITECOPY, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOPY,
+};
+
+use super::LoadError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    NoAccess,
+    ReadOnly,
+    ReadWrite,
+    WriteCopy,
+    Execute,
+    ExecuteRead,
+    ExecuteReadWrite,
+    ExecuteWriteCopy,
+}
+
+impl Protection {
+    pub fn is_readable(self) -> bool {
+        !matches!(self, Protection::NoAccess | Protection::Execute)
+    }
+
+    // The same protection, but also allowing reads.
+    pub(crate) fn readable(self) -> Protection {
+        match self {
+            Protection::NoAccess => Protection::ReadOnly,
+            Protection::Execute => Protection::ExecuteRead,
+            protection => protection,
+        }
+    }
+}
+
+/// Memory an image is mapped into.
+///
+/// # Safety
+///
+/// The loader reads and writes through `as_ptr` without further checks. Implementations have to
+/// guarantee that `as_ptr` always returns the same pointer to `size` bytes, which stay allocated
+/// until the memory is dropped and are valid for reads and writes for as long as they are
+/// readable and writable according to the last call to `protect` covering them. Memory starts
+/// out zeroed and `ReadWrite`. `is_native` may only return `true` if `base` is the address
+/// `as_ptr` points to.
+pub unsafe trait Memory {
+    /// Address of the mapping as seen by the image, i.e. what it gets relocated to.
+    fn base(&self) -> u64;
+
+    fn size(&self) -> usize;
+
+    /// Pointer to the start of the mapping in this process.
+    fn as_ptr(&self) -> *mut u8;
+
+    /// Whether `base` is also the address of the mapping in this process, which is required for
+    /// running any code of the image.
+    fn is_native(&self) -> bool;
+
+    /// Changes the protection of `size` bytes starting at `offset` and returns the previous
+    /// protection.
+    fn protect(
+        &mut self,
+        offset: usize,
+        size: usize,
+        protection: Protection,
+    ) -> Result<Protection, LoadError>;
+}
+
+// A copy of all of `memory`. The protection of the ranges in `protections` that can't be read is
+// lifted while copying, every range is given with the protection it was last set to.
+pub(crate) fn read_memory(
+    memory: &mut dyn Memory,
+    protections: &[(usize, usize, Protection)],
+) -> Result<Vec<u8>, LoadError> {
+    let mut lifted = vec![];
+    for &(offset, size, protection) in protections {
+        if !protection.is_readable() {
+            let old = memory.protect(offset, size, protection.readable())?;
+            lifted.push((offset, size, old));
+        }
+    }
+
+    let copy = unsafe { slice::from_raw_parts(memory.as_ptr(), memory.size()) }.to_vec();
+
+    for (offset, size, old) in lifted {
+        memory.protect(offset, size, old)?;
+    }
+    Ok(copy)
+}
+
+/// # Safety
+///
+/// The loader trusts the memory returned by `allocate` to be at least `size` bytes large and to
+/// uphold the contract of `Memory`. `page_size` has to be a power of two.
+pub unsafe trait Backend {
+    /// Allocates `size` bytes of zeroed, writable memory. The image would like to be mapped at
+    /// `preferred_base`, but the backend is free to choose any other address.
+    fn allocate(&mut self, preferred_base: u64, size: usize) -> Result<Box<dyn Memory>, LoadError>;
+
+    fn page_size(&self) -> usize;
+}
+
+const IN_MEMORY_PAGE_SIZE: usize = 0x1000;
+
+/// Maps images into heap memory, either at their preferred base or at a fixed one.
+#[derive(Default)]
+pub struct InMemoryBackend {
+    base: Option<u64>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps every image at `base` instead of its preferred base.
+    pub fn at(base: u64) -> Self {
+        Self { base: Some(base) }
+    }
+}
+
+unsafe impl Backend for InMemoryBackend {
+    fn allocate(&mut self, preferred_base: u64, size: usize) -> Result<Box<dyn Memory>, LoadError> {
+        Ok(Box::new(InMemory::new(
+            self.base.unwrap_or(preferred_base),
+            size,
+        )))
+    }
+
+    fn page_size(&self) -> usize {
+        IN_MEMORY_PAGE_SIZE
+    }
+}
+
+pub struct InMemory {
+    base: u64,
+    raw: *mut [u8],
+    // One entry per page
+    protection: Vec<Protection>,
+}
+
+impl InMemory {
+    fn new(base: u64, size: usize) -> Self {
+        let pages = size.div_ceil(IN_MEMORY_PAGE_SIZE);
+
+        Self {
+            base,
+            raw: Box::into_raw(vec![0u8; size].into_boxed_slice()),
+            protection: vec![Protection::ReadWrite; pages],
+        }
+    }
+}
+
+unsafe impl Memory for InMemory {
+    fn base(&self) -> u64 {
+        self.base
+    }
+
+    fn size(&self) -> usize {
+        self.raw.len()
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.raw as *mut u8
+    }
+
+    fn is_native(&self) -> bool {
+        false
+    }
+
+    fn protect(
+        &mut self,
+        offset: usize,
+        size: usize,
+        protection: Protection,
+    ) -> Result<Protection, LoadError> {
+        let first = offset / IN_MEMORY_PAGE_SIZE;
+        let last = (offset + size).div_ceil(IN_MEMORY_PAGE_SIZE);
+        if size == 0 || last > self.protection.len() {
+            return Err(LoadError::VirtualProtectFailed);
+        }
+
+        let old = self.protection[first];
+        for p in &mut self.protection[first..last] {
+            *p = protection;
+        }
+        Ok(old)
+    }
+}
+
+impl Drop for InMemory {
+    fn drop(&mut self) {
+        unsafe { drop(Box::from_raw(self.raw)) };
+    }
+}
+
+/// Maps images into the current process with `VirtualAlloc`.
+#[cfg(windows)]
+#[derive(Default)]
+pub struct WindowsBackend;
+
+#[cfg(windows)]
+unsafe fn get_native_page_size() -> u32 {
+    let mut sys_info = mem::zeroed();
+    GetNativeSystemInfo(&mut sys_info);
+    sys_info.dwPageSize
+}
+
+#[cfg(windows)]
+unsafe impl Backend for WindowsBackend {
+    fn allocate(&mut self, preferred_base: u64, size: usize) -> Result<Box<dyn Memory>, LoadError> {
+        let flags = MEM_RESERVE | MEM_COMMIT;
+        let memory = WindowsBox::alloc(preferred_base, size as _, flags, PAGE_READWRITE)
+            .or_else(|_| WindowsBox::alloc(0, size as _, flags, PAGE_READWRITE))?;
+        Ok(Box::new(memory))
+    }
+
+    fn page_size(&self) -> usize {
+        unsafe { get_native_page_size() as usize }
+    }
+}
+
+#[cfg(windows)]
+struct WindowsBox {
+    raw: *mut u8,
+    size: usize,
+}
+
+#[cfg(windows)]
+impl WindowsBox {
+    // Anywhere if `address` is zero.
+    fn alloc(address: u64, size: u64, flags: u32, protection: u32) -> Result<Self, LoadError> {
+        let result = unsafe { VirtualAlloc(address as *mut _, size, flags, protection) };
+
+        if result.is_null() {
+            Err(LoadError::NoMemory)
+        } else {
+            Ok(Self {
+                raw: result as *mut _,
+                size: size as usize,
+            })
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for WindowsBox {
+    fn drop(&mut self) {
+        unsafe { VirtualFree(self.raw as *mut _, 0, MEM_RELEASE) };
+    }
+}
+
+#[cfg(windows)]
+fn page_protection(protection: Protection) -> u32 {
+    match protection {
+        Protection::NoAccess => PAGE_NOACCESS,
+        Protection::ReadOnly => PAGE_READONLY,
+        Protection::ReadWrite => PAGE_READWRITE,
+        Protection::WriteCopy => PAGE_WRITECOPY,
+        Protection::Execute => PAGE_EXECUTE,
+        Protection::ExecuteRead => PAGE_EXECUTE_READ,
+        Protection::ExecuteReadWrite => PAGE_EXECUTE_READWRITE,
+        Protection::ExecuteWriteCopy => PAGE_EXECUTE_WRITECOPY,
+    }
+}
+
+#[cfg(windows)]
+unsafe impl Memory for WindowsBox {
+    fn base(&self) -> u64 {
+        self.raw as u64
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.raw
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+
+    fn protect(
+        &mut self,
+        offset: usize,
+        size: usize,
+        protection: Protection,
+    ) -> Result<Protection, LoadError> {
+        let mut old_protect = 0u32;
+        if unsafe {
+            VirtualProtect(
+                self.raw.add(offset) as _,
+                size as _,
+                page_protection(protection),
+                &mut old_protect,
+            )
+        } == 0
+        {
+            return Err(LoadError::VirtualProtectFailed);
+        }
+
+        [
+            Protection::NoAccess,
+            Protection::ReadOnly,
+            Protection::ReadWrite,
+            Protection::WriteCopy,
+            Protection::Execute,
+            Protection::ExecuteRead,
+            Protection::ExecuteReadWrite,
+            Protection::ExecuteWriteCopy,
+        ]
+        .iter()
+        .cloned()
+        .find(|&p| page_protection(p) == old_protect & 0xff)
+        .ok_or(LoadError::VirtualProtectFailed)
+    }
+}
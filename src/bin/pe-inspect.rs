@@ -0,0 +1,1226 @@
+// Prints everything the parser knows about a PE file.
+//
+//     pe-inspect [--json] <file>
+//
+// Only `PEFile` is used, the file is never mapped, so this works for images of any architecture
+// on any host.
+
+extern crate pe_load;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::ops::Range;
+use std::process;
+
+use pe_load::*;
+
+const USAGE: &str = "usage: pe-inspect [--json] <file>";
+
+fn relocation_name(kind: u16) -> String {
+    match kind {
+        0 => "ABSOLUTE".into(),
+        1 => "HIGH".into(),
+        2 => "LOW".into(),
+        3 => "HIGHLOW".into(),
+        4 => "HIGHADJ".into(),
+        10 => "DIR64".into(),
+        kind => format!("TYPE{}", kind),
+    }
+}
+
+fn debug_name(kind: u32) -> String {
+    match kind {
+        DEBUG_TYPE_COFF => "COFF".into(),
+        DEBUG_TYPE_CODEVIEW => "CODEVIEW".into(),
+        DEBUG_TYPE_FPO => "FPO".into(),
+        DEBUG_TYPE_MISC => "MISC".into(),
+        DEBUG_TYPE_OMAP_TO_SRC => "OMAP_TO_SRC".into(),
+        DEBUG_TYPE_OMAP_FROM_SRC => "OMAP_FROM_SRC".into(),
+        DEBUG_TYPE_BORLAND => "BORLAND".into(),
+        DEBUG_TYPE_CLSID => "CLSID".into(),
+        DEBUG_TYPE_VC_FEATURE => "VC_FEATURE".into(),
+        DEBUG_TYPE_POGO => "POGO".into(),
+        DEBUG_TYPE_ILTCG => "ILTCG".into(),
+        DEBUG_TYPE_REPRO => "REPRO".into(),
+        DEBUG_TYPE_EX_DLL_CHARACTERISTICS => "EX_DLLCHARACTERISTICS".into(),
+        kind => format!("TYPE{}", kind),
+    }
+}
+
+fn resource_type_name(id: &ResourceId) -> String {
+    let names = [
+        "", "CURSOR", "BITMAP", "ICON", "MENU", "DIALOG", "STRING", "FONTDIR", "FONT",
+        "ACCELERATOR", "RCDATA", "MESSAGETABLE", "GROUP_CURSOR", "", "GROUP_ICON", "", "VERSION",
+        "DLGINCLUDE", "", "PLUGPLAY", "VXD", "ANICURSOR", "ANIICON", "HTML", "MANIFEST",
+    ];
+    match *id {
+        ResourceId::Id(id) if names.get(id as usize).is_some_and(|n| !n.is_empty()) => {
+            names[id as usize].into()
+        }
+        ref id => resource_id(id),
+    }
+}
+
+fn resource_id(id: &ResourceId) -> String {
+    match *id {
+        ResourceId::Id(id) => id.to_string(),
+        ResourceId::Name(ref name) => name.clone(),
+    }
+}
+
+fn protection(characteristics: SectionCharacteristics) -> String {
+    [
+        (characteristics.is_readable(), 'R'),
+        (characteristics.is_writable(), 'W'),
+        (characteristics.is_executable(), 'X'),
+    ]
+    .iter()
+    .map(|&(set, c)| if set { c } else { '-' })
+    .collect()
+}
+
+fn guid(guid: &[u8; 16]) -> String {
+    format!(
+        "{{{:08X}-{:04X}-{:04X}-{}-{}}}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8..10].iter().map(|b| format!("{:02X}", b)).collect::<String>(),
+        guid[10..].iter().map(|b| format!("{:02X}", b)).collect::<String>()
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn certificate_type_name(kind: u16) -> String {
+    match kind {
+        CERT_TYPE_X509 => "X.509".into(),
+        CERT_TYPE_PKCS_SIGNED_DATA => "PKCS#7".into(),
+        _ => format!("type {}", kind),
+    }
+}
+
+fn digest_name(algorithm: Option<DigestAlgorithm>) -> &'static str {
+    match algorithm {
+        Some(DigestAlgorithm::Sha1) => "SHA-1",
+        Some(DigestAlgorithm::Sha256) => "SHA-256",
+        Some(DigestAlgorithm::Sha384) => "SHA-384",
+        Some(DigestAlgorithm::Sha512) => "SHA-512",
+        None => "unknown",
+    }
+}
+
+// The decoded signature and whether its digest matches the file.
+type Signature<'a> = Result<(SignedData<'a>, bool), ParseError>;
+
+struct Relocations {
+    blocks: usize,
+    // Number of relocations of each type.
+    kinds: BTreeMap<u16, usize>,
+}
+
+// The entry point and exports of the ARM64EC view of an ARM64X image.
+struct EcView {
+    entry_point: u32,
+    exports: Vec<(Option<String>, u32)>,
+}
+
+struct Hybrid {
+    machine: Machine,
+    metadata: ChpeMetadata,
+    code_ranges: Vec<CodeRange>,
+    arm64x_relocations: usize,
+    ec_view: Option<EcView>,
+}
+
+impl Hybrid {
+    fn new(file: PEFile) -> Result<Option<Self>, ParseError> {
+        let (machine, metadata) = match (file.hybrid_machine()?, file.chpe_metadata()?) {
+            (Some(machine), Some(metadata)) => (machine, metadata),
+            _ => return Ok(None),
+        };
+        let ec_view = match file.ec_view()? {
+            None => None,
+            Some(view) => {
+                let ec = PEFile::parse(&view)?;
+                let exports = match ec.exports()? {
+                    None => vec![],
+                    Some(exports) => exports
+                        .iter()?
+                        .map(|e| e.map(|e| (e.name.map(|n| n.to_owned()), e.rva)))
+                        .collect::<Result<_, _>>()?,
+                };
+                Some(EcView {
+                    entry_point: ec.address_of_entry_point(),
+                    exports,
+                })
+            }
+        };
+        Ok(Some(Self {
+            machine,
+            metadata,
+            code_ranges: file.code_ranges()?,
+            arm64x_relocations: file.arm64x_relocations()?.len(),
+            ec_view,
+        }))
+    }
+}
+
+fn code_range_kind(kind: CodeRangeKind) -> &'static str {
+    match kind {
+        CodeRangeKind::Arm64 => "ARM64",
+        CodeRangeKind::Arm64EC => "ARM64EC",
+        CodeRangeKind::Amd64 => "AMD64",
+    }
+}
+
+struct Report<'a> {
+    file: PEFile<'a>,
+    rich: Result<Option<RichHeader>, ParseError>,
+    sections: Vec<SectionFingerprint>,
+    imphash: Result<Option<String>, ParseError>,
+    overlay: Result<Option<Range<usize>>, ParseError>,
+    symbols: Result<Option<Vec<Symbol<'a>>>, ParseError>,
+    findings: Vec<Finding>,
+    directories: Vec<(DirectoryEntry, DataDirectory)>,
+    imports: Result<Vec<(ImportedModule<'a>, Vec<ImportEntry<'a>>)>, ParseError>,
+    exports: Result<Option<(Exports<'a>, Vec<Export<'a>>)>, ParseError>,
+    relocations: Result<Relocations, ParseError>,
+    tls: Result<Option<Tls>, ParseError>,
+    resources: Result<Vec<Resource>, ParseError>,
+    debug: Result<(Vec<DebugDirectory>, Option<CodeView<'a>>), ParseError>,
+    certificates: Result<Vec<(Certificate<'a>, Option<Signature<'a>>)>, ParseError>,
+    clr: Result<Option<ClrHeader>, ParseError>,
+    metadata: Result<Option<Metadata<'a>>, ParseError>,
+    hybrid: Result<Option<Hybrid>, ParseError>,
+}
+
+impl<'a> Report<'a> {
+    fn new(file: PEFile<'a>) -> Self {
+        let imports = file.imports().and_then(|modules| {
+            modules
+                .map(|module| {
+                    let module = module?;
+                    Ok((module, module.entries().collect::<Result<Vec<_>, _>>()?))
+                })
+                .collect()
+        });
+
+        let exports = file.exports().and_then(|exports| match exports {
+            None => Ok(None),
+            Some(exports) => Ok(Some((exports, exports.iter()?.collect::<Result<_, _>>()?))),
+        });
+
+        let relocations = file.relocations().and_then(|blocks| {
+            let mut relocations = Relocations {
+                blocks: 0,
+                kinds: BTreeMap::new(),
+            };
+            for block in blocks {
+                relocations.blocks += 1;
+                for relocation in block?.relocations() {
+                    *relocations.kinds.entry(relocation.kind).or_insert(0) += 1;
+                }
+            }
+            Ok(relocations)
+        });
+
+        let debug = file
+            .debug_directories()
+            .and_then(|entries| Ok((entries, file.codeview()?)));
+
+        let certificates = file.certificates().map(|certificates| {
+            certificates
+                .into_iter()
+                .map(|certificate| {
+                    let signature = match certificate.kind {
+                        CERT_TYPE_PKCS_SIGNED_DATA => {
+                            Some(certificate.signed_data().and_then(|signed_data| {
+                                let matches = signed_data.matches(&file)?;
+                                Ok((signed_data, matches))
+                            }))
+                        }
+                        _ => None,
+                    };
+                    (certificate, signature)
+                })
+                .collect()
+        });
+
+        let symbols = file.symbol_table().and_then(|table| match table {
+            None => Ok(None),
+            Some(table) => Ok(Some(table.iter().collect::<Result<_, _>>()?)),
+        });
+
+        Self {
+            file,
+            rich: file.rich_header(),
+            sections: file.section_fingerprints(),
+            imphash: file.imphash(),
+            overlay: file.overlay_range(),
+            symbols,
+            findings: file.validate(),
+            directories: DIRECTORIES
+                .iter()
+                .filter_map(|&e| file.data_directory(e).ok().and_then(|d| d).map(|d| (e, d)))
+                .collect(),
+            imports,
+            exports,
+            relocations,
+            tls: file.tls(),
+            resources: file.resources(),
+            debug,
+            certificates,
+            clr: file.clr_header(),
+            metadata: file.clr_metadata(),
+            hybrid: Hybrid::new(file),
+        }
+    }
+
+    // Section names can be longer than 8 bytes if the image has a string table.
+    fn section_name<'s>(&self, section: &'s Section) -> &'s str
+    where
+        'a: 's,
+    {
+        self.file.section_name(section).unwrap_or("?")
+    }
+
+    fn print(&self, name: &str) {
+        let file = &self.file;
+        println!(
+            "{}: {} {}, {}",
+            name,
+            if file.is_64_bit() { "PE32+" } else { "PE32" },
+            if file.file_characteristics().is_dll() {
+                "DLL"
+            } else {
+                "executable"
+            },
+            file.machine().name()
+        );
+
+        println!("\nHeaders");
+        let headers = [
+            ("Machine", format!("{:#06x} ({})", file.machine().0, file.machine().name())),
+            ("Sections", file.num_sections().to_string()),
+            ("Time stamp", format!("{:#010x}", file.time_date_stamp())),
+            ("Characteristics", format!("{:#06x}", file.file_characteristics().bits())),
+            ("Entry point", format!("{:#010x}", file.address_of_entry_point())),
+            ("Image base", format!("{:#x}", file.image_base())),
+            ("Section alignment", format!("{:#x}", file.section_alignment())),
+            ("File alignment", format!("{:#x}", file.file_alignment())),
+            ("Size of image", format!("{:#x}", file.size_of_image())),
+            ("Size of headers", format!("{:#x}", file.size_of_headers())),
+            (
+                "Checksum",
+                if file.has_valid_checksum() {
+                    format!("{:#010x} (valid)", file.checksum())
+                } else {
+                    format!(
+                        "{:#010x} (should be {:#010x})",
+                        file.checksum(),
+                        file.compute_checksum()
+                    )
+                },
+            ),
+            (
+                "Subsystem",
+                format!("{} ({})", file.subsystem().0, file.subsystem().name()),
+            ),
+            ("DLL characteristics", format!("{:#06x}", file.dll_characteristics().bits())),
+        ];
+        for &(name, ref value) in &headers {
+            println!("  {:<20} {}", name, value);
+        }
+
+        println!("\nFindings");
+        if self.findings.is_empty() {
+            println!("  none");
+        }
+        for finding in &self.findings {
+            println!("  {:?}", finding);
+        }
+
+        println!("\nRich header");
+        match self.rich {
+            Ok(Some(ref rich)) => {
+                println!(
+                    "  {} bytes at {:#x}, key {:#010x} ({})",
+                    rich.size,
+                    rich.offset,
+                    rich.key,
+                    if rich.is_valid() { "valid" } else { "invalid" }
+                );
+                for entry in &rich.entries {
+                    println!(
+                        "  Product {:#06x}  build {:<5}  {} objects",
+                        entry.product_id, entry.build, entry.count
+                    );
+                }
+            }
+            Ok(None) => println!("  none"),
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nSections");
+        println!(
+            "  {:<8}  {:<8}  {:<8}  {:<8}  {:<8}  {:<5}  Entropy",
+            "Name", "VirtAddr", "VirtSize", "RawAddr", "RawSize", "Flags"
+        );
+        for &SectionFingerprint { ref section, entropy, .. } in &self.sections {
+            println!(
+                "  {:<8}  {:08x}  {:08x}  {:08x}  {:08x}  {:<5}  {:.2}",
+                self.section_name(section),
+                section.virtual_address,
+                section.virtual_size,
+                section.p_raw_data,
+                section.size_of_raw_data,
+                protection(section.characteristics),
+                entropy
+            );
+        }
+
+        println!("\nData directories");
+        for &(entry, directory) in &self.directories {
+            println!(
+                "  {:<14} {:08x}  {:08x}",
+                format!("{:?}", entry),
+                directory.virtual_address,
+                directory.size
+            );
+        }
+
+        println!("\nImports");
+        match self.imports {
+            Ok(ref modules) if modules.is_empty() => println!("  none"),
+            Ok(ref modules) => {
+                for (module, entries) in modules {
+                    println!("  {}", module.name);
+                    for entry in entries {
+                        match entry.name {
+                            ImportName::Name { hint, name } => {
+                                println!("    {:08x}  {} (hint {})", entry.iat_rva, name, hint)
+                            }
+                            ImportName::Ordinal(ordinal) => {
+                                println!("    {:08x}  #{}", entry.iat_rva, ordinal)
+                            }
+                        }
+                    }
+                }
+            }
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nExports");
+        match self.exports {
+            Ok(Some((ref exports, ref all))) => {
+                println!("  {}", exports.name);
+                for export in all {
+                    let name = export.name.unwrap_or("");
+                    match export.forwarder {
+                        Some(forwarder) => {
+                            println!("    {:>5}  {:8}  {} -> {}", export.ordinal, "", name, forwarder)
+                        }
+                        None => println!("    {:>5}  {:08x}  {}", export.ordinal, export.rva, name),
+                    }
+                }
+            }
+            Ok(None) => println!("  none"),
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nRelocations");
+        match self.relocations {
+            Ok(ref relocations) => {
+                let kinds = relocations
+                    .kinds
+                    .iter()
+                    .map(|(&kind, count)| format!("{} {}", relocation_name(kind), count))
+                    .collect::<Vec<_>>();
+                println!(
+                    "  {} blocks, {} relocations{}{}",
+                    relocations.blocks,
+                    relocations.kinds.values().sum::<usize>(),
+                    if kinds.is_empty() { "" } else { ": " },
+                    kinds.join(", ")
+                );
+            }
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nTLS");
+        match self.tls {
+            Ok(Some(ref tls)) => {
+                println!(
+                    "  Raw data   {:#x}-{:#x}",
+                    tls.start_address_of_raw_data, tls.end_address_of_raw_data
+                );
+                println!("  Index      {:#x}", tls.address_of_index);
+                for callback in &tls.callbacks {
+                    println!("  Callback   {:#x}", callback);
+                }
+            }
+            Ok(None) => println!("  none"),
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nResources");
+        match self.resources {
+            Ok(ref resources) if resources.is_empty() => println!("  none"),
+            Ok(ref resources) => {
+                for resource in resources {
+                    println!(
+                        "  {}/{}/{}  {:08x}  {} bytes, code page {}",
+                        resource_type_name(&resource.kind),
+                        resource_id(&resource.name),
+                        resource_id(&resource.language),
+                        resource.rva,
+                        resource.size,
+                        resource.code_page
+                    );
+                }
+            }
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nDebug");
+        match self.debug {
+            Ok((ref entries, _)) if entries.is_empty() => println!("  none"),
+            Ok((ref entries, ref codeview)) => {
+                for entry in entries {
+                    println!(
+                        "  {:<12} {:#010x}  {} bytes at {:#x}",
+                        debug_name(entry.kind),
+                        entry.time_date_stamp,
+                        entry.size_of_data,
+                        entry.pointer_to_raw_data
+                    );
+                }
+                if let Some(ref codeview) = *codeview {
+                    println!(
+                        "  PDB          {} {} age {}",
+                        codeview.path,
+                        guid(&codeview.guid),
+                        codeview.age
+                    );
+                }
+            }
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nCertificates");
+        match self.certificates {
+            Ok(ref certificates) if certificates.is_empty() => println!("  none"),
+            Ok(ref certificates) => {
+                for (certificate, signature) in certificates {
+                    println!(
+                        "  {:<12} revision {:#06x}, {} bytes at {:#x}",
+                        certificate_type_name(certificate.kind),
+                        certificate.revision,
+                        certificate.data.len(),
+                        certificate.offset
+                    );
+                    match *signature {
+                        Some(Ok((ref signed_data, matches))) => {
+                            println!(
+                                "  Digest       {} {} ({})",
+                                digest_name(signed_data.digest_algorithm),
+                                hex(signed_data.digest),
+                                if matches { "matches" } else { "doesn't match" }
+                            );
+                            for signer in signed_data.signer_certificates() {
+                                println!(
+                                    "  Signer       {} (issued by {})",
+                                    signer.subject_name().unwrap_or("?"),
+                                    signer.issuer_name().unwrap_or("?")
+                                );
+                            }
+                        }
+                        Some(Err(ref e)) => println!("  error: {:?}", e),
+                        None => {}
+                    }
+                }
+            }
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nCLR");
+        match self.clr {
+            Ok(Some(ref clr)) => {
+                println!(
+                    "  {:<12} {}.{}",
+                    "Runtime", clr.major_runtime_version, clr.minor_runtime_version
+                );
+                println!(
+                    "  {:<12} {:#x}{}",
+                    "Flags",
+                    clr.flags,
+                    if clr.is_il_only() { " (IL only)" } else { "" }
+                );
+                println!("  {:<12} {:#010x}", "Entry point", clr.entry_point);
+                match self.metadata {
+                    Ok(Some(ref metadata)) => {
+                        println!("  {:<12} {}", "Metadata", metadata.version);
+                        for stream in &metadata.streams {
+                            println!(
+                                "    {:<10} {:08x}  {:08x}",
+                                stream.name,
+                                stream.offset,
+                                stream.data.len()
+                            );
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(ref e) => println!("  {:<12} error: {:?}", "Metadata", e),
+                }
+            }
+            Ok(None) => println!("  none"),
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nHybrid");
+        match self.hybrid {
+            Ok(Some(ref hybrid)) => {
+                println!("  {:<15} {}", "Machine", hybrid.machine.name());
+                println!("  {:<15} {}", "CHPE version", hybrid.metadata.version);
+                println!("  {:<15} {}", "ARM64X fixups", hybrid.arm64x_relocations);
+                println!("  Code ranges");
+                for range in &hybrid.code_ranges {
+                    println!(
+                        "    {:08x}  {:08x}  {}",
+                        range.start,
+                        range.length,
+                        code_range_kind(range.kind)
+                    );
+                }
+                if let Some(ref ec) = hybrid.ec_view {
+                    println!("  {:<15} {:#010x}", "EC entry point", ec.entry_point);
+                    println!("  EC exports");
+                    for &(ref name, rva) in &ec.exports {
+                        println!("    {:08x}  {}", rva, name.as_ref().map_or("-", |n| n));
+                    }
+                }
+            }
+            Ok(None) => println!("  none"),
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nSymbols");
+        match self.symbols {
+            Ok(Some(ref symbols)) => {
+                println!("  {} symbols", symbols.len());
+                for symbol in symbols.iter().filter(|s| s.is_external()) {
+                    println!(
+                        "  {:08x}  {:>7}  {}",
+                        symbol.value,
+                        match symbol.section_number {
+                            IMAGE_SYM_UNDEFINED => "UNDEF".to_owned(),
+                            IMAGE_SYM_ABSOLUTE => "ABS".to_owned(),
+                            n => format!("sect {}", n),
+                        },
+                        symbol.name
+                    );
+                }
+            }
+            Ok(None) => println!("  none"),
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nOverlay");
+        match self.overlay {
+            Ok(Some(ref range)) => println!(
+                "  {} bytes at {:#x}, entropy {:.2}",
+                range.len(),
+                range.start,
+                entropy(&file.buffer()[range.clone()])
+            ),
+            Ok(None) => println!("  none"),
+            Err(ref e) => println!("  error: {:?}", e),
+        }
+
+        println!("\nFingerprints");
+        match self.imphash {
+            Ok(Some(ref imphash)) => println!("  {:<12} {}", "Imphash", imphash),
+            Ok(None) => println!("  {:<12} none", "Imphash"),
+            Err(ref e) => println!("  {:<12} error: {:?}", "Imphash", e),
+        }
+        if let Ok(Some(ref rich)) = self.rich {
+            println!("  {:<12} {}", "Rich hash", rich.hash());
+        }
+        for fingerprint in &self.sections {
+            println!(
+                "  {:<12} MD5 {}  SHA-256 {}",
+                self.section_name(&fingerprint.section),
+                fingerprint.md5,
+                fingerprint.sha256
+            );
+        }
+    }
+
+    fn json(&self) -> Json {
+        let file = &self.file;
+        let headers = Json::Object(vec![
+            ("format", Json::from(if file.is_64_bit() { "PE32+" } else { "PE32" })),
+            ("machine", Json::from(file.machine().0)),
+            ("machine_name", Json::from(file.machine().name())),
+            ("num_sections", Json::from(file.num_sections())),
+            ("time_date_stamp", Json::from(file.time_date_stamp())),
+            ("characteristics", Json::from(file.file_characteristics().bits())),
+            ("address_of_entry_point", Json::from(file.address_of_entry_point())),
+            ("image_base", Json::from(file.image_base())),
+            ("section_alignment", Json::from(file.section_alignment())),
+            ("file_alignment", Json::from(file.file_alignment())),
+            ("size_of_image", Json::from(file.size_of_image())),
+            ("size_of_headers", Json::from(file.size_of_headers())),
+            ("checksum", Json::from(file.checksum())),
+            ("computed_checksum", Json::from(file.compute_checksum())),
+            ("subsystem", Json::from(file.subsystem().0)),
+            ("dll_characteristics", Json::from(file.dll_characteristics().bits())),
+        ]);
+
+        let rich = result(&self.rich, |rich| match *rich {
+            None => Json::Null,
+            Some(ref rich) => Json::Object(vec![
+                ("offset", Json::from(rich.offset)),
+                ("size", Json::from(rich.size)),
+                ("key", Json::from(rich.key)),
+                ("checksum", Json::from(rich.checksum)),
+                ("valid", Json::Bool(rich.is_valid())),
+                ("hash", Json::from(rich.hash().as_str())),
+                (
+                    "entries",
+                    Json::Array(
+                        rich.entries
+                            .iter()
+                            .map(|e| {
+                                Json::Object(vec![
+                                    ("product_id", Json::from(e.product_id)),
+                                    ("build", Json::from(e.build)),
+                                    ("count", Json::from(e.count)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+            ]),
+        });
+
+        let sections = self
+            .sections
+            .iter()
+            .map(|fingerprint| {
+                let s = &fingerprint.section;
+                Json::Object(vec![
+                    ("name", Json::from(self.file.section_name(s).unwrap_or(""))),
+                    ("virtual_address", Json::from(s.virtual_address)),
+                    ("virtual_size", Json::from(s.virtual_size)),
+                    ("pointer_to_raw_data", Json::from(s.p_raw_data)),
+                    ("size_of_raw_data", Json::from(s.size_of_raw_data)),
+                    ("characteristics", Json::from(s.characteristics.bits())),
+                    ("entropy", Json::Float(fingerprint.entropy)),
+                    ("md5", Json::from(fingerprint.md5.as_str())),
+                    ("sha256", Json::from(fingerprint.sha256.as_str())),
+                ])
+            })
+            .collect();
+
+        let directories = self
+            .directories
+            .iter()
+            .map(|&(entry, directory)| {
+                Json::Object(vec![
+                    ("name", Json::from(format!("{:?}", entry).as_str())),
+                    ("virtual_address", Json::from(directory.virtual_address)),
+                    ("size", Json::from(directory.size)),
+                ])
+            })
+            .collect();
+
+        let imports = result(&self.imports, |modules| {
+            Json::Array(
+                modules
+                    .iter()
+                    .map(|(module, entries)| {
+                        let entries = entries.iter().map(|entry| {
+                            let (name, hint, ordinal) = match entry.name {
+                                ImportName::Name { hint, name } => {
+                                    (Json::from(name), Json::from(hint), Json::Null)
+                                }
+                                ImportName::Ordinal(o) => (Json::Null, Json::Null, Json::from(o)),
+                            };
+                            Json::Object(vec![
+                                ("name", name),
+                                ("hint", hint),
+                                ("ordinal", ordinal),
+                                ("iat_rva", Json::from(entry.iat_rva)),
+                            ])
+                        });
+                        Json::Object(vec![
+                            ("module", Json::from(module.name)),
+                            ("bound", Json::Bool(module.is_bound())),
+                            ("entries", Json::Array(entries.collect())),
+                        ])
+                    })
+                    .collect(),
+            )
+        });
+
+        let exports = result(&self.exports, |exports| match *exports {
+            None => Json::Null,
+            Some((ref exports, ref all)) => Json::Object(vec![
+                ("name", Json::from(exports.name)),
+                ("ordinal_base", Json::from(exports.ordinal_base)),
+                (
+                    "functions",
+                    Json::Array(
+                        all.iter()
+                            .map(|e| {
+                                Json::Object(vec![
+                                    ("ordinal", Json::from(e.ordinal)),
+                                    ("rva", Json::from(e.rva)),
+                                    ("name", e.name.map_or(Json::Null, Json::from)),
+                                    ("forwarder", e.forwarder.map_or(Json::Null, Json::from)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+            ]),
+        });
+
+        let relocations = result(&self.relocations, |relocations| {
+            Json::Object(vec![
+                ("blocks", Json::from(relocations.blocks)),
+                (
+                    "types",
+                    Json::Array(
+                        relocations
+                            .kinds
+                            .iter()
+                            .map(|(&kind, &count)| {
+                                Json::Object(vec![
+                                    ("type", Json::from(kind)),
+                                    ("name", Json::from(relocation_name(kind).as_str())),
+                                    ("count", Json::from(count)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+            ])
+        });
+
+        let tls = result(&self.tls, |tls| match *tls {
+            None => Json::Null,
+            Some(ref tls) => Json::Object(vec![
+                ("start_address_of_raw_data", Json::from(tls.start_address_of_raw_data)),
+                ("end_address_of_raw_data", Json::from(tls.end_address_of_raw_data)),
+                ("address_of_index", Json::from(tls.address_of_index)),
+                ("address_of_callbacks", Json::from(tls.address_of_callbacks)),
+                ("size_of_zero_fill", Json::from(tls.size_of_zero_fill)),
+                (
+                    "callbacks",
+                    Json::Array(tls.callbacks.iter().map(|&c| Json::from(c)).collect()),
+                ),
+            ]),
+        });
+
+        let id = |id: &ResourceId| match *id {
+            ResourceId::Id(id) => Json::from(id),
+            ResourceId::Name(ref name) => Json::from(name.as_str()),
+        };
+        let resources = result(&self.resources, |resources| {
+            Json::Array(
+                resources
+                    .iter()
+                    .map(|r| {
+                        Json::Object(vec![
+                            ("type", id(&r.kind)),
+                            ("type_name", Json::from(resource_type_name(&r.kind).as_str())),
+                            ("name", id(&r.name)),
+                            ("language", id(&r.language)),
+                            ("rva", Json::from(r.rva)),
+                            ("size", Json::from(r.size)),
+                            ("code_page", Json::from(r.code_page)),
+                        ])
+                    })
+                    .collect(),
+            )
+        });
+
+        let debug = result(&self.debug, |(entries, codeview)| {
+            Json::Object(vec![
+                (
+                    "entries",
+                    Json::Array(
+                        entries
+                            .iter()
+                            .map(|e| {
+                                Json::Object(vec![
+                                    ("type", Json::from(e.kind)),
+                                    ("type_name", Json::from(debug_name(e.kind).as_str())),
+                                    ("time_date_stamp", Json::from(e.time_date_stamp)),
+                                    ("size_of_data", Json::from(e.size_of_data)),
+                                    ("address_of_raw_data", Json::from(e.address_of_raw_data)),
+                                    ("pointer_to_raw_data", Json::from(e.pointer_to_raw_data)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+                (
+                    "codeview",
+                    codeview.map_or(Json::Null, |c| {
+                        Json::Object(vec![
+                            ("path", Json::from(c.path)),
+                            ("guid", Json::from(guid(&c.guid).as_str())),
+                            ("age", Json::from(c.age)),
+                        ])
+                    }),
+                ),
+            ])
+        });
+
+        let certificates = result(&self.certificates, |certificates| {
+            Json::Array(
+                certificates
+                    .iter()
+                    .map(|(certificate, signature)| {
+                        let signature = match *signature {
+                            None => Json::Null,
+                            Some(ref signature) => result(signature, |&(ref signed_data, matches)| {
+                                signature_json(signed_data, matches)
+                            }),
+                        };
+                        Json::Object(vec![
+                            ("offset", Json::from(certificate.offset)),
+                            ("revision", Json::from(certificate.revision)),
+                            ("type", Json::from(certificate.kind)),
+                            ("size", Json::from(certificate.data.len())),
+                            ("signature", signature),
+                        ])
+                    })
+                    .collect(),
+            )
+        });
+
+        let clr = result(&self.clr, |clr| match *clr {
+            None => Json::Null,
+            Some(ref clr) => Json::Object(vec![
+                ("major_runtime_version", Json::from(clr.major_runtime_version)),
+                ("minor_runtime_version", Json::from(clr.minor_runtime_version)),
+                ("flags", Json::from(clr.flags)),
+                ("il_only", Json::Bool(clr.is_il_only())),
+                ("entry_point", Json::from(clr.entry_point)),
+                (
+                    "metadata",
+                    result(&self.metadata, |metadata| match *metadata {
+                        None => Json::Null,
+                        Some(ref metadata) => Json::Object(vec![
+                            ("version", Json::from(metadata.version)),
+                            (
+                                "streams",
+                                Json::Array(
+                                    metadata
+                                        .streams
+                                        .iter()
+                                        .map(|s| {
+                                            Json::Object(vec![
+                                                ("name", Json::from(s.name)),
+                                                ("offset", Json::from(s.offset)),
+                                                ("size", Json::from(s.data.len())),
+                                            ])
+                                        })
+                                        .collect(),
+                                ),
+                            ),
+                        ]),
+                    }),
+                ),
+            ]),
+        });
+
+        let hybrid = result(&self.hybrid, |hybrid| match *hybrid {
+            None => Json::Null,
+            Some(ref hybrid) => Json::Object(vec![
+                ("machine", Json::from(hybrid.machine.name())),
+                ("chpe_version", Json::from(hybrid.metadata.version)),
+                (
+                    "code_ranges",
+                    Json::Array(
+                        hybrid
+                            .code_ranges
+                            .iter()
+                            .map(|r| {
+                                Json::Object(vec![
+                                    ("start", Json::from(r.start)),
+                                    ("length", Json::from(r.length)),
+                                    ("kind", Json::from(code_range_kind(r.kind))),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+                ("arm64x_relocations", Json::from(hybrid.arm64x_relocations)),
+                (
+                    "ec_view",
+                    hybrid.ec_view.as_ref().map_or(Json::Null, |ec| {
+                        let exports = ec
+                            .exports
+                            .iter()
+                            .map(|(name, rva)| {
+                                let name = name.as_ref().map_or(Json::Null, |n| n.as_str().into());
+                                Json::Object(vec![("name", name), ("rva", Json::from(*rva))])
+                            })
+                            .collect();
+                        Json::Object(vec![
+                            ("entry_point", Json::from(ec.entry_point)),
+                            ("exports", Json::Array(exports)),
+                        ])
+                    }),
+                ),
+            ]),
+        });
+
+        let symbols = result(&self.symbols, |symbols| match *symbols {
+            None => Json::Null,
+            Some(ref symbols) => Json::Array(
+                symbols
+                    .iter()
+                    .map(|s| {
+                        Json::Object(vec![
+                            ("name", Json::from(s.name)),
+                            ("value", Json::from(s.value)),
+                            ("section_number", Json::from(s.section_number)),
+                            ("type", Json::from(s.kind)),
+                            ("storage_class", Json::from(s.storage_class)),
+                            ("aux_symbols", Json::from(s.num_aux_symbols())),
+                        ])
+                    })
+                    .collect(),
+            ),
+        });
+
+        let overlay = result(&self.overlay, |overlay| match *overlay {
+            None => Json::Null,
+            Some(ref range) => Json::Object(vec![
+                ("offset", Json::from(range.start)),
+                ("size", Json::from(range.len())),
+                ("entropy", Json::Float(entropy(&file.buffer()[range.clone()]))),
+            ]),
+        });
+
+        Json::Object(vec![
+            ("headers", headers),
+            (
+                "findings",
+                Json::Array(
+                    self.findings
+                        .iter()
+                        .map(|f| Json::from(format!("{:?}", f).as_str()))
+                        .collect(),
+                ),
+            ),
+            ("rich_header", rich),
+            ("sections", Json::Array(sections)),
+            ("data_directories", Json::Array(directories)),
+            ("imports", imports),
+            (
+                "imphash",
+                result(&self.imphash, |imphash| {
+                    imphash.as_ref().map_or(Json::Null, |imphash| Json::from(imphash.as_str()))
+                }),
+            ),
+            ("exports", exports),
+            ("relocations", relocations),
+            ("tls", tls),
+            ("resources", resources),
+            ("debug", debug),
+            ("certificates", certificates),
+            ("clr", clr),
+            ("hybrid", hybrid),
+            ("symbols", symbols),
+            ("overlay", overlay),
+        ])
+    }
+}
+
+fn signature_json(signed_data: &SignedData, matches: bool) -> Json {
+    let name = |name: Option<&str>| name.map_or(Json::Null, Json::from);
+    let signers = signed_data
+        .signer_certificates()
+        .iter()
+        .map(|signer| {
+            Json::Object(vec![
+                ("subject", name(signer.subject_name())),
+                ("issuer", name(signer.issuer_name())),
+            ])
+        })
+        .collect();
+    Json::Object(vec![
+        ("digest_algorithm", Json::from(digest_name(signed_data.digest_algorithm))),
+        ("digest", Json::from(hex(signed_data.digest).as_str())),
+        ("digest_matches", Json::Bool(matches)),
+        ("signers", Json::Array(signers)),
+    ])
+}
+
+// Parts of the file that couldn't be parsed are reported as `{"error": "..."}`.
+fn result<T, F: Fn(&T) -> Json>(result: &Result<T, ParseError>, f: F) -> Json {
+    match *result {
+        Ok(ref value) => f(value),
+        Err(ref e) => Json::Object(vec![("error", Json::from(format!("{:?}", e).as_str()))]),
+    }
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl<'a> From<&'a str> for Json {
+    fn from(s: &'a str) -> Self {
+        Json::String(s.into())
+    }
+}
+
+macro_rules! json_number {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Json {
+            fn from(n: $t) -> Self {
+                Json::Number(n as u64)
+            }
+        })*
+    };
+}
+
+json_number!(u8, u16, u32, u64, usize);
+
+impl From<i16> for Json {
+    fn from(n: i16) -> Self {
+        Json::Signed(n as i64)
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl Json {
+    fn write(&self, out: &mut String, indent: usize) {
+        let pad = |out: &mut String, indent: usize| {
+            out.push('\n');
+            for _ in 0..indent {
+                out.push_str("  ");
+            }
+        };
+
+        match *self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => write!(out, "{}", b).unwrap(),
+            Json::Number(n) => write!(out, "{}", n).unwrap(),
+            Json::Signed(n) => write!(out, "{}", n).unwrap(),
+            Json::Float(f) => write!(out, "{:.4}", f).unwrap(),
+            Json::String(ref s) => write_string(out, s),
+            Json::Array(ref values) if values.is_empty() => out.push_str("[]"),
+            Json::Array(ref values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    pad(out, indent + 1);
+                    value.write(out, indent + 1);
+                }
+                pad(out, indent);
+                out.push(']');
+            }
+            Json::Object(ref fields) => {
+                out.push('{');
+                for (i, &(key, ref value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    pad(out, indent + 1);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                }
+                pad(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut json = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let buffer = match fs::read(&path) {
+        Ok(buffer) => buffer,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let file = match PEFile::parse(&buffer) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: not a PE file: {:?}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let report = Report::new(file);
+    if json {
+        let mut out = String::new();
+        report.json().write(&mut out, 0);
+        println!("{}", out);
+    } else {
+        report.print(&path);
+    }
+}
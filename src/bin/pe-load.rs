@@ -0,0 +1,283 @@
+// Loads an image with `Loader` and prints where it ended up and what its imports resolved to.
+//
+//     pe-load [options] <file>
+//
+// Code of the image is only run when asked to, which requires the image to be mapped into this
+// process, i.e. Windows and an image matching our pointer size. Elsewhere the image is mapped into
+// heap memory, which is still enough to look at the base it got and its imports.
+
+extern crate pe_load;
+
+use std::env;
+use std::fs;
+use std::mem;
+use std::os::raw::c_void;
+use std::path::Path;
+use std::process;
+use std::ptr;
+
+use pe_load::*;
+
+const USAGE: &str = "usage: pe-load [options] <file>
+
+options:
+    --base <base>    where to map the image: default, default-exact, <address> (exactly there)
+                     or try:<address>
+    --with <dll>     load <dll> first and resolve imports from it, can be repeated
+    --unresolved     leave imports that can't be resolved zero instead of failing
+    --any-machine    map images into this process even if they are for another machine
+    --ec-view        load the ARM64EC view of ARM64X images
+    --entry          call DllMain with DLL_PROCESS_ATTACH, or the entry point of an executable
+    --call <export>  call an export (by name or #ordinal) without arguments and print what it
+                     returns";
+
+const DLL_PROCESS_ATTACH: u32 = 1;
+
+type DllMain = extern "system" fn(*mut c_void, u32, *mut c_void) -> i32;
+type Export = extern "C" fn() -> usize;
+
+struct Options {
+    path: String,
+    base: PreferredBase,
+    with: Vec<String>,
+    unresolved: bool,
+    any_machine: bool,
+    ec_view: bool,
+    entry: bool,
+    call: Option<ImportSymbol>,
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn parse_address(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_base(s: &str) -> Option<PreferredBase> {
+    match s {
+        "default" => Some(PreferredBase::Default),
+        "default-exact" => Some(PreferredBase::DefaultExact),
+        _ => match s.strip_prefix("try:") {
+            Some(address) => parse_address(address).map(PreferredBase::TryExact),
+            None => parse_address(s).map(PreferredBase::Exact),
+        },
+    }
+}
+
+fn parse_symbol(s: &str) -> Option<ImportSymbol> {
+    match s.strip_prefix('#') {
+        Some(ordinal) => ordinal.parse().ok().map(ImportSymbol::Ordinal),
+        None => Some(ImportSymbol::Name(s.to_owned())),
+    }
+}
+
+fn parse_options() -> Options {
+    let mut args = env::args().skip(1);
+    let mut path = None;
+    let mut options = Options {
+        path: String::new(),
+        base: PreferredBase::Default,
+        with: vec![],
+        unresolved: false,
+        any_machine: false,
+        ec_view: false,
+        entry: false,
+        call: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "--base" => {
+                options.base = args
+                    .next()
+                    .and_then(|base| parse_base(&base))
+                    .unwrap_or_else(|| usage())
+            }
+            "--with" => options.with.push(args.next().unwrap_or_else(|| usage())),
+            "--unresolved" => options.unresolved = true,
+            "--any-machine" => options.any_machine = true,
+            "--ec-view" => options.ec_view = true,
+            "--entry" => options.entry = true,
+            "--call" => {
+                options.call = Some(
+                    args.next()
+                        .and_then(|symbol| parse_symbol(&symbol))
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    options.path = path.unwrap_or_else(|| usage());
+    options
+}
+
+// Module handle for modules the resolver couldn't load.
+const MISSING_MODULE: u64 = u64::MAX;
+
+/// Resolves what the platform resolver can and leaves everything else zero.
+struct Unresolved<R>(R);
+
+impl<R: ImportResolver> ImportResolver for Unresolved<R> {
+    fn load_module(&mut self, module: &str) -> Result<u64, LoadError> {
+        Ok(self.0.load_module(module).unwrap_or(MISSING_MODULE))
+    }
+
+    fn resolve(&mut self, module: u64, symbol: &ImportName) -> u64 {
+        match module {
+            MISSING_MODULE => 0,
+            _ => self.0.resolve(module, symbol),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn platform_resolver() -> WindowsResolver {
+    WindowsResolver
+}
+
+#[cfg(not(windows))]
+fn platform_resolver() -> NoImports {
+    NoImports
+}
+
+fn config(options: &Options) -> LoaderConfig {
+    let config = LoaderConfig::new()
+        .allow_foreign_machine(options.any_machine)
+        .ec_view(options.ec_view);
+    if options.unresolved {
+        config.resolver(Unresolved(platform_resolver()))
+    } else {
+        config
+    }
+}
+
+fn read(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)))
+}
+
+fn symbol_name(symbol: &ImportSymbol) -> String {
+    match *symbol {
+        ImportSymbol::Name(ref name) => name.clone(),
+        ImportSymbol::Ordinal(ordinal) => format!("#{}", ordinal),
+    }
+}
+
+fn print_imports(image: &LoadedPEFile, registry: &ModuleRegistry) {
+    println!("Imports");
+    let mut any = false;
+    for import in image.imports() {
+        any = true;
+        let target = match import.target {
+            0 => "unresolved".to_owned(),
+            target => match registry.module_at(target) {
+                Some(module) => format!("{:#x} ({})", target, module.name),
+                None => format!("{:#x}", target),
+            },
+        };
+        println!(
+            "  {}!{}  slot {:#x} -> {}",
+            import.module,
+            symbol_name(import.symbol),
+            import.slot,
+            target
+        );
+    }
+    if !any {
+        println!("  none");
+    }
+}
+
+fn main() {
+    let options = parse_options();
+
+    let registry = ModuleRegistry::new();
+    for path in &options.with {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+        match registry.load(name, read(path), config(&options)) {
+            Ok(module) => println!("{}: mapped at {:#x}", module.name, module.base),
+            Err(e) => fail(format!("{}: couldn't load: {:?}", path, e)),
+        }
+    }
+
+    let path = &options.path;
+    let buffer = read(path);
+    let (is_dll, image_base) = match PEFile::parse(&buffer) {
+        Ok(file) => (
+            file.file_characteristics().is_dll(),
+            file.image_base(),
+        ),
+        Err(e) => fail(format!("{}: not a PE file: {:?}", path, e)),
+    };
+
+    let config = config(&options)
+        .registry(registry.clone())
+        .preferred_base(options.base);
+    let image = Loader::with_config(&buffer[..], config)
+        .load()
+        .unwrap_or_else(|e| fail(format!("{}: couldn't load: {:?}", path, e)));
+
+    println!(
+        "{}: mapped at {:#x}, {:#x} bytes",
+        path,
+        image.base(),
+        image.size()
+    );
+    if image.base() != image_base {
+        println!("  relocated from {:#x}", image_base);
+    }
+    print_imports(&image, &registry);
+
+    let export = options.call.as_ref().map(|symbol| {
+        match image.export_address(symbol.clone()) {
+            Some(address) => (symbol_name(symbol), address),
+            None => fail(format!(
+                "{}: doesn't export {}",
+                path,
+                symbol_name(symbol)
+            )),
+        }
+    });
+    if (options.entry || export.is_some()) && !image.is_runnable() {
+        fail(format!("{}: the image can't be run in this process", path));
+    }
+
+    if options.entry {
+        let entry = image
+            .entry_point
+            .unwrap_or_else(|| fail(format!("{}: no entry point", path)));
+        if is_dll {
+            let dll_main: DllMain = unsafe { mem::transmute(entry) };
+            let result = dll_main(image.base() as *mut _, DLL_PROCESS_ATTACH, ptr::null_mut());
+            println!("DllMain returned {}", result);
+        } else {
+            entry();
+            println!("entry point returned");
+        }
+    }
+
+    if let Some((name, address)) = export {
+        let export: Export = unsafe { mem::transmute(address as usize) };
+        println!("{} returned {:#x}", name, export());
+    }
+}
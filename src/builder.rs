@@ -0,0 +1,1034 @@
+// Lays out and serialises PE images.
+//
+// Sections are added with their contents, everything that refers to an address inside the image
+// (entry point, exports, pointers, TLS callbacks) is given as a `Location` in a section, which is
+// only turned into an RVA once the layout is known. Imports, exports, TLS and relocations end up
+// in sections of their own after the user supplied ones.
+
+use std::collections::BTreeMap;
+
+use crate::config::ImportSymbol;
+use crate::pe_file::{update_checksum, write_bytes, write_u16, write_u32, write_u64, ParseError};
+use crate::rich::{encode_rich_header, RichEntry};
+use crate::structs::{
+    DirectoryEntry, DllCharacteristics, FileCharacteristics, Machine, OptionalHeaderMagic,
+    SectionCharacteristics, Subsystem,
+};
+
+const DOS_SIGNATURE: u16 = 0x5a4d; // MZ
+const PE_SIGNATURE: u32 = 0x0000_4550; // PE\0\0
+const DOS_STUB_OFFSET: usize = 0x40;
+// Where the PE header goes if there is no Rich header, which would be placed here instead.
+const PE_HEADER_OFFSET: usize = 0x80;
+
+// The DOS header fields and stub every linker emits, prints the usual message when run under DOS.
+const DOS_HEADER: [u16; 12] = [0x90, 3, 0, 4, 0, 0xffff, 0, 0xb8, 0, 0, 0, 0x40];
+const DOS_STUB: &[u8] = b"\x0e\x1f\xba\x0e\x00\xb4\x09\xcd\x21\xb8\x01\x4c\xcd\x21\
+    This program cannot be run in DOS mode.\r\r\n$";
+const FILE_HEADER_SIZE: usize = 20;
+const SECTION_HEADER_SIZE: usize = 40;
+const IMPORT_DESCRIPTOR_SIZE: usize = 20;
+const EXPORT_DIRECTORY_SIZE: usize = 40;
+const NUM_DATA_DIRECTORIES: usize = 16;
+const PAGE_SIZE: u32 = 0x1000;
+
+const SYMBOL_SIZE: usize = 18;
+const SYM_CLASS_EXTERNAL: u8 = 2;
+const SYM_CLASS_STATIC: u8 = 3;
+const SYM_DTYPE_FUNCTION: u16 = 0x20;
+
+/// Characteristics of a `.text` section.
+pub const SECTION_CODE: SectionCharacteristics = SectionCharacteristics::CNT_CODE
+    .union(SectionCharacteristics::MEM_EXECUTE)
+    .union(SectionCharacteristics::MEM_READ);
+/// Characteristics of a `.rdata` section.
+pub const SECTION_READ_ONLY_DATA: SectionCharacteristics =
+    SectionCharacteristics::CNT_INITIALIZED_DATA.union(SectionCharacteristics::MEM_READ);
+/// Characteristics of a `.data` section.
+pub const SECTION_DATA: SectionCharacteristics =
+    SECTION_READ_ONLY_DATA.union(SectionCharacteristics::MEM_WRITE);
+/// Characteristics of a `.bss` section.
+pub const SECTION_UNINITIALIZED_DATA: SectionCharacteristics =
+    SectionCharacteristics::CNT_UNINITIALIZED_DATA
+        .union(SectionCharacteristics::MEM_READ)
+        .union(SectionCharacteristics::MEM_WRITE);
+
+const RELOCATION_ABSOLUTE: u16 = 0;
+const RELOCATION_HIGH_LOW: u16 = 3;
+const RELOCATION_DIR64: u16 = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    /// Alignments have to be powers of two and the file alignment can't exceed the section
+    /// alignment.
+    InvalidAlignment,
+    /// The location is outside of its section.
+    InvalidLocation(Location),
+    /// A pointer doesn't fit into the raw data of its section.
+    InvalidPointer(Location),
+    /// The image doesn't fit into the 4 GiB RVAs and file offsets can address, or has more
+    /// sections or exports than the headers can count.
+    TooLarge,
+    Parse(ParseError),
+}
+
+impl From<ParseError> for BuildError {
+    fn from(e: ParseError) -> Self {
+        BuildError::Parse(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SectionId(usize);
+
+/// An offset into a section.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub section: SectionId,
+    pub offset: u32,
+}
+
+impl SectionId {
+    pub fn at(self, offset: u32) -> Location {
+        Location {
+            section: self,
+            offset,
+        }
+    }
+}
+
+struct SectionData {
+    name: String,
+    characteristics: SectionCharacteristics,
+    data: Vec<u8>,
+    // At least the size of `data` once validated.
+    virtual_size: u32,
+}
+
+enum ExportData {
+    Location(Location),
+    Forwarder(String),
+}
+
+struct Layout {
+    size_of_headers: u32,
+    // RVA and file offset of every section, including the generated ones.
+    rvas: Vec<u32>,
+    offsets: Vec<u32>,
+    size_of_image: u32,
+}
+
+fn add(a: u32, b: u32) -> Result<u32, BuildError> {
+    a.checked_add(b).ok_or(BuildError::TooLarge)
+}
+
+fn align_up(value: u32, alignment: u32) -> Result<u32, BuildError> {
+    Ok(add(value, alignment - 1)? & !(alignment - 1))
+}
+
+fn size_of(data: &[u8]) -> Result<u32, BuildError> {
+    if data.len() > u32::MAX as usize {
+        return Err(BuildError::TooLarge);
+    }
+    Ok(data.len() as u32)
+}
+
+fn push_c_str(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend_from_slice(s.as_bytes());
+    buffer.push(0);
+}
+
+fn push_zeros(buffer: &mut Vec<u8>, count: usize) {
+    let len = buffer.len();
+    buffer.resize(len + count, 0);
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+// The 8 byte name field of a section header or symbol. Names that don't fit are moved to
+// `strings` and referred to by their offset, which section headers write as `/<offset>`.
+fn coff_name(strings: &mut Vec<u8>, name: &str, is_section: bool) -> [u8; 8] {
+    let mut field = [0; 8];
+    if name.len() <= 8 {
+        field[..name.len()].copy_from_slice(name.as_bytes());
+    } else if is_section {
+        let offset = format!("/{}", strings.len());
+        field[..offset.len()].copy_from_slice(offset.as_bytes());
+        push_c_str(strings, name);
+    } else {
+        field[4..].copy_from_slice(&(strings.len() as u32).to_le_bytes());
+        push_c_str(strings, name);
+    }
+    field
+}
+
+fn push_symbol(
+    symbols: &mut Vec<u8>,
+    name: [u8; 8],
+    value: u32,
+    section_number: u16,
+    kind: u16,
+    storage_class: u8,
+    aux: Option<&[u8; SYMBOL_SIZE]>,
+) {
+    symbols.extend_from_slice(&name);
+    push_u32(symbols, value);
+    symbols.extend_from_slice(&section_number.to_le_bytes());
+    symbols.extend_from_slice(&kind.to_le_bytes());
+    symbols.extend_from_slice(&[storage_class, aux.is_some() as u8]);
+    if let Some(record) = aux {
+        symbols.extend_from_slice(record);
+    }
+}
+
+/// Builds PE32 and PE32+ images.
+pub struct PEBuilder {
+    is_64_bit: bool,
+    machine: Machine,
+    image_base: u64,
+    section_alignment: u32,
+    file_alignment: u32,
+    file_characteristics: FileCharacteristics,
+    dll_characteristics: DllCharacteristics,
+    subsystem: Subsystem,
+    time_date_stamp: u32,
+    entry_point: Option<Location>,
+    sections: Vec<SectionData>,
+    imports: Vec<(String, Vec<ImportSymbol>)>,
+    export_name: Option<String>,
+    exports: Vec<(Option<String>, ExportData)>,
+    pointers: Vec<(Location, Location)>,
+    tls_data: Vec<u8>,
+    tls_callbacks: Vec<Location>,
+    directories: Vec<(DirectoryEntry, Location, u32)>,
+    rich_header: Vec<RichEntry>,
+    symbols: Vec<(String, Location)>,
+}
+
+impl PEBuilder {
+    /// An AMD64 executable.
+    pub fn pe32_plus() -> Self {
+        Self::new(true)
+    }
+
+    /// An i386 executable.
+    pub fn pe32() -> Self {
+        Self::new(false)
+    }
+
+    fn new(is_64_bit: bool) -> Self {
+        Self {
+            is_64_bit,
+            machine: if is_64_bit {
+                Machine::AMD64
+            } else {
+                Machine::I386
+            },
+            image_base: if is_64_bit { 0x1_4000_0000 } else { 0x40_0000 },
+            section_alignment: 0x1000,
+            file_alignment: 0x200,
+            file_characteristics: FileCharacteristics::EXECUTABLE_IMAGE
+                | if is_64_bit {
+                    FileCharacteristics::LARGE_ADDRESS_AWARE
+                } else {
+                    FileCharacteristics::MACHINE_32BIT
+                },
+            dll_characteristics: DllCharacteristics::DYNAMIC_BASE
+                | DllCharacteristics::NX_COMPAT
+                | DllCharacteristics::TERMINAL_SERVER_AWARE
+                | if is_64_bit {
+                    DllCharacteristics::HIGH_ENTROPY_VA
+                } else {
+                    DllCharacteristics::empty()
+                },
+            subsystem: Subsystem::WINDOWS_CUI,
+            time_date_stamp: 0,
+            entry_point: None,
+            sections: vec![],
+            imports: vec![],
+            export_name: None,
+            exports: vec![],
+            pointers: vec![],
+            tls_data: vec![],
+            tls_callbacks: vec![],
+            directories: vec![],
+            rich_header: vec![],
+            symbols: vec![],
+        }
+    }
+
+    pub fn machine(&mut self, machine: Machine) -> &mut Self {
+        self.machine = machine;
+        self
+    }
+
+    pub fn image_base(&mut self, image_base: u64) -> &mut Self {
+        self.image_base = image_base;
+        self
+    }
+
+    pub fn section_alignment(&mut self, alignment: u32) -> &mut Self {
+        self.section_alignment = alignment;
+        self
+    }
+
+    pub fn file_alignment(&mut self, alignment: u32) -> &mut Self {
+        self.file_alignment = alignment;
+        self
+    }
+
+    pub fn file_characteristics(&mut self, characteristics: FileCharacteristics) -> &mut Self {
+        self.file_characteristics = characteristics;
+        self
+    }
+
+    pub fn dll_characteristics(&mut self, characteristics: DllCharacteristics) -> &mut Self {
+        self.dll_characteristics = characteristics;
+        self
+    }
+
+    pub fn subsystem(&mut self, subsystem: Subsystem) -> &mut Self {
+        self.subsystem = subsystem;
+        self
+    }
+
+    pub fn time_date_stamp(&mut self, time_date_stamp: u32) -> &mut Self {
+        self.time_date_stamp = time_date_stamp;
+        self
+    }
+
+    /// Marks the image as a DLL.
+    pub fn dll(&mut self) -> &mut Self {
+        self.file_characteristics |= FileCharacteristics::DLL;
+        self
+    }
+
+    pub fn entry_point(&mut self, location: Location) -> &mut Self {
+        self.entry_point = Some(location);
+        self
+    }
+
+    pub fn section(
+        &mut self,
+        name: &str,
+        characteristics: SectionCharacteristics,
+        data: Vec<u8>,
+    ) -> SectionId {
+        self.section_with_virtual_size(name, characteristics, data, 0)
+    }
+
+    /// A section that is larger in memory than on disk, the rest is zero filled when mapped.
+    /// `data` can be empty for sections like `.bss`.
+    pub fn section_with_virtual_size(
+        &mut self,
+        name: &str,
+        characteristics: SectionCharacteristics,
+        data: Vec<u8>,
+        virtual_size: u32,
+    ) -> SectionId {
+        self.sections.push(SectionData {
+            name: name.to_owned(),
+            characteristics,
+            data,
+            virtual_size,
+        });
+        SectionId(self.sections.len() - 1)
+    }
+
+    pub fn import<S: Into<ImportSymbol>>(&mut self, module: &str, symbol: S) -> &mut Self {
+        let symbol = symbol.into();
+        match self
+            .imports
+            .iter_mut()
+            .find(|&&mut (ref m, _)| m.eq_ignore_ascii_case(module))
+        {
+            Some(&mut (_, ref mut symbols)) => symbols.push(symbol),
+            None => self.imports.push((module.to_owned(), vec![symbol])),
+        }
+        self
+    }
+
+    /// Name of the DLL in the export directory.
+    pub fn export_name(&mut self, name: &str) -> &mut Self {
+        self.export_name = Some(name.to_owned());
+        self
+    }
+
+    /// Exports `location` as `name`. Ordinals are assigned in the order exports are added,
+    /// starting at 1.
+    pub fn export(&mut self, name: &str, location: Location) -> &mut Self {
+        self.exports
+            .push((Some(name.to_owned()), ExportData::Location(location)));
+        self
+    }
+
+    /// Exports `location` by ordinal only.
+    pub fn export_ordinal(&mut self, location: Location) -> &mut Self {
+        self.exports.push((None, ExportData::Location(location)));
+        self
+    }
+
+    /// Forwards `name` to another module, e.g. `NTDLL.RtlAllocateHeap`.
+    pub fn export_forwarder(&mut self, name: &str, forwarder: &str) -> &mut Self {
+        self.exports
+            .push((Some(name.to_owned()), ExportData::Forwarder(forwarder.to_owned())));
+        self
+    }
+
+    /// Stores the address of `target` at `at` and adds a relocation for it.
+    pub fn pointer(&mut self, at: Location, target: Location) -> &mut Self {
+        self.pointers.push((at, target));
+        self
+    }
+
+    /// Template the TLS data of every thread is initialised from.
+    pub fn tls_data(&mut self, data: Vec<u8>) -> &mut Self {
+        self.tls_data = data;
+        self
+    }
+
+    pub fn tls_callback(&mut self, location: Location) -> &mut Self {
+        self.tls_callbacks.push(location);
+        self
+    }
+
+    /// Points a data directory at data in one of the sections, for directories the builder
+    /// doesn't generate itself such as resources or debug information.
+    pub fn directory(&mut self, entry: DirectoryEntry, location: Location, size: u32) -> &mut Self {
+        self.directories.push((entry, location, size));
+        self
+    }
+
+    /// Adds a Rich header listing `entries`, as if the image was linked by Microsoft's linker.
+    pub fn rich_header(&mut self, entries: Vec<RichEntry>) -> &mut Self {
+        self.rich_header = entries;
+        self
+    }
+
+    /// Adds an external symbol to the COFF symbol table, which MinGW's linker keeps in images.
+    pub fn symbol(&mut self, name: &str, location: Location) -> &mut Self {
+        self.symbols.push((name.to_owned(), location));
+        self
+    }
+
+    fn pe_header_offset(&self) -> usize {
+        match self.rich_header.len() {
+            0 => PE_HEADER_OFFSET,
+            // DanS and padding, the entries, then Rich and the key, which are all 8 bytes.
+            n => PE_HEADER_OFFSET + 16 + n * 8 + 8,
+        }
+    }
+
+    fn pointer_size(&self) -> u32 {
+        if self.is_64_bit {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn has_exports(&self) -> bool {
+        !self.exports.is_empty() || self.export_name.is_some()
+    }
+
+    fn has_tls(&self) -> bool {
+        !self.tls_data.is_empty() || !self.tls_callbacks.is_empty()
+    }
+
+    fn has_relocations(&self) -> bool {
+        !self.pointers.is_empty() || self.has_tls()
+    }
+
+    fn optional_header_size(&self) -> usize {
+        let fixed = if self.is_64_bit { 112 } else { 96 };
+        fixed + NUM_DATA_DIRECTORIES * 8
+    }
+
+    fn virtual_size(&self, section: &SectionData) -> u32 {
+        section.virtual_size.max(section.data.len() as u32)
+    }
+
+    fn validate(&self) -> Result<(), BuildError> {
+        let valid_alignment = |a: u32| a != 0 && a.is_power_of_two();
+        if !valid_alignment(self.section_alignment)
+            || !valid_alignment(self.file_alignment)
+            || self.file_alignment > self.section_alignment
+        {
+            return Err(BuildError::InvalidAlignment);
+        }
+
+        // The generated sections come on top of this, `build` checks the final layout again.
+        size_of(&self.tls_data)?;
+        let mut sizes = vec![];
+        for section in &self.sections {
+            sizes.push((size_of(&section.data)?, self.virtual_size(section)));
+        }
+        self.layout(&sizes)?;
+
+        // The file header counts sections with 16 bits and the export name ordinals index the
+        // functions with 16 bits.
+        let generated = [
+            self.has_exports(),
+            !self.imports.is_empty(),
+            self.has_tls(),
+            self.has_relocations(),
+        ];
+        let sections = self.sections.len() + generated.iter().filter(|&&g| g).count();
+        if sections > u16::MAX as usize || self.exports.len() > 1 << 16 {
+            return Err(BuildError::TooLarge);
+        }
+
+        // Code and symbols have to be inside their section, pointers and directories may also
+        // point at its end.
+        let inside = self
+            .entry_point
+            .iter()
+            .chain(self.exports.iter().filter_map(|e| match e.1 {
+                ExportData::Location(ref l) => Some(l),
+                ExportData::Forwarder(_) => None,
+            }))
+            .chain(self.tls_callbacks.iter())
+            .chain(self.symbols.iter().map(|s| &s.1));
+        for &location in inside {
+            match self.sections.get(location.section.0) {
+                Some(s) if location.offset < self.virtual_size(s) => {}
+                _ => return Err(BuildError::InvalidLocation(location)),
+            }
+        }
+
+        let up_to_end = self
+            .pointers
+            .iter()
+            .map(|p| &p.1)
+            .chain(self.directories.iter().map(|d| &d.1));
+        for &location in up_to_end {
+            match self.sections.get(location.section.0) {
+                Some(s) if location.offset <= self.virtual_size(s) => {}
+                _ => return Err(BuildError::InvalidLocation(location)),
+            }
+        }
+
+        for &(at, _) in &self.pointers {
+            match self.sections.get(at.section.0) {
+                Some(s) if at.offset as usize + self.pointer_size() as usize <= s.data.len() => {}
+                _ => return Err(BuildError::InvalidPointer(at)),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Fails if a section or the end of the image can't be addressed with 32 bits. Everything
+    // derived from the layout, such as the file size, stays below these bounds.
+    fn layout(&self, sizes: &[(u32, u32)]) -> Result<Layout, BuildError> {
+        let headers = self.pe_header_offset()
+            + 4
+            + FILE_HEADER_SIZE
+            + self.optional_header_size()
+            + sizes.len() * SECTION_HEADER_SIZE;
+        if headers > u32::MAX as usize {
+            return Err(BuildError::TooLarge);
+        }
+        let size_of_headers = align_up(headers as u32, self.file_alignment)?;
+
+        let mut rva = align_up(size_of_headers, self.section_alignment)?;
+        let mut offset = size_of_headers;
+        let mut rvas = vec![];
+        let mut offsets = vec![];
+        for &(raw_size, virtual_size) in sizes {
+            rvas.push(rva);
+            offsets.push(if raw_size == 0 { 0 } else { offset });
+            rva = align_up(add(rva, virtual_size.max(1))?, self.section_alignment)?;
+            offset = add(offset, align_up(raw_size, self.file_alignment)?)?;
+        }
+
+        Ok(Layout {
+            size_of_headers,
+            rvas,
+            offsets,
+            size_of_image: rva,
+        })
+    }
+
+    fn build_exports(&self, rva: u32, rva_of: &dyn Fn(Location) -> u32) -> Vec<u8> {
+        let count = self.exports.len() as u32;
+        let mut names = self
+            .exports
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.0.as_ref().map(|name| (name.as_str(), i as u16)))
+            .collect::<Vec<_>>();
+        names.sort();
+        let name_count = names.len() as u32;
+
+        let functions = rva + EXPORT_DIRECTORY_SIZE as u32;
+        let name_pointers = functions + count * 4;
+        let name_ordinals = name_pointers + name_count * 4;
+
+        // Strings come after the tables, collect them first to know their RVAs.
+        let mut strings = vec![];
+        let strings_rva = name_ordinals + name_count * 2;
+        let dll_name = strings_rva;
+        push_c_str(&mut strings, self.export_name.as_ref().map_or("", |n| n));
+
+        let mut name_rvas = vec![];
+        for &(name, _) in &names {
+            name_rvas.push(strings_rva + strings.len() as u32);
+            push_c_str(&mut strings, name);
+        }
+
+        let mut function_rvas = vec![];
+        for export in &self.exports {
+            function_rvas.push(match export.1 {
+                ExportData::Location(location) => rva_of(location),
+                ExportData::Forwarder(ref forwarder) => {
+                    let forwarder_rva = strings_rva + strings.len() as u32;
+                    push_c_str(&mut strings, forwarder);
+                    forwarder_rva
+                }
+            });
+        }
+
+        let mut buffer = vec![];
+        push_u32(&mut buffer, 0); // Characteristics
+        push_u32(&mut buffer, self.time_date_stamp);
+        push_u32(&mut buffer, 0); // Version
+        push_u32(&mut buffer, dll_name);
+        push_u32(&mut buffer, 1); // Ordinal base
+        push_u32(&mut buffer, count);
+        push_u32(&mut buffer, name_count);
+        push_u32(&mut buffer, functions);
+        push_u32(&mut buffer, name_pointers);
+        push_u32(&mut buffer, name_ordinals);
+        for f in function_rvas {
+            push_u32(&mut buffer, f);
+        }
+        for &name in &name_rvas {
+            push_u32(&mut buffer, name);
+        }
+        for &(_, ordinal) in &names {
+            buffer.extend_from_slice(&ordinal.to_le_bytes());
+        }
+        buffer.extend_from_slice(&strings);
+        buffer
+    }
+
+    // Returns the section and the RVA and size of the IAT.
+    fn build_imports(&self, rva: u32) -> Result<(Vec<u8>, u32, u32), BuildError> {
+        let thunk_size = self.pointer_size();
+        let thunks = self
+            .imports
+            .iter()
+            .map(|i| i.1.len() as u32 + 1)
+            .sum::<u32>();
+
+        let descriptors_size = (self.imports.len() as u32 + 1) * IMPORT_DESCRIPTOR_SIZE as u32;
+        // The thunks have to be naturally aligned.
+        let lookup_table = align_up(add(rva, descriptors_size)?, thunk_size)?;
+        let iat = lookup_table + thunks * thunk_size;
+        let strings_rva = iat + thunks * thunk_size;
+
+        let ordinal_flag = if self.is_64_bit { 1 << 63 } else { 1 << 31 };
+        let mut strings = vec![];
+        let mut thunk_values = vec![];
+        let mut descriptors = vec![];
+        for (module, symbols) in &self.imports {
+            let first_thunk = thunk_values.len() as u32 * thunk_size;
+            for symbol in symbols {
+                thunk_values.push(match *symbol {
+                    ImportSymbol::Ordinal(ordinal) => ordinal_flag | ordinal as u64,
+                    ImportSymbol::Name(ref name) => {
+                        if strings.len() % 2 != 0 {
+                            strings.push(0);
+                        }
+                        let hint_name = strings_rva + strings.len() as u32;
+                        strings.extend_from_slice(&0u16.to_le_bytes());
+                        push_c_str(&mut strings, name);
+                        hint_name as u64
+                    }
+                });
+            }
+            thunk_values.push(0);
+
+            let name = strings_rva + strings.len() as u32;
+            push_c_str(&mut strings, module);
+            descriptors.push((lookup_table + first_thunk, name, iat + first_thunk));
+        }
+
+        let mut buffer = vec![];
+        for (original_first_thunk, name, first_thunk) in descriptors {
+            push_u32(&mut buffer, original_first_thunk);
+            push_u32(&mut buffer, 0); // TimeDateStamp
+            push_u32(&mut buffer, 0); // ForwarderChain
+            push_u32(&mut buffer, name);
+            push_u32(&mut buffer, first_thunk);
+        }
+        buffer.extend_from_slice(&[0; IMPORT_DESCRIPTOR_SIZE]);
+        buffer.resize((lookup_table - rva) as usize, 0);
+
+        // The lookup table and the IAT start out the same.
+        for _ in 0..2 {
+            for &thunk in &thunk_values {
+                buffer.extend_from_slice(&thunk.to_le_bytes()[..thunk_size as usize]);
+            }
+        }
+        buffer.extend_from_slice(&strings);
+
+        Ok((buffer, iat, thunks * thunk_size))
+    }
+
+    // Returns the section, the RVA of the TLS directory and the pointers in it (location, target
+    // RVA) that have to be relocated.
+    fn build_tls(
+        &self,
+        rva: u32,
+        rva_of: &dyn Fn(Location) -> u32,
+    ) -> (Vec<u8>, u32, Vec<(u32, u32)>) {
+        let pointer_size = self.pointer_size();
+        let mut buffer = self.tls_data.clone();
+        let data_end = rva + buffer.len() as u32;
+        let padding = buffer.len().wrapping_neg() % 8;
+        push_zeros(&mut buffer, padding);
+
+        let index = rva + buffer.len() as u32;
+        push_zeros(&mut buffer, 8);
+
+        let mut pointers = vec![];
+        let callbacks = rva + buffer.len() as u32;
+        for &callback in &self.tls_callbacks {
+            pointers.push((rva + buffer.len() as u32, rva_of(callback)));
+            push_zeros(&mut buffer, pointer_size as usize);
+        }
+        push_zeros(&mut buffer, pointer_size as usize);
+
+        let directory = rva + buffer.len() as u32;
+        for &target in &[rva, data_end, index, callbacks] {
+            pointers.push((rva + buffer.len() as u32, target));
+            push_zeros(&mut buffer, pointer_size as usize);
+        }
+        push_u32(&mut buffer, 0); // SizeOfZeroFill
+        push_u32(&mut buffer, 0); // Characteristics
+
+        (buffer, directory, pointers)
+    }
+
+    fn build_relocations(&self, pointers: &[(u32, u32)]) -> Vec<u8> {
+        let relocation_type = if self.is_64_bit {
+            RELOCATION_DIR64
+        } else {
+            RELOCATION_HIGH_LOW
+        };
+
+        let mut pages = BTreeMap::new();
+        for &(at, _) in pointers {
+            pages
+                .entry(at & !(PAGE_SIZE - 1))
+                .or_insert_with(Vec::new)
+                .push(relocation_type << 12 | (at & (PAGE_SIZE - 1)) as u16);
+        }
+
+        let mut buffer = vec![];
+        for (page, mut entries) in pages {
+            entries.sort();
+            // Blocks have to be 32 bit aligned.
+            if entries.len() % 2 != 0 {
+                entries.push(RELOCATION_ABSOLUTE << 12);
+            }
+            push_u32(&mut buffer, page);
+            push_u32(&mut buffer, 8 + entries.len() as u32 * 2);
+            for entry in entries {
+                buffer.extend_from_slice(&entry.to_le_bytes());
+            }
+        }
+        buffer
+    }
+
+    /// Lays out the image and returns the file.
+    pub fn build(&self) -> Result<Vec<u8>, BuildError> {
+        self.validate()?;
+
+        let has_exports = self.has_exports();
+        let has_imports = !self.imports.is_empty();
+        let has_tls = self.has_tls();
+        let has_relocations = self.has_relocations();
+
+        // The sizes of the generated sections don't depend on where they end up, so a first pass
+        // at RVA zero is enough to lay everything out.
+        let user = self.sections.len();
+        let mut sections = self
+            .sections
+            .iter()
+            .map(|s| {
+                (
+                    s.name.clone(),
+                    s.characteristics,
+                    s.data.clone(),
+                    self.virtual_size(s),
+                )
+            })
+            .collect::<Vec<_>>();
+        let zero = |_: Location| 0;
+        if has_exports {
+            let size = self.build_exports(0, &zero).len() as u32;
+            sections.push((
+                ".edata".into(),
+                SECTION_READ_ONLY_DATA,
+                vec![0; size as usize],
+                size,
+            ));
+        }
+        if has_imports {
+            let size = self.build_imports(0)?.0.len() as u32;
+            sections.push((".idata".into(), SECTION_DATA, vec![0; size as usize], size));
+        }
+        if has_tls {
+            let size = self.build_tls(0, &zero).0.len() as u32;
+            sections.push((".tls".into(), SECTION_DATA, vec![0; size as usize], size));
+        }
+
+        // The relocations only depend on the RVAs of the other sections.
+        let sizes = sections
+            .iter()
+            .map(|s| (s.2.len() as u32, s.3))
+            .chain(if has_relocations { Some((0, 0)) } else { None })
+            .collect::<Vec<_>>();
+        let mut layout = self.layout(&sizes)?;
+        let rvas = layout.rvas.clone();
+        let rva_of = move |l: Location| rvas[l.section.0] + l.offset;
+
+        let mut directories = [(0u32, 0u32); NUM_DATA_DIRECTORIES];
+        let mut pointers = self
+            .pointers
+            .iter()
+            .map(|&(at, target)| (rva_of(at), rva_of(target)))
+            .collect::<Vec<_>>();
+        for &(entry, location, size) in &self.directories {
+            directories[entry as usize] = (rva_of(location), size);
+        }
+        let mut generated = user;
+        if has_exports {
+            let rva = layout.rvas[generated];
+            sections[generated].2 = self.build_exports(rva, &rva_of);
+            directories[DirectoryEntry::Export as usize] = (rva, sections[generated].3);
+            generated += 1;
+        }
+        if has_imports {
+            let rva = layout.rvas[generated];
+            let (data, iat, iat_size) = self.build_imports(rva)?;
+            let descriptors = (self.imports.len() + 1) * IMPORT_DESCRIPTOR_SIZE;
+            sections[generated].2 = data;
+            directories[DirectoryEntry::Import as usize] = (rva, descriptors as u32);
+            directories[DirectoryEntry::Iat as usize] = (iat, iat_size);
+            generated += 1;
+        }
+        if has_tls {
+            let rva = layout.rvas[generated];
+            let (data, directory, tls_pointers) = self.build_tls(rva, &rva_of);
+            sections[generated].2 = data;
+            let size = if self.is_64_bit { 40 } else { 24 };
+            directories[DirectoryEntry::Tls as usize] = (directory, size);
+            pointers.extend(tls_pointers);
+            generated += 1;
+        }
+
+        if has_relocations {
+            let data = self.build_relocations(&pointers);
+            let size = data.len() as u32;
+            sections.push((
+                ".reloc".into(),
+                SECTION_READ_ONLY_DATA | SectionCharacteristics::MEM_DISCARDABLE,
+                data,
+                size,
+            ));
+            let sizes = sections
+                .iter()
+                .map(|s| (s.2.len() as u32, s.3))
+                .collect::<Vec<_>>();
+            layout = self.layout(&sizes)?;
+            directories[DirectoryEntry::Basereloc as usize] = (layout.rvas[generated], size);
+        }
+
+        // Pointers are written once all sections have their contents.
+        for &(at, target) in &pointers {
+            let address = self.image_base + target as u64;
+            let (index, rva) = layout
+                .rvas
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|&(_, &rva)| rva <= at)
+                .expect("pointers are inside of sections");
+            let offset = (at - rva) as usize;
+            if self.is_64_bit {
+                write_u64(&mut sections[index].2, offset, address)?;
+            } else {
+                write_u32(&mut sections[index].2, offset, address as u32)?;
+            }
+        }
+
+        let mut file_size = layout.size_of_headers;
+        for (section, &offset) in sections.iter().zip(&layout.offsets) {
+            let raw_size = align_up(section.2.len() as u32, self.file_alignment)?;
+            file_size = file_size.max(add(offset, raw_size)?);
+        }
+
+        // Like MinGW, the symbol table goes behind the sections, with a symbol for every section
+        // and section names longer than 8 bytes in the string table.
+        let mut strings = vec![0; 4];
+        let mut names = vec![];
+        for section in &sections {
+            names.push(coff_name(&mut strings, &section.0, true));
+        }
+        let mut symbols = vec![];
+        if !self.symbols.is_empty() || strings.len() > 4 {
+            for (i, section) in sections.iter().enumerate() {
+                // The section definition, only the length is filled in.
+                let mut aux = [0u8; SYMBOL_SIZE];
+                aux[..4].copy_from_slice(&(section.2.len() as u32).to_le_bytes());
+                let name = coff_name(&mut strings, &section.0, false);
+                let number = i as u16 + 1;
+                push_symbol(&mut symbols, name, 0, number, 0, SYM_CLASS_STATIC, Some(&aux));
+            }
+            for &(ref name, location) in &self.symbols {
+                let index = location.section.0;
+                let kind = if sections[index].1.contains(SectionCharacteristics::CNT_CODE) {
+                    SYM_DTYPE_FUNCTION
+                } else {
+                    0
+                };
+                let name = coff_name(&mut strings, name, false);
+                push_symbol(
+                    &mut symbols,
+                    name,
+                    location.offset,
+                    index as u16 + 1,
+                    kind,
+                    SYM_CLASS_EXTERNAL,
+                    None,
+                );
+            }
+            let size = strings.len() as u32;
+            strings[..4].copy_from_slice(&size.to_le_bytes());
+        }
+
+        let mut buffer = vec![0u8; file_size as usize];
+
+        write_u16(&mut buffer, 0, DOS_SIGNATURE)?;
+        for (i, &value) in DOS_HEADER.iter().enumerate() {
+            write_u16(&mut buffer, 2 + i * 2, value)?;
+        }
+        write_bytes(&mut buffer, DOS_STUB_OFFSET, DOS_STUB)?;
+        if !self.rich_header.is_empty() {
+            let rich_header = encode_rich_header(&buffer[..PE_HEADER_OFFSET], &self.rich_header);
+            write_bytes(&mut buffer, PE_HEADER_OFFSET, &rich_header)?;
+        }
+        let pe_header = self.pe_header_offset();
+        write_u32(&mut buffer, 0x3c, pe_header as u32)?;
+        write_u32(&mut buffer, pe_header, PE_SIGNATURE)?;
+
+        let file_header = pe_header + 4;
+        write_u16(&mut buffer, file_header, self.machine.0)?;
+        write_u16(&mut buffer, file_header + 2, sections.len() as u16)?;
+        write_u32(&mut buffer, file_header + 4, self.time_date_stamp)?;
+        if !symbols.is_empty() {
+            write_u32(&mut buffer, file_header + 8, file_size)?;
+            write_u32(
+                &mut buffer,
+                file_header + 12,
+                (symbols.len() / SYMBOL_SIZE) as u32,
+            )?;
+            buffer.extend_from_slice(&symbols);
+            buffer.extend_from_slice(&strings);
+        }
+        write_u16(
+            &mut buffer,
+            file_header + 16,
+            self.optional_header_size() as u16,
+        )?;
+        write_u16(&mut buffer, file_header + 18, self.file_characteristics.bits())?;
+
+        let sum_sizes = |flag: SectionCharacteristics| {
+            sections
+                .iter()
+                .filter(|s| s.1.intersects(flag))
+                .map(|s| align_up(s.3, self.file_alignment))
+                .sum::<Result<u32, _>>()
+        };
+        let base_of = |flag: SectionCharacteristics| {
+            sections
+                .iter()
+                .zip(&layout.rvas)
+                .find(|&(s, _)| s.1.intersects(flag))
+                .map_or(0, |(_, &rva)| rva)
+        };
+
+        let optional_header = file_header + FILE_HEADER_SIZE;
+        let o = optional_header;
+        let magic = if self.is_64_bit {
+            OptionalHeaderMagic::PE32_PLUS.0
+        } else {
+            OptionalHeaderMagic::PE32.0
+        };
+        write_u16(&mut buffer, o, magic)?;
+        write_bytes(&mut buffer, o + 2, &[14, 0])?; // Linker version
+        write_u32(&mut buffer, o + 4, sum_sizes(SectionCharacteristics::CNT_CODE)?)?;
+        write_u32(&mut buffer, o + 8, sum_sizes(SectionCharacteristics::CNT_INITIALIZED_DATA)?)?;
+        write_u32(&mut buffer, o + 12, sum_sizes(SectionCharacteristics::CNT_UNINITIALIZED_DATA)?)?;
+        write_u32(&mut buffer, o + 16, self.entry_point.map_or(0, rva_of))?;
+        write_u32(&mut buffer, o + 20, base_of(SectionCharacteristics::CNT_CODE))?;
+        if self.is_64_bit {
+            write_u64(&mut buffer, o + 24, self.image_base)?;
+        } else {
+            write_u32(&mut buffer, o + 24, base_of(SectionCharacteristics::CNT_INITIALIZED_DATA))?;
+            write_u32(&mut buffer, o + 28, self.image_base as u32)?;
+        }
+        write_u32(&mut buffer, o + 32, self.section_alignment)?;
+        write_u32(&mut buffer, o + 36, self.file_alignment)?;
+        write_u16(&mut buffer, o + 40, 6)?; // Operating system version
+        write_u16(&mut buffer, o + 48, 6)?; // Subsystem version
+        write_u32(&mut buffer, o + 56, layout.size_of_image)?;
+        write_u32(&mut buffer, o + 60, layout.size_of_headers)?;
+        write_u16(&mut buffer, o + 68, self.subsystem.0)?;
+        write_u16(&mut buffer, o + 70, self.dll_characteristics.bits())?;
+
+        // Stack and heap reserve and commit
+        let sizes = [0x10_0000u64, 0x1000, 0x10_0000, 0x1000];
+        let mut offset = o + 72;
+        for &size in &sizes {
+            if self.is_64_bit {
+                write_u64(&mut buffer, offset, size)?;
+            } else {
+                write_u32(&mut buffer, offset, size as u32)?;
+            }
+            offset += self.pointer_size() as usize;
+        }
+        // Loader flags
+        offset += 4;
+        write_u32(&mut buffer, offset, NUM_DATA_DIRECTORIES as u32)?;
+        offset += 4;
+        for &(rva, size) in &directories {
+            write_u32(&mut buffer, offset, rva)?;
+            write_u32(&mut buffer, offset + 4, size)?;
+            offset += 8;
+        }
+
+        let section_table = optional_header + self.optional_header_size();
+        for (i, section) in sections.iter().enumerate() {
+            let header = section_table + i * SECTION_HEADER_SIZE;
+            let raw_size = align_up(section.2.len() as u32, self.file_alignment)?;
+            write_bytes(&mut buffer, header, &names[i])?;
+            write_u32(&mut buffer, header + 8, section.3)?;
+            write_u32(&mut buffer, header + 12, layout.rvas[i])?;
+            write_u32(&mut buffer, header + 16, raw_size)?;
+            write_u32(&mut buffer, header + 20, layout.offsets[i])?;
+            write_u32(&mut buffer, header + 36, section.1.bits())?;
+            write_bytes(&mut buffer, layout.offsets[i] as usize, &section.2)?;
+        }
+
+        update_checksum(&mut buffer)?;
+
+        Ok(buffer)
+    }
+}
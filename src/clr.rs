@@ -0,0 +1,188 @@
+// The CLR header of .NET assemblies and the metadata root it points to.
+//
+// The COM descriptor data directory points to an IMAGE_COR20_HEADER, whose metadata directory in
+// turn points to the metadata root (ECMA-335 II.24.2.1):
+//
+//     "BSJB", major version, minor version, reserved, version length, version string
+//     flags, number of streams
+//     offset, size, name (NUL terminated, padded to 4 bytes)    (one per stream)
+//
+// Stream offsets are relative to the metadata root. We only locate the streams, the tables in
+// `#~` aren't decoded.
+
+use crate::pe_file::{
+    read_bytes, read_c_str, read_u16, read_u32, DataDirectory, PEFile, ParseError,
+};
+use crate::structs::DirectoryEntry;
+
+const CLR_HEADER_SIZE: usize = 72;
+const METADATA_SIGNATURE: u32 = 0x424a_5342; // BSJB
+const MAX_VERSION_LENGTH: u32 = 255;
+const MAX_STREAM_NAME_LENGTH: usize = 32;
+
+pub const COMIMAGE_FLAGS_ILONLY: u32 = 0x1;
+pub const COMIMAGE_FLAGS_32BITREQUIRED: u32 = 0x2;
+pub const COMIMAGE_FLAGS_IL_LIBRARY: u32 = 0x4;
+pub const COMIMAGE_FLAGS_STRONGNAMESIGNED: u32 = 0x8;
+pub const COMIMAGE_FLAGS_NATIVE_ENTRYPOINT: u32 = 0x10;
+pub const COMIMAGE_FLAGS_TRACKDEBUGDATA: u32 = 0x1_0000;
+pub const COMIMAGE_FLAGS_32BITPREFERRED: u32 = 0x2_0000;
+
+/// The IMAGE_COR20_HEADER.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ClrHeader {
+    pub cb: u32,
+    pub major_runtime_version: u16,
+    pub minor_runtime_version: u16,
+    pub metadata: DataDirectory,
+    /// A combination of the `COMIMAGE_FLAGS_*` constants.
+    pub flags: u32,
+    /// A method token, or an RVA if `COMIMAGE_FLAGS_NATIVE_ENTRYPOINT` is set.
+    pub entry_point: u32,
+    pub resources: DataDirectory,
+    pub strong_name_signature: DataDirectory,
+    pub code_manager_table: DataDirectory,
+    pub vtable_fixups: DataDirectory,
+    pub export_address_table_jumps: DataDirectory,
+    pub managed_native_header: DataDirectory,
+}
+
+impl ClrHeader {
+    /// Whether the image contains nothing but IL, i.e. there is no native code to run.
+    pub fn is_il_only(&self) -> bool {
+        self.flags & COMIMAGE_FLAGS_ILONLY != 0
+    }
+}
+
+/// A stream of the metadata, e.g. `#~`, `#Strings`, `#US`, `#GUID` or `#Blob`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetadataStream<'a> {
+    pub name: &'a str,
+    /// Relative to the metadata root.
+    pub offset: u32,
+    pub data: &'a [u8],
+}
+
+/// The metadata root and its stream headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata<'a> {
+    pub major_version: u16,
+    pub minor_version: u16,
+    /// The runtime version the assembly was built against, e.g. `v4.0.30319`.
+    pub version: &'a str,
+    pub flags: u16,
+    pub streams: Vec<MetadataStream<'a>>,
+}
+
+impl<'a> Metadata<'a> {
+    pub fn stream(&self, name: &str) -> Option<&MetadataStream<'a>> {
+        self.streams.iter().find(|s| s.name == name)
+    }
+
+    /// The compressed (`#~`) or uncompressed (`#-`) tables stream.
+    pub fn tables(&self) -> Option<&MetadataStream<'a>> {
+        self.stream("#~").or_else(|| self.stream("#-"))
+    }
+
+    pub fn strings(&self) -> Option<&MetadataStream<'a>> {
+        self.stream("#Strings")
+    }
+
+    pub fn user_strings(&self) -> Option<&MetadataStream<'a>> {
+        self.stream("#US")
+    }
+
+    pub fn guids(&self) -> Option<&MetadataStream<'a>> {
+        self.stream("#GUID")
+    }
+
+    pub fn blobs(&self) -> Option<&MetadataStream<'a>> {
+        self.stream("#Blob")
+    }
+}
+
+fn read_directory(buffer: &[u8], offset: usize) -> Result<DataDirectory, ParseError> {
+    Ok(DataDirectory {
+        virtual_address: read_u32(buffer, offset)?,
+        size: read_u32(buffer, offset + 4)?,
+    })
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+impl<'a> PEFile<'a> {
+    /// The CLR header, present in .NET assemblies only.
+    pub fn clr_header(&self) -> Result<Option<ClrHeader>, ParseError> {
+        let directory = match self.data_directory(DirectoryEntry::ComDescriptor)? {
+            None => return Ok(None),
+            Some(directory) => directory,
+        };
+
+        let header = self.read_rva(directory.virtual_address, CLR_HEADER_SIZE)?;
+        Ok(Some(ClrHeader {
+            cb: read_u32(header, 0)?,
+            major_runtime_version: read_u16(header, 4)?,
+            minor_runtime_version: read_u16(header, 6)?,
+            metadata: read_directory(header, 8)?,
+            flags: read_u32(header, 16)?,
+            entry_point: read_u32(header, 20)?,
+            resources: read_directory(header, 24)?,
+            strong_name_signature: read_directory(header, 32)?,
+            code_manager_table: read_directory(header, 40)?,
+            vtable_fixups: read_directory(header, 48)?,
+            export_address_table_jumps: read_directory(header, 56)?,
+            managed_native_header: read_directory(header, 64)?,
+        }))
+    }
+
+    /// The metadata root the CLR header points to. `None` if the image isn't a .NET assembly.
+    pub fn clr_metadata(&self) -> Result<Option<Metadata<'a>>, ParseError> {
+        let directory = match self.clr_header()? {
+            None => return Ok(None),
+            Some(header) => header.metadata,
+        };
+
+        let metadata = self.read_rva(directory.virtual_address, directory.size as usize)?;
+        if read_u32(metadata, 0)? != METADATA_SIGNATURE {
+            return Err(ParseError::InvalidMetadata);
+        }
+        let version_length = read_u32(metadata, 12)?;
+        if version_length > MAX_VERSION_LENGTH {
+            return Err(ParseError::InvalidMetadata);
+        }
+        // The version is padded with NULs, which don't belong to it.
+        let version = read_c_str(read_bytes(metadata, 16, version_length as usize)?, 0)?;
+
+        let mut offset = 16 + align4(version_length as usize);
+        let flags = read_u16(metadata, offset)?;
+        let num_streams = read_u16(metadata, offset + 2)?;
+        offset += 4;
+
+        let mut streams = Vec::with_capacity(num_streams as usize);
+        for _ in 0..num_streams {
+            let stream_offset = read_u32(metadata, offset)?;
+            let size = read_u32(metadata, offset + 4)?;
+            let name = metadata.get(offset + 8..).ok_or(ParseError::OutOfBounds)?;
+            let name = read_c_str(&name[..name.len().min(MAX_STREAM_NAME_LENGTH)], 0)?;
+            offset += 8 + align4(name.len() + 1);
+
+            streams.push(MetadataStream {
+                name,
+                offset: stream_offset,
+                data: read_bytes(metadata, stream_offset as usize, size as usize)
+                    .map_err(|_| ParseError::InvalidMetadata)?,
+            });
+        }
+
+        Ok(Some(Metadata {
+            major_version: read_u16(metadata, 4)?,
+            minor_version: read_u16(metadata, 6)?,
+            version,
+            flags,
+            streams,
+        }))
+    }
+}
@@ -0,0 +1,465 @@
+// COFF object files, as produced by compilers and assemblers before linking, and the COFF symbol
+// table.
+//
+// An object starts right away with the file header that PE files put behind their signature, has
+// no optional header and keeps what the linker needs in the sections: raw data, relocations and
+// a symbol table with a string table for names longer than 8 bytes directly behind it.
+//
+// Images normally don't have a symbol table, but MinGW's linker keeps it, and with it the string
+// table that section names longer than 8 bytes (`.debug_info` and friends) are stored in.
+
+use std::str;
+
+use crate::pe_file::{
+    read_bytes, read_c_str, read_u16, read_u32, PEFile, ParseError, Section, Sections,
+};
+use crate::structs::{Machine, SectionCharacteristics};
+
+const FILE_HEADER_SIZE: usize = 20;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 18;
+const RELOCATION_SIZE: usize = 10;
+
+/// Section numbers with a special meaning, real sections are numbered starting from 1.
+pub const IMAGE_SYM_UNDEFINED: i16 = 0;
+pub const IMAGE_SYM_ABSOLUTE: i16 = -1;
+pub const IMAGE_SYM_DEBUG: i16 = -2;
+
+pub const IMAGE_SYM_CLASS_EXTERNAL: u8 = 2;
+pub const IMAGE_SYM_CLASS_STATIC: u8 = 3;
+pub const IMAGE_SYM_CLASS_LABEL: u8 = 6;
+pub const IMAGE_SYM_CLASS_FUNCTION: u8 = 101;
+pub const IMAGE_SYM_CLASS_FILE: u8 = 103;
+pub const IMAGE_SYM_CLASS_SECTION: u8 = 104;
+pub const IMAGE_SYM_CLASS_WEAK_EXTERNAL: u8 = 105;
+
+// The derived type in bits 4 and 5 of `Symbol::kind`.
+const IMAGE_SYM_DTYPE_MASK: u16 = 0x30;
+const IMAGE_SYM_DTYPE_FUNCTION: u16 = 0x20;
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub const IMAGE_REL_AMD64_ABSOLUTE: u16 = 0x0;
+pub const IMAGE_REL_AMD64_ADDR64: u16 = 0x1;
+pub const IMAGE_REL_AMD64_ADDR32: u16 = 0x2;
+pub const IMAGE_REL_AMD64_ADDR32NB: u16 = 0x3;
+pub const IMAGE_REL_AMD64_REL32: u16 = 0x4;
+pub const IMAGE_REL_AMD64_REL32_1: u16 = 0x5;
+pub const IMAGE_REL_AMD64_REL32_2: u16 = 0x6;
+pub const IMAGE_REL_AMD64_REL32_3: u16 = 0x7;
+pub const IMAGE_REL_AMD64_REL32_4: u16 = 0x8;
+pub const IMAGE_REL_AMD64_REL32_5: u16 = 0x9;
+pub const IMAGE_REL_AMD64_SECTION: u16 = 0xa;
+pub const IMAGE_REL_AMD64_SECREL: u16 = 0xb;
+
+pub const IMAGE_REL_I386_ABSOLUTE: u16 = 0x0;
+pub const IMAGE_REL_I386_DIR32: u16 = 0x6;
+pub const IMAGE_REL_I386_DIR32NB: u16 = 0x7;
+pub const IMAGE_REL_I386_SECTION: u16 = 0xa;
+pub const IMAGE_REL_I386_SECREL: u16 = 0xb;
+pub const IMAGE_REL_I386_REL32: u16 = 0x14;
+
+/// A record of the symbol table. Auxiliary records aren't symbols of their own, they belong to
+/// the symbol in front of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Symbol<'a> {
+    /// Index of the record in the symbol table, which is what relocations refer to.
+    pub index: u32,
+    pub name: &'a str,
+    pub value: u32,
+    /// One based index of the section the symbol is defined in, or one of the `IMAGE_SYM_*`
+    /// section numbers.
+    pub section_number: i16,
+    pub kind: u16,
+    pub storage_class: u8,
+    /// The raw auxiliary records following the symbol, 18 bytes each.
+    pub aux: &'a [u8],
+}
+
+impl<'a> Symbol<'a> {
+    pub fn num_aux_symbols(&self) -> usize {
+        self.aux.len() / SYMBOL_SIZE
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.storage_class == IMAGE_SYM_CLASS_EXTERNAL
+    }
+
+    /// Whether the symbol has to be provided by something else than the object.
+    pub fn is_undefined(&self) -> bool {
+        self.section_number == IMAGE_SYM_UNDEFINED
+    }
+
+    /// Uninitialized data the linker has to allocate, `value` is its size.
+    pub fn is_common(&self) -> bool {
+        self.is_external() && self.is_undefined() && self.value != 0
+    }
+
+    pub fn is_function(&self) -> bool {
+        self.kind & IMAGE_SYM_DTYPE_MASK == IMAGE_SYM_DTYPE_FUNCTION
+    }
+
+    /// The auxiliary records, decoded according to the kind of symbol they belong to. `None` if
+    /// there are none.
+    pub fn aux_symbol(&self) -> Result<Option<AuxSymbol<'a>>, ParseError> {
+        let aux = self.aux;
+        if aux.is_empty() {
+            return Ok(None);
+        }
+
+        let aux_symbol = match self.storage_class {
+            IMAGE_SYM_CLASS_FILE => {
+                let len = aux.iter().position(|&c| c == 0).unwrap_or(aux.len());
+                AuxSymbol::File(str::from_utf8(&aux[..len]).map_err(|_| ParseError::InvalidName)?)
+            }
+            IMAGE_SYM_CLASS_WEAK_EXTERNAL => AuxSymbol::WeakExternal {
+                tag_index: read_u32(aux, 0)?,
+                characteristics: read_u32(aux, 4)?,
+            },
+            IMAGE_SYM_CLASS_FUNCTION => AuxSymbol::FunctionBoundary {
+                line_number: read_u16(aux, 4)?,
+                pointer_to_next_function: read_u32(aux, 12)?,
+            },
+            IMAGE_SYM_CLASS_EXTERNAL if self.is_function() && self.section_number > 0 => {
+                AuxSymbol::FunctionDefinition {
+                    tag_index: read_u32(aux, 0)?,
+                    total_size: read_u32(aux, 4)?,
+                    pointer_to_line_number: read_u32(aux, 8)?,
+                    pointer_to_next_function: read_u32(aux, 12)?,
+                }
+            }
+            IMAGE_SYM_CLASS_STATIC if self.value == 0 && self.section_number > 0 => {
+                AuxSymbol::SectionDefinition {
+                    length: read_u32(aux, 0)?,
+                    num_relocations: read_u16(aux, 4)?,
+                    num_line_numbers: read_u16(aux, 6)?,
+                    checksum: read_u32(aux, 8)?,
+                    number: read_u16(aux, 12)?,
+                    selection: aux[14],
+                }
+            }
+            _ => AuxSymbol::Unknown(aux),
+        };
+        Ok(Some(aux_symbol))
+    }
+}
+
+/// The auxiliary records of a symbol.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuxSymbol<'a> {
+    /// Follows the definition of a function.
+    FunctionDefinition {
+        /// Index of the `.bf` symbol of the function.
+        tag_index: u32,
+        total_size: u32,
+        pointer_to_line_number: u32,
+        pointer_to_next_function: u32,
+    },
+    /// Follows the `.bf` and `.ef` symbols marking where a function begins and ends.
+    FunctionBoundary {
+        line_number: u16,
+        pointer_to_next_function: u32,
+    },
+    WeakExternal {
+        /// Index of the symbol used if the external isn't defined anywhere else.
+        tag_index: u32,
+        characteristics: u32,
+    },
+    /// The source file of a `.file` symbol.
+    File(&'a str),
+    /// Follows the symbol of a section.
+    SectionDefinition {
+        length: u32,
+        num_relocations: u16,
+        num_line_numbers: u16,
+        checksum: u32,
+        /// The associated section of a COMDAT section.
+        number: u16,
+        /// How the linker picks one of several COMDAT sections.
+        selection: u8,
+    },
+    Unknown(&'a [u8]),
+}
+
+impl Section {
+    /// Offset of the name in the string table, for names longer than 8 bytes. They are written as
+    /// `/<decimal offset>`, or as `//<base64 offset>` if that doesn't fit.
+    pub fn long_name_offset(&self) -> Option<u32> {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(8);
+        let name = &self.name[..len];
+
+        if name.starts_with(b"//") {
+            name[2..].iter().try_fold(0u32, |offset, &c| {
+                let digit = BASE64.iter().position(|&b| b == c)?;
+                offset.checked_mul(64)?.checked_add(digit as u32)
+            })
+        } else if name.len() > 1 && name[0] == b'/' {
+            str::from_utf8(&name[1..]).ok()?.parse().ok()
+        } else {
+            None
+        }
+    }
+}
+
+// The name of `section`, long names are looked up in the string table of `table`.
+fn section_name<'a: 'b, 'b>(
+    section: &'b Section,
+    table: Option<SymbolTable<'a>>,
+) -> Result<&'b str, ParseError> {
+    match section.long_name_offset() {
+        None => section.name_str().ok_or(ParseError::InvalidName),
+        Some(offset) => table.ok_or(ParseError::InvalidName)?.string(offset),
+    }
+}
+
+/// The symbol table together with the string table behind it.
+#[derive(Clone, Copy, Debug)]
+pub struct SymbolTable<'a> {
+    symbols: &'a [u8],
+    // Starts with its own size, offsets into it include those 4 bytes.
+    strings: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Parses the `num_symbols` records at `offset` in `buffer`, followed by the string table.
+    pub(crate) fn parse(
+        buffer: &'a [u8],
+        offset: usize,
+        num_symbols: u32,
+    ) -> Result<Self, ParseError> {
+        let size = (num_symbols as usize)
+            .checked_mul(SYMBOL_SIZE)
+            .ok_or(ParseError::OutOfBounds)?;
+        let symbols = read_bytes(buffer, offset, size)?;
+
+        // Some tools leave the string table out when it would be empty.
+        let strings_offset = offset + size;
+        let strings = match read_u32(buffer, strings_offset) {
+            Err(_) => &[][..],
+            Ok(size) => read_bytes(buffer, strings_offset, (size as usize).max(4))?,
+        };
+
+        Ok(Self { symbols, strings })
+    }
+
+    /// Number of records, including auxiliary ones.
+    pub fn len(&self) -> usize {
+        self.symbols.len() / SYMBOL_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// The NUL terminated string at `offset` in the string table.
+    pub fn string(&self, offset: u32) -> Result<&'a str, ParseError> {
+        if (offset as usize) < 4 {
+            return Err(ParseError::InvalidName);
+        }
+        read_c_str(self.strings, offset as usize)
+    }
+
+    /// The symbol at `index`, which must not be an auxiliary record.
+    pub fn symbol(&self, index: u32) -> Result<Symbol<'a>, ParseError> {
+        let offset = (index as usize)
+            .checked_mul(SYMBOL_SIZE)
+            .ok_or(ParseError::OutOfBounds)?;
+        let record = read_bytes(self.symbols, offset, SYMBOL_SIZE)?;
+
+        let name = if read_u32(record, 0)? == 0 {
+            self.string(read_u32(record, 4)?)?
+        } else {
+            let len = record[..8].iter().position(|&c| c == 0).unwrap_or(8);
+            str::from_utf8(&record[..len]).map_err(|_| ParseError::InvalidName)?
+        };
+
+        let num_aux_symbols = record[17] as usize;
+        Ok(Symbol {
+            index,
+            name,
+            value: read_u32(record, 8)?,
+            section_number: read_u16(record, 12)? as i16,
+            kind: read_u16(record, 14)?,
+            storage_class: record[16],
+            aux: read_bytes(
+                self.symbols,
+                offset + SYMBOL_SIZE,
+                num_aux_symbols * SYMBOL_SIZE,
+            )?,
+        })
+    }
+
+    /// Every symbol, skipping over the auxiliary records.
+    pub fn iter(&self) -> SymbolIterator<'a> {
+        SymbolIterator {
+            table: *self,
+            index: 0,
+        }
+    }
+}
+
+pub struct SymbolIterator<'a> {
+    table: SymbolTable<'a>,
+    index: usize,
+}
+
+impl<'a> Iterator for SymbolIterator<'a> {
+    type Item = Result<Symbol<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.table.len() {
+            return None;
+        }
+
+        let symbol = self.table.symbol(self.index as u32);
+        match symbol {
+            Ok(ref symbol) => self.index += 1 + symbol.num_aux_symbols(),
+            Err(_) => self.index = self.table.len(),
+        }
+        Some(symbol)
+    }
+}
+
+/// A COFF relocation, applied to the section it belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CoffRelocation {
+    /// Offset of the location to patch, relative to the start of the section.
+    pub virtual_address: u32,
+    pub symbol_table_index: u32,
+    /// One of the `IMAGE_REL_*` constants of the machine.
+    pub kind: u16,
+}
+
+#[derive(Clone, Copy)]
+pub struct CoffFile<'a> {
+    buffer: &'a [u8],
+    section_table: usize,
+    num_sections: usize,
+}
+
+impl<'a> CoffFile<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self, ParseError> {
+        let num_sections = read_u16(buffer, 2)? as usize;
+        let size_optional_header = read_u16(buffer, 16)? as usize;
+        let section_table = FILE_HEADER_SIZE + size_optional_header;
+        read_bytes(buffer, section_table, num_sections * SECTION_HEADER_SIZE)?;
+
+        Ok(Self {
+            buffer,
+            section_table,
+            num_sections,
+        })
+    }
+
+    pub fn buffer(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    pub fn machine(&self) -> Machine {
+        Machine(read_u16(self.buffer, 0).unwrap())
+    }
+
+    pub fn num_sections(&self) -> usize {
+        self.num_sections
+    }
+
+    pub fn time_date_stamp(&self) -> u32 {
+        read_u32(self.buffer, 4).unwrap()
+    }
+
+    pub fn characteristics(&self) -> u16 {
+        read_u16(self.buffer, 18).unwrap()
+    }
+
+    pub fn sections(&self) -> Sections<'a> {
+        Sections::new(self.buffer, self.section_table, self.num_sections)
+    }
+
+    /// The section with the one based `number` symbols use.
+    pub fn section(&self, number: i16) -> Option<Section> {
+        match number {
+            n if n < 1 => None,
+            n => self.sections().nth(n as usize - 1),
+        }
+    }
+
+    /// The raw data of `section`, empty for uninitialized data.
+    pub fn section_data(&self, section: &Section) -> Result<&'a [u8], ParseError> {
+        if section.p_raw_data == 0 {
+            return Ok(&[]);
+        }
+        read_bytes(
+            self.buffer,
+            section.p_raw_data as usize,
+            section.size_of_raw_data as usize,
+        )
+    }
+
+    pub fn symbol_table(&self) -> Result<SymbolTable<'a>, ParseError> {
+        SymbolTable::parse(
+            self.buffer,
+            read_u32(self.buffer, 8)? as usize,
+            read_u32(self.buffer, 12)?,
+        )
+    }
+
+    /// The name of `section`, including names longer than 8 bytes.
+    pub fn section_name<'b>(&self, section: &'b Section) -> Result<&'b str, ParseError>
+    where
+        'a: 'b,
+    {
+        section_name(section, self.symbol_table().ok())
+    }
+
+    pub fn relocations(&self, section: &Section) -> Result<Vec<CoffRelocation>, ParseError> {
+        let offset = section.p_reloc as usize;
+        let mut count = section.num_relocations as usize;
+        let mut first = 0;
+        // Sections with more than 0xffff relocations have the real count in the `virtual_address`
+        // of the first relocation.
+        let overflow = section.characteristics.contains(SectionCharacteristics::LNK_NRELOC_OVFL);
+        if overflow && count == 0xffff {
+            // The count includes the record holding it.
+            count = read_u32(self.buffer, offset)? as usize;
+            first = 1;
+        }
+        let size = count
+            .checked_mul(RELOCATION_SIZE)
+            .ok_or(ParseError::OutOfBounds)?;
+        read_bytes(self.buffer, offset, size)?;
+
+        (first..count)
+            .map(|i| {
+                let record = offset + i * RELOCATION_SIZE;
+                Ok(CoffRelocation {
+                    virtual_address: read_u32(self.buffer, record)?,
+                    symbol_table_index: read_u32(self.buffer, record + 4)?,
+                    kind: read_u16(self.buffer, record + 8)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl<'a> PEFile<'a> {
+    /// The COFF symbol table, `None` if the image doesn't have one, which is the case unless it
+    /// was linked by MinGW.
+    pub fn symbol_table(&self) -> Result<Option<SymbolTable<'a>>, ParseError> {
+        let file_header = self.file_header_offset();
+        let offset = read_u32(self.buffer(), file_header + 8)?;
+        if offset == 0 {
+            return Ok(None);
+        }
+        let num_symbols = read_u32(self.buffer(), file_header + 12)?;
+        SymbolTable::parse(self.buffer(), offset as usize, num_symbols).map(Some)
+    }
+
+    /// The name of `section`, including names longer than 8 bytes.
+    pub fn section_name<'b>(&self, section: &'b Section) -> Result<&'b str, ParseError>
+    where
+        'a: 'b,
+    {
+        section_name(section, self.symbol_table()?)
+    }
+}
@@ -0,0 +1,300 @@
+use super::{ExportTarget, LoadError};
+use crate::backend::{Backend, Memory};
+#[cfg(windows)]
+use crate::backend::WindowsBackend;
+#[cfg(not(windows))]
+use crate::backend::InMemoryBackend;
+use crate::pe_file::{split_forwarder, ImportName};
+use crate::registry::ModuleRegistry;
+use crate::resolver::ImportResolver;
+use crate::structs::Machine;
+#[cfg(windows)]
+use crate::resolver::WindowsResolver;
+#[cfg(not(windows))]
+use crate::resolver::NoImports;
+
+// Forwarders can form cycles, give up after this many.
+const MAX_FORWARDER_DEPTH: usize = 16;
+
+/// An imported function, either by name or by ordinal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ImportSymbol {
+    Name(String),
+    Ordinal(u16),
+}
+
+impl ImportSymbol {
+    pub fn matches(&self, name: &ImportName) -> bool {
+        match (self, name) {
+            (ImportSymbol::Name(a), &ImportName::Name { name: b, .. }) => a == b,
+            (ImportSymbol::Ordinal(a), &ImportName::Ordinal(b)) => *a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for ImportSymbol {
+    fn from(name: &'a str) -> Self {
+        ImportSymbol::Name(name.to_owned())
+    }
+}
+
+impl From<String> for ImportSymbol {
+    fn from(name: String) -> Self {
+        ImportSymbol::Name(name)
+    }
+}
+
+impl From<u16> for ImportSymbol {
+    fn from(ordinal: u16) -> Self {
+        ImportSymbol::Ordinal(ordinal)
+    }
+}
+
+impl<'a> From<ImportName<'a>> for ImportSymbol {
+    fn from(name: ImportName<'a>) -> Self {
+        match name {
+            ImportName::Name { name, .. } => ImportSymbol::Name(name.to_owned()),
+            ImportName::Ordinal(ordinal) => ImportSymbol::Ordinal(ordinal),
+        }
+    }
+}
+
+/// Where the loader asks the backend to map an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreferredBase {
+    /// The image base from the optional header, or anywhere else if that isn't available.
+    #[default]
+    Default,
+    /// The image base from the optional header, loading fails if that isn't available.
+    DefaultExact,
+    /// Exactly this address, loading fails if it isn't available.
+    Exact(u64),
+    /// This address, or anywhere else if it isn't available.
+    TryExact(u64),
+}
+
+impl PreferredBase {
+    /// The address to ask the backend for, given the image base of the image.
+    pub fn address(&self, image_base: u64) -> u64 {
+        match *self {
+            PreferredBase::Default | PreferredBase::DefaultExact => image_base,
+            PreferredBase::Exact(base) | PreferredBase::TryExact(base) => base,
+        }
+    }
+
+    /// Whether loading fails if the image can't be mapped at `address`.
+    pub fn is_exact(&self) -> bool {
+        match *self {
+            PreferredBase::DefaultExact | PreferredBase::Exact(_) => true,
+            PreferredBase::Default | PreferredBase::TryExact(_) => false,
+        }
+    }
+}
+
+struct ImportHook {
+    module: String,
+    symbol: ImportSymbol,
+    hook: Box<dyn Fn(u64) -> u64>,
+}
+
+/// A module opened while resolving imports.
+pub(crate) enum ModuleHandle {
+    /// Loaded through the `ModuleRegistry`, by its normalized name.
+    Registered(String),
+    /// Loaded by the `ImportResolver`.
+    Resolver(u64),
+}
+
+pub struct LoaderConfig {
+    import_hooks: Vec<ImportHook>,
+    pub(crate) backend: Box<dyn Backend>,
+    resolver: Box<dyn ImportResolver>,
+    registry: Option<ModuleRegistry>,
+    // Registered modules opened while resolving imports, by their normalized names.
+    pub(crate) dependencies: Vec<String>,
+    pub(crate) preferred_base: PreferredBase,
+    pub(crate) allow_il_only: bool,
+    pub(crate) refuse_invalid: bool,
+    allow_foreign_machine: bool,
+    pub(crate) ec_view: bool,
+}
+
+impl Default for LoaderConfig {
+    /// Maps images into the current process and resolves imports with the OS loader on Windows.
+    /// Elsewhere images are mapped into heap memory and every import fails to resolve.
+    fn default() -> Self {
+        #[cfg(windows)]
+        let (backend, resolver): (Box<dyn Backend>, Box<dyn ImportResolver>) =
+            (Box::new(WindowsBackend), Box::new(WindowsResolver));
+        #[cfg(not(windows))]
+        let (backend, resolver): (Box<dyn Backend>, Box<dyn ImportResolver>) =
+            (Box::new(InMemoryBackend::new()), Box::new(NoImports));
+
+        Self {
+            import_hooks: vec![],
+            backend,
+            resolver,
+            registry: None,
+            dependencies: vec![],
+            preferred_base: PreferredBase::default(),
+            allow_il_only: false,
+            refuse_invalid: false,
+            allow_foreign_machine: false,
+            ec_view: false,
+        }
+    }
+}
+
+impl LoaderConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backend<B: Backend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Box::new(backend);
+        self
+    }
+
+    pub fn resolver<R: ImportResolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    /// Resolves imports from the modules in `registry` before falling back to the resolver.
+    pub fn registry(mut self, registry: ModuleRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Where the image should be mapped, the default is its image base or anywhere else if that
+    /// isn't available.
+    pub fn preferred_base(mut self, preferred_base: PreferredBase) -> Self {
+        self.preferred_base = preferred_base;
+        self
+    }
+
+    /// Whether IL-only .NET assemblies may be loaded. They don't contain any code that could run
+    /// without the CLR, so by default loading them fails with `LoadError::IlOnlyImage`.
+    pub fn allow_il_only(mut self, allow: bool) -> Self {
+        self.allow_il_only = allow;
+        self
+    }
+
+    /// Whether loading fails with `LoadError::InvalidImage` if `PEFile::validate` reports any
+    /// findings. Off by default.
+    pub fn refuse_invalid(mut self, refuse: bool) -> Self {
+        self.refuse_invalid = refuse;
+        self
+    }
+
+    /// Whether images for another machine than the host may be mapped into this process. They
+    /// can't run there, so by default loading them fails with `LoadError::UnsupportedMachine`.
+    /// Backends that don't map into this process, like `InMemoryBackend`, accept any machine.
+    pub fn allow_foreign_machine(mut self, allow: bool) -> Self {
+        self.allow_foreign_machine = allow;
+        self
+    }
+
+    /// Whether ARM64X images are loaded as the ARM64EC view x64 processes see, with the ARM64X
+    /// dynamic relocations applied, rather than as the ARM64 view on disk. Off by default, other
+    /// images aren't affected. ARM64EC code needs the emulator of the OS to run, so images loaded
+    /// this way are never runnable.
+    pub fn ec_view(mut self, ec_view: bool) -> Self {
+        self.ec_view = ec_view;
+        self
+    }
+
+    /// Overrides what `symbol` from `module` resolves to.
+    ///
+    /// `hook` is called with the address the import was resolved to (zero if it couldn't be
+    /// found) and the returned address is written into the IAT instead. Module names are compared
+    /// case insensitively, like the Windows loader does.
+    pub fn hook_import<S, F>(mut self, module: &str, symbol: S, hook: F) -> Self
+    where
+        S: Into<ImportSymbol>,
+        F: Fn(u64) -> u64 + 'static,
+    {
+        self.import_hooks.push(ImportHook {
+            module: module.to_ascii_lowercase(),
+            symbol: symbol.into(),
+            hook: Box::new(hook),
+        });
+        self
+    }
+
+    /// Fails if code for `machine` was mapped into this process without matching the host.
+    pub(crate) fn check_machine(
+        &self,
+        machine: Machine,
+        memory: &dyn Memory,
+    ) -> Result<(), LoadError> {
+        if memory.is_native() && Machine::host() != Some(machine) && !self.allow_foreign_machine {
+            return Err(LoadError::UnsupportedMachine(machine));
+        }
+        Ok(())
+    }
+
+    /// Runs all hooks registered for the import, in the order they were registered.
+    pub(crate) fn apply_import_hooks(&self, module: &str, name: &ImportName, address: u64) -> u64 {
+        self.import_hooks
+            .iter()
+            .filter(|h| h.module.eq_ignore_ascii_case(module) && h.symbol.matches(name))
+            .fold(address, |address, h| (h.hook)(address))
+    }
+
+    pub(crate) fn open_module(&mut self, module: &str) -> Result<ModuleHandle, LoadError> {
+        if let Some(name) = self.registry.as_ref().and_then(|r| r.normalized(module)) {
+            if !self.dependencies.contains(&name) {
+                self.dependencies.push(name.clone());
+            }
+            return Ok(ModuleHandle::Registered(name));
+        }
+
+        self.resolver.load_module(module).map(ModuleHandle::Resolver)
+    }
+
+    /// Returns the address of `symbol`, following forwarders of registered modules. Zero if it
+    /// can't be found.
+    pub(crate) fn resolve_symbol(
+        &mut self,
+        module: &ModuleHandle,
+        symbol: &ImportName,
+    ) -> Result<u64, LoadError> {
+        let mut forwarder = match *module {
+            ModuleHandle::Resolver(handle) => return Ok(self.resolver.resolve(handle, symbol)),
+            ModuleHandle::Registered(ref name) => match self.registered_export(name, symbol) {
+                Some(ExportTarget::Address(address)) => return Ok(address),
+                Some(ExportTarget::Forwarder(forwarder)) => forwarder,
+                None => return Ok(0),
+            },
+        };
+
+        for _ in 0..MAX_FORWARDER_DEPTH {
+            let target = match split_forwarder(&forwarder) {
+                None => return Ok(0),
+                Some((module, symbol)) => match self.open_module(module)? {
+                    ModuleHandle::Resolver(handle) => {
+                        return Ok(self.resolver.resolve(handle, &symbol))
+                    }
+                    ModuleHandle::Registered(ref name) => self.registered_export(name, &symbol),
+                },
+            };
+
+            forwarder = match target {
+                Some(ExportTarget::Address(address)) => return Ok(address),
+                Some(ExportTarget::Forwarder(next)) => next,
+                None => return Ok(0),
+            };
+        }
+
+        Ok(0)
+    }
+
+    fn registered_export(&self, module: &str, symbol: &ImportName) -> Option<ExportTarget> {
+        self.registry
+            .as_ref()
+            .and_then(|r| r.export(module, symbol))
+    }
+}
@@ -0,0 +1,397 @@
+// Data directories the loader doesn't need itself: relocations as they are on disk, TLS, debug
+// information and resources. Like everything else on `PEFile` all reads are bounds checked.
+
+use std::collections::BTreeMap;
+use std::str;
+
+use crate::pe_file::{read_bytes, read_u16, read_u32, read_u64, PEFile, ParseError};
+use crate::structs::DirectoryEntry;
+
+const BASE_RELOCATION_SIZE: u32 = 8;
+const DEBUG_DIRECTORY_SIZE: usize = 28;
+const RESOURCE_DIRECTORY_SIZE: usize = 16;
+const RESOURCE_ENTRY_SIZE: usize = 8;
+const RESOURCE_DATA_ENTRY_SIZE: usize = 16;
+const RESOURCE_SUBDIRECTORY: u32 = 0x8000_0000;
+
+const CODEVIEW_RSDS: u32 = 0x5344_5352; // RSDS
+
+pub const DEBUG_TYPE_COFF: u32 = 1;
+pub const DEBUG_TYPE_CODEVIEW: u32 = 2;
+pub const DEBUG_TYPE_FPO: u32 = 3;
+pub const DEBUG_TYPE_MISC: u32 = 4;
+pub const DEBUG_TYPE_OMAP_TO_SRC: u32 = 7;
+pub const DEBUG_TYPE_OMAP_FROM_SRC: u32 = 8;
+pub const DEBUG_TYPE_BORLAND: u32 = 9;
+pub const DEBUG_TYPE_CLSID: u32 = 11;
+pub const DEBUG_TYPE_VC_FEATURE: u32 = 12;
+pub const DEBUG_TYPE_POGO: u32 = 13;
+pub const DEBUG_TYPE_ILTCG: u32 = 14;
+pub const DEBUG_TYPE_REPRO: u32 = 16;
+pub const DEBUG_TYPE_EX_DLL_CHARACTERISTICS: u32 = 20;
+
+/// A single base relocation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Relocation {
+    pub rva: u32,
+    /// The type in the upper four bits of the entry, e.g. 3 for `HIGHLOW` or 10 for `DIR64`.
+    pub kind: u16,
+}
+
+/// The relocations of one page.
+#[derive(Clone, Copy)]
+pub struct RelocationBlock<'a> {
+    pub page_rva: u32,
+    entries: &'a [u8],
+}
+
+impl<'a> RelocationBlock<'a> {
+    /// All entries of the block, including the `ABSOLUTE` ones used as padding.
+    pub fn relocations(&self) -> impl Iterator<Item = Relocation> + 'a {
+        let page_rva = self.page_rva;
+        self.entries.chunks(2).map(move |entry| {
+            let entry = u16::from_le_bytes([entry[0], entry[1]]);
+            Relocation {
+                rva: page_rva.wrapping_add((entry & 0xfff) as u32),
+                kind: entry >> 12,
+            }
+        })
+    }
+}
+
+pub struct RelocationBlocks<'a> {
+    file: PEFile<'a>,
+    rva: u32,
+    end: u64,
+}
+
+impl<'a> RelocationBlocks<'a> {
+    fn next_block(&mut self) -> Result<Option<RelocationBlock<'a>>, ParseError> {
+        if self.rva as u64 + BASE_RELOCATION_SIZE as u64 > self.end {
+            return Ok(None);
+        }
+
+        let header = self.file.read_rva(self.rva, BASE_RELOCATION_SIZE as usize)?;
+        let page_rva = read_u32(header, 0)?;
+        let size = read_u32(header, 4)?;
+        // Blocks start on a 32 bit boundary.
+        if size < BASE_RELOCATION_SIZE
+            || size % 4 != 0
+            || self.rva as u64 + size as u64 > self.end
+        {
+            return Err(ParseError::InvalidRva(self.rva));
+        }
+
+        let block = self.file.read_rva(self.rva, size as usize)?;
+        self.rva = self.rva.checked_add(size).ok_or(ParseError::InvalidRva(self.rva))?;
+        Ok(Some(RelocationBlock {
+            page_rva,
+            entries: &block[BASE_RELOCATION_SIZE as usize..],
+        }))
+    }
+}
+
+impl<'a> Iterator for RelocationBlocks<'a> {
+    type Item = Result<RelocationBlock<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_block() {
+            Ok(block) => block.map(Ok),
+            Err(e) => {
+                self.end = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// The TLS directory. Everything but the sizes is a VA, i.e. relative to the preferred base.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Tls {
+    pub start_address_of_raw_data: u64,
+    pub end_address_of_raw_data: u64,
+    pub address_of_index: u64,
+    pub address_of_callbacks: u64,
+    pub size_of_zero_fill: u32,
+    pub characteristics: u32,
+    pub callbacks: Vec<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DebugDirectory {
+    pub characteristics: u32,
+    pub time_date_stamp: u32,
+    pub major_version: u16,
+    pub minor_version: u16,
+    /// One of the `DEBUG_TYPE_*` constants.
+    pub kind: u32,
+    pub size_of_data: u32,
+    /// Zero if the data isn't mapped.
+    pub address_of_raw_data: u32,
+    pub pointer_to_raw_data: u32,
+}
+
+/// The PDB a `DEBUG_TYPE_CODEVIEW` entry refers to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CodeView<'a> {
+    /// As it is stored in the file, i.e. the first three fields are little endian.
+    pub guid: [u8; 16],
+    pub age: u32,
+    pub path: &'a str,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ResourceId {
+    Id(u16),
+    Name(String),
+}
+
+/// A leaf of the resource tree, which is always type / name / language.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Resource {
+    pub kind: ResourceId,
+    pub name: ResourceId,
+    pub language: ResourceId,
+    pub rva: u32,
+    pub size: u32,
+    pub code_page: u32,
+}
+
+impl<'a> PEFile<'a> {
+    /// Iterates over the base relocation blocks. Empty if the image doesn't have relocations.
+    pub fn relocations(&self) -> Result<RelocationBlocks<'a>, ParseError> {
+        let directory = self.data_directory(DirectoryEntry::Basereloc)?;
+        Ok(RelocationBlocks {
+            file: *self,
+            rva: directory.map_or(0, |d| d.virtual_address),
+            end: directory.map_or(0, |d| d.virtual_address as u64 + d.size as u64),
+        })
+    }
+
+    pub fn tls(&self) -> Result<Option<Tls>, ParseError> {
+        let directory = match self.data_directory(DirectoryEntry::Tls)? {
+            None => return Ok(None),
+            Some(directory) => directory,
+        };
+
+        let pointer = self.thunk_size();
+        let tls = self.read_rva(directory.virtual_address, 4 * pointer + 8)?;
+        let read_pointer = |offset: usize| {
+            if self.is_64_bit() {
+                read_u64(tls, offset * pointer)
+            } else {
+                read_u32(tls, offset * pointer).map(|p| p as u64)
+            }
+        };
+
+        let mut tls = Tls {
+            start_address_of_raw_data: read_pointer(0)?,
+            end_address_of_raw_data: read_pointer(1)?,
+            address_of_index: read_pointer(2)?,
+            address_of_callbacks: read_pointer(3)?,
+            size_of_zero_fill: read_u32(tls, 4 * pointer)?,
+            characteristics: read_u32(tls, 4 * pointer + 4)?,
+            callbacks: vec![],
+        };
+
+        if tls.address_of_callbacks != 0 {
+            let mut va = tls.address_of_callbacks;
+            loop {
+                let rva = va.wrapping_sub(self.image_base());
+                if rva > u32::MAX as u64 {
+                    return Err(ParseError::OutOfBounds);
+                }
+                // Past the raw data of a section the array reads as zeros once mapped, which ends
+                // it like a null entry.
+                let rva = rva as u32;
+                let zero_filled = self
+                    .sections()
+                    .any(|s| s.contains_rva(rva) && rva - s.virtual_address >= s.size_of_raw_data);
+                if zero_filled {
+                    break;
+                }
+                match self.read_rva_thunk(rva)? {
+                    0 => break,
+                    callback => tls.callbacks.push(callback),
+                }
+                va = va.wrapping_add(pointer as u64);
+            }
+        }
+
+        Ok(Some(tls))
+    }
+
+    pub fn debug_directories(&self) -> Result<Vec<DebugDirectory>, ParseError> {
+        let directory = match self.data_directory(DirectoryEntry::Debug)? {
+            None => return Ok(vec![]),
+            Some(directory) => directory,
+        };
+
+        let count = directory.size as usize / DEBUG_DIRECTORY_SIZE;
+        let entries = self.read_rva(directory.virtual_address, count * DEBUG_DIRECTORY_SIZE)?;
+        entries
+            .chunks(DEBUG_DIRECTORY_SIZE)
+            .map(|entry| {
+                Ok(DebugDirectory {
+                    characteristics: read_u32(entry, 0)?,
+                    time_date_stamp: read_u32(entry, 4)?,
+                    major_version: read_u16(entry, 8)?,
+                    minor_version: read_u16(entry, 10)?,
+                    kind: read_u32(entry, 12)?,
+                    size_of_data: read_u32(entry, 16)?,
+                    address_of_raw_data: read_u32(entry, 20)?,
+                    pointer_to_raw_data: read_u32(entry, 24)?,
+                })
+            })
+            .collect()
+    }
+
+    /// The data of a debug directory entry, which doesn't have to be part of any section.
+    pub fn debug_data(&self, entry: &DebugDirectory) -> Result<&'a [u8], ParseError> {
+        read_bytes(
+            self.buffer(),
+            entry.pointer_to_raw_data as usize,
+            entry.size_of_data as usize,
+        )
+    }
+
+    /// The PDB information of the first CodeView entry in the RSDS format, if there is one.
+    pub fn codeview(&self) -> Result<Option<CodeView<'a>>, ParseError> {
+        for entry in self.debug_directories()? {
+            if entry.kind != DEBUG_TYPE_CODEVIEW {
+                continue;
+            }
+
+            let data = self.debug_data(&entry)?;
+            if read_u32(data, 0)? != CODEVIEW_RSDS {
+                continue;
+            }
+
+            let mut guid = [0u8; 16];
+            guid.copy_from_slice(read_bytes(data, 4, 16)?);
+            let path = &data[24.min(data.len())..];
+            let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+            return Ok(Some(CodeView {
+                guid,
+                age: read_u32(data, 20)?,
+                path: str::from_utf8(&path[..len]).map_err(|_| ParseError::InvalidName)?,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// Flattens the resource tree. Directories may not overlap each other, which rejects loops and
+    /// limits the entries walked to what fits in the file.
+    pub fn resources(&self) -> Result<Vec<Resource>, ParseError> {
+        let directory = match self.data_directory(DirectoryEntry::Resource)? {
+            None => return Ok(vec![]),
+            Some(directory) => directory,
+        };
+
+        let walker = ResourceWalker {
+            file: *self,
+            base: directory.virtual_address,
+        };
+        let mut visited = BTreeMap::new();
+        let mut resources = vec![];
+
+        for (kind, types) in walker.directory(0, &mut visited)? {
+            let types = walker.subdirectory(types)?;
+            for (name, names) in walker.directory(types, &mut visited)? {
+                let names = walker.subdirectory(names)?;
+                for (language, data) in walker.directory(names, &mut visited)? {
+                    if data & RESOURCE_SUBDIRECTORY != 0 {
+                        return Err(ParseError::InvalidRva(walker.rva(data)?));
+                    }
+
+                    let entry = walker.read(data, RESOURCE_DATA_ENTRY_SIZE)?;
+                    resources.push(Resource {
+                        kind: kind.clone(),
+                        name: name.clone(),
+                        language,
+                        rva: read_u32(entry, 0)?,
+                        size: read_u32(entry, 4)?,
+                        code_page: read_u32(entry, 8)?,
+                    });
+                }
+            }
+        }
+
+        Ok(resources)
+    }
+}
+
+// Offsets in the resource tree are relative to the start of the directory.
+struct ResourceWalker<'a> {
+    file: PEFile<'a>,
+    base: u32,
+}
+
+impl<'a> ResourceWalker<'a> {
+    fn rva(&self, offset: u32) -> Result<u32, ParseError> {
+        self.base
+            .checked_add(offset & !RESOURCE_SUBDIRECTORY)
+            .ok_or(ParseError::InvalidRva(offset))
+    }
+
+    fn read(&self, offset: u32, len: usize) -> Result<&'a [u8], ParseError> {
+        self.file.read_rva(self.rva(offset)?, len)
+    }
+
+    fn subdirectory(&self, offset: u32) -> Result<u32, ParseError> {
+        if offset & RESOURCE_SUBDIRECTORY == 0 {
+            return Err(ParseError::InvalidRva(self.rva(offset)?));
+        }
+        Ok(offset & !RESOURCE_SUBDIRECTORY)
+    }
+
+    fn id(&self, id: u32) -> Result<ResourceId, ParseError> {
+        if id & RESOURCE_SUBDIRECTORY == 0 {
+            return Ok(ResourceId::Id(id as u16));
+        }
+
+        // A length prefixed UTF-16 string.
+        let len = read_u16(self.read(id, 2)?, 0)? as usize;
+        let name = self.read(id, 2 + len * 2)?[2..]
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>();
+        Ok(ResourceId::Name(String::from_utf16_lossy(&name)))
+    }
+
+    // The entries of the directory at `offset` with the offsets they point to. `visited` maps the
+    // file offset of every directory walked so far to its end, so sections sharing their raw data
+    // can't be used to walk the same bytes twice either.
+    fn directory(
+        &self,
+        offset: u32,
+        visited: &mut BTreeMap<usize, usize>,
+    ) -> Result<Vec<(ResourceId, u32)>, ParseError> {
+        let header = self.read(offset, RESOURCE_DIRECTORY_SIZE)?;
+        let count = read_u16(header, 12)? as usize + read_u16(header, 14)? as usize;
+        let entries_offset = offset
+            .checked_add(RESOURCE_DIRECTORY_SIZE as u32)
+            .ok_or(ParseError::OutOfBounds)?;
+        let entries = self.read(entries_offset, count * RESOURCE_ENTRY_SIZE)?;
+
+        let rva = self.rva(offset)?;
+        let start = self.file.rva_to_offset(rva).ok_or(ParseError::InvalidRva(rva))?;
+        let end = start + RESOURCE_DIRECTORY_SIZE + entries.len();
+        let before = visited.range(..end).next_back();
+        if before.is_some_and(|(_, &before_end)| before_end > start) {
+            return Err(ParseError::InvalidRva(rva));
+        }
+        visited.insert(start, end);
+
+        entries
+            .chunks(RESOURCE_ENTRY_SIZE)
+            .map(|entry| Ok((self.id(read_u32(entry, 0)?)?, read_u32(entry, 4)?)))
+            .collect()
+    }
+}
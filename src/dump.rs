@@ -0,0 +1,228 @@
+// Turns a mapped image back into a PE file.
+//
+// In the mapped image RVAs are offsets, so everything in here indexes the image directly. Only the
+// headers are read through `PEFile`.
+
+use super::LoadError;
+use crate::pe_file::{
+    read_u16, read_u32, read_u64, update_checksum, write_u32, write_u64, PEFile, ParseError,
+    Section,
+};
+use crate::structs::{DirectoryEntry, RelocationType};
+
+const IMPORT_DESCRIPTOR_SIZE: usize = 20;
+const DEBUG_DIRECTORY_SIZE: usize = 28;
+const BASE_RELOCATION_SIZE: usize = 8;
+const DEFAULT_FILE_ALIGNMENT: u32 = 0x200;
+const MAX_FILE_ALIGNMENT: u32 = 0x10000;
+
+const RELOCATION_ABSOLUTE: u16 = 0;
+const RELOCATION_HIGH_LOW: u16 = 3;
+const RELOCATION_DIR64: u16 = 10;
+
+/// What `LoadedPEFile::dump_with_options` does besides realigning the sections.
+#[derive(Clone, Debug, Default)]
+pub struct DumpOptions {
+    rebase: bool,
+    rebuild_imports: bool,
+}
+
+impl DumpOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the relocations again so the dump is based at the preferred image base. Images
+    /// without relocations keep the address they were loaded at.
+    pub fn rebase(mut self, rebase: bool) -> Self {
+        self.rebase = rebase;
+        self
+    }
+
+    /// Restores the IAT to the import lookup values the loader resolved it from, so the dump can
+    /// be loaded again. Otherwise the IAT contains the resolved addresses.
+    pub fn rebuild_imports(mut self, rebuild_imports: bool) -> Self {
+        self.rebuild_imports = rebuild_imports;
+        self
+    }
+}
+
+fn align_up(value: u32, alignment: u32) -> Result<u32, ParseError> {
+    value
+        .checked_add(alignment - 1)
+        .map(|v| v & !(alignment - 1))
+        .ok_or(ParseError::OutOfBounds)
+}
+
+fn relocate(image: &mut [u8], rva: u32, size: u32, delta: u64) -> Result<(), LoadError> {
+    let mut block = rva as usize;
+    let end = rva as usize + size as usize;
+
+    while block + BASE_RELOCATION_SIZE <= end {
+        let page = read_u32(image, block)? as usize;
+        let size_of_block = read_u32(image, block + 4)? as usize;
+        if size_of_block < BASE_RELOCATION_SIZE {
+            break;
+        }
+
+        let entries = (size_of_block - BASE_RELOCATION_SIZE) / 2;
+        for i in 0..entries {
+            let entry = read_u16(image, block + BASE_RELOCATION_SIZE + i * 2)?;
+            let address = page + (entry & 0xfff) as usize;
+
+            match entry >> 12 {
+                RELOCATION_ABSOLUTE => {}
+                RELOCATION_HIGH_LOW => {
+                    let value = read_u32(image, address)?.wrapping_add(delta as u32);
+                    write_u32(image, address, value)?;
+                }
+                RELOCATION_DIR64 => {
+                    let value = read_u64(image, address)?.wrapping_add(delta);
+                    write_u64(image, address, value)?;
+                }
+                t => return Err(LoadError::UnsupporrtedRelocationType(RelocationType(t))),
+            }
+        }
+
+        block += size_of_block;
+    }
+
+    Ok(())
+}
+
+// Marks every import descriptor as not bound, the IAT now contains lookup values again.
+fn unbind_imports(image: &mut [u8], rva: u32) -> Result<(), ParseError> {
+    let mut descriptor = rva as usize;
+    while read_u32(image, descriptor + 12)? != 0 {
+        write_u32(image, descriptor + 4, 0)?;
+        write_u32(image, descriptor + 8, 0)?;
+        descriptor += IMPORT_DESCRIPTOR_SIZE;
+    }
+    Ok(())
+}
+
+/// Reconstructs a PE file from `image`, which was mapped at `base`. `imports` are the RVAs of the
+/// IAT slots with the lookup values they were resolved from.
+pub(crate) fn dump(
+    image: &[u8],
+    base: u64,
+    imports: &[(u32, u64)],
+    options: &DumpOptions,
+) -> Result<Vec<u8>, LoadError> {
+    let mut image = image.to_vec();
+
+    let file = PEFile::parse(&image)?;
+    let is_64_bit = file.is_64_bit();
+    let preferred_base = file.image_base();
+    let size_of_headers = file.size_of_headers();
+    let file_alignment = match file.file_alignment() {
+        alignment if alignment.is_power_of_two() && alignment <= MAX_FILE_ALIGNMENT => alignment,
+        _ => DEFAULT_FILE_ALIGNMENT,
+    };
+    let file_header = file.file_header_offset();
+    let optional_header = file.optional_header_offset();
+    let sections = file.sections().collect::<Vec<Section>>();
+    let section_headers = (0..sections.len())
+        .map(|i| file.section_header_offset(i))
+        .collect::<Vec<_>>();
+    let relocations = file.data_directory(DirectoryEntry::Basereloc)?;
+    let import_directory = file.data_directory(DirectoryEntry::Import)?;
+    let debug = file.data_directory(DirectoryEntry::Debug)?;
+    let cleared_directories = [DirectoryEntry::Security, DirectoryEntry::BoundImport]
+        .iter()
+        .filter(|&&e| (e as u32) < file.num_of_rva_and_sizes())
+        .map(|&e| file.data_directory_offset(e))
+        .collect::<Vec<_>>();
+
+    let mut image_base = base;
+    if let (true, Some(relocations)) = (options.rebase, relocations) {
+        relocate(
+            &mut image,
+            relocations.virtual_address,
+            relocations.size,
+            preferred_base.wrapping_sub(base),
+        )?;
+        image_base = preferred_base;
+    }
+
+    if options.rebuild_imports {
+        for &(slot, thunk) in imports {
+            if is_64_bit {
+                write_u64(&mut image, slot as usize, thunk)?;
+            } else {
+                write_u32(&mut image, slot as usize, thunk as u32)?;
+            }
+        }
+        if let Some(directory) = import_directory {
+            unbind_imports(&mut image, directory.virtual_address)?;
+        }
+    }
+
+    if is_64_bit {
+        write_u64(&mut image, optional_header + 24, image_base)?;
+    } else {
+        write_u32(&mut image, optional_header + 28, image_base as u32)?;
+    }
+
+    // Neither the certificates nor the COFF symbols are part of the image.
+    for &offset in &cleared_directories {
+        write_u64(&mut image, offset, 0)?;
+    }
+    write_u64(&mut image, file_header + 8, 0)?;
+
+    // Lay the sections out one after the other, each taking up as much space as it does in memory.
+    // Nothing past the end of the image was mapped, so there is nothing to write for it either.
+    let size_of_headers = size_of_headers.min(image.len() as u32);
+    let headers_size = align_up(size_of_headers, file_alignment)?;
+    let mut file_size = headers_size;
+    let mut layout = vec![];
+    for section in &sections {
+        let remaining = (image.len() as u64).saturating_sub(section.virtual_address as u64);
+        let size = match section.virtual_address {
+            0 => 0,
+            _ => align_up((section.mapped_size() as u64).min(remaining) as u32, file_alignment)?,
+        };
+        let offset = if size == 0 { 0 } else { file_size };
+        layout.push((offset, size));
+        file_size = file_size.checked_add(size).ok_or(ParseError::OutOfBounds)?;
+    }
+
+    write_u32(&mut image, optional_header + 60, headers_size)?;
+    for (&header, &(offset, size)) in section_headers.iter().zip(&layout) {
+        write_u32(&mut image, header + 16, size)?;
+        write_u32(&mut image, header + 20, offset)?;
+    }
+
+    let rva_to_offset = |rva: u32| {
+        sections
+            .iter()
+            .zip(&layout)
+            .find(|&(s, &(_, size))| rva >= s.virtual_address && rva - s.virtual_address < size)
+            .map(|(s, &(offset, _))| offset + rva - s.virtual_address)
+    };
+    if let Some(debug) = debug {
+        for i in 0..debug.size as usize / DEBUG_DIRECTORY_SIZE {
+            let entry = debug.virtual_address as usize + i * DEBUG_DIRECTORY_SIZE;
+            let rva = read_u32(&image, entry + 20)?;
+            let offset = if rva == 0 {
+                0
+            } else {
+                rva_to_offset(rva).unwrap_or(0)
+            };
+            write_u32(&mut image, entry + 24, offset)?;
+        }
+    }
+
+    let mut pe = vec![0u8; file_size as usize];
+    let headers = size_of_headers as usize;
+    pe[..headers].copy_from_slice(&image[..headers]);
+    for (section, &(offset, size)) in sections.iter().zip(&layout) {
+        let start = (section.virtual_address as usize).min(image.len());
+        let end = (start + size as usize).min(image.len());
+        let offset = offset as usize;
+        pe[offset..offset + end - start].copy_from_slice(&image[start..end]);
+    }
+    update_checksum(&mut pe)?;
+
+    Ok(pe)
+}
@@ -0,0 +1,108 @@
+// Fingerprints commonly used to cluster and look up samples: the imphash, hashes and entropy of
+// the sections and the Rich header hash. Hashes are returned as lowercase hex strings, the way
+// they are usually shared.
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::ordinals::ordinal_name;
+use crate::pe_file::{ImportName, PEFile, ParseError, Section};
+use crate::rich::RichHeader;
+
+/// Hashes and entropy of the raw data of a section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectionFingerprint {
+    pub section: Section,
+    pub md5: String,
+    pub sha256: String,
+    pub entropy: f64,
+}
+
+/// Shannon entropy in bits per byte, from 0 for empty or constant data to 8 for random data.
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    counts
+        .iter()
+        .filter(|&&c| c != 0)
+        .map(|&c| {
+            let p = c as f64 / data.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn hex<D: Digest>(data: &[u8]) -> String {
+    D::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl RichHeader {
+    /// MD5 of the decoded header, see `decoded`.
+    pub fn hash(&self) -> String {
+        hex::<Md5>(&self.decoded())
+    }
+}
+
+impl<'a> PEFile<'a> {
+    /// The import hash as computed by pefile: the MD5 of the comma separated `module.function`
+    /// pairs, lowercased, with `.dll`, `.ocx` and `.sys` dropped from the module names. Functions
+    /// imported by ordinal are looked up with `ordinal_name` or else written as `ord<n>`. `None`
+    /// if there are no imports.
+    pub fn imphash(&self) -> Result<Option<String>, ParseError> {
+        let mut imports = vec![];
+        for module in self.imports()? {
+            let module = module?;
+            let name = module.name.to_ascii_lowercase();
+            let library = match name.rfind('.') {
+                Some(dot) if ["dll", "ocx", "sys"].contains(&&name[dot + 1..]) => &name[..dot],
+                _ => &name[..],
+            };
+
+            for entry in module.entries() {
+                let function = match entry?.name {
+                    ImportName::Name { name, .. } => name.to_ascii_lowercase(),
+                    ImportName::Ordinal(ordinal) => match ordinal_name(&name, ordinal) {
+                        Some(name) => name.to_ascii_lowercase(),
+                        None => format!("ord{}", ordinal),
+                    },
+                };
+                imports.push(format!("{}.{}", library, function));
+            }
+        }
+
+        if imports.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(hex::<Md5>(imports.join(",").as_bytes())))
+        }
+    }
+
+    /// The raw data of `section`, cut off at the end of the file.
+    pub fn section_data(&self, section: &Section) -> &'a [u8] {
+        let buffer = self.buffer();
+        let start = (section.p_raw_data as usize).min(buffer.len());
+        let end = start.saturating_add(section.size_of_raw_data as usize).min(buffer.len());
+        &buffer[start..end]
+    }
+
+    /// Fingerprints of the raw data of every section.
+    pub fn section_fingerprints(&self) -> Vec<SectionFingerprint> {
+        self.sections()
+            .map(|section| {
+                let data = self.section_data(&section);
+                SectionFingerprint {
+                    section,
+                    md5: hex::<Md5>(data),
+                    sha256: hex::<Sha256>(data),
+                    entropy: entropy(data),
+                }
+            })
+            .collect()
+    }
+}
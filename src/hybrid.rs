@@ -0,0 +1,304 @@
+// ARM64EC and ARM64X hybrid images.
+//
+// ARM64EC code runs next to emulated x64 code in the same process. Images containing it have CHPE
+// (compiled hybrid PE) metadata, which the load config points to. Its code map says which ranges
+// of the image hold ARM64, ARM64EC or x64 code.
+//
+// An ARM64X image is an ARM64 and an ARM64EC image in one file. On disk it is the ARM64 view that
+// native ARM64 processes load. The ARM64EC view, which x64 processes load, is derived from it by
+// the ARM64X dynamic relocations. They patch the machine, the data directories and whatever else
+// differs between the two, e.g. to point at a second export table. The fixups come in blocks like
+// base relocations, but every 16 bit record is
+//
+//     offset (12 bits), type (2 bits), argument (2 bits), data
+//
+// A zero fill clears 2^argument bytes, a value copies that many bytes of data following the
+// record. A delta adds a 16 bit value times 8 if bit 1 of the argument is set, times 4 otherwise,
+// to the 32 bit value at the offset, and subtracts it instead if bit 0 is set.
+
+use crate::load_config::DYNAMIC_RELOCATION_ARM64X;
+use crate::pe_file::{read_bytes, read_u16, read_u32, write_bytes, write_u32, PEFile, ParseError};
+use crate::structs::Machine;
+
+const CHPE_METADATA_SIZE: usize = 80;
+const CHPE_METADATA_V2_SIZE: usize = 92;
+const CODE_RANGE_SIZE: usize = 8;
+const FIXUP_BLOCK_SIZE: usize = 8;
+
+const FIXUP_ZERO_FILL: u16 = 0;
+const FIXUP_VALUE: u16 = 1;
+const FIXUP_DELTA: u16 = 2;
+
+/// IMAGE_ARM64EC_METADATA. Everything but the version, counts and sizes is an RVA.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChpeMetadata {
+    pub version: u32,
+    pub code_map: u32,
+    pub code_map_count: u32,
+    pub code_ranges_to_entry_points: u32,
+    pub redirection_metadata: u32,
+    pub dispatch_call_no_redirect: u32,
+    pub dispatch_ret: u32,
+    pub dispatch_call: u32,
+    pub dispatch_icall: u32,
+    pub dispatch_icall_cfg: u32,
+    pub alternate_entry_point: u32,
+    pub auxiliary_iat: u32,
+    pub code_ranges_to_entry_points_count: u32,
+    pub redirection_metadata_count: u32,
+    pub get_x64_information_function_pointer: u32,
+    pub set_x64_information_function_pointer: u32,
+    pub extra_rfe_table: u32,
+    pub extra_rfe_table_size: u32,
+    pub dispatch_fptr: u32,
+    pub auxiliary_iat_copy: u32,
+    /// Only version 2 and later have the last three fields, they are zero otherwise.
+    pub auxiliary_delayload_iat: u32,
+    pub auxiliary_delayload_iat_copy: u32,
+    pub hybrid_image_info: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CodeRangeKind {
+    Arm64,
+    Arm64EC,
+    Amd64,
+}
+
+/// An entry of the code map of the CHPE metadata.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CodeRange {
+    pub start: u32,
+    pub length: u32,
+    pub kind: CodeRangeKind,
+}
+
+impl CodeRange {
+    pub fn contains(&self, rva: u32) -> bool {
+        rva >= self.start && ((rva - self.start) as u64) < self.length as u64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arm64XFixup<'a> {
+    /// Clears that many bytes.
+    ZeroFill(usize),
+    /// Overwrites the bytes with these.
+    Value(&'a [u8]),
+    /// Adds to the 32 bit value.
+    Delta(i32),
+}
+
+/// A fixup the ARM64EC view of an ARM64X image applies to the ARM64 one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arm64XRelocation<'a> {
+    pub rva: u32,
+    pub fixup: Arm64XFixup<'a>,
+}
+
+impl<'a> Arm64XRelocation<'a> {
+    /// The number of bytes the fixup changes.
+    pub fn size(&self) -> usize {
+        match self.fixup {
+            Arm64XFixup::ZeroFill(size) => size,
+            Arm64XFixup::Value(data) => data.len(),
+            Arm64XFixup::Delta(_) => 4,
+        }
+    }
+}
+
+fn parse_fixups<'a>(
+    fixups: &'a [u8],
+    relocations: &mut Vec<Arm64XRelocation<'a>>,
+) -> Result<(), ParseError> {
+    let mut offset = 0;
+    while offset < fixups.len() {
+        let page_rva = read_u32(fixups, offset)?;
+        let size = read_u32(fixups, offset + 4)? as usize;
+        if size < FIXUP_BLOCK_SIZE {
+            return Err(ParseError::InvalidLoadConfig);
+        }
+        let block = read_bytes(fixups, offset, size).map_err(|_| ParseError::InvalidLoadConfig)?;
+        offset += size;
+
+        let mut record = FIXUP_BLOCK_SIZE;
+        while record + 2 <= block.len() {
+            let entry = read_u16(block, record)?;
+            record += 2;
+            // Blocks are padded to 32 bits with empty records.
+            if entry == 0 {
+                break;
+            }
+
+            let rva = page_rva.wrapping_add((entry & 0xfff) as u32);
+            let argument = entry >> 14;
+            let fixup = match (entry >> 12) & 3 {
+                FIXUP_ZERO_FILL => Arm64XFixup::ZeroFill(1 << argument),
+                FIXUP_VALUE => {
+                    let data = read_bytes(block, record, 1 << argument)
+                        .map_err(|_| ParseError::InvalidLoadConfig)?;
+                    // Single byte values are padded to a whole record.
+                    record += (data.len() + 1) & !1;
+                    Arm64XFixup::Value(data)
+                }
+                FIXUP_DELTA => {
+                    let value =
+                        read_u16(block, record).map_err(|_| ParseError::InvalidLoadConfig)? as i32;
+                    record += 2;
+                    let value = value * if argument & 2 != 0 { 8 } else { 4 };
+                    Arm64XFixup::Delta(if argument & 1 != 0 { -value } else { value })
+                }
+                _ => return Err(ParseError::InvalidLoadConfig),
+            };
+            relocations.push(Arm64XRelocation { rva, fixup });
+        }
+    }
+    Ok(())
+}
+
+impl<'a> PEFile<'a> {
+    /// The CHPE metadata of ARM64EC and ARM64X images. `None` for anything else, including the
+    /// x86 CHPE images of older Windows versions, whose metadata looks different.
+    pub fn chpe_metadata(&self) -> Result<Option<ChpeMetadata>, ParseError> {
+        if !self.is_64_bit() || !matches!(self.machine(), Machine::ARM64 | Machine::AMD64) {
+            return Ok(None);
+        }
+        let pointer = match self.load_config()? {
+            Some(ref config) if config.chpe_metadata_pointer != 0 => config.chpe_metadata_pointer,
+            _ => return Ok(None),
+        };
+        let rva = pointer
+            .checked_sub(self.image_base())
+            .filter(|&rva| rva <= u32::MAX as u64)
+            .ok_or(ParseError::InvalidLoadConfig)? as u32;
+
+        let m = self.read_rva(rva, CHPE_METADATA_SIZE)?;
+        let field = |index: usize| read_u32(m, index * 4);
+        let version = field(0)?;
+        let v2 = if version >= 2 {
+            self.read_rva(rva, CHPE_METADATA_V2_SIZE)?
+        } else {
+            &[0; CHPE_METADATA_V2_SIZE]
+        };
+        let v2_field = |index: usize| read_u32(v2, index * 4);
+
+        Ok(Some(ChpeMetadata {
+            version,
+            code_map: field(1)?,
+            code_map_count: field(2)?,
+            code_ranges_to_entry_points: field(3)?,
+            redirection_metadata: field(4)?,
+            dispatch_call_no_redirect: field(5)?,
+            dispatch_ret: field(6)?,
+            dispatch_call: field(7)?,
+            dispatch_icall: field(8)?,
+            dispatch_icall_cfg: field(9)?,
+            alternate_entry_point: field(10)?,
+            auxiliary_iat: field(11)?,
+            code_ranges_to_entry_points_count: field(12)?,
+            redirection_metadata_count: field(13)?,
+            get_x64_information_function_pointer: field(14)?,
+            set_x64_information_function_pointer: field(15)?,
+            extra_rfe_table: field(16)?,
+            extra_rfe_table_size: field(17)?,
+            dispatch_fptr: field(18)?,
+            auxiliary_iat_copy: field(19)?,
+            auxiliary_delayload_iat: v2_field(20)?,
+            auxiliary_delayload_iat_copy: v2_field(21)?,
+            hybrid_image_info: v2_field(22)?,
+        }))
+    }
+
+    /// `Machine::ARM64X` for the ARM64 view of an ARM64X image, `Machine::ARM64EC` for ARM64EC
+    /// images and the ARM64EC view of ARM64X images. `None` if the image isn't a hybrid.
+    pub fn hybrid_machine(&self) -> Result<Option<Machine>, ParseError> {
+        if self.chpe_metadata()?.is_none() {
+            return Ok(None);
+        }
+        Ok(match self.machine() {
+            Machine::ARM64 => Some(Machine::ARM64X),
+            _ => Some(Machine::ARM64EC),
+        })
+    }
+
+    /// The code map of the CHPE metadata, empty if the image isn't a hybrid.
+    pub fn code_ranges(&self) -> Result<Vec<CodeRange>, ParseError> {
+        let metadata = match self.chpe_metadata()? {
+            None => return Ok(vec![]),
+            Some(metadata) => metadata,
+        };
+        let size = (metadata.code_map_count as usize)
+            .checked_mul(CODE_RANGE_SIZE)
+            .ok_or(ParseError::InvalidLoadConfig)?;
+        let map = self.read_rva(metadata.code_map, size)?;
+
+        map.chunks(CODE_RANGE_SIZE)
+            .map(|entry| {
+                let start = read_u32(entry, 0)?;
+                Ok(CodeRange {
+                    start: start & !3,
+                    length: read_u32(entry, 4)?,
+                    kind: match start & 3 {
+                        0 => CodeRangeKind::Arm64,
+                        1 => CodeRangeKind::Arm64EC,
+                        2 => CodeRangeKind::Amd64,
+                        _ => return Err(ParseError::InvalidLoadConfig),
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// The ARM64X dynamic relocations in the order they are applied, empty if the image has none.
+    pub fn arm64x_relocations(&self) -> Result<Vec<Arm64XRelocation<'a>>, ParseError> {
+        let mut relocations = vec![];
+        for relocation in self.dynamic_relocations()? {
+            if relocation.symbol == DYNAMIC_RELOCATION_ARM64X {
+                parse_fixups(relocation.fixups, &mut relocations)?;
+            }
+        }
+        Ok(relocations)
+    }
+
+    /// A copy of the file with the ARM64X dynamic relocations applied, i.e. the ARM64EC view of
+    /// an ARM64X image. Its exports, imports and everything else can be read by parsing it in
+    /// turn. `None` if the image isn't the ARM64 view of an ARM64X image.
+    ///
+    /// Fixups have to land in the headers or in the raw data of a section, and have to change the
+    /// machine.
+    pub fn ec_view(&self) -> Result<Option<Vec<u8>>, ParseError> {
+        if self.machine() != Machine::ARM64 {
+            return Ok(None);
+        }
+        let relocations = self.arm64x_relocations()?;
+        if relocations.is_empty() {
+            return Ok(None);
+        }
+
+        let mut view = self.buffer().to_vec();
+        for relocation in &relocations {
+            let offset = self
+                .rva_to_offset(relocation.rva)
+                .ok_or(ParseError::InvalidRva(relocation.rva))?;
+            let bytes = view
+                .get_mut(offset..)
+                .and_then(|rest| rest.get_mut(..relocation.size()))
+                .ok_or(ParseError::InvalidRva(relocation.rva))?;
+            match relocation.fixup {
+                Arm64XFixup::ZeroFill(_) => bytes.iter_mut().for_each(|b| *b = 0),
+                Arm64XFixup::Value(data) => write_bytes(bytes, 0, data)?,
+                Arm64XFixup::Delta(delta) => {
+                    let value = read_u32(bytes, 0)?.wrapping_add(delta as u32);
+                    write_u32(bytes, 0, value)?;
+                }
+            }
+        }
+        if PEFile::parse(&view)?.machine() == Machine::ARM64 {
+            return Err(ParseError::InvalidLoadConfig);
+        }
+        Ok(Some(view))
+    }
+}
@@ -1,79 +1,302 @@
-#![feature(conservative_impl_trait)]
+// The structures mirror the names used in winnt.h.
+#![allow(non_upper_case_globals, clippy::upper_case_acronyms)]
 
+#[macro_use]
+extern crate bitflags;
+#[cfg(windows)]
 extern crate kernel32;
+extern crate md5;
+extern crate memmap2;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+extern crate sha1;
+extern crate sha2;
+#[cfg(windows)]
 extern crate winapi;
 
+mod authenticode;
+mod backend;
+mod builder;
+mod clr;
+mod coff;
+mod config;
+mod directories;
+mod dump;
+mod fingerprint;
+mod hybrid;
+mod load_config;
+mod object;
+mod ordinals;
+mod pe_file;
+mod registry;
+mod resolver;
+mod rich;
+mod snapshot;
+mod source;
+mod validate;
 pub(crate) mod rva;
 mod structs;
 
-use std::borrow::Borrow;
+use std::io::{self, Read, Seek};
 use std::mem;
-use std::os::raw::{c_char, c_void};
+#[cfg(windows)]
+use std::os::raw::c_void;
+use std::path::Path;
 use std::ptr;
+use std::slice;
+
+use memmap2::Mmap;
+
+#[cfg(windows)]
+use winapi::HINSTANCE;
+
+pub use crate::authenticode::*;
+pub use crate::backend::*;
+pub use crate::builder::*;
+pub use crate::clr::*;
+pub use crate::coff::*;
+pub use crate::config::*;
+pub use crate::directories::*;
+pub use crate::dump::DumpOptions;
+pub use crate::fingerprint::*;
+pub use crate::hybrid::*;
+pub use crate::load_config::*;
+pub use crate::object::*;
+pub use crate::ordinals::ordinal_name;
+pub use crate::pe_file::*;
+pub use crate::registry::*;
+pub use crate::resolver::*;
+pub use crate::rich::{RichEntry, RichHeader};
+pub use crate::snapshot::*;
+use crate::rva::{Pointer, RVA};
+pub use crate::structs::{
+    DirectoryEntry, DllCharacteristics, DosHeader, FileCharacteristics, Machine,
+    OptionalHeaderMagic, SectionCharacteristics, Subsystem, DIRECTORIES,
+};
+pub use crate::validate::*;
+use crate::structs::*;
 
-use kernel32::{GetNativeSystemInfo, GetProcAddress, LoadLibraryA, VirtualAlloc, VirtualFree,
-               VirtualProtect};
-use winapi::{HINSTANCE, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE, PAGE_EXECUTE_READ,
-             PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_NOACCESS, PAGE_READONLY,
-             PAGE_READWRITE, PAGE_WRITECOPY};
+const DLL_PROCESS_ATTACH: u32 = 1;
 
-use structs::*;
+#[derive(Debug)]
+pub enum LoadError {
+    LoadLibraryFailed,
+    NoMemory,
+    UnsupporrtedRelocationType(RelocationType),
+    VirtualProtectFailed,
+    UnknownImportSlot(u64),
+    /// The image couldn't be mapped at the address required by `PreferredBase`.
+    BaseUnavailable(u64),
+    /// The object file is for a machine the loader can't relocate code for, or the image was
+    /// mapped into this process but doesn't match the host, see
+    /// `LoaderConfig::allow_foreign_machine`.
+    UnsupportedMachine(Machine),
+    /// An external of an object file couldn't be resolved.
+    UnresolvedSymbol(String),
+    /// The symbol is too far away for a relocation of an object file to reach it.
+    RelocationOutOfRange(String),
+    /// The image is a .NET assembly without native code, see `LoaderConfig::allow_il_only`.
+    IlOnlyImage,
+    /// `PEFile::validate` reported these findings, see `LoaderConfig::refuse_invalid`.
+    InvalidImage(Vec<Finding>),
+    Parse(ParseError),
+    Io(io::Error),
+}
 
-const DLL_PROCESS_ATTACH: u32 = 1;
+impl From<ParseError> for LoadError {
+    fn from(e: ParseError) -> Self {
+        LoadError::Parse(e)
+    }
+}
 
-unsafe fn get_native_page_size() -> u32 {
-    let mut sys_info = mem::zeroed();
-    GetNativeSystemInfo(&mut sys_info);
-    sys_info.dwPageSize
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
 }
 
-struct WindowsBox<T> {
-    raw: *mut T,
+struct ImportRecord {
+    module: String,
+    symbol: ImportSymbol,
+    slot: u64,
+    // Import lookup value the slot was resolved from.
+    thunk: u64,
 }
 
-impl<T> WindowsBox<T> {
-    fn new(raw: *mut T) -> Self {
-        Self { raw }
+/// An import of a loaded image, as it is currently resolved.
+#[derive(Clone, Copy, Debug)]
+pub struct ResolvedImport<'a> {
+    pub module: &'a str,
+    pub symbol: &'a ImportSymbol,
+    /// Address of the IAT slot.
+    pub slot: u64,
+    /// Address the IAT slot currently points to.
+    pub target: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportTarget {
+    Address(u64),
+    /// Forwarded to another module, e.g. `NTDLL.RtlAllocateHeap`.
+    Forwarder(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadedExport {
+    pub name: Option<String>,
+    pub ordinal: u32,
+    pub target: ExportTarget,
+}
+
+impl LoadedExport {
+    pub(crate) fn matches(&self, symbol: &ImportName) -> bool {
+        match *symbol {
+            ImportName::Name { name, .. } => self.name.as_ref().is_some_and(|n| n == name),
+            ImportName::Ordinal(ordinal) => self.ordinal == ordinal as u32,
+        }
+    }
+}
+
+pub struct LoadedPEFile {
+    /// Only set if the image was mapped into this process.
+    pub entry_point: Option<extern "C" fn()>,
+    memory: Box<dyn Memory>,
+    runnable: bool,
+    // Size of an IAT slot, 4 for PE32 and 8 for PE32+ images.
+    thunk_size: usize,
+    imports: Vec<ImportRecord>,
+    exports: Vec<LoadedExport>,
+    // Registered modules imports were resolved from.
+    pub(crate) dependencies: Vec<String>,
+    // Offset, size and protection of every section.
+    protections: Vec<(usize, usize, Protection)>,
+}
+
+impl LoadedPEFile {
+    /// Address the image was loaded at.
+    pub fn base(&self) -> u64 {
+        self.memory.base()
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.size()
     }
 
-    fn alloc(size: u64, flags: u32, protection: u32) -> Result<Self, LoadError> {
-        let result = unsafe { VirtualAlloc(ptr::null_mut(), size, flags, protection) };
+    /// Whether code of the image can be called, i.e. it was mapped into this process and matches
+    /// our pointer size.
+    pub fn is_runnable(&self) -> bool {
+        self.runnable
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.base() && address - self.base() < self.size() as u64
+    }
 
-        if result == ptr::null_mut() {
-            Err(LoadError::NoMemory)
+    fn read_slot(&self, slot: u64) -> u64 {
+        let p = unsafe { self.memory.as_ptr().add((slot - self.base()) as usize) };
+        if self.thunk_size == 8 {
+            unsafe { ptr::read_volatile(p as *const u64) }
         } else {
-            Ok(Self::new(result as *mut _))
+            unsafe { ptr::read_volatile(p as *const u32) as u64 }
         }
     }
 
-    fn get(&self) -> &T {
-        unsafe { &*self.raw }
+    fn resolved_import<'a>(&'a self, record: &'a ImportRecord) -> ResolvedImport<'a> {
+        ResolvedImport {
+            module: &record.module,
+            symbol: &record.symbol,
+            slot: record.slot,
+            target: self.read_slot(record.slot),
+        }
     }
 
-    fn get_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.raw }
+    pub fn imports<'a>(&'a self) -> impl Iterator<Item = ResolvedImport<'a>> + 'a {
+        self.imports
+            .iter()
+            .map(move |record| self.resolved_import(record))
     }
-}
 
-impl<T> Drop for WindowsBox<T> {
-    fn drop(&mut self) {
-        unsafe { VirtualFree(self.raw as *mut _, 0, MEM_RELEASE) };
+    pub fn find_import<'a, S: Into<ImportSymbol>>(
+        &'a self,
+        module: &str,
+        symbol: S,
+    ) -> Option<ResolvedImport<'a>> {
+        let symbol = symbol.into();
+        self.imports
+            .iter()
+            .find(|i| i.module.eq_ignore_ascii_case(module) && i.symbol == symbol)
+            .map(|record| self.resolved_import(record))
     }
-}
 
-#[derive(Debug)]
-pub enum LoadError {
-    LoadLibraryFailed,
-    NoMemory,
-    UnsupporrtedRelocationType(RelocationType),
-    VirtualProtectFailed,
-}
+    /// Points the IAT slot at `slot` to `target` and returns the previous target.
+    ///
+    /// Only slots returned by `imports` can be patched, the page protection is lifted for the
+    /// duration of the write.
+    pub fn set_import_target(&mut self, slot: u64, target: u64) -> Result<u64, LoadError> {
+        if !self.imports.iter().any(|i| i.slot == slot) {
+            return Err(LoadError::UnknownImportSlot(slot));
+        }
 
-pub struct LoadedPEFile {
-    pub entry_point: Option<extern "C" fn()>,
-    memory: WindowsBox<u8>,
+        let offset = (slot - self.base()) as usize;
+        let size = self.thunk_size;
+        let old_protect = self.memory.protect(offset, size, Protection::ReadWrite)?;
+
+        let p = unsafe { self.memory.as_ptr().add(offset) };
+        let previous = if size == 8 {
+            unsafe { ptr::replace(p as *mut u64, target) }
+        } else {
+            unsafe { ptr::replace(p as *mut u32, target as u32) as u64 }
+        };
+
+        self.memory.protect(offset, size, old_protect)?;
+
+        Ok(previous)
+    }
+
+    /// Reconstructs a PE file from the image as it currently is in memory. Sections that can't be
+    /// read are made readable while they are copied.
+    pub fn dump(&mut self) -> Result<Vec<u8>, LoadError> {
+        self.dump_with_options(&DumpOptions::new())
+    }
+
+    /// Like `dump`, but can also rebase the image and rebuild the IAT.
+    pub fn dump_with_options(&mut self, options: &DumpOptions) -> Result<Vec<u8>, LoadError> {
+        let imports = self.imports
+            .iter()
+            .map(|i| ((i.slot - self.base()) as u32, i.thunk))
+            .collect::<Vec<_>>();
+        // Like `set_import_target`, protections are lifted for the duration of the copy.
+        let image = read_memory(&mut *self.memory, &self.protections)?;
+        dump::dump(&image, self.base(), &imports, options)
+    }
+
+    pub fn exports(&self) -> &[LoadedExport] {
+        &self.exports
+    }
+
+    pub(crate) fn export(&self, symbol: &ImportName) -> Option<&ExportTarget> {
+        self.exports
+            .iter()
+            .find(|e| e.matches(symbol))
+            .map(|e| &e.target)
+    }
+
+    /// Address of an export of the image, `None` if it isn't exported or is forwarded.
+    pub fn export_address<S: Into<ImportSymbol>>(&self, symbol: S) -> Option<u64> {
+        let target = match symbol.into() {
+            ImportSymbol::Name(ref name) => self.export(&ImportName::Name { hint: 0, name }),
+            ImportSymbol::Ordinal(ordinal) => self.export(&ImportName::Ordinal(ordinal)),
+        };
+
+        match target {
+            Some(&ExportTarget::Address(address)) => Some(address),
+            _ => None,
+        }
+    }
 }
 
+#[cfg(windows)]
 pub fn wrapped_dll_main(ep: extern "C" fn()) -> impl FnOnce() -> () {
     let x: extern "stdcall" fn(HINSTANCE, u32, *mut c_void) = unsafe { mem::transmute(ep) };
     let y = move || {
@@ -83,275 +306,406 @@ pub fn wrapped_dll_main(ep: extern "C" fn()) -> impl FnOnce() -> () {
     y
 }
 
-/*
-TODO:
-
-enum PreferredBase {
-    Exact(u64), // error if cant get
-    Default, // try default image base, if dont succeed use any
-    DefaultExact, // fail if can't get default
-    Any, // no preference
-    TryExact(u64), // try to get specified address, but don't error if cant get it and just use any
-}
-
-struct LoaderConfig<DllLoadFunc: Fn(...)..., TLSLoadFunc: Fn(...)...> {
-    dll: DllLoadFunc,
-    tls: TLSLoadFunc,
-    preferred_base: PrefferedBase
-}
-
-impl<..., ...> LoaderConfig<..., ...> {
-    fn default() -> LoaderConfig<..., ...>;
-
-    fn set_dll_func<NewFunc: Fn(...)...>(self) -> LoaderConfig<NewFunc, old_type_params...>
-}
-
-*/
-
 // TODO: Use RVA's
 
 pub struct Loader<T: AsRef<[u8]>> {
     pe_buffer: T,
+    // Address the image is loaded at, relocations are applied relative to this.
     image_base: u64,
+    // Address of the mapping in this process, differs from `image_base` if the backend isn't
+    // native.
+    mapped_base: u64,
+    // Every pointer the loader follows has to stay below `mapped_base + mapped_size`.
+    mapped_size: u64,
+    config: LoaderConfig,
 }
 
-fn resolve<T>(base: &WindowsBox<T>, offset: isize) -> *mut T {
-    unsafe { base.raw.offset(offset) }
+// Page protection for a section with `characteristics`.
+pub(crate) fn section_protection(characteristics: SectionCharacteristics) -> Protection {
+    match (
+        characteristics.is_executable(),
+        characteristics.is_readable(),
+        characteristics.is_writable(),
+    ) {
+        (false, false, false) => Protection::NoAccess,
+        (false, false, true) => Protection::WriteCopy,
+        (false, true, false) => Protection::ReadOnly,
+        (false, true, true) => Protection::ReadWrite,
+        (true, false, false) => Protection::Execute,
+        (true, false, true) => Protection::ExecuteWriteCopy,
+        (true, true, false) => Protection::ExecuteRead,
+        (true, true, true) => Protection::ExecuteReadWrite,
+    }
 }
 
 fn resolve_raw(base: u64, offset: isize) -> u64 {
     base + offset as u64
 }
 
+impl Loader<Mmap> {
+    /// Memory maps the file at `path` and loads the image from the mapping.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        Self::from_path_with_config(path, LoaderConfig::new())
+    }
+
+    pub fn from_path_with_config<P: AsRef<Path>>(
+        path: P,
+        config: LoaderConfig,
+    ) -> Result<Self, LoadError> {
+        Ok(Self::with_config(source::map_file(path.as_ref())?, config))
+    }
+}
+
+impl Loader<Vec<u8>> {
+    /// Reads only the headers and sections from `reader`, anything else in the file is skipped.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self, LoadError> {
+        Self::from_reader_with_config(reader, LoaderConfig::new())
+    }
+
+    pub fn from_reader_with_config<R: Read + Seek>(
+        mut reader: R,
+        config: LoaderConfig,
+    ) -> Result<Self, LoadError> {
+        Ok(Self::with_config(source::read_image(&mut reader)?, config))
+    }
+}
+
 impl<T: AsRef<[u8]>> Loader<T> {
     pub fn new(pe_buffer: T) -> Self {
+        Self::with_config(pe_buffer, LoaderConfig::new())
+    }
+
+    pub fn with_config(pe_buffer: T, config: LoaderConfig) -> Self {
         Self {
             pe_buffer,
             image_base: 0,
+            mapped_base: 0,
+            mapped_size: 0,
+            config,
         }
     }
 
     pub fn load(mut self) -> Result<LoadedPEFile, LoadError> {
-        let mapped_module = self.map_module()?;
-        self.image_base = mapped_module.raw as _;
+        // Rejects anything that isn't a PE file before we start following pointers in it.
+        let file = PEFile::parse(self.pe_buffer.as_ref())?;
+        if self.config.ec_view {
+            if let Some(view) = file.ec_view()? {
+                // The view is loaded as it is, it never has another one.
+                self.config.ec_view = false;
+                return Loader::with_config(view, self.config).load();
+            }
+        }
+        // A broken CLR header doesn't keep native code from running, so only a valid one counts.
+        let is_il_only = matches!(file.clr_header(), Ok(Some(header)) if header.is_il_only());
+        if is_il_only && !self.config.allow_il_only {
+            return Err(LoadError::IlOnlyImage);
+        }
+        if self.config.refuse_invalid {
+            let findings = file.validate();
+            if !findings.is_empty() {
+                return Err(LoadError::InvalidImage(findings));
+            }
+        }
+        let is_64_bit = file.is_64_bit();
+        let machine = file.machine();
+        let address_of_entry_point = file.address_of_entry_point();
+        let thunk_size = file.thunk_size();
+        let is_ec = matches!(file.hybrid_machine(), Ok(Some(Machine::ARM64EC)));
+
+        let mut mapped_module = self.map_module()?;
+        self.image_base = mapped_module.base();
+        self.mapped_base = mapped_module.as_ptr() as u64;
+        self.mapped_size = mapped_module.size() as u64;
 
         self.relocate()?;
-        self.resolve_imports()?;
-        self.mem_protect()?;
-        self.tls_callbacks()?;
+        let imports = if is_64_bit {
+            self.resolve_imports::<ThunkData>()?
+        } else {
+            self.resolve_imports::<ThunkData32>()?
+        };
+        let protections = self.mem_protect(&mut *mapped_module)?;
+
+        // None of the code of the image can be run unless it was mapped into this process, which
+        // also means it has to be for our machine and match our pointer size. ARM64EC code
+        // depends on the emulator of the OS.
+        let runnable = mapped_module.is_native()
+            && Machine::host() == Some(machine)
+            && !is_ec
+            && is_64_bit == (mem::size_of::<usize>() == 8);
+        if runnable {
+            self.tls_callbacks()?;
+        }
 
-        // TODO: We're leaking all mapped sections, lul
-        // we actually aren't VirtualFree is best
-        let dos_header = self.get_dos_header();
-        let file_header = &dos_header.get_pe_header().file_header;
-        let optional_header = &dos_header.get_pe_header().optional_header;
-        let address = resolve_raw(self.image_base, optional_header.address_of_entry_point as _);
+        let exports = self.exports()?;
+        let address = resolve_raw(self.image_base, address_of_entry_point as _);
 
         Ok(LoadedPEFile {
             memory: mapped_module,
-            entry_point: match address {
+            runnable,
+            thunk_size,
+            imports,
+            exports,
+            dependencies: mem::take(&mut self.config.dependencies),
+            protections,
+            entry_point: match address_of_entry_point {
                 0 => None,
-                x => unsafe { Some(mem::transmute(x)) },
+                _ if !runnable => None,
+                _ => unsafe { Some(mem::transmute::<u64, extern "C" fn()>(address)) },
             },
         })
     }
 
-    fn get_dos_header<'a>(&'a self) -> &'a DosHeader {
-        unsafe { &*(self.pe_buffer.as_ref().as_ptr() as *const _) }
+    fn file(&self) -> Result<PEFile<'_>, LoadError> {
+        Ok(PEFile::parse(self.pe_buffer.as_ref())?)
+    }
+
+    fn mapped_end(&self) -> u64 {
+        self.mapped_base + self.mapped_size
     }
 
-    fn map_module(&mut self) -> Result<WindowsBox<u8>, LoadError> {
-        let dos_header = self.get_dos_header();
-        let file_header = &dos_header.get_pe_header().file_header;
+    // A `D` at `rva` in the mapped image, as long as it fits into the image.
+    fn mapped<D>(&self, rva: u32) -> Result<Pointer<*mut D>, LoadError> {
+        let pointer = RVA::<u32, Pointer<*mut D>>::new(rva).resolve(self.mapped_base);
+        if pointer.p as usize % mem::align_of::<D>() != 0
+            || rva as u64 + mem::size_of::<D>() as u64 > self.mapped_size
+        {
+            return Err(ParseError::InvalidRva(rva).into());
+        }
+        Ok(pointer)
+    }
 
-        let size = file_header
-            .get_sections()
+    // The directory in the mapped image, `None` if the image doesn't have it.
+    fn directory<D>(&self, entry: DirectoryEntry) -> Result<Option<Pointer<*mut D>>, LoadError> {
+        match self.file()?.data_directory(entry)? {
+            None => Ok(None),
+            Some(d) => self.mapped(d.virtual_address).map(Some),
+        }
+    }
+
+    // Reads a NUL terminated string from the mapped image.
+    fn mapped_str(&self, rva: u32) -> Result<String, LoadError> {
+        let image =
+            unsafe { slice::from_raw_parts(self.mapped_base as *const u8, self.mapped_size as _) };
+        let string = image.get(rva as usize..).ok_or(ParseError::InvalidRva(rva))?;
+        let len = string
             .iter()
-            .filter(|&s| s.virtual_address.value != 0)
-            .map(|x| x.virtual_address.value + x.size_of_raw_data)
-            .max()
-            .unwrap();
-        let page_size = unsafe { get_native_page_size() } - 1;
+            .position(|&c| c == 0)
+            .ok_or(ParseError::InvalidName)?;
+        Ok(String::from_utf8_lossy(&string[..len]).into_owned())
+    }
+
+    fn map_module(&mut self) -> Result<Box<dyn Memory>, LoadError> {
+        let file = PEFile::parse(self.pe_buffer.as_ref())?;
+
+        // Some linkers leave size_of_image too small, cover every section in any case.
+        let size = file
+            .sections()
+            .filter(|s| s.virtual_address != 0)
+            .map(|s| s.virtual_address as u64 + s.mapped_size().max(s.size_of_raw_data) as u64)
+            .fold(file.size_of_image() as u64, u64::max);
+        let page_size = self.config.backend.page_size() as u64 - 1;
         let size = (size + page_size) & !page_size;
+        if size > u32::MAX as u64 {
+            return Err(ParseError::OutOfBounds.into());
+        }
 
-        let base = WindowsBox::alloc(size as _, MEM_RESERVE | MEM_COMMIT, PAGE_READWRITE)?;
+        let preferred_base = self.config.preferred_base;
+        let address = preferred_base.address(file.image_base());
+        let base = self.config.backend.allocate(address, size as _)?;
+        if preferred_base.is_exact() && base.base() != address {
+            return Err(LoadError::BaseUnavailable(address));
+        }
+        self.config.check_machine(file.machine(), &*base)?;
 
         // TODO: We probably (99.9999%) don't need this, even when loading dlls with LoadLibraryA
-        unsafe {
-            ptr::copy(
-                self.pe_buffer.as_ref().as_ptr(),
-                base.raw,
-                dos_header.get_pe_header().optional_header.size_of_headers as _,
-            )
-        };
-
-        for section in file_header
-            .get_sections()
-            .iter()
-            .filter(|&s| s.virtual_address.value != 0)
+        let headers = (file.size_of_headers() as usize)
+            .min(self.pe_buffer.as_ref().len())
+            .min(size as usize);
+        unsafe { ptr::copy(self.pe_buffer.as_ref().as_ptr(), base.as_ptr(), headers) };
+
+        // The memory is zeroed already, so sections without raw data don't need to be touched.
+        for section in file
+            .sections()
+            .filter(|s| s.virtual_address != 0 && s.size_of_raw_data != 0)
         {
-            let p = section.virtual_address.resolve(base.raw as _).p;
-            if section.size_of_raw_data == 0 {
-                // ???
-                unsafe {
-                    ptr::write_bytes(
-                        p,
-                        0,
-                        dos_header.get_pe_header().optional_header.section_alignment as _,
-                    )
-                };
-            } else {
-                let source = unsafe {
-                    self.pe_buffer
-                        .as_ref()
-                        .as_ptr()
-                        .offset(section.p_raw_data as _)
-                };
-                unsafe { ptr::copy(source, p, section.size_of_raw_data as _) };
-            }
+            let start = section.p_raw_data as usize;
+            let source = self
+                .pe_buffer
+                .as_ref()
+                .get(start..start + section.size_of_raw_data as usize)
+                .ok_or(ParseError::OutOfBounds)?;
+            let p = RVA::<u32, Pointer<*mut u8>>::new(section.virtual_address)
+                .resolve(base.as_ptr() as _)
+                .p;
+            unsafe { ptr::copy(source.as_ptr(), p, source.len()) };
         }
 
         Ok(base)
     }
 
     fn relocate(&mut self) -> Result<(), LoadError> {
-        let dos_header = self.get_dos_header();
-        let optional_header = &dos_header.get_pe_header().optional_header;
+        let preferred_base = self.file()?.image_base();
 
         // We don't need to relocate if we managed to load the image at the preferred base address.
-        if self.image_base == optional_header.image_base {
+        if self.image_base == preferred_base {
             return Ok(());
         }
 
-        match optional_header.get_relocation_entries(self.image_base) {
-            None => Ok(()),
-            Some(base_relocation) => {
-                let delta = self.image_base - optional_header.image_base;
-
-                for base_reloc in base_relocation.base_relocations() {
-                    for (relocation_type, offset) in base_reloc.relocations() {
-                        let mut address = base_reloc
-                            .virtual_address
-                            .resolve(self.image_base + offset as u64);
-
-                        if relocation_type == RelocateAbsolute {
-                            // TODO what to even do lul ?
-                            //return Err(LoadError::UnsupporrtedRelocationType(relocation_type));
-                        } else if relocation_type == RelocateDir64 {
-                            *address += delta;
-                        } else if relocation_type == RelocateHighLow {
-                            *address += delta & u32::max_value() as u64;
-                        } else {
-                            return Err(LoadError::UnsupporrtedRelocationType(relocation_type));
-                        }
-                    }
+        let directory = match self.file()?.data_directory(DirectoryEntry::Basereloc)? {
+            Some(directory) => directory,
+            None => return Ok(()),
+        };
+        // Blocks are checked while iterating, a truncated first one is an error like any other.
+        let base_relocation = self
+            .mapped::<ImageBaseRelocation>(directory.virtual_address)?
+            .p;
+        let end = (base_relocation as u64 + directory.size as u64).min(self.mapped_end());
+
+        let delta = self.image_base.wrapping_sub(preferred_base);
+
+        for base_reloc in unsafe { &*base_relocation }.base_relocations(end) {
+            let base_reloc = base_reloc?;
+            for (relocation_type, offset) in base_reloc.relocations() {
+                let rva = base_reloc.virtual_address.value as u64 + offset as u64;
+                let address = base_reloc.virtual_address.resolve(self.mapped_base + offset as u64).p;
+
+                let size = if relocation_type == RelocateDir64 { 8 } else { 4 };
+                if relocation_type != RelocateAbsolute && rva + size > self.mapped_size {
+                    return Err(ParseError::InvalidRva(rva as u32).into());
                 }
 
-                Ok(())
+                // Relocations don't have to be aligned.
+                if relocation_type == RelocateAbsolute {
+                    // Padding
+                } else if relocation_type == RelocateDir64 {
+                    unsafe {
+                        ptr::write_unaligned(
+                            address,
+                            ptr::read_unaligned(address).wrapping_add(delta),
+                        )
+                    };
+                } else if relocation_type == RelocateHighLow {
+                    let address = address as *mut u32;
+                    unsafe {
+                        ptr::write_unaligned(
+                            address,
+                            ptr::read_unaligned(address).wrapping_add(delta as u32),
+                        )
+                    };
+                } else {
+                    return Err(LoadError::UnsupporrtedRelocationType(relocation_type));
+                }
             }
         }
-    }
 
-    fn resolve_imports(&mut self) -> Result<(), LoadError> {
-        let dos_header = self.get_dos_header();
-        let optional_header = &dos_header.get_pe_header().optional_header;
-
-        match optional_header.get_import_descriptor(self.image_base) {
-            None => Ok(()),
-            Some(import_descriptor) => {
-                for import_descriptor in import_descriptor.import_iterator() {
-                    let dll_name = import_descriptor.name.resolve(self.image_base);
-                    let hmod = unsafe { LoadLibraryA(dll_name.p) };
+        Ok(())
+    }
 
-                    if hmod.is_null() {
-                        return Err(LoadError::LoadLibraryFailed);
+    fn resolve_imports<Th: Thunk>(&mut self) -> Result<Vec<ImportRecord>, LoadError> {
+        let import_descriptor = match self.directory::<ImportDescriptor>(DirectoryEntry::Import)? {
+            None => return Ok(vec![]),
+            Some(import_descriptor) => import_descriptor.p as *const ImportDescriptor,
+        };
+        let mut imports = vec![];
+
+        let end = self.mapped_end();
+        for import_descriptor in unsafe { &*import_descriptor }.import_iterator(end) {
+            let import_descriptor = import_descriptor?;
+            let module = self.mapped_str(import_descriptor.name.value)?;
+            let handle = self.config.open_module(&module)?;
+
+            for thunk in import_descriptor.thunk_iterator::<Th>(self.mapped_base, end) {
+                let (lookup, thunk) = thunk?;
+                let name;
+                let symbol = if lookup.is_ordinal() {
+                    ImportName::Ordinal(image_ordinal(lookup.value()) as u16)
+                } else {
+                    let by_name = self.mapped::<ImageImportByName>(lookup.import_by_name().value)?;
+                    name = self.mapped_str(lookup.import_by_name().value + 2)?;
+                    ImportName::Name {
+                        hint: by_name.hint,
+                        name: &name,
                     }
+                };
 
-                    for thunk in import_descriptor.thunk_iterator(self.image_base) {
-                        let function = if image_snap_by_ordinal(unsafe { thunk.ordinal }) {
-                            unsafe {
-                                GetProcAddress(hmod, image_ordinal(thunk.ordinal) as *const c_char)
-                            }
-                        } else {
-                            let name = unsafe { thunk.address_of_data }.resolve(self.image_base);
-                            unsafe { GetProcAddress(hmod, &name.name as *const c_char) }
-                        };
-
-                        thunk.function = function as u64;
-                    }
-                }
+                let function = self.config.resolve_symbol(&handle, &symbol)?;
+                thunk.set_function(self.config.apply_import_hooks(&module, &symbol, function));
 
-                Ok(())
+                let slot = thunk as *mut Th as u64 - self.mapped_base;
+                imports.push(ImportRecord {
+                    module: module.to_string(),
+                    symbol: symbol.into(),
+                    slot: self.image_base + slot,
+                    thunk: lookup.value(),
+                });
             }
         }
-    }
 
-    fn mem_protect(&mut self) -> Result<(), LoadError> {
-        let dos_header = self.get_dos_header();
-        let file_header = &dos_header.get_pe_header().file_header;
-        let mut old_protect = 0u32;
+        Ok(imports)
+    }
 
-        for section in file_header
-            .get_sections()
-            .iter()
-            .filter(|&s| s.virtual_address.value != 0 && s.size_of_raw_data != 0)
+    fn mem_protect(
+        &mut self,
+        memory: &mut dyn Memory,
+    ) -> Result<Vec<(usize, usize, Protection)>, LoadError> {
+        let mut protections = vec![];
+        for section in self
+            .file()?
+            .sections()
+            .filter(|s| s.virtual_address != 0 && s.size_of_raw_data != 0)
         {
-            let section_base = resolve_raw(self.image_base, section.virtual_address.value as _);
-            let characteristics = section.characteristics as u32;
-
-            let flags = match (
-                characteristics & Characteristics::MemExecute as u32,
-                characteristics & Characteristics::MemRead as u32,
-                characteristics & Characteristics::MemWrite as u32,
-            ) {
-                (0, 0, 0) => PAGE_NOACCESS,
-                (0, 0, _) => PAGE_WRITECOPY,
-                (0, _, 0) => PAGE_READONLY,
-                (0, _, _) => PAGE_READWRITE,
-                (_, 0, 0) => PAGE_EXECUTE,
-                (_, 0, _) => PAGE_EXECUTE_WRITECOPY,
-                (_, _, 0) => PAGE_EXECUTE_READ,
-                (_, _, _) => PAGE_EXECUTE_READWRITE,
-            };
-
-            if unsafe {
-                VirtualProtect(
-                    section_base as _,
-                    section.size_of_raw_data as _,
-                    flags,
-                    &mut old_protect,
-                )
-            } == 0
-            {
-                return Err(LoadError::VirtualProtectFailed);
-            }
+            let protection = section_protection(section.characteristics);
+            let (offset, size) = (section.virtual_address as _, section.size_of_raw_data as _);
+
+            memory.protect(offset, size, protection)?;
+            protections.push((offset, size, protection));
         }
 
-        Ok(())
+        Ok(protections)
     }
 
-    fn tls_callbacks(&mut self) -> Result<(), LoadError> {
-        let dos_header = self.get_dos_header();
-        let optional_header = &dos_header.get_pe_header().optional_header;
+    // The image has to be runnable, the callbacks are called directly.
+    fn tls_callbacks(&self) -> Result<(), LoadError> {
+        let file = self.file()?;
+        let tls = match file.tls()? {
+            None => return Ok(()),
+            Some(tls) => tls,
+        };
+
+        for &callback in &tls.callbacks {
+            // Relocated like the array they are in.
+            let address = callback
+                .wrapping_sub(file.image_base())
+                .wrapping_add(self.image_base);
+            let f = unsafe { mem::transmute::<usize, TlsCallback>(address as usize) };
+            if let Some(f) = f {
+                f(self.image_base as *mut _, DLL_PROCESS_ATTACH, ptr::null_mut());
+            }
+        }
 
-        match optional_header.get_tls_entries(self.image_base) {
-            None => Ok(()),
-            Some(entry) => {
-                let mut callback = entry.address_of_callbacks as *const TlsCallback;
+        Ok(())
+    }
 
-                if !callback.is_null() {
-                    while let &Some(f) = unsafe { &*callback } {
-                        f(
-                            self.image_base as *mut _,
-                            DLL_PROCESS_ATTACH,
-                            ptr::null_mut(),
-                        );
-                        callback = unsafe { callback.offset(1) };
-                    }
-                }
+    fn exports(&self) -> Result<Vec<LoadedExport>, LoadError> {
+        let file = PEFile::parse(self.pe_buffer.as_ref())?;
+        let exports = match file.exports()? {
+            None => return Ok(vec![]),
+            Some(exports) => exports,
+        };
 
-                Ok(())
-            }
+        let mut result = vec![];
+        for export in exports.iter()? {
+            let export = export?;
+            result.push(LoadedExport {
+                name: export.name.map(|n| n.to_owned()),
+                ordinal: export.ordinal,
+                target: match export.forwarder {
+                    Some(forwarder) => ExportTarget::Forwarder(forwarder.to_owned()),
+                    None => ExportTarget::Address(self.image_base + export.rva as u64),
+                },
+            });
         }
+
+        Ok(result)
     }
 }
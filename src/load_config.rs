@@ -0,0 +1,179 @@
+// The load config directory and the dynamic value relocation table it points to.
+//
+// IMAGE_LOAD_CONFIG_DIRECTORY grew with almost every Windows release, its first field holds the
+// size the linker wrote. Fields beyond that size read as zero. Pointers are VAs and have a
+// different size and, in a few places, a different order in PE32 and PE32+ images, which is why
+// every field has two offsets below.
+//
+// The dynamic value relocation table starts with a version and a size, followed by entries of
+//
+//     symbol (pointer sized), size of fixups, fixups
+//
+// where the symbol says what kind of fixups follow. Only version 1 tables are understood.
+
+use crate::pe_file::{read_bytes, read_u16, read_u32, read_u64, PEFile, ParseError};
+use crate::structs::DirectoryEntry;
+
+const DYNAMIC_RELOCATION_TABLE_SIZE: usize = 8;
+const DYNAMIC_RELOCATION_TABLE_VERSION: u32 = 1;
+
+pub const DYNAMIC_RELOCATION_GUARD_RF_PROLOGUE: u64 = 1;
+pub const DYNAMIC_RELOCATION_GUARD_RF_EPILOGUE: u64 = 2;
+pub const DYNAMIC_RELOCATION_GUARD_IMPORT_CONTROL_TRANSFER: u64 = 3;
+pub const DYNAMIC_RELOCATION_GUARD_INDIR_CONTROL_TRANSFER: u64 = 4;
+pub const DYNAMIC_RELOCATION_GUARD_SWITCHTABLE_BRANCH: u64 = 5;
+pub const DYNAMIC_RELOCATION_ARM64X: u64 = 6;
+pub const DYNAMIC_RELOCATION_FUNCTION_OVERRIDE: u64 = 7;
+pub const DYNAMIC_RELOCATION_ARM64_KERNEL_IMPORT_CALL_TRANSFER: u64 = 8;
+
+/// The parts of IMAGE_LOAD_CONFIG_DIRECTORY this crate knows about. Pointers are VAs, i.e.
+/// relative to the preferred base.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LoadConfig {
+    pub size: u32,
+    pub time_date_stamp: u32,
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub global_flags_clear: u32,
+    pub global_flags_set: u32,
+    pub security_cookie: u64,
+    pub se_handler_table: u64,
+    pub se_handler_count: u64,
+    pub guard_cf_check_function_pointer: u64,
+    pub guard_cf_dispatch_function_pointer: u64,
+    pub guard_cf_function_table: u64,
+    pub guard_cf_function_count: u64,
+    pub guard_flags: u32,
+    pub dynamic_value_reloc_table: u64,
+    pub chpe_metadata_pointer: u64,
+    pub dynamic_value_reloc_table_offset: u32,
+    /// The one based index of the section `dynamic_value_reloc_table_offset` is relative to.
+    pub dynamic_value_reloc_table_section: u16,
+}
+
+/// An entry of the dynamic value relocation table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DynamicRelocation<'a> {
+    /// One of the `DYNAMIC_RELOCATION_*` constants.
+    pub symbol: u64,
+    /// Fixups in the format `symbol` calls for, most use blocks like those of base relocations.
+    pub fixups: &'a [u8],
+}
+
+// The fields present in the first `data.len()` bytes of the load config.
+struct Fields<'a> {
+    data: &'a [u8],
+    is_64_bit: bool,
+}
+
+impl<'a> Fields<'a> {
+    fn offset(&self, offset32: usize, offset64: usize) -> usize {
+        if self.is_64_bit {
+            offset64
+        } else {
+            offset32
+        }
+    }
+
+    fn u16(&self, offset32: usize, offset64: usize) -> u16 {
+        read_u16(self.data, self.offset(offset32, offset64)).unwrap_or(0)
+    }
+
+    fn u32(&self, offset32: usize, offset64: usize) -> u32 {
+        read_u32(self.data, self.offset(offset32, offset64)).unwrap_or(0)
+    }
+
+    fn pointer(&self, offset32: usize, offset64: usize) -> u64 {
+        if self.is_64_bit {
+            read_u64(self.data, offset64).unwrap_or(0)
+        } else {
+            read_u32(self.data, offset32).map_or(0, |p| p as u64)
+        }
+    }
+}
+
+impl<'a> PEFile<'a> {
+    pub fn load_config(&self) -> Result<Option<LoadConfig>, ParseError> {
+        let directory = match self.data_directory(DirectoryEntry::LoadConfig)? {
+            None => return Ok(None),
+            Some(directory) => directory,
+        };
+
+        let size = read_u32(self.read_rva(directory.virtual_address, 4)?, 0)?;
+        let f = Fields {
+            data: self.read_rva(directory.virtual_address, size as usize)?,
+            is_64_bit: self.is_64_bit(),
+        };
+        Ok(Some(LoadConfig {
+            size,
+            time_date_stamp: f.u32(4, 4),
+            major_version: f.u16(8, 8),
+            minor_version: f.u16(10, 10),
+            global_flags_clear: f.u32(12, 12),
+            global_flags_set: f.u32(16, 16),
+            security_cookie: f.pointer(60, 88),
+            se_handler_table: f.pointer(64, 96),
+            se_handler_count: f.pointer(68, 104),
+            guard_cf_check_function_pointer: f.pointer(72, 112),
+            guard_cf_dispatch_function_pointer: f.pointer(76, 120),
+            guard_cf_function_table: f.pointer(80, 128),
+            guard_cf_function_count: f.pointer(84, 136),
+            guard_flags: f.u32(88, 144),
+            dynamic_value_reloc_table: f.pointer(120, 192),
+            chpe_metadata_pointer: f.pointer(124, 200),
+            dynamic_value_reloc_table_offset: f.u32(136, 224),
+            dynamic_value_reloc_table_section: f.u16(140, 228),
+        }))
+    }
+
+    /// The entries of the dynamic value relocation table, empty if the image doesn't have one or
+    /// it isn't version 1.
+    pub fn dynamic_relocations(&self) -> Result<Vec<DynamicRelocation<'a>>, ParseError> {
+        let config = match self.load_config()? {
+            Some(config) => config,
+            None => return Ok(vec![]),
+        };
+        if config.dynamic_value_reloc_table_section == 0 {
+            return Ok(vec![]);
+        }
+        let rva = self
+            .sections()
+            .nth(config.dynamic_value_reloc_table_section as usize - 1)
+            .and_then(|s| {
+                s.virtual_address
+                    .checked_add(config.dynamic_value_reloc_table_offset)
+            })
+            .ok_or(ParseError::InvalidLoadConfig)?;
+
+        let header = self.read_rva(rva, DYNAMIC_RELOCATION_TABLE_SIZE)?;
+        if read_u32(header, 0)? != DYNAMIC_RELOCATION_TABLE_VERSION {
+            return Ok(vec![]);
+        }
+        let size = read_u32(header, 4)? as usize;
+        let table = self
+            .read_rva(rva, DYNAMIC_RELOCATION_TABLE_SIZE.saturating_add(size))
+            .map_err(|_| ParseError::InvalidLoadConfig)?;
+        let table = &table[DYNAMIC_RELOCATION_TABLE_SIZE..];
+
+        let (symbol_size, header_size) = if self.is_64_bit() { (8, 12) } else { (4, 8) };
+        let mut relocations = vec![];
+        let mut offset = 0;
+        while offset < table.len() {
+            let symbol = if self.is_64_bit() {
+                read_u64(table, offset)
+            } else {
+                read_u32(table, offset).map(|s| s as u64)
+            };
+            let symbol = symbol.map_err(|_| ParseError::InvalidLoadConfig)?;
+            let fixups_size =
+                read_u32(table, offset + symbol_size).map_err(|_| ParseError::InvalidLoadConfig)?;
+            let fixups = read_bytes(table, offset + header_size, fixups_size as usize)
+                .map_err(|_| ParseError::InvalidLoadConfig)?;
+            relocations.push(DynamicRelocation { symbol, fixups });
+            offset += header_size + fixups.len();
+        }
+
+        Ok(relocations)
+    }
+}
@@ -0,0 +1,528 @@
+// Loads COFF object files straight into memory, doing what a (very small) linker would do when
+// linking a single object into an image. This is how beacon object files are run.
+//
+// The sections are laid out one after the other, each starting on a new page so it can get its own
+// protection, followed by the common symbols and a table with a slot for every external the
+// object references. Externals are resolved through the `LoaderConfig`:
+//
+//     __imp_KERNEL32$VirtualAlloc    VirtualAlloc from KERNEL32, the slot is what gets called
+//     KERNEL32$VirtualAlloc          the same, but called directly
+//     BeaconPrintf                   defined with `ObjectLoader::symbol` or found in one of the
+//                                    modules added with `ObjectLoader::search_module`
+//
+// Direct calls are 32 bit displacements on AMD64, which can't reach other modules, so they are
+// pointed to a stub jumping through the slot instead.
+
+use std::mem;
+use std::slice;
+
+use super::{section_protection, LoadError};
+use crate::backend::{read_memory, Memory, Protection};
+use crate::coff::*;
+use crate::config::LoaderConfig;
+use crate::pe_file::{
+    read_u16, read_u32, read_u64, write_bytes, write_u16, write_u32, write_u64, ImportName,
+    ParseError, Section,
+};
+use crate::structs::{Machine, RelocationType};
+
+const IMPORT_PREFIX: &str = "__imp_";
+const COMMON_ALIGNMENT: u64 = 16;
+// `jmp [rip + slot]`, padded with `int3`.
+const STUB_SIZE: u64 = 8;
+
+/// A symbol the object defines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadedSymbol {
+    pub name: String,
+    pub address: u64,
+}
+
+/// An external the object references and what it was resolved to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectImport {
+    /// Name of the symbol in the object, e.g. `__imp_KERNEL32$VirtualAlloc`.
+    pub symbol: String,
+    /// The module it was resolved from, `None` for symbols defined with `ObjectLoader::symbol`.
+    pub module: Option<String>,
+    /// The undecorated name it was resolved as, e.g. `VirtualAlloc`.
+    pub name: String,
+    pub address: u64,
+    /// Address of the slot holding `address`.
+    pub slot: u64,
+}
+
+pub struct LoadedObject {
+    memory: Box<dyn Memory>,
+    runnable: bool,
+    symbols: Vec<LoadedSymbol>,
+    imports: Vec<ObjectImport>,
+    // Offset, size and protection of every section and the import slots.
+    protections: Vec<(usize, usize, Protection)>,
+}
+
+impl LoadedObject {
+    /// Address the object was loaded at.
+    pub fn base(&self) -> u64 {
+        self.memory.base()
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.size()
+    }
+
+    /// Whether code of the object can be called, i.e. it was mapped into this process and was
+    /// compiled for the machine we run on.
+    pub fn is_runnable(&self) -> bool {
+        self.runnable
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.base() && address - self.base() < self.size() as u64
+    }
+
+    /// The external symbols defined by the object.
+    pub fn symbols(&self) -> &[LoadedSymbol] {
+        &self.symbols
+    }
+
+    /// Address of the external symbol `name`. i386 objects decorate C functions with a leading
+    /// underscore, which doesn't have to be part of `name`.
+    pub fn symbol_address(&self, name: &str) -> Option<u64> {
+        let find = |name: &str| self.symbols.iter().find(|s| s.name == name);
+        find(name)
+            .or_else(|| find(&format!("_{}", name)))
+            .map(|s| s.address)
+    }
+
+    pub fn imports(&self) -> &[ObjectImport] {
+        &self.imports
+    }
+
+    /// A copy of the memory the object was loaded into. Sections that can't be read are made
+    /// readable while they are copied.
+    pub fn image(&mut self) -> Result<Vec<u8>, LoadError> {
+        read_memory(&mut *self.memory, &self.protections)
+    }
+}
+
+// Splits an external into the module and the undecorated function name.
+fn split_external(symbol: &str, is_64_bit: bool) -> (Option<&str>, &str) {
+    let mut name = symbol.strip_prefix(IMPORT_PREFIX).unwrap_or(symbol);
+
+    // i386 C functions start with an underscore, stdcall ones also end with `@<argument size>`.
+    if !is_64_bit {
+        name = name.strip_prefix('_').unwrap_or(name);
+        if let Some(at) = name.rfind('@') {
+            if at != 0 && name[at + 1..].bytes().all(|c| c.is_ascii_digit()) {
+                name = &name[..at];
+            }
+        }
+    }
+
+    match name.find('$') {
+        Some(dollar) => (Some(&name[..dollar]), &name[dollar + 1..]),
+        None => (None, name),
+    }
+}
+
+fn align(offset: u64, alignment: u64) -> u64 {
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+// Where everything ended up, relative to `base`.
+struct Layout<'a> {
+    table: SymbolTable<'a>,
+    base: u64,
+    is_64_bit: bool,
+    // Indexed by section number - 1, `None` for sections that aren't loaded.
+    sections: Vec<Option<u64>>,
+    commons: Vec<(u32, u64)>,
+    externals: Vec<&'a str>,
+    slots: u64,
+    stubs: u64,
+}
+
+impl<'a> Layout<'a> {
+    fn thunk_size(&self) -> u64 {
+        if self.is_64_bit {
+            8
+        } else {
+            4
+        }
+    }
+
+    // The symbol at `index`, weak externals are replaced by their default definition.
+    fn symbol(&self, index: u32) -> Result<Symbol<'a>, ParseError> {
+        let symbol = self.table.symbol(index)?;
+        match symbol.aux_symbol()? {
+            Some(AuxSymbol::WeakExternal { tag_index, .. }) if symbol.is_undefined() => {
+                self.table.symbol(tag_index)
+            }
+            _ => Ok(symbol),
+        }
+    }
+
+    fn external(&self, name: &str) -> Option<u64> {
+        self.externals
+            .iter()
+            .position(|&e| e == name)
+            .map(|n| n as u64)
+    }
+
+    // Address of `symbol`, `imports` being what the externals were resolved to. Relative
+    // relocations can't reach imports on AMD64 and get their stub instead.
+    fn address(
+        &self,
+        symbol: &Symbol,
+        imports: &[ObjectImport],
+        relative: bool,
+    ) -> Result<u64, LoadError> {
+        let unresolved = || LoadError::UnresolvedSymbol(symbol.name.to_owned());
+
+        if symbol.section_number == IMAGE_SYM_ABSOLUTE {
+            return Ok(symbol.value as u64);
+        }
+        if symbol.section_number > 0 {
+            return match self.sections.get(symbol.section_number as usize - 1) {
+                Some(&Some(offset)) => Ok(self.base + offset + symbol.value as u64),
+                _ => Err(unresolved()),
+            };
+        }
+        if let Some(&(_, offset)) = self.commons.iter().find(|c| c.0 == symbol.index) {
+            return Ok(self.base + offset);
+        }
+
+        let n = self.external(symbol.name).ok_or_else(unresolved)?;
+        if symbol.name.starts_with(IMPORT_PREFIX) {
+            Ok(self.base + self.slots + n * self.thunk_size())
+        } else if relative && self.is_64_bit {
+            Ok(self.base + self.stubs + n * STUB_SIZE)
+        } else {
+            Ok(imports[n as usize].address)
+        }
+    }
+}
+
+/// Loads a COFF object file for AMD64 or i386.
+pub struct ObjectLoader<T: AsRef<[u8]>> {
+    buffer: T,
+    config: LoaderConfig,
+    symbols: Vec<(String, u64)>,
+    search_modules: Vec<String>,
+}
+
+impl<T: AsRef<[u8]>> ObjectLoader<T> {
+    pub fn new(buffer: T) -> Self {
+        Self::with_config(buffer, LoaderConfig::new())
+    }
+
+    pub fn with_config(buffer: T, config: LoaderConfig) -> Self {
+        Self {
+            buffer,
+            config,
+            symbols: vec![],
+            search_modules: vec![],
+        }
+    }
+
+    /// Resolves the external `name` to `address`. `name` is either the symbol as it appears in
+    /// the object or its undecorated name.
+    pub fn symbol(mut self, name: &str, address: u64) -> Self {
+        self.symbols.push((name.to_owned(), address));
+        self
+    }
+
+    /// Looks up externals that don't name their module in `module`. Modules are searched in the
+    /// order they were added.
+    pub fn search_module(mut self, module: &str) -> Self {
+        self.search_modules.push(module.to_owned());
+        self
+    }
+
+    pub fn load(self) -> Result<LoadedObject, LoadError> {
+        let ObjectLoader {
+            buffer,
+            mut config,
+            symbols,
+            search_modules,
+        } = self;
+        let file = CoffFile::parse(buffer.as_ref())?;
+        let machine = file.machine();
+        let is_64_bit = match machine {
+            Machine::AMD64 => true,
+            Machine::I386 => false,
+            machine => return Err(LoadError::UnsupportedMachine(machine)),
+        };
+        let table = file.symbol_table()?;
+        let page_size = config.backend.page_size() as u64;
+
+        let sections = file.sections().collect::<Vec<Section>>();
+        let mut layout = Layout {
+            table,
+            base: 0,
+            is_64_bit,
+            sections: vec![None; sections.len()],
+            commons: vec![],
+            externals: vec![],
+            slots: 0,
+            stubs: 0,
+        };
+
+        let mut size = 0;
+        let mut relocations = vec![];
+        for (i, section) in sections.iter().enumerate() {
+            // Linker directives (.drectve) and debug information (.debug$S, ...) don't end up in
+            // images.
+            if section.characteristics.is_removed() {
+                continue;
+            }
+            size = align(size, page_size);
+            layout.sections[i] = Some(size);
+            size += section.size_of_raw_data as u64;
+            relocations.push((i, file.relocations(section)?));
+        }
+
+        size = align(size, page_size);
+        for symbol in table.iter() {
+            let symbol = symbol?;
+            if symbol.is_common() {
+                size = align(size, COMMON_ALIGNMENT);
+                layout.commons.push((symbol.index, size));
+                size += symbol.value as u64;
+            }
+        }
+
+        // Only externals that are actually referenced have to be resolved.
+        for relocation in relocations.iter().flat_map(|r| &r.1) {
+            let symbol = layout.symbol(relocation.symbol_table_index)?;
+            if symbol.is_external()
+                && symbol.is_undefined()
+                && !symbol.is_common()
+                && layout.external(symbol.name).is_none()
+            {
+                layout.externals.push(symbol.name);
+            }
+        }
+
+        size = align(size, page_size);
+        layout.slots = size;
+        size += layout.externals.len() as u64 * layout.thunk_size();
+        layout.stubs = size;
+        if is_64_bit {
+            size += layout.externals.len() as u64 * STUB_SIZE;
+        }
+        let imports_size = size - layout.slots;
+        let size = align(size.max(1), page_size);
+        if size > u32::MAX as u64 {
+            return Err(ParseError::OutOfBounds.into());
+        }
+
+        let preferred_base = config.preferred_base;
+        let address = preferred_base.address(0);
+        let mut memory = config.backend.allocate(address, size as _)?;
+        if preferred_base.is_exact() && memory.base() != address {
+            return Err(LoadError::BaseUnavailable(address));
+        }
+        config.check_machine(machine, &*memory)?;
+        layout.base = memory.base();
+
+        let image = unsafe { slice::from_raw_parts_mut(memory.as_ptr(), memory.size()) };
+        for (section, offset) in sections.iter().zip(&layout.sections) {
+            if let Some(offset) = *offset {
+                write_bytes(image, offset as usize, file.section_data(section)?)?;
+            }
+        }
+
+        let mut imports = vec![];
+        for (n, &symbol) in layout.externals.iter().enumerate() {
+            let (module, name) = split_external(symbol, is_64_bit);
+            let defined = symbols.iter().find(|s| s.0 == symbol || s.0 == name);
+
+            let (module, address) = match (defined, module) {
+                (Some(&(_, address)), _) => (None, address),
+                (None, Some(module)) => {
+                    let address = resolve(&mut config, module, name)?;
+                    (Some(module.to_owned()), address)
+                }
+                (None, None) => {
+                    let mut found = (None, 0);
+                    for module in &search_modules {
+                        let address = resolve(&mut config, module, name)?;
+                        if address != 0 {
+                            found = (Some(module.clone()), address);
+                            break;
+                        }
+                    }
+                    found
+                }
+            };
+            if address == 0 {
+                return Err(LoadError::UnresolvedSymbol(symbol.to_owned()));
+            }
+
+            let slot = layout.slots + n as u64 * layout.thunk_size();
+            if is_64_bit {
+                write_u64(image, slot as usize, address)?;
+
+                // The displacement is relative to the end of the 6 byte instruction.
+                let stub = layout.stubs + n as u64 * STUB_SIZE;
+                let displacement = slot.wrapping_sub(stub + 6) as u32;
+                write_bytes(image, stub as usize, &[0xff, 0x25])?;
+                write_u32(image, stub as usize + 2, displacement)?;
+                write_bytes(image, stub as usize + 6, &[0xcc, 0xcc])?;
+            } else {
+                write_u32(image, slot as usize, address as u32)?;
+            }
+
+            imports.push(ObjectImport {
+                symbol: symbol.to_owned(),
+                module,
+                name: name.to_owned(),
+                address,
+                slot: layout.base + slot,
+            });
+        }
+
+        for &(i, ref relocations) in &relocations {
+            let offset = layout.sections[i].unwrap() as usize;
+            let data = &mut image[offset..offset + sections[i].size_of_raw_data as usize];
+            for relocation in relocations {
+                let symbol = layout.symbol(relocation.symbol_table_index)?;
+                let place = layout.base + offset as u64 + relocation.virtual_address as u64;
+                relocate(&layout, data, place, relocation, &symbol, &imports)?;
+            }
+        }
+
+        let mut protections = vec![];
+        for (section, offset) in sections.iter().zip(&layout.sections) {
+            match *offset {
+                Some(offset) if section.size_of_raw_data != 0 => {
+                    let protection = section_protection(section.characteristics);
+                    protections.push((offset as _, section.size_of_raw_data as _, protection));
+                }
+                _ => {}
+            }
+        }
+        if imports_size != 0 {
+            let protection = if is_64_bit {
+                Protection::ExecuteRead
+            } else {
+                Protection::ReadOnly
+            };
+            protections.push((layout.slots as _, imports_size as _, protection));
+        }
+        for &(offset, size, protection) in &protections {
+            memory.protect(offset, size, protection)?;
+        }
+
+        let mut defined = vec![];
+        for symbol in table.iter() {
+            let symbol = symbol?;
+            if symbol.is_external() && (!symbol.is_undefined() || symbol.is_common()) {
+                defined.push(LoadedSymbol {
+                    name: symbol.name.to_owned(),
+                    address: layout.address(&symbol, &imports, false)?,
+                });
+            }
+        }
+
+        let runnable = memory.is_native()
+            && Machine::host() == Some(machine)
+            && is_64_bit == (mem::size_of::<usize>() == 8);
+
+        Ok(LoadedObject {
+            memory,
+            runnable,
+            symbols: defined,
+            imports,
+            protections,
+        })
+    }
+}
+
+// Resolves `name` from `module`, including the import hooks of the config. Zero if it can't be
+// found.
+fn resolve(config: &mut LoaderConfig, module: &str, name: &str) -> Result<u64, LoadError> {
+    let symbol = ImportName::Name { hint: 0, name };
+    let handle = config.open_module(module)?;
+    let address = config.resolve_symbol(&handle, &symbol)?;
+    Ok(config.apply_import_hooks(module, &symbol, address))
+}
+
+// Applies `relocation` to `data`, the section it belongs to, which is loaded at `place` minus the
+// relocation's offset.
+fn relocate(
+    layout: &Layout,
+    data: &mut [u8],
+    place: u64,
+    relocation: &CoffRelocation,
+    symbol: &Symbol,
+    imports: &[ObjectImport],
+) -> Result<(), LoadError> {
+    let at = relocation.virtual_address as usize;
+    let out_of_range = || LoadError::RelocationOutOfRange(symbol.name.to_owned());
+    let kind = relocation.kind;
+
+    let (absolute_32, relative_32, image_relative_32, section, section_relative) =
+        if layout.is_64_bit {
+            (
+                IMAGE_REL_AMD64_ADDR32,
+                IMAGE_REL_AMD64_REL32,
+                IMAGE_REL_AMD64_ADDR32NB,
+                IMAGE_REL_AMD64_SECTION,
+                IMAGE_REL_AMD64_SECREL,
+            )
+        } else {
+            (
+                IMAGE_REL_I386_DIR32,
+                IMAGE_REL_I386_REL32,
+                IMAGE_REL_I386_DIR32NB,
+                IMAGE_REL_I386_SECTION,
+                IMAGE_REL_I386_SECREL,
+            )
+        };
+
+    if kind == IMAGE_REL_AMD64_ABSOLUTE {
+        // Padding, IMAGE_REL_I386_ABSOLUTE has the same value.
+    } else if layout.is_64_bit && kind == IMAGE_REL_AMD64_ADDR64 {
+        let target = layout.address(symbol, imports, false)?;
+        write_u64(data, at, read_u64(data, at)?.wrapping_add(target))?;
+    } else if kind == absolute_32 {
+        let target = layout.address(symbol, imports, false)?;
+        let value = (read_u32(data, at)? as u64).wrapping_add(target);
+        if layout.is_64_bit && value > u32::MAX as u64 {
+            return Err(out_of_range());
+        }
+        write_u32(data, at, value as u32)?;
+    } else if kind == image_relative_32 {
+        let target = layout
+            .address(symbol, imports, false)?
+            .wrapping_sub(layout.base);
+        write_u32(data, at, read_u32(data, at)?.wrapping_add(target as u32))?;
+    } else if kind == relative_32
+        || (layout.is_64_bit && kind > IMAGE_REL_AMD64_REL32 && kind <= IMAGE_REL_AMD64_REL32_5)
+    {
+        // REL32_1 to REL32_5 are used when that many bytes of the instruction follow the
+        // displacement.
+        let target = layout.address(symbol, imports, true)?;
+        let next = place + 4 + (kind - relative_32) as u64;
+        let addend = read_u32(data, at)? as i32 as i64;
+        let displacement = (target as i64)
+            .wrapping_sub(next as i64)
+            .wrapping_add(addend);
+        if layout.is_64_bit && displacement != displacement as i32 as i64 {
+            return Err(out_of_range());
+        }
+        write_u32(data, at, displacement as u32)?;
+    } else if kind == section {
+        let number = symbol.section_number as u16;
+        write_u16(data, at, read_u16(data, at)?.wrapping_add(number))?;
+    } else if kind == section_relative {
+        write_u32(data, at, read_u32(data, at)?.wrapping_add(symbol.value))?;
+    } else {
+        return Err(LoadError::UnsupporrtedRelocationType(RelocationType(kind)));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,889 @@
+// Read-only access to a PE file as it is laid out on disk.
+//
+// Unlike the `Loader`, nothing in here needs the image to be mapped: every RVA is translated
+// to a file offset through the section table and every read is bounds checked, so this works on
+// any host and with untrusted input.
+
+use std::ops::Range;
+use std::str;
+
+use super::structs::{
+    DirectoryEntry, DllCharacteristics, DosHeader, FileCharacteristics, Machine,
+    OptionalHeaderMagic, SectionCharacteristics, Subsystem,
+};
+
+const DOS_SIGNATURE: u16 = 0x5a4d; // MZ
+const PE_SIGNATURE: u32 = 0x0000_4550; // PE\0\0
+const OFFSET_TO_PE_HEADER: usize = 0x3c;
+
+const FILE_HEADER_SIZE: usize = 20;
+const SECTION_HEADER_SIZE: usize = 40;
+const IMPORT_DESCRIPTOR_SIZE: usize = 20;
+const EXPORT_DIRECTORY_SIZE: usize = 40;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    OutOfBounds,
+    InvalidDosSignature,
+    InvalidPeSignature,
+    UnknownOptionalHeader(OptionalHeaderMagic),
+    InvalidRva(u32),
+    InvalidName,
+    /// There is a `Rich` signature but no matching `DanS`.
+    InvalidRichHeader,
+    /// The certificate table or a signature in it is malformed.
+    InvalidSignature,
+    /// The CLR metadata root is malformed.
+    InvalidMetadata,
+    /// The load config, the CHPE metadata or the dynamic relocations are malformed.
+    InvalidLoadConfig,
+}
+
+pub(crate) fn read_bytes(buffer: &[u8], offset: usize, len: usize) -> Result<&[u8], ParseError> {
+    offset
+        .checked_add(len)
+        .and_then(|end| buffer.get(offset..end))
+        .ok_or(ParseError::OutOfBounds)
+}
+
+pub(crate) fn read_u16(buffer: &[u8], offset: usize) -> Result<u16, ParseError> {
+    let b = read_bytes(buffer, offset, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+pub(crate) fn read_u32(buffer: &[u8], offset: usize) -> Result<u32, ParseError> {
+    let b = read_bytes(buffer, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+pub(crate) fn read_u64(buffer: &[u8], offset: usize) -> Result<u64, ParseError> {
+    let b = read_bytes(buffer, offset, 8)?;
+    Ok(u64::from_le_bytes([
+        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+    ]))
+}
+
+pub(crate) fn write_bytes(
+    buffer: &mut [u8],
+    offset: usize,
+    bytes: &[u8],
+) -> Result<(), ParseError> {
+    offset
+        .checked_add(bytes.len())
+        .and_then(|end| buffer.get_mut(offset..end))
+        .ok_or(ParseError::OutOfBounds)?
+        .copy_from_slice(bytes);
+    Ok(())
+}
+
+pub(crate) fn write_u16(buffer: &mut [u8], offset: usize, value: u16) -> Result<(), ParseError> {
+    write_bytes(buffer, offset, &value.to_le_bytes())
+}
+
+pub(crate) fn write_u32(buffer: &mut [u8], offset: usize, value: u32) -> Result<(), ParseError> {
+    write_bytes(buffer, offset, &value.to_le_bytes())
+}
+
+pub(crate) fn write_u64(buffer: &mut [u8], offset: usize, value: u64) -> Result<(), ParseError> {
+    write_bytes(buffer, offset, &value.to_le_bytes())
+}
+
+pub(crate) fn read_c_str(buffer: &[u8], offset: usize) -> Result<&str, ParseError> {
+    let rest = buffer.get(offset..).ok_or(ParseError::OutOfBounds)?;
+    let len = rest
+        .iter()
+        .position(|&c| c == 0)
+        .ok_or(ParseError::OutOfBounds)?;
+    str::from_utf8(&rest[..len]).map_err(|_| ParseError::InvalidName)
+}
+
+// Offset of the data directories from the start of the optional header.
+fn data_directories_offset(is_64_bit: bool) -> usize {
+    if is_64_bit {
+        112
+    } else {
+        96
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DataDirectory {
+    pub virtual_address: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Section {
+    pub name: [u8; 8],
+    pub virtual_size: u32,
+    pub virtual_address: u32,
+    pub size_of_raw_data: u32,
+    pub p_raw_data: u32,
+    pub p_reloc: u32,
+    pub p_line_nums: u32,
+    pub num_relocations: u16,
+    pub num_line_nums: u16,
+    pub characteristics: SectionCharacteristics,
+}
+
+impl Section {
+    fn parse(buffer: &[u8], offset: usize) -> Result<Self, ParseError> {
+        let mut name = [0u8; 8];
+        name.copy_from_slice(read_bytes(buffer, offset, 8)?);
+        let characteristics = read_u32(buffer, offset + 36)?;
+
+        Ok(Self {
+            name,
+            virtual_size: read_u32(buffer, offset + 8)?,
+            virtual_address: read_u32(buffer, offset + 12)?,
+            size_of_raw_data: read_u32(buffer, offset + 16)?,
+            p_raw_data: read_u32(buffer, offset + 20)?,
+            p_reloc: read_u32(buffer, offset + 24)?,
+            p_line_nums: read_u32(buffer, offset + 28)?,
+            num_relocations: read_u16(buffer, offset + 32)?,
+            num_line_nums: read_u16(buffer, offset + 34)?,
+            characteristics: SectionCharacteristics::from_bits_retain(characteristics),
+        })
+    }
+
+    /// The section name up to the first NUL byte, if it is valid UTF-8.
+    pub fn name_str(&self) -> Option<&str> {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(8);
+        str::from_utf8(&self.name[..len]).ok()
+    }
+
+    /// Size of the section once it is mapped. Falls back to the raw size for linkers that leave
+    /// the virtual size empty.
+    pub fn mapped_size(&self) -> u32 {
+        if self.virtual_size == 0 {
+            self.size_of_raw_data
+        } else {
+            self.virtual_size
+        }
+    }
+
+    pub fn contains_rva(&self, rva: u32) -> bool {
+        rva >= self.virtual_address && rva - self.virtual_address < self.mapped_size()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PEFile<'a> {
+    buffer: &'a [u8],
+    file_header: usize,
+    optional_header: usize,
+    section_table: usize,
+    num_sections: usize,
+    is_64_bit: bool,
+}
+
+impl<'a> PEFile<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self, ParseError> {
+        if read_u16(buffer, 0)? != DOS_SIGNATURE {
+            return Err(ParseError::InvalidDosSignature);
+        }
+
+        let pe_header = read_u32(buffer, OFFSET_TO_PE_HEADER)? as usize;
+        if read_u32(buffer, pe_header)? != PE_SIGNATURE {
+            return Err(ParseError::InvalidPeSignature);
+        }
+
+        let file_header = pe_header + 4;
+        let optional_header = file_header + FILE_HEADER_SIZE;
+        let is_64_bit = match OptionalHeaderMagic(read_u16(buffer, optional_header)?) {
+            OptionalHeaderMagic::PE32 => false,
+            OptionalHeaderMagic::PE32_PLUS => true,
+            magic => return Err(ParseError::UnknownOptionalHeader(magic)),
+        };
+
+        // Everything up to the data directories has a fixed layout, check it once here so the
+        // accessors don't have to.
+        let data_directories = data_directories_offset(is_64_bit);
+        read_bytes(buffer, optional_header, data_directories)?;
+
+        let size_optional_header = read_u16(buffer, file_header + 16)? as usize;
+        let num_sections = read_u16(buffer, file_header + 2)? as usize;
+        let section_table = optional_header + size_optional_header;
+        read_bytes(buffer, section_table, num_sections * SECTION_HEADER_SIZE)?;
+
+        Ok(Self {
+            buffer,
+            file_header,
+            optional_header,
+            section_table,
+            num_sections,
+            is_64_bit,
+        })
+    }
+
+    pub fn buffer(&self) -> &'a [u8] {
+        self.buffer
+    }
+
+    pub fn is_64_bit(&self) -> bool {
+        self.is_64_bit
+    }
+
+    pub fn magic(&self) -> OptionalHeaderMagic {
+        OptionalHeaderMagic(read_u16(self.buffer, self.optional_header).unwrap())
+    }
+
+    pub fn dos_header(&self) -> DosHeader {
+        // `parse` read `offset_to_pe_header`, so the whole header is there.
+        let field = |offset: usize| read_u16(self.buffer, offset).unwrap();
+        let mut reserved = [0; 4];
+        let mut reserved2 = [0; 10];
+        for (i, r) in reserved.iter_mut().enumerate() {
+            *r = field(0x1c + i * 2);
+        }
+        for (i, r) in reserved2.iter_mut().enumerate() {
+            *r = field(0x28 + i * 2);
+        }
+
+        DosHeader {
+            signature: field(0),
+            bytes_on_last_page: field(0x02),
+            pages_in_file: field(0x04),
+            relocations: field(0x06),
+            header_paragraphs: field(0x08),
+            min_extra_paragraphs: field(0x0a),
+            max_extra_paragraphs: field(0x0c),
+            initial_ss: field(0x0e),
+            initial_sp: field(0x10),
+            checksum: field(0x12),
+            initial_ip: field(0x14),
+            initial_cs: field(0x16),
+            p_relocation_table: field(0x18),
+            overlay_number: field(0x1a),
+            reserved,
+            oem_id: field(0x24),
+            oem_info: field(0x26),
+            reserved2,
+            offset_to_pe_header: read_u32(self.buffer, OFFSET_TO_PE_HEADER).unwrap(),
+        }
+    }
+
+    /// Size of an import thunk (and of a pointer) in this image.
+    pub fn thunk_size(&self) -> usize {
+        if self.is_64_bit {
+            8
+        } else {
+            4
+        }
+    }
+
+    pub fn machine(&self) -> Machine {
+        Machine(read_u16(self.buffer, self.file_header).unwrap())
+    }
+
+    pub fn num_sections(&self) -> usize {
+        self.num_sections
+    }
+
+    pub fn time_date_stamp(&self) -> u32 {
+        read_u32(self.buffer, self.file_header + 4).unwrap()
+    }
+
+    pub fn file_characteristics(&self) -> FileCharacteristics {
+        FileCharacteristics::from_bits_retain(read_u16(self.buffer, self.file_header + 18).unwrap())
+    }
+
+    fn optional_u32(&self, offset: usize) -> u32 {
+        read_u32(self.buffer, self.optional_header + offset).unwrap()
+    }
+
+    pub fn address_of_entry_point(&self) -> u32 {
+        self.optional_u32(16)
+    }
+
+    pub fn image_base(&self) -> u64 {
+        if self.is_64_bit {
+            read_u64(self.buffer, self.optional_header + 24).unwrap()
+        } else {
+            self.optional_u32(28) as u64
+        }
+    }
+
+    pub fn section_alignment(&self) -> u32 {
+        self.optional_u32(32)
+    }
+
+    pub fn file_alignment(&self) -> u32 {
+        self.optional_u32(36)
+    }
+
+    pub fn size_of_image(&self) -> u32 {
+        self.optional_u32(56)
+    }
+
+    pub fn size_of_headers(&self) -> u32 {
+        self.optional_u32(60)
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.optional_u32(64)
+    }
+
+    /// The checksum the file should have, as computed by `CheckSumMappedFile`.
+    pub fn compute_checksum(&self) -> u32 {
+        image_checksum(self.buffer, self.optional_header + 64)
+    }
+
+    /// Whether `checksum` matches the file. Most user mode images don't set one, only drivers and
+    /// some system DLLs are required to.
+    pub fn has_valid_checksum(&self) -> bool {
+        self.checksum() == self.compute_checksum()
+    }
+
+    pub fn subsystem(&self) -> Subsystem {
+        Subsystem(read_u16(self.buffer, self.optional_header + 68).unwrap())
+    }
+
+    pub fn dll_characteristics(&self) -> DllCharacteristics {
+        let characteristics = read_u16(self.buffer, self.optional_header + 70).unwrap();
+        DllCharacteristics::from_bits_retain(characteristics)
+    }
+
+    pub fn num_of_rva_and_sizes(&self) -> u32 {
+        self.optional_u32(data_directories_offset(self.is_64_bit) - 4)
+    }
+
+    pub(crate) fn file_header_offset(&self) -> usize {
+        self.file_header
+    }
+
+    pub(crate) fn optional_header_offset(&self) -> usize {
+        self.optional_header
+    }
+
+    // Only valid if `entry` is below `num_of_rva_and_sizes`.
+    pub(crate) fn data_directory_offset(&self, entry: DirectoryEntry) -> usize {
+        self.optional_header + data_directories_offset(self.is_64_bit) + entry as usize * 8
+    }
+
+    pub(crate) fn section_header_offset(&self, index: usize) -> usize {
+        self.section_table + index * SECTION_HEADER_SIZE
+    }
+
+    /// Returns the given data directory, or `None` if the image doesn't have it.
+    pub fn data_directory(
+        &self,
+        entry: DirectoryEntry,
+    ) -> Result<Option<DataDirectory>, ParseError> {
+        let index = entry as u32;
+        if index >= self.num_of_rva_and_sizes() {
+            return Ok(None);
+        }
+
+        let offset = self.data_directory_offset(entry);
+        let directory = DataDirectory {
+            virtual_address: read_u32(self.buffer, offset)?,
+            size: read_u32(self.buffer, offset + 4)?,
+        };
+
+        if directory.virtual_address == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(directory))
+        }
+    }
+
+    pub fn sections(&self) -> Sections<'a> {
+        Sections::new(self.buffer, self.section_table, self.num_sections)
+    }
+
+    /// Translates an RVA to an offset into the file buffer.
+    ///
+    /// Returns `None` if the RVA doesn't point into the headers or into the raw data of a section,
+    /// which is also the case for the zero filled tail of a section.
+    pub fn rva_to_offset(&self, rva: u32) -> Option<usize> {
+        if rva < self.size_of_headers() {
+            return if (rva as usize) < self.buffer.len() {
+                Some(rva as usize)
+            } else {
+                None
+            };
+        }
+
+        let section = self.sections().find(|s| s.contains_rva(rva))?;
+        let delta = rva - section.virtual_address;
+        if delta >= section.size_of_raw_data {
+            return None;
+        }
+
+        let offset = section.p_raw_data as usize + delta as usize;
+        if offset < self.buffer.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `len` bytes of the file starting at the given RVA.
+    pub fn read_rva(&self, rva: u32, len: usize) -> Result<&'a [u8], ParseError> {
+        let offset = self.rva_to_offset(rva).ok_or(ParseError::InvalidRva(rva))?;
+        read_bytes(self.buffer, offset, len)
+    }
+
+    // Offset of the end of the headers or the raw data of the last section, whichever is later.
+    fn image_end(&self) -> usize {
+        self.sections()
+            .filter(|s| s.size_of_raw_data != 0)
+            .map(|s| (s.p_raw_data as usize).saturating_add(s.size_of_raw_data as usize))
+            .fold(self.size_of_headers() as usize, usize::max)
+            .min(self.buffer.len())
+    }
+
+    /// The file range of the data appended after the image, `None` if there is none.
+    ///
+    /// The certificate table isn't part of the overlay if it comes right after the image or at the
+    /// end of the file, which is where signing puts it. Should it sit anywhere else it is included.
+    pub fn overlay_range(&self) -> Result<Option<Range<usize>>, ParseError> {
+        let (mut start, mut end) = (self.image_end(), self.buffer.len());
+        if let Some(table) = self.certificate_table()? {
+            if table.start <= start && table.end > start {
+                start = table.end;
+            } else if table.end >= end && table.start < end {
+                end = table.start.max(start);
+            }
+        }
+
+        if start < end {
+            Ok(Some(start..end))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The data appended after the image, see `overlay_range`.
+    pub fn overlay(&self) -> Result<Option<&'a [u8]>, ParseError> {
+        Ok(self.overlay_range()?.map(|range| &self.buffer[range]))
+    }
+
+    pub(crate) fn read_rva_u16(&self, rva: u32) -> Result<u16, ParseError> {
+        let offset = self.rva_to_offset(rva).ok_or(ParseError::InvalidRva(rva))?;
+        read_u16(self.buffer, offset)
+    }
+
+    /// Reads a thunk sized value (32 or 64 bits depending on the image) at the given RVA.
+    pub(crate) fn read_rva_thunk(&self, rva: u32) -> Result<u64, ParseError> {
+        let offset = self.rva_to_offset(rva).ok_or(ParseError::InvalidRva(rva))?;
+        if self.is_64_bit {
+            read_u64(self.buffer, offset)
+        } else {
+            read_u32(self.buffer, offset).map(|x| x as u64)
+        }
+    }
+
+    pub(crate) fn read_rva_str(&self, rva: u32) -> Result<&'a str, ParseError> {
+        let offset = self.rva_to_offset(rva).ok_or(ParseError::InvalidRva(rva))?;
+        read_c_str(self.buffer, offset)
+    }
+
+    pub fn imports(&self) -> Result<ImportedModules<'a>, ParseError> {
+        let directory = self.data_directory(DirectoryEntry::Import)?;
+        Ok(ImportedModules {
+            file: *self,
+            current: directory.map(|d| d.virtual_address),
+        })
+    }
+
+    pub fn exports(&self) -> Result<Option<Exports<'a>>, ParseError> {
+        match self.data_directory(DirectoryEntry::Export)? {
+            None => Ok(None),
+            Some(directory) => Exports::parse(*self, directory).map(Some),
+        }
+    }
+}
+
+pub struct Sections<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'a> Sections<'a> {
+    // The caller has to have bounds checked the whole table.
+    pub(crate) fn new(buffer: &'a [u8], offset: usize, count: usize) -> Self {
+        Self {
+            buffer,
+            offset,
+            remaining: count,
+        }
+    }
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Section;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        // The whole section table was bounds checked in `PEFile::parse`.
+        let section = Section::parse(self.buffer, self.offset).unwrap();
+        self.offset += SECTION_HEADER_SIZE;
+        self.remaining -= 1;
+        Some(section)
+    }
+}
+
+pub struct ImportedModules<'a> {
+    file: PEFile<'a>,
+    current: Option<u32>,
+}
+
+impl<'a> Iterator for ImportedModules<'a> {
+    type Item = Result<ImportedModule<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rva = self.current?;
+        match ImportedModule::parse(self.file, rva) {
+            Ok(None) => {
+                self.current = None;
+                None
+            }
+            Ok(Some(module)) => {
+                self.current = rva.checked_add(IMPORT_DESCRIPTOR_SIZE as u32);
+                Some(Ok(module))
+            }
+            Err(e) => {
+                self.current = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// One import descriptor, i.e. all the functions imported from a single DLL.
+#[derive(Clone, Copy)]
+pub struct ImportedModule<'a> {
+    file: PEFile<'a>,
+    pub name: &'a str,
+    /// RVA of the import lookup table, zero if the linker only emitted the IAT.
+    pub original_first_thunk: u32,
+    pub time_date_stamp: u32,
+    pub forwarder_chain: u32,
+    /// RVA of the import address table.
+    pub first_thunk: u32,
+}
+
+impl<'a> ImportedModule<'a> {
+    fn parse(file: PEFile<'a>, rva: u32) -> Result<Option<Self>, ParseError> {
+        let descriptor = file.read_rva(rva, IMPORT_DESCRIPTOR_SIZE)?;
+        let name = read_u32(descriptor, 12)?;
+        if name == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            file,
+            name: file.read_rva_str(name)?,
+            original_first_thunk: read_u32(descriptor, 0)?,
+            time_date_stamp: read_u32(descriptor, 4)?,
+            forwarder_chain: read_u32(descriptor, 8)?,
+            first_thunk: read_u32(descriptor, 16)?,
+        }))
+    }
+
+    /// Whether the IAT on disk already contains bound addresses.
+    pub fn is_bound(&self) -> bool {
+        self.time_date_stamp != 0
+    }
+
+    pub fn entries(&self) -> ImportEntries<'a> {
+        ImportEntries {
+            file: self.file,
+            original_thunk: self.original_first_thunk,
+            thunk: self.first_thunk,
+            done: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportName<'a> {
+    Name { hint: u16, name: &'a str },
+    Ordinal(u16),
+}
+
+/// Splits a forwarder like `NTDLL.RtlAllocateHeap` or `NTDLL.#12` into the module and the symbol.
+pub fn split_forwarder<'a>(forwarder: &'a str) -> Option<(&'a str, ImportName<'a>)> {
+    let dot = forwarder.rfind('.')?;
+    let (module, symbol) = (&forwarder[..dot], &forwarder[dot + 1..]);
+    if module.is_empty() || symbol.is_empty() {
+        return None;
+    }
+
+    let symbol = if let Some(ordinal) = symbol.strip_prefix('#') {
+        ImportName::Ordinal(ordinal.parse().ok()?)
+    } else {
+        ImportName::Name {
+            hint: 0,
+            name: symbol,
+        }
+    };
+
+    Some((module, symbol))
+}
+
+// Sums the file as 16 bit words with the carries folded back in, leaving out the checksum field,
+// and adds the file size.
+fn image_checksum(buffer: &[u8], checksum_offset: usize) -> u32 {
+    let field = checksum_offset..checksum_offset + 4;
+    let byte = |i: usize| match buffer.get(i) {
+        Some(&b) if !field.contains(&i) => b as u64,
+        _ => 0,
+    };
+    let mut sum = 0u64;
+    for i in (0..buffer.len()).step_by(2) {
+        let word = byte(i) | byte(i + 1) << 8;
+        sum += word;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    (sum as u32).wrapping_add(buffer.len() as u32)
+}
+
+/// Recomputes the checksum of the PE file in `buffer` and writes it to the optional header.
+/// Returns the new checksum.
+pub fn update_checksum(buffer: &mut [u8]) -> Result<u32, ParseError> {
+    let (offset, checksum) = {
+        let file = PEFile::parse(buffer)?;
+        (file.optional_header + 64, file.compute_checksum())
+    };
+    write_u32(buffer, offset, checksum)?;
+    Ok(checksum)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImportEntry<'a> {
+    pub name: ImportName<'a>,
+    /// RVA of the IAT slot the resolved address is written to.
+    pub iat_rva: u32,
+    /// Value of the import lookup table entry, zero if there is no lookup table.
+    pub original_thunk: u64,
+    /// Value of the IAT entry on disk. Equal to `original_thunk` unless the image is bound.
+    pub bound_thunk: u64,
+}
+
+pub struct ImportEntries<'a> {
+    file: PEFile<'a>,
+    original_thunk: u32,
+    thunk: u32,
+    done: bool,
+}
+
+impl<'a> ImportEntries<'a> {
+    fn next_entry(&mut self) -> Result<Option<ImportEntry<'a>>, ParseError> {
+        let bound_thunk = self.file.read_rva_thunk(self.thunk)?;
+        let original_thunk = if self.original_thunk != 0 {
+            self.file.read_rva_thunk(self.original_thunk)?
+        } else {
+            0
+        };
+
+        // Prefer the lookup table, the IAT might already be bound.
+        let lookup = if self.original_thunk != 0 {
+            original_thunk
+        } else {
+            bound_thunk
+        };
+        if lookup == 0 {
+            return Ok(None);
+        }
+
+        let ordinal_flag = 1u64 << (self.file.thunk_size() * 8 - 1);
+        let name = if lookup & ordinal_flag != 0 {
+            ImportName::Ordinal(lookup as u16)
+        } else {
+            let rva = lookup as u32;
+            let name = rva.checked_add(2).ok_or(ParseError::InvalidRva(rva))?;
+            ImportName::Name {
+                hint: self.file.read_rva_u16(rva)?,
+                name: self.file.read_rva_str(name)?,
+            }
+        };
+
+        let entry = ImportEntry {
+            name,
+            iat_rva: self.thunk,
+            original_thunk,
+            bound_thunk,
+        };
+
+        let thunk_size = self.file.thunk_size() as u32;
+        let next = |rva: u32| rva.checked_add(thunk_size).ok_or(ParseError::InvalidRva(rva));
+        self.thunk = next(self.thunk)?;
+        if self.original_thunk != 0 {
+            self.original_thunk = next(self.original_thunk)?;
+        }
+
+        Ok(Some(entry))
+    }
+}
+
+impl<'a> Iterator for ImportEntries<'a> {
+    type Item = Result<ImportEntry<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Exports<'a> {
+    file: PEFile<'a>,
+    directory: DataDirectory,
+    pub name: &'a str,
+    pub time_date_stamp: u32,
+    pub ordinal_base: u32,
+    functions: &'a [u8],
+    names: &'a [u8],
+    name_ordinals: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Export<'a> {
+    pub ordinal: u32,
+    pub rva: u32,
+    pub name: Option<&'a str>,
+    /// Set if the export is forwarded to another DLL, e.g. `NTDLL.RtlAllocateHeap`.
+    pub forwarder: Option<&'a str>,
+}
+
+impl<'a> Exports<'a> {
+    fn parse(file: PEFile<'a>, directory: DataDirectory) -> Result<Self, ParseError> {
+        let header = file.read_rva(directory.virtual_address, EXPORT_DIRECTORY_SIZE)?;
+        let num_functions = read_u32(header, 20)? as usize;
+        let num_names = read_u32(header, 24)? as usize;
+
+        let table = |rva: u32, len: usize| {
+            if len == 0 {
+                Ok(&[][..])
+            } else {
+                file.read_rva(rva, len)
+            }
+        };
+
+        Ok(Self {
+            file,
+            directory,
+            name: file.read_rva_str(read_u32(header, 12)?)?,
+            time_date_stamp: read_u32(header, 4)?,
+            ordinal_base: read_u32(header, 16)?,
+            functions: table(read_u32(header, 28)?, num_functions.saturating_mul(4))?,
+            names: table(read_u32(header, 32)?, num_names.saturating_mul(4))?,
+            name_ordinals: table(read_u32(header, 36)?, num_names.saturating_mul(2))?,
+        })
+    }
+
+    fn export(&self, index: usize, name: Option<&'a str>) -> Result<Export<'a>, ParseError> {
+        let rva = read_u32(self.functions, index * 4)?;
+        let directory_end = self.directory.virtual_address as u64 + self.directory.size as u64;
+        let forwarder = if rva >= self.directory.virtual_address && (rva as u64) < directory_end {
+            Some(self.file.read_rva_str(rva)?)
+        } else {
+            None
+        };
+
+        Ok(Export {
+            ordinal: self.ordinal_base.wrapping_add(index as u32),
+            rva,
+            name,
+            forwarder,
+        })
+    }
+
+    /// Iterates over all exported functions in ordinal order, including the ones that are only
+    /// exported by ordinal.
+    pub fn iter(&self) -> Result<ExportIterator<'a>, ParseError> {
+        let mut names = vec![None; self.functions.len() / 4];
+        for i in 0..self.names.len() / 4 {
+            let index = read_u16(self.name_ordinals, i * 2)? as usize;
+            let name = self.file.read_rva_str(read_u32(self.names, i * 4)?)?;
+            match names.get_mut(index) {
+                Some(slot) => *slot = Some(name),
+                None => return Err(ParseError::OutOfBounds),
+            }
+        }
+
+        Ok(ExportIterator {
+            exports: *self,
+            names,
+            index: 0,
+        })
+    }
+
+    pub fn by_name(&self, name: &str) -> Result<Option<Export<'a>>, ParseError> {
+        for i in 0..self.names.len() / 4 {
+            let found = self.file.read_rva_str(read_u32(self.names, i * 4)?)?;
+            if found == name {
+                let index = read_u16(self.name_ordinals, i * 2)? as usize;
+                return self.export(index, Some(found)).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn by_ordinal(&self, ordinal: u32) -> Result<Option<Export<'a>>, ParseError> {
+        let index = ordinal.wrapping_sub(self.ordinal_base) as usize;
+        if index >= self.functions.len() / 4 {
+            return Ok(None);
+        }
+
+        let mut name = None;
+        for i in 0..self.names.len() / 4 {
+            if read_u16(self.name_ordinals, i * 2)? as usize == index {
+                name = Some(self.file.read_rva_str(read_u32(self.names, i * 4)?)?);
+                break;
+            }
+        }
+
+        self.export(index, name).map(Some)
+    }
+}
+
+pub struct ExportIterator<'a> {
+    exports: Exports<'a>,
+    names: Vec<Option<&'a str>>,
+    index: usize,
+}
+
+impl<'a> Iterator for ExportIterator<'a> {
+    type Item = Result<Export<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.names.len() {
+            let index = self.index;
+            self.index += 1;
+
+            match read_u32(self.exports.functions, index * 4) {
+                // Unused slot in the address table.
+                Ok(0) => continue,
+                Ok(_) => return Some(self.exports.export(index, self.names[index])),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,181 @@
+// Keeps track of images loaded by us, so they can import from each other without going through
+// the OS loader.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use super::{ExportTarget, LoadError, LoadedPEFile, Loader};
+use crate::config::{ImportSymbol, LoaderConfig};
+use crate::pe_file::ImportName;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub base: u64,
+    pub size: usize,
+    pub ref_count: usize,
+}
+
+struct Module {
+    name: String,
+    image: LoadedPEFile,
+    ref_count: usize,
+}
+
+impl Module {
+    fn info(&self) -> ModuleInfo {
+        ModuleInfo {
+            name: self.name.clone(),
+            base: self.image.base(),
+            size: self.image.size(),
+            ref_count: self.ref_count,
+        }
+    }
+}
+
+// Module names are compared like the Windows loader does: case insensitive, without the directory
+// and with `.dll` appended if there is no extension.
+fn normalize_name(name: &str) -> String {
+    let name = name.rsplit(['\\', '/']).next().unwrap_or(name);
+    let mut name = name.to_ascii_lowercase();
+    if !name.contains('.') {
+        name.push_str(".dll");
+    }
+    name
+}
+
+/// Modules loaded through the registry.
+///
+/// The registry is cheap to clone, clones refer to the same set of modules. A `LoaderConfig` with a
+/// registry resolves imports from the registered modules before asking its `ImportResolver`.
+#[derive(Clone, Default)]
+pub struct ModuleRegistry {
+    modules: Rc<RefCell<Vec<Module>>>,
+}
+
+impl ModuleRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads `pe_buffer` as `name`, or increments the reference count if a module with that name is
+    /// already loaded.
+    ///
+    /// Imports of the module are resolved from this registry first, then by the resolver of
+    /// `config`. Every registered module it imports from is referenced until it is freed.
+    pub fn load<T: AsRef<[u8]>>(
+        &self,
+        name: &str,
+        pe_buffer: T,
+        config: LoaderConfig,
+    ) -> Result<ModuleInfo, LoadError> {
+        let name = normalize_name(name);
+        if let Some(module) = self
+            .modules
+            .borrow_mut()
+            .iter_mut()
+            .find(|m| m.name == name)
+        {
+            module.ref_count += 1;
+            return Ok(module.info());
+        }
+
+        // Not borrowed while loading, the loader looks up imports in the registry.
+        let image = Loader::with_config(pe_buffer, config.registry(self.clone())).load()?;
+        for module in self.modules.borrow_mut().iter_mut() {
+            if image.dependencies.contains(&module.name) {
+                module.ref_count += 1;
+            }
+        }
+        let module = Module {
+            name,
+            image,
+            ref_count: 1,
+        };
+        let info = module.info();
+        self.modules.borrow_mut().push(module);
+
+        Ok(info)
+    }
+
+    /// Decrements the reference count of `name` and unloads it once it reaches zero, which also
+    /// frees the modules it imports from. Returns the new reference count, or `None` if the module
+    /// isn't loaded.
+    pub fn free(&self, name: &str) -> Option<usize> {
+        let name = normalize_name(name);
+        let mut unloaded = {
+            let mut modules = self.modules.borrow_mut();
+            let index = modules.iter().position(|m| m.name == name)?;
+
+            modules[index].ref_count -= 1;
+            let ref_count = modules[index].ref_count;
+            if ref_count != 0 {
+                return Some(ref_count);
+            }
+            modules.remove(index)
+        };
+
+        // Unmapped before its dependencies, and not borrowed while freeing them.
+        let dependencies = mem::take(&mut unloaded.image.dependencies);
+        drop(unloaded);
+        for dependency in &dependencies {
+            self.free(dependency);
+        }
+        Some(0)
+    }
+
+    pub fn get(&self, name: &str) -> Option<ModuleInfo> {
+        let name = normalize_name(name);
+        self.modules
+            .borrow()
+            .iter()
+            .find(|m| m.name == name)
+            .map(Module::info)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// The module whose image contains `address`.
+    pub fn module_at(&self, address: u64) -> Option<ModuleInfo> {
+        self.modules
+            .borrow()
+            .iter()
+            .find(|m| m.image.contains(address))
+            .map(Module::info)
+    }
+
+    pub fn modules(&self) -> Vec<ModuleInfo> {
+        self.modules.borrow().iter().map(Module::info).collect()
+    }
+
+    /// Address of an export of the module `name`, `None` if the module isn't loaded or doesn't
+    /// export it.
+    pub fn export_address<S: Into<ImportSymbol>>(&self, name: &str, symbol: S) -> Option<u64> {
+        let name = normalize_name(name);
+        self.modules
+            .borrow()
+            .iter()
+            .find(|m| m.name == name)
+            .and_then(|m| m.image.export_address(symbol))
+    }
+
+    pub(crate) fn export(&self, name: &str, symbol: &ImportName) -> Option<ExportTarget> {
+        self.modules
+            .borrow()
+            .iter()
+            .find(|m| m.name == name)
+            .and_then(|m| m.image.export(symbol).cloned())
+    }
+
+    pub(crate) fn normalized(&self, name: &str) -> Option<String> {
+        let name = normalize_name(name);
+        if self.modules.borrow().iter().any(|m| m.name == name) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+}
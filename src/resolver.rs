@@ -0,0 +1,68 @@
+// Resolves the imports of an image to addresses.
+
+#[cfg(windows)]
+use std::ffi::CString;
+#[cfg(windows)]
+use std::os::raw::c_char;
+
+#[cfg(windows)]
+use kernel32::{GetProcAddress, LoadLibraryA};
+
+use super::LoadError;
+use crate::pe_file::ImportName;
+
+pub trait ImportResolver {
+    /// Loads `module` and returns a handle that is passed to `resolve`.
+    fn load_module(&mut self, module: &str) -> Result<u64, LoadError>;
+
+    /// Returns the address of `symbol` in the module, or zero if it doesn't export it.
+    fn resolve(&mut self, module: u64, symbol: &ImportName) -> u64;
+}
+
+/// Resolves imports from DLLs loaded into the current process with `LoadLibraryA`.
+#[cfg(windows)]
+#[derive(Default)]
+pub struct WindowsResolver;
+
+#[cfg(windows)]
+impl ImportResolver for WindowsResolver {
+    fn load_module(&mut self, module: &str) -> Result<u64, LoadError> {
+        let name = CString::new(module).map_err(|_| LoadError::LoadLibraryFailed)?;
+        let hmod = unsafe { LoadLibraryA(name.as_ptr()) };
+
+        if hmod.is_null() {
+            Err(LoadError::LoadLibraryFailed)
+        } else {
+            Ok(hmod as u64)
+        }
+    }
+
+    fn resolve(&mut self, module: u64, symbol: &ImportName) -> u64 {
+        let hmod = module as _;
+        let function = match *symbol {
+            ImportName::Ordinal(ordinal) => unsafe {
+                GetProcAddress(hmod, ordinal as usize as *const c_char)
+            },
+            ImportName::Name { name, .. } => match CString::new(name) {
+                Ok(name) => unsafe { GetProcAddress(hmod, name.as_ptr()) },
+                Err(_) => return 0,
+            },
+        };
+
+        function as u64
+    }
+}
+
+/// Fails to load any module. Used where there is no OS loader to fall back to.
+#[derive(Default)]
+pub struct NoImports;
+
+impl ImportResolver for NoImports {
+    fn load_module(&mut self, _: &str) -> Result<u64, LoadError> {
+        Err(LoadError::LoadLibraryFailed)
+    }
+
+    fn resolve(&mut self, _: u64, _: &ImportName) -> u64 {
+        0
+    }
+}
@@ -0,0 +1,147 @@
+// The Rich header Microsoft's linker leaves between the DOS stub and the PE header.
+//
+// It lists the tools (compilers, assembler, linker, ...) that produced the object files and how
+// many objects each produced, XOR masked with a key that doubles as a checksum over the DOS
+// header, the stub and the decoded entries:
+//
+//     "DanS" ^ key, 0 ^ key, 0 ^ key, 0 ^ key
+//     comp id ^ key, count ^ key        (one pair per tool)
+//     "Rich", key
+
+use crate::pe_file::{read_u32, PEFile, ParseError};
+
+const DOS_HEADER_SIZE: usize = 0x40;
+const OFFSET_TO_PE_HEADER: usize = 0x3c;
+const RICH_SIGNATURE: u32 = 0x6863_6952; // Rich
+const DANS_SIGNATURE: u32 = 0x536e_6144; // DanS
+// The signature is followed by three zeroed (before masking) dwords.
+const RICH_HEADER_START: usize = 16;
+
+/// A tool that contributed to the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RichEntry {
+    /// Identifies the tool and its version, e.g. the C++ compiler of some Visual Studio release.
+    pub product_id: u16,
+    pub build: u16,
+    /// Number of object files produced by the tool.
+    pub count: u32,
+}
+
+impl RichEntry {
+    /// `product_id` and `build` as they are stored.
+    pub fn comp_id(&self) -> u32 {
+        (self.product_id as u32) << 16 | self.build as u32
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RichHeader {
+    /// File offset of the `DanS` signature.
+    pub offset: usize,
+    /// Size up to and including the key after `Rich`.
+    pub size: usize,
+    pub key: u32,
+    pub entries: Vec<RichEntry>,
+    /// The checksum recomputed from the file, equal to `key` unless the header or the DOS header
+    /// and stub in front of it were modified.
+    pub checksum: u32,
+}
+
+impl RichHeader {
+    pub fn is_valid(&self) -> bool {
+        self.key == self.checksum
+    }
+
+    /// The entries as they are stored, but unmasked: `DanS`, the padding and comp id / count
+    /// pairs, without the trailing `Rich` and key.
+    pub fn decoded(&self) -> Vec<u8> {
+        let mut decoded = vec![];
+        for &value in &[DANS_SIGNATURE, 0, 0, 0] {
+            decoded.extend_from_slice(&value.to_le_bytes());
+        }
+        for entry in &self.entries {
+            decoded.extend_from_slice(&entry.comp_id().to_le_bytes());
+            decoded.extend_from_slice(&entry.count.to_le_bytes());
+        }
+        decoded
+    }
+}
+
+/// The checksum of a Rich header at `dos.len()`, where `dos` is everything in front of it.
+pub(crate) fn rich_checksum(dos: &[u8], entries: &[RichEntry]) -> u32 {
+    let mut checksum = dos.len() as u32;
+    for (i, &b) in dos.iter().enumerate() {
+        // `offset_to_pe_header` isn't known yet when the linker computes the checksum.
+        if (OFFSET_TO_PE_HEADER..OFFSET_TO_PE_HEADER + 4).contains(&i) {
+            continue;
+        }
+        checksum = checksum.wrapping_add((b as u32).rotate_left(i as u32));
+    }
+    for entry in entries {
+        checksum = checksum.wrapping_add(entry.comp_id().rotate_left(entry.count));
+    }
+    checksum
+}
+
+/// Serialises a Rich header to be placed at `dos.len()`.
+pub(crate) fn encode_rich_header(dos: &[u8], entries: &[RichEntry]) -> Vec<u8> {
+    let key = rich_checksum(dos, entries);
+    let mut header = vec![];
+    for &value in &[DANS_SIGNATURE, 0, 0, 0] {
+        header.extend_from_slice(&(value ^ key).to_le_bytes());
+    }
+    for entry in entries {
+        header.extend_from_slice(&(entry.comp_id() ^ key).to_le_bytes());
+        header.extend_from_slice(&(entry.count ^ key).to_le_bytes());
+    }
+    header.extend_from_slice(&RICH_SIGNATURE.to_le_bytes());
+    header.extend_from_slice(&key.to_le_bytes());
+    header
+}
+
+impl<'a> PEFile<'a> {
+    /// The Rich header in the DOS stub, `None` if there is none.
+    pub fn rich_header(&self) -> Result<Option<RichHeader>, ParseError> {
+        let buffer = self.buffer();
+        let end = (self.dos_header().offset_to_pe_header as usize).min(buffer.len());
+
+        let rich = match (DOS_HEADER_SIZE..=end.saturating_sub(4))
+            .step_by(4)
+            .find(|&offset| read_u32(buffer, offset) == Ok(RICH_SIGNATURE))
+        {
+            Some(rich) => rich,
+            None => return Ok(None),
+        };
+        let key = read_u32(buffer, rich + 4)?;
+
+        let offset = (DOS_HEADER_SIZE..rich)
+            .step_by(4)
+            .rev()
+            .find(|&offset| read_u32(buffer, offset).map(|v| v ^ key) == Ok(DANS_SIGNATURE))
+            .ok_or(ParseError::InvalidRichHeader)?;
+        let start = offset + RICH_HEADER_START;
+        if start > rich || (rich - start) % 8 != 0 {
+            return Err(ParseError::InvalidRichHeader);
+        }
+
+        let mut entries = vec![];
+        for pair in (start..rich).step_by(8) {
+            let comp_id = read_u32(buffer, pair)? ^ key;
+            entries.push(RichEntry {
+                product_id: (comp_id >> 16) as u16,
+                build: comp_id as u16,
+                count: read_u32(buffer, pair + 4)? ^ key,
+            });
+        }
+
+        Ok(Some(RichHeader {
+            offset,
+            size: rich + 8 - offset,
+            key,
+            checksum: rich_checksum(&buffer[..offset], &entries),
+            entries,
+        }))
+    }
+}
@@ -2,8 +2,6 @@
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
-use super::Loader;
-
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub(crate) struct RVA<StorageType: Copy + Into<u64>, ResolvedType: From<u64>> {
@@ -12,6 +10,13 @@ pub(crate) struct RVA<StorageType: Copy + Into<u64>, ResolvedType: From<u64>> {
 }
 
 impl<StorageType: Copy + Into<u64>, ResolvedType: From<u64>> RVA<StorageType, ResolvedType> {
+    pub(crate) fn new(value: StorageType) -> Self {
+        Self {
+            value,
+            _p: PhantomData,
+        }
+    }
+
     pub(crate) fn resolve(&self, base: u64) -> ResolvedType {
         (base + (self.value.into())).into()
     }
@@ -26,9 +31,9 @@ pub(crate) struct Pointer<T> {
     pub(crate) p: T,
 }
 
-impl<T> Into<Pointer<*const T>> for Pointer<*mut T> {
-    fn into(self) -> Pointer<*const T> {
-        Pointer { p: self.p as _ }
+impl<T> From<Pointer<*mut T>> for Pointer<*const T> {
+    fn from(pointer: Pointer<*mut T>) -> Self {
+        Self { p: pointer.p as _ }
     }
 }
 
@@ -0,0 +1,450 @@
+// Owned copies of everything the parser knows about an image.
+//
+// Most of what `PEFile` returns borrows from the file. A `Snapshot` doesn't, so it can outlive the
+// buffer, be compared against snapshots of other versions of the image and, with the `serde`
+// feature, be stored.
+
+use crate::clr::ClrHeader;
+use crate::config::ImportSymbol;
+use crate::directories::{DebugDirectory, Relocation, Resource, Tls};
+use crate::hybrid::{ChpeMetadata, CodeRange};
+use crate::load_config::LoadConfig;
+use crate::pe_file::{
+    read_u16, read_u32, read_u64, DataDirectory, ImportName, PEFile, ParseError, Section,
+};
+use crate::rich::RichHeader;
+use crate::structs::{DirectoryEntry, DosHeader, DIRECTORIES};
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileHeaderSnapshot {
+    pub machine: u16,
+    pub number_of_sections: u16,
+    pub time_date_stamp: u32,
+    pub pointer_to_symbol_table: u32,
+    pub number_of_symbols: u32,
+    pub size_of_optional_header: u16,
+    pub characteristics: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OptionalHeaderSnapshot {
+    pub magic: u16,
+    pub major_linker_version: u8,
+    pub minor_linker_version: u8,
+    pub size_of_code: u32,
+    pub size_of_initialized_data: u32,
+    pub size_of_uninitialized_data: u32,
+    pub address_of_entry_point: u32,
+    pub base_of_code: u32,
+    /// Only PE32 images have this field.
+    pub base_of_data: Option<u32>,
+    pub image_base: u64,
+    pub section_alignment: u32,
+    pub file_alignment: u32,
+    pub major_operating_system_version: u16,
+    pub minor_operating_system_version: u16,
+    pub major_image_version: u16,
+    pub minor_image_version: u16,
+    pub major_subsystem_version: u16,
+    pub minor_subsystem_version: u16,
+    pub win32_version_value: u32,
+    pub size_of_image: u32,
+    pub size_of_headers: u32,
+    pub checksum: u32,
+    pub subsystem: u16,
+    pub dll_characteristics: u16,
+    pub size_of_stack_reserve: u64,
+    pub size_of_stack_commit: u64,
+    pub size_of_heap_reserve: u64,
+    pub size_of_heap_commit: u64,
+    pub loader_flags: u32,
+    pub number_of_rva_and_sizes: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DataDirectorySnapshot {
+    pub entry: DirectoryEntry,
+    pub virtual_address: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImportEntrySnapshot {
+    pub symbol: ImportSymbol,
+    /// Zero for imports by ordinal.
+    pub hint: u16,
+    pub iat_rva: u32,
+    pub original_thunk: u64,
+    pub bound_thunk: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImportedModuleSnapshot {
+    pub name: String,
+    pub original_first_thunk: u32,
+    pub time_date_stamp: u32,
+    pub forwarder_chain: u32,
+    pub first_thunk: u32,
+    pub entries: Vec<ImportEntrySnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExportSnapshot {
+    pub ordinal: u32,
+    pub rva: u32,
+    pub name: Option<String>,
+    pub forwarder: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExportsSnapshot {
+    pub name: String,
+    pub time_date_stamp: u32,
+    pub ordinal_base: u32,
+    pub exports: Vec<ExportSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RelocationBlockSnapshot {
+    pub page_rva: u32,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CodeViewSnapshot {
+    pub guid: [u8; 16],
+    pub age: u32,
+    pub path: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CertificateSnapshot {
+    pub offset: usize,
+    pub revision: u16,
+    pub kind: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OverlaySnapshot {
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SymbolSnapshot {
+    pub index: u32,
+    pub name: String,
+    pub value: u32,
+    pub section_number: i16,
+    pub kind: u16,
+    pub storage_class: u8,
+    pub aux: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetadataStreamSnapshot {
+    pub name: String,
+    pub offset: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetadataSnapshot {
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub version: String,
+    pub flags: u16,
+    pub streams: Vec<MetadataStreamSnapshot>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DynamicRelocationSnapshot {
+    pub symbol: u64,
+    pub fixups: Vec<u8>,
+}
+
+/// Everything `PEFile` parses, owned.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    pub dos_header: DosHeader,
+    pub rich_header: Option<RichHeader>,
+    pub file_header: FileHeaderSnapshot,
+    pub optional_header: OptionalHeaderSnapshot,
+    /// Only the directories the image has.
+    pub data_directories: Vec<DataDirectorySnapshot>,
+    pub sections: Vec<Section>,
+    pub imports: Vec<ImportedModuleSnapshot>,
+    pub exports: Option<ExportsSnapshot>,
+    pub relocations: Vec<RelocationBlockSnapshot>,
+    pub tls: Option<Tls>,
+    pub debug: Vec<DebugDirectory>,
+    pub codeview: Option<CodeViewSnapshot>,
+    pub resources: Vec<Resource>,
+    pub certificates: Vec<CertificateSnapshot>,
+    pub overlay: Option<OverlaySnapshot>,
+    /// Empty unless the image has a COFF symbol table.
+    pub symbols: Vec<SymbolSnapshot>,
+    pub clr_header: Option<ClrHeader>,
+    pub clr_metadata: Option<MetadataSnapshot>,
+    pub load_config: Option<LoadConfig>,
+    pub dynamic_relocations: Vec<DynamicRelocationSnapshot>,
+    pub chpe_metadata: Option<ChpeMetadata>,
+    pub code_ranges: Vec<CodeRange>,
+}
+
+impl<'a> PEFile<'a> {
+    /// Parses everything in the image, fails if any part of it is malformed.
+    pub fn snapshot(&self) -> Result<Snapshot, ParseError> {
+        Ok(Snapshot {
+            dos_header: self.dos_header(),
+            rich_header: self.rich_header()?,
+            file_header: self.file_header_snapshot()?,
+            optional_header: self.optional_header_snapshot()?,
+            data_directories: self.data_directories_snapshot()?,
+            sections: self.sections().collect(),
+            imports: self.imports_snapshot()?,
+            exports: self.exports_snapshot()?,
+            relocations: self.relocations_snapshot()?,
+            tls: self.tls()?,
+            debug: self.debug_directories()?,
+            codeview: self.codeview()?.map(|codeview| CodeViewSnapshot {
+                guid: codeview.guid,
+                age: codeview.age,
+                path: codeview.path.to_owned(),
+            }),
+            resources: self.resources()?,
+            certificates: self
+                .certificates()?
+                .into_iter()
+                .map(|certificate| CertificateSnapshot {
+                    offset: certificate.offset,
+                    revision: certificate.revision,
+                    kind: certificate.kind,
+                    data: certificate.data.to_vec(),
+                })
+                .collect(),
+            overlay: self.overlay_range()?.map(|range| OverlaySnapshot {
+                offset: range.start,
+                data: self.buffer()[range].to_vec(),
+            }),
+            symbols: self.symbols_snapshot()?,
+            clr_header: self.clr_header()?,
+            clr_metadata: self.clr_metadata()?.map(|metadata| MetadataSnapshot {
+                major_version: metadata.major_version,
+                minor_version: metadata.minor_version,
+                version: metadata.version.to_owned(),
+                flags: metadata.flags,
+                streams: metadata
+                    .streams
+                    .iter()
+                    .map(|stream| MetadataStreamSnapshot {
+                        name: stream.name.to_owned(),
+                        offset: stream.offset,
+                        data: stream.data.to_vec(),
+                    })
+                    .collect(),
+            }),
+            load_config: self.load_config()?,
+            dynamic_relocations: self
+                .dynamic_relocations()?
+                .into_iter()
+                .map(|relocation| DynamicRelocationSnapshot {
+                    symbol: relocation.symbol,
+                    fixups: relocation.fixups.to_vec(),
+                })
+                .collect(),
+            chpe_metadata: self.chpe_metadata()?,
+            code_ranges: self.code_ranges()?,
+        })
+    }
+
+    fn file_header_snapshot(&self) -> Result<FileHeaderSnapshot, ParseError> {
+        let buffer = self.buffer();
+        let offset = self.file_header_offset();
+        Ok(FileHeaderSnapshot {
+            machine: read_u16(buffer, offset)?,
+            number_of_sections: read_u16(buffer, offset + 2)?,
+            time_date_stamp: read_u32(buffer, offset + 4)?,
+            pointer_to_symbol_table: read_u32(buffer, offset + 8)?,
+            number_of_symbols: read_u32(buffer, offset + 12)?,
+            size_of_optional_header: read_u16(buffer, offset + 16)?,
+            characteristics: read_u16(buffer, offset + 18)?,
+        })
+    }
+
+    fn optional_header_snapshot(&self) -> Result<OptionalHeaderSnapshot, ParseError> {
+        let buffer = self.buffer();
+        let offset = self.optional_header_offset();
+        let u16_at = |field: usize| read_u16(buffer, offset + field);
+        let u32_at = |field: usize| read_u32(buffer, offset + field);
+
+        // The stack and heap sizes are pointer sized, which also moves everything after them.
+        let (base_of_data, sizes, rest) = if self.is_64_bit() {
+            let u64_at = |field: usize| read_u64(buffer, offset + field);
+            let sizes = [u64_at(72)?, u64_at(80)?, u64_at(88)?, u64_at(96)?];
+            (None, sizes, 104)
+        } else {
+            let u64_at = |field: usize| u32_at(field).map(u64::from);
+            let sizes = [u64_at(72)?, u64_at(76)?, u64_at(80)?, u64_at(84)?];
+            (Some(u32_at(24)?), sizes, 88)
+        };
+
+        Ok(OptionalHeaderSnapshot {
+            magic: u16_at(0)?,
+            major_linker_version: buffer[offset + 2],
+            minor_linker_version: buffer[offset + 3],
+            size_of_code: u32_at(4)?,
+            size_of_initialized_data: u32_at(8)?,
+            size_of_uninitialized_data: u32_at(12)?,
+            address_of_entry_point: u32_at(16)?,
+            base_of_code: u32_at(20)?,
+            base_of_data,
+            image_base: self.image_base(),
+            section_alignment: u32_at(32)?,
+            file_alignment: u32_at(36)?,
+            major_operating_system_version: u16_at(40)?,
+            minor_operating_system_version: u16_at(42)?,
+            major_image_version: u16_at(44)?,
+            minor_image_version: u16_at(46)?,
+            major_subsystem_version: u16_at(48)?,
+            minor_subsystem_version: u16_at(50)?,
+            win32_version_value: u32_at(52)?,
+            size_of_image: u32_at(56)?,
+            size_of_headers: u32_at(60)?,
+            checksum: u32_at(64)?,
+            subsystem: u16_at(68)?,
+            dll_characteristics: u16_at(70)?,
+            size_of_stack_reserve: sizes[0],
+            size_of_stack_commit: sizes[1],
+            size_of_heap_reserve: sizes[2],
+            size_of_heap_commit: sizes[3],
+            loader_flags: u32_at(rest)?,
+            number_of_rva_and_sizes: u32_at(rest + 4)?,
+        })
+    }
+
+    fn data_directories_snapshot(&self) -> Result<Vec<DataDirectorySnapshot>, ParseError> {
+        let mut directories = vec![];
+        for &entry in DIRECTORIES.iter() {
+            if let Some(DataDirectory {
+                virtual_address,
+                size,
+            }) = self.data_directory(entry)?
+            {
+                directories.push(DataDirectorySnapshot {
+                    entry,
+                    virtual_address,
+                    size,
+                });
+            }
+        }
+        Ok(directories)
+    }
+
+    fn imports_snapshot(&self) -> Result<Vec<ImportedModuleSnapshot>, ParseError> {
+        let mut modules = vec![];
+        for module in self.imports()? {
+            let module = module?;
+            let mut entries = vec![];
+            for entry in module.entries() {
+                let entry = entry?;
+                entries.push(ImportEntrySnapshot {
+                    symbol: entry.name.into(),
+                    hint: match entry.name {
+                        ImportName::Name { hint, .. } => hint,
+                        ImportName::Ordinal(_) => 0,
+                    },
+                    iat_rva: entry.iat_rva,
+                    original_thunk: entry.original_thunk,
+                    bound_thunk: entry.bound_thunk,
+                });
+            }
+
+            modules.push(ImportedModuleSnapshot {
+                name: module.name.to_owned(),
+                original_first_thunk: module.original_first_thunk,
+                time_date_stamp: module.time_date_stamp,
+                forwarder_chain: module.forwarder_chain,
+                first_thunk: module.first_thunk,
+                entries,
+            });
+        }
+        Ok(modules)
+    }
+
+    fn exports_snapshot(&self) -> Result<Option<ExportsSnapshot>, ParseError> {
+        let exports = match self.exports()? {
+            None => return Ok(None),
+            Some(exports) => exports,
+        };
+
+        let mut snapshots = vec![];
+        for export in exports.iter()? {
+            let export = export?;
+            snapshots.push(ExportSnapshot {
+                ordinal: export.ordinal,
+                rva: export.rva,
+                name: export.name.map(|n| n.to_owned()),
+                forwarder: export.forwarder.map(|f| f.to_owned()),
+            });
+        }
+
+        Ok(Some(ExportsSnapshot {
+            name: exports.name.to_owned(),
+            time_date_stamp: exports.time_date_stamp,
+            ordinal_base: exports.ordinal_base,
+            exports: snapshots,
+        }))
+    }
+
+    fn symbols_snapshot(&self) -> Result<Vec<SymbolSnapshot>, ParseError> {
+        let table = match self.symbol_table()? {
+            None => return Ok(vec![]),
+            Some(table) => table,
+        };
+
+        let mut symbols = vec![];
+        for symbol in table.iter() {
+            let symbol = symbol?;
+            symbols.push(SymbolSnapshot {
+                index: symbol.index,
+                name: symbol.name.to_owned(),
+                value: symbol.value,
+                section_number: symbol.section_number,
+                kind: symbol.kind,
+                storage_class: symbol.storage_class,
+                aux: symbol.aux.to_vec(),
+            });
+        }
+        Ok(symbols)
+    }
+
+    fn relocations_snapshot(&self) -> Result<Vec<RelocationBlockSnapshot>, ParseError> {
+        let mut blocks = vec![];
+        for block in self.relocations()? {
+            let block = block?;
+            blocks.push(RelocationBlockSnapshot {
+                page_rva: block.page_rva,
+                relocations: block.relocations().collect(),
+            });
+        }
+        Ok(blocks)
+    }
+}
@@ -0,0 +1,80 @@
+// Getting images into memory without reading (or copying) the whole file.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use super::LoadError;
+use crate::pe_file::{read_u16, read_u32, PEFile, ParseError};
+
+const DOS_HEADER_SIZE: usize = 0x40;
+const OFFSET_TO_PE_HEADER: usize = 0x3c;
+// Signature and file header
+const PE_HEADER_SIZE: usize = 4 + 20;
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// Maps the file at `path` read-only.
+///
+/// The mapping is only valid as long as the file isn't modified by someone else while it is
+/// mapped, which is the same assumption the Windows loader makes about image files.
+pub(crate) fn map_file(path: &Path) -> Result<Mmap, LoadError> {
+    let file = File::open(path)?;
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(buffer)
+}
+
+/// Reads the headers and the raw data of every section from `reader`.
+///
+/// The returned buffer has the same layout as the file, but anything that isn't part of the
+/// headers or a section (the overlay, COFF symbols, certificates) is left zeroed instead of being
+/// read.
+pub(crate) fn read_image<R: Read + Seek>(reader: &mut R) -> Result<Vec<u8>, LoadError> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    // Don't allocate anything for headers that claim to be bigger than the file.
+    let check_size = |size: usize| {
+        if size as u64 > file_size {
+            Err(ParseError::OutOfBounds)
+        } else {
+            Ok(size)
+        }
+    };
+
+    let mut headers = vec![0u8; check_size(DOS_HEADER_SIZE)?];
+    read_at(reader, 0, &mut headers)?;
+
+    let pe_header = read_u32(&headers, OFFSET_TO_PE_HEADER)? as usize;
+    headers.resize(check_size(pe_header + PE_HEADER_SIZE)?, 0);
+    read_at(reader, 0, &mut headers)?;
+
+    let num_sections = read_u16(&headers, pe_header + 6)? as usize;
+    let size_optional_header = read_u16(&headers, pe_header + 20)? as usize;
+    let headers_end =
+        pe_header + PE_HEADER_SIZE + size_optional_header + num_sections * SECTION_HEADER_SIZE;
+    headers.resize(check_size(headers_end)?, 0);
+    read_at(reader, 0, &mut headers)?;
+
+    let file = PEFile::parse(&headers)?;
+    let sections = file.sections().collect::<Vec<_>>();
+
+    let size_of_headers = (file.size_of_headers() as usize).max(headers_end);
+    let size = sections
+        .iter()
+        .map(|s| s.p_raw_data as usize + s.size_of_raw_data as usize)
+        .fold(size_of_headers, usize::max);
+    // Zeroed allocations are lazily backed by the OS, the holes don't take up any memory.
+    let mut image = vec![0u8; check_size(size)?];
+    read_at(reader, 0, &mut image[..size_of_headers])?;
+    for section in sections.iter().filter(|s| s.size_of_raw_data != 0) {
+        let start = section.p_raw_data as usize;
+        let end = start + section.size_of_raw_data as usize;
+        read_at(reader, start as u64, &mut image[start..end])?;
+    }
+
+    Ok(image)
+}
@@ -1,72 +1,289 @@
+use std::fmt;
 
 use std::marker::PhantomData;
-use std::mem::size_of;
+use std::mem::{align_of, size_of};
 use std::os::raw::{c_char, c_void};
-use std::slice;
 
 use super::rva::{Pointer, RVA};
+use crate::pe_file::ParseError;
+
+/// The machine field of the file header. Any value can be represented, the constants cover the
+/// ones Windows knows about.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Machine(pub u16);
+
+impl Machine {
+    pub const UNKNOWN: Machine = Machine(0);
+    pub const I386: Machine = Machine(0x14c);
+    pub const AMD64: Machine = Machine(0x8664);
+    pub const ARM: Machine = Machine(0x1c0);
+    /// ARM Thumb-2, i.e. 32 bit ARM Windows.
+    pub const ARMNT: Machine = Machine(0x1c4);
+    pub const ARM64: Machine = Machine(0xaa64);
+    /// Only used by object files, ARM64EC images carry `AMD64`.
+    pub const ARM64EC: Machine = Machine(0xa641);
+    /// Only used by object files, ARM64X images carry `ARM64`.
+    pub const ARM64X: Machine = Machine(0xa64e);
+    pub const IA64: Machine = Machine(0x200);
+    pub const RISCV32: Machine = Machine(0x5032);
+    pub const RISCV64: Machine = Machine(0x5064);
+    pub const RISCV128: Machine = Machine(0x5128);
+
+    /// The machine code of this process is for, if Windows has a name for it.
+    pub fn host() -> Option<Machine> {
+        if cfg!(target_arch = "x86_64") {
+            Some(Machine::AMD64)
+        } else if cfg!(target_arch = "x86") {
+            Some(Machine::I386)
+        } else if cfg!(target_arch = "aarch64") {
+            Some(Machine::ARM64)
+        } else if cfg!(target_arch = "arm") {
+            Some(Machine::ARMNT)
+        } else if cfg!(target_arch = "riscv64") {
+            Some(Machine::RISCV64)
+        } else {
+            None
+        }
+    }
 
-#[repr(u16)]
-pub enum Machine {
-    X64 = 0x8864,
-    I386 = 0x14c,
+    /// Whether the value is one of the constants other than `UNKNOWN`.
+    pub fn is_known(&self) -> bool {
+        self.name() != "unknown"
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Machine::I386 => "i386",
+            Machine::AMD64 => "AMD64",
+            Machine::ARM => "ARM",
+            Machine::ARMNT => "ARMNT",
+            Machine::ARM64 => "ARM64",
+            Machine::ARM64EC => "ARM64EC",
+            Machine::ARM64X => "ARM64X",
+            Machine::IA64 => "IA64",
+            Machine::RISCV32 => "RISCV32",
+            Machine::RISCV64 => "RISCV64",
+            Machine::RISCV128 => "RISCV128",
+            _ => "unknown",
+        }
+    }
 }
 
-#[repr(u16)]
-pub enum OptionalHeaderSignature {
-    X64 = 523,
-    X86 = 267,
-    ROM = 263,
-}
-
-#[repr(u32)]
-#[derive(Clone, Copy)]
-pub enum Characteristics {
-    NoPad = 0x8,
-    CntCode = 0x20,
-    CntInitializedData = 0x40,
-    CntUninitializedData = 0x80,
-    Gprel = 0x8000,
-    NumRelocationsOverflow = 0x1000000,
-    MemExecute = 0x20000000,
-    MemRead = 0x40000000,
-    MemWrite = 0x80000000,
+impl From<u16> for Machine {
+    fn from(machine: u16) -> Self {
+        Machine(machine)
+    }
 }
 
-#[repr(u16)]
-pub enum FileCharacteristics {
-    RelocsStripped = 0x1,
-    ExecutableImage = 0x2,
-    LineNumsStripped = 0x4,
-    LocalSymsStripped = 0x8,
-    LargeAddressAware = 0x20,
-    X86Machine = 0x100, // 32 bit words are supported
-    DebugStripped = 0x200,
-    RemovableRunFromSwap = 0x400,
-    NetRunFromSwap = 0x800,
-    System = 0x1000,              // system file
-    Dll = 0x2000,                 // dll file
-    SingleProcessorOnly = 0x4000, // no multiprocessor systems
+impl From<Machine> for u16 {
+    fn from(machine: Machine) -> Self {
+        machine.0
+    }
 }
 
-#[repr(u16)]
-pub enum Subsystem {
-    Unknown = 0,
-    Native = 1,
-    WindowsGUI = 2,
-    WindowsCUI = 3,
-    OS2Cui = 5,
-    PosixCui = 7,
-    WindowsCEGui = 9,
-    EFIApplication = 10,
-    EFIBootServiceDriver = 11,
-    EFIRuntimeDriver = 12,
-    EFIRom = 13,
-    XBox = 14,
-    WindowsBootApplication = 16,
+/// The magic of the optional header, which tells PE32 and PE32+ images apart.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OptionalHeaderMagic(pub u16);
+
+impl OptionalHeaderMagic {
+    pub const PE32: OptionalHeaderMagic = OptionalHeaderMagic(0x10b);
+    pub const PE32_PLUS: OptionalHeaderMagic = OptionalHeaderMagic(0x20b);
+    pub const ROM: OptionalHeaderMagic = OptionalHeaderMagic(0x107);
+
+    pub fn is_64_bit(&self) -> bool {
+        *self == OptionalHeaderMagic::PE32_PLUS
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OptionalHeaderMagic::PE32 => "PE32",
+            OptionalHeaderMagic::PE32_PLUS => "PE32+",
+            OptionalHeaderMagic::ROM => "ROM",
+            _ => "unknown",
+        }
+    }
+}
+
+/// The subsystem an image wants to run in.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Subsystem(pub u16);
+
+impl Subsystem {
+    pub const UNKNOWN: Subsystem = Subsystem(0);
+    pub const NATIVE: Subsystem = Subsystem(1);
+    pub const WINDOWS_GUI: Subsystem = Subsystem(2);
+    pub const WINDOWS_CUI: Subsystem = Subsystem(3);
+    pub const OS2_CUI: Subsystem = Subsystem(5);
+    pub const POSIX_CUI: Subsystem = Subsystem(7);
+    pub const NATIVE_WINDOWS: Subsystem = Subsystem(8);
+    pub const WINDOWS_CE_GUI: Subsystem = Subsystem(9);
+    pub const EFI_APPLICATION: Subsystem = Subsystem(10);
+    pub const EFI_BOOT_SERVICE_DRIVER: Subsystem = Subsystem(11);
+    pub const EFI_RUNTIME_DRIVER: Subsystem = Subsystem(12);
+    pub const EFI_ROM: Subsystem = Subsystem(13);
+    pub const XBOX: Subsystem = Subsystem(14);
+    pub const WINDOWS_BOOT_APPLICATION: Subsystem = Subsystem(16);
+
+    /// Whether the image runs outside of Windows, in UEFI firmware.
+    pub fn is_efi(&self) -> bool {
+        (Subsystem::EFI_APPLICATION.0..=Subsystem::EFI_ROM.0).contains(&self.0)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Subsystem::NATIVE => "native",
+            Subsystem::WINDOWS_GUI => "Windows GUI",
+            Subsystem::WINDOWS_CUI => "Windows CUI",
+            Subsystem::OS2_CUI => "OS/2 CUI",
+            Subsystem::POSIX_CUI => "POSIX CUI",
+            Subsystem::NATIVE_WINDOWS => "native Win9x driver",
+            Subsystem::WINDOWS_CE_GUI => "Windows CE GUI",
+            Subsystem::EFI_APPLICATION => "EFI application",
+            Subsystem::EFI_BOOT_SERVICE_DRIVER => "EFI boot service driver",
+            Subsystem::EFI_RUNTIME_DRIVER => "EFI runtime driver",
+            Subsystem::EFI_ROM => "EFI ROM",
+            Subsystem::XBOX => "Xbox",
+            Subsystem::WINDOWS_BOOT_APPLICATION => "Windows boot application",
+            _ => "unknown",
+        }
+    }
+}
+
+// Flags read from a file can have any bits set, the ones we don't know are kept as they are and
+// printed in hex.
+macro_rules! debug_flags {
+    ($flags:ident) => {
+        impl fmt::Debug for $flags {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}(", stringify!($flags))?;
+                if self.is_empty() {
+                    write!(f, "{:#x}", self.bits())?;
+                } else {
+                    bitflags::parser::to_writer(self, &mut *f)?;
+                }
+                write!(f, ")")
+            }
+        }
+    };
+}
+
+/// The characteristics of a section, i.e. the `IMAGE_SCN_*` flags.
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SectionCharacteristics(u32);
+
+bitflags! {
+    impl SectionCharacteristics: u32 {
+        const TYPE_NO_PAD = 0x8;
+        const CNT_CODE = 0x20;
+        const CNT_INITIALIZED_DATA = 0x40;
+        const CNT_UNINITIALIZED_DATA = 0x80;
+        const LNK_OTHER = 0x100;
+        const LNK_INFO = 0x200;
+        const LNK_REMOVE = 0x800;
+        const LNK_COMDAT = 0x1000;
+        const GPREL = 0x8000;
+        const LNK_NRELOC_OVFL = 0x0100_0000;
+        const MEM_DISCARDABLE = 0x0200_0000;
+        const MEM_NOT_CACHED = 0x0400_0000;
+        const MEM_NOT_PAGED = 0x0800_0000;
+        const MEM_SHARED = 0x1000_0000;
+        const MEM_EXECUTE = 0x2000_0000;
+        const MEM_READ = 0x4000_0000;
+        const MEM_WRITE = 0x8000_0000;
+        // The alignment of object file sections, see `alignment`.
+        const _ = 0x00f0_0000;
+    }
+}
+
+debug_flags!(SectionCharacteristics);
+
+impl SectionCharacteristics {
+    pub fn is_executable(&self) -> bool {
+        self.contains(SectionCharacteristics::MEM_EXECUTE)
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.contains(SectionCharacteristics::MEM_READ)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.contains(SectionCharacteristics::MEM_WRITE)
+    }
+
+    pub fn is_discardable(&self) -> bool {
+        self.contains(SectionCharacteristics::MEM_DISCARDABLE)
+    }
+
+    /// Sections of object files that don't end up in the image, like `.drectve`.
+    pub fn is_removed(&self) -> bool {
+        self.intersects(SectionCharacteristics::LNK_INFO | SectionCharacteristics::LNK_REMOVE)
+    }
+
+    /// The alignment of a section in an object file, `None` if it isn't given.
+    pub fn alignment(&self) -> Option<u32> {
+        match (self.bits() >> 20) & 0xf {
+            0 | 0xf => None,
+            n => Some(1 << (n - 1)),
+        }
+    }
+}
+
+/// The characteristics in the file header, i.e. the `IMAGE_FILE_*` flags.
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileCharacteristics(u16);
+
+bitflags! {
+    impl FileCharacteristics: u16 {
+        const RELOCS_STRIPPED = 0x1;
+        const EXECUTABLE_IMAGE = 0x2;
+        const LINE_NUMS_STRIPPED = 0x4;
+        const LOCAL_SYMS_STRIPPED = 0x8;
+        const AGGRESSIVE_WS_TRIM = 0x10;
+        const LARGE_ADDRESS_AWARE = 0x20;
+        const BYTES_REVERSED_LO = 0x80;
+        /// 32 bit words are supported.
+        const MACHINE_32BIT = 0x100;
+        const DEBUG_STRIPPED = 0x200;
+        const REMOVABLE_RUN_FROM_SWAP = 0x400;
+        const NET_RUN_FROM_SWAP = 0x800;
+        const SYSTEM = 0x1000;
+        const DLL = 0x2000;
+        /// No multiprocessor systems.
+        const UP_SYSTEM_ONLY = 0x4000;
+        const BYTES_REVERSED_HI = 0x8000;
+    }
+}
+
+debug_flags!(FileCharacteristics);
+
+impl FileCharacteristics {
+    pub fn is_dll(&self) -> bool {
+        self.contains(FileCharacteristics::DLL)
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.contains(FileCharacteristics::EXECUTABLE_IMAGE)
+    }
+
+    /// Whether the image can't be relocated.
+    pub fn is_relocs_stripped(&self) -> bool {
+        self.contains(FileCharacteristics::RELOCS_STRIPPED)
+    }
 }
 
 #[repr(u16)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DirectoryEntry {
     Export = 0,
     Import = 1,
@@ -85,41 +302,69 @@ pub enum DirectoryEntry {
     ComDescriptor = 14,
 }
 
+/// Every data directory, in the order of the optional header.
+pub const DIRECTORIES: [DirectoryEntry; 15] = [
+    DirectoryEntry::Export,
+    DirectoryEntry::Import,
+    DirectoryEntry::Resource,
+    DirectoryEntry::Exception,
+    DirectoryEntry::Security,
+    DirectoryEntry::Basereloc,
+    DirectoryEntry::Debug,
+    DirectoryEntry::Architecture,
+    DirectoryEntry::Globalptr,
+    DirectoryEntry::Tls,
+    DirectoryEntry::LoadConfig,
+    DirectoryEntry::BoundImport,
+    DirectoryEntry::Iat,
+    DirectoryEntry::DelayImport,
+    DirectoryEntry::ComDescriptor,
+];
+
 #[derive(Debug, PartialEq)]
-pub struct RelocationType(u16);
+pub struct RelocationType(pub(crate) u16);
 pub const RelocateAbsolute: RelocationType = RelocationType(0);
 pub const RelocateHighLow: RelocationType = RelocationType(3);
 pub const RelocateDir64: RelocationType = RelocationType(10);
 
-#[repr(u16)]
-pub enum DllCharacteristics {
-    DynamicBase = 0x40,
-    ForceIntegrity = 0x80,
-    NXCompat = 0x100, // DEP
-    NoIsolation = 0x200,
-    NoSEH = 0x400,
-    NoBind = 0x800,
-    WDMDriver = 0x2000,
-    TerminalServerAware = 0x8000,
+/// The DLL characteristics in the optional header, i.e. the `IMAGE_DLLCHARACTERISTICS_*` flags.
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DllCharacteristics(u16);
+
+bitflags! {
+    impl DllCharacteristics: u16 {
+        const HIGH_ENTROPY_VA = 0x20;
+        const DYNAMIC_BASE = 0x40;
+        const FORCE_INTEGRITY = 0x80;
+        /// DEP.
+        const NX_COMPAT = 0x100;
+        const NO_ISOLATION = 0x200;
+        const NO_SEH = 0x400;
+        const NO_BIND = 0x800;
+        const APPCONTAINER = 0x1000;
+        const WDM_DRIVER = 0x2000;
+        const GUARD_CF = 0x4000;
+        const TERMINAL_SERVER_AWARE = 0x8000;
+    }
 }
 
-pub union MiscUnion {
-    physical_address: u32,
-    virtual_size: u32,
-}
+debug_flags!(DllCharacteristics);
 
-#[repr(C)]
-pub struct ImageSectionHeader {
-    pub name: [c_char; 8],
-    pub misc: MiscUnion,
-    pub(crate) virtual_address: RVA<u32, Pointer<*mut u8>>,
-    pub size_of_raw_data: u32,
-    pub p_raw_data: u32,
-    pub p_reloc: u32,
-    pub p_line_nums: u32,
-    pub num_relocations: u16,
-    pub num_line_nums: u16,
-    pub characteristics: Characteristics,
+impl DllCharacteristics {
+    /// Whether the image supports ASLR.
+    pub fn is_dynamic_base(&self) -> bool {
+        self.contains(DllCharacteristics::DYNAMIC_BASE)
+    }
+
+    pub fn is_nx_compat(&self) -> bool {
+        self.contains(DllCharacteristics::NX_COMPAT)
+    }
+
+    pub fn is_guard_cf(&self) -> bool {
+        self.contains(DllCharacteristics::GUARD_CF)
+    }
 }
 
 // TODO: Template to pointer size of the pe file being loaded
@@ -130,19 +375,29 @@ pub struct ImageBaseRelocation {
 }
 
 impl ImageBaseRelocation {
-    pub fn base_relocations<'a>(&'a self) -> BaseRelocationIterator<'a> {
-        BaseRelocationIterator::new(self)
+    /// Iterates over this block and the ones following it. `end` is the address right after the
+    /// relocation directory, no block may extend past it.
+    pub fn base_relocations<'a>(&'a self, end: u64) -> BaseRelocationIterator<'a> {
+        BaseRelocationIterator::new(self, end)
     }
 
     pub fn relocations(&self) -> RelocationIterator {
         RelocationIterator::new(self)
     }
 
+    // The caller has to make sure there is a block after this one. Blocks start on a 32 bit
+    // boundary and can't be smaller than their header, otherwise there is no next block.
     pub fn next_relocation(&self) -> Option<&ImageBaseRelocation> {
-        let relocations_start = unsafe { (self as *const _).offset(1) as *const u16 };
-        let count = (self.size_of_block as usize - size_of::<ImageBaseRelocation>()) / 2;
+        if self.size_of_block % 4 != 0 {
+            return None;
+        }
+
+        let relocations_start =
+            unsafe { (self as *const ImageBaseRelocation).offset(1) as *const u16 };
+        let count =
+            (self.size_of_block as usize).checked_sub(size_of::<ImageBaseRelocation>())? / 2;
         let next_base_relocation =
-            unsafe { &*(relocations_start.offset(count as _) as *const ImageBaseRelocation) };
+            unsafe { &*(relocations_start.add(count) as *const ImageBaseRelocation) };
 
         // TODO: Is there a better condition to check?
         if next_base_relocation.virtual_address.value == 0
@@ -157,28 +412,51 @@ impl ImageBaseRelocation {
 
 pub type RelocationOffset = u16;
 
+// Whether a `T` at `p` ends before `end` and may be referenced.
+fn fits<T>(p: *const T, end: u64) -> bool {
+    p as usize % align_of::<T>() == 0 && p as u64 + size_of::<T>() as u64 <= end
+}
+
 pub struct BaseRelocationIterator<'a> {
-    current: Option<&'a ImageBaseRelocation>,
+    current: Option<*const ImageBaseRelocation>,
+    end: u64,
+    _p: PhantomData<&'a ImageBaseRelocation>,
 }
 
 impl<'a> BaseRelocationIterator<'a> {
-    fn new(current: &'a ImageBaseRelocation) -> Self {
+    fn new(current: &'a ImageBaseRelocation, end: u64) -> Self {
         Self {
             current: Some(current),
+            end,
+            _p: PhantomData,
         }
     }
 }
 
 impl<'a> Iterator for BaseRelocationIterator<'a> {
-    type Item = &'a ImageBaseRelocation;
+    type Item = Result<&'a ImageBaseRelocation, ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(c) = self.current {
-            self.current = c.next_relocation();
-            Some(c)
-        } else {
-            None
+        let current = self.current.take()?;
+        if !fits(current, self.end) {
+            return Some(Err(ParseError::OutOfBounds));
+        }
+
+        let current = unsafe { &*current };
+        let size = current.size_of_block as u64;
+        let block_end = current as *const _ as u64 + size;
+        if size < size_of::<ImageBaseRelocation>() as u64
+            || size % 4 != 0
+            || block_end > self.end
+        {
+            return Some(Err(ParseError::OutOfBounds));
+        }
+
+        // The directory may end without an empty block.
+        if block_end + (size_of::<ImageBaseRelocation>() as u64) <= self.end {
+            self.current = current.next_relocation().map(|r| r as *const _);
         }
+        Some(Ok(current))
     }
 }
 
@@ -191,9 +469,13 @@ pub struct RelocationIterator {
 impl RelocationIterator {
     fn new(base_relocation: &ImageBaseRelocation) -> Self {
         Self {
-            relocation: unsafe { (base_relocation as *const _).offset(1) as *const _ },
+            relocation: unsafe {
+                (base_relocation as *const ImageBaseRelocation).offset(1) as *const _
+            },
             current: 0,
-            count: (base_relocation.size_of_block as usize - size_of::<ImageBaseRelocation>()) / 2,
+            count: (base_relocation.size_of_block as usize)
+                .saturating_sub(size_of::<ImageBaseRelocation>())
+                / 2,
         }
     }
 }
@@ -205,7 +487,7 @@ impl Iterator for RelocationIterator {
         if self.current >= self.count {
             None
         } else {
-            let value = unsafe { &*self.relocation.offset(self.current as _) };
+            let value = unsafe { &*self.relocation.add(self.current) };
             self.current += 1;
             Some((
                 RelocationType((value >> 12) & 0b00001111u16),
@@ -215,31 +497,103 @@ impl Iterator for RelocationIterator {
     }
 }
 
-pub(crate) struct ThunkIterator<'a> {
-    current: Pointer<*mut ThunkData>,
+pub(crate) trait Thunk {
+    const ORDINAL_FLAG: u64;
+
+    fn value(&self) -> u64;
+
+    fn set_function(&mut self, function: u64);
+
+    fn is_ordinal(&self) -> bool {
+        self.value() & Self::ORDINAL_FLAG != 0
+    }
+
+    fn import_by_name(&self) -> RVA<u32, Pointer<*const ImageImportByName>> {
+        // The name table is always addressed by a 31 bit RVA.
+        RVA::new(self.value() as u32)
+    }
+}
+
+impl Thunk for ThunkData {
+    const ORDINAL_FLAG: u64 = 0x8000000000000000;
+
+    fn value(&self) -> u64 {
+        unsafe { self.ordinal }
+    }
+
+    fn set_function(&mut self, function: u64) {
+        self.function = function;
+    }
+}
+
+impl Thunk for ThunkData32 {
+    const ORDINAL_FLAG: u64 = 0x80000000;
+
+    fn value(&self) -> u64 {
+        unsafe { self.ordinal as u64 }
+    }
+
+    fn set_function(&mut self, function: u64) {
+        self.function = function as u32;
+    }
+}
+
+pub(crate) struct ThunkIterator<'a, T: 'a> {
+    // The import lookup table if there is one, otherwise the same as `current`.
+    lookup: Pointer<*const T>,
+    current: Pointer<*mut T>,
+    end: u64,
+    done: bool,
     _p: PhantomData<&'a u32>,
 }
 
-impl<'a> ThunkIterator<'a> {
-    fn new(c: &'a ImportDescriptor, base: u64) -> Self {
+impl<'a, T: Thunk> ThunkIterator<'a, T> {
+    fn new(c: &'a ImportDescriptor, base: u64, end: u64) -> Self {
+        let current = Pointer {
+            p: c.first_thunk.resolve(base).p as *mut T,
+        };
+
         Self {
-            current: c.first_thunk.resolve(base),
+            lookup: if c.original_first_thunk.value != 0 {
+                Pointer {
+                    p: c.original_first_thunk.resolve(base).p as *const T,
+                }
+            } else {
+                current.into()
+            },
+            current,
+            end,
+            done: false,
             _p: PhantomData,
         }
     }
 }
 
-impl<'a> Iterator for ThunkIterator<'a> {
-    type Item = &'a mut ThunkData;
+impl<'a, T: Thunk> Iterator for ThunkIterator<'a, T> {
+    // (entry in the import lookup table, IAT slot to write the resolved address to)
+    type Item = Result<(&'a T, &'a mut T), ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if unsafe { self.current.address_of_data.value } != 0 {
+        if self.done {
+            return None;
+        }
+        if !fits(self.lookup.p, self.end) || !fits(self.current.p, self.end) {
+            self.done = true;
+            return Some(Err(ParseError::OutOfBounds));
+        }
+
+        if self.lookup.value() != 0 {
+            let lookup = self.lookup.p;
             let current = self.current.p;
+            self.lookup = Pointer {
+                p: unsafe { lookup.offset(1) },
+            };
             self.current = Pointer {
                 p: unsafe { current.offset(1) },
             };
-            Some(unsafe { &mut *current })
+            Some(Ok(unsafe { (&*lookup, &mut *current) }))
         } else {
+            self.done = true;
             None
         }
     }
@@ -247,7 +601,9 @@ impl<'a> Iterator for ThunkIterator<'a> {
 
 #[repr(C)]
 pub struct ImportDescriptor {
-    pub imports_by_name: u32,
+    // Also called the import lookup table, contains the names or ordinals of the imports. The
+    // IAT may already contain bound addresses instead, so only use it if this is zero.
+    pub(crate) original_first_thunk: RVA<u32, Pointer<*const ThunkData>>,
     pub time_stamp: u32,
     pub forwarder_chain: u32,
     pub(crate) name: RVA<u32, Pointer<*const c_char>>,
@@ -255,49 +611,63 @@ pub struct ImportDescriptor {
 }
 
 impl ImportDescriptor {
-    pub(crate) fn thunk_iterator<'a>(&'a self, base: u64) -> ThunkIterator<'a> {
-        ThunkIterator::new(self, base)
+    // `end` is the address right after the mapped image, the tables may not extend past it.
+    pub(crate) fn thunk_iterator<'a, T: Thunk>(
+        &'a self,
+        base: u64,
+        end: u64,
+    ) -> ThunkIterator<'a, T> {
+        ThunkIterator::new(self, base, end)
     }
 
-    pub(crate) fn import_iterator<'a>(&'a self) -> ImportIterator<'a> {
-        ImportIterator::new(self)
+    pub(crate) fn import_iterator<'a>(&'a self, end: u64) -> ImportIterator<'a> {
+        ImportIterator::new(self, end)
     }
 }
 
 pub(crate) struct ImportIterator<'a> {
     p: *const ImportDescriptor,
+    end: u64,
+    done: bool,
     _p: PhantomData<&'a ImportDescriptor>,
 }
 
 impl<'a> ImportIterator<'a> {
-    fn new(i: &'a ImportDescriptor) -> Self {
+    fn new(i: &'a ImportDescriptor, end: u64) -> Self {
         Self {
             p: i as *const _,
+            end,
+            done: false,
             _p: PhantomData,
         }
     }
 }
 
 impl<'a> Iterator for ImportIterator<'a> {
-    type Item = &'a ImportDescriptor;
+    type Item = Result<&'a ImportDescriptor, ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if !fits(self.p, self.end) {
+            self.done = true;
+            return Some(Err(ParseError::OutOfBounds));
+        }
+
         unsafe {
             if (*self.p).name.value == 0 {
+                self.done = true;
                 None
             } else {
                 let item = &(*self.p);
                 self.p = self.p.offset(1);
-                Some(item)
+                Some(Ok(item))
             }
         }
     }
 }
 
-pub fn image_snap_by_ordinal(ordinal: u64) -> bool {
-    (ordinal & 0x8000000000000000) != 0
-}
-
 pub fn image_ordinal(ordinal: u64) -> u64 {
     ordinal & 0xffff
 }
@@ -311,16 +681,16 @@ pub union ThunkData {
     pub(crate) address_of_data: RVA<u64, Pointer<*const ImageImportByName>>,
 }
 
+// 32 bit
 #[repr(C)]
-pub struct TlsDirectory {
-    pub address_of_raw_data: u64,
-    pub end_address_of_raw_data: u64,
-    pub address_of_index: u64,
-    pub address_of_callbacks: u64,
-    // We don't really care about the characteristics so I won't bother putting them here
+pub union ThunkData32 {
+    pub forwarder_string: u32,
+    pub function: u32,
+    pub ordinal: u32,
+    pub(crate) address_of_data: RVA<u32, Pointer<*const ImageImportByName>>,
 }
 
-pub type TlsCallback = Option<extern "stdcall" fn(*mut c_void, u32, *mut c_void)>;
+pub type TlsCallback = Option<extern "system" fn(*mut c_void, u32, *mut c_void)>;
 
 #[repr(C)]
 pub struct ImageImportByName {
@@ -328,152 +698,28 @@ pub struct ImageImportByName {
     pub name: c_char,
 }
 
-// TODO: Template this so we can resolve it to the correct types
-// and then provide getter functions in the optional header
-#[repr(C)]
-pub struct DataEntry<T> {
-    pub(crate) virtual_address: RVA<u32, Pointer<*mut T>>,
-    pub size: u32,
-}
-
-#[repr(C)]
-pub struct OptionalHeader {
-    pub signature: OptionalHeaderSignature,
-    pub _major_linker_version: c_char,
-    pub _minor_linker_version: c_char,
-    pub size_of_code: u32,
-    pub size_of_initialized_data: u32,
-    pub size_of_uninitialized_data: u32,
-    pub address_of_entry_point: u32,
-    pub base_of_code: u32,
-    // Extensions:
-    pub image_base: u64,
-    pub section_alignment: u32,
-    pub file_alignment: u32,
-    pub major_os_version: u16,
-    pub minor_os_version: u16,
-    pub major_image_version: u16,
-    pub minor_image_version: u16,
-    pub major_subsystem_version: u16,
-    pub minor_subsystem_version: u16,
-    pub win32_version: u32,
-    pub size_of_image: u32,
-    pub size_of_headers: u32,
-    pub checksum: u32,
-    pub subsystem: Subsystem,
-    pub dll_characteristics: DllCharacteristics,
-    pub size_of_stack_reserve: u64,
-    pub size_of_stack_commit: u64,
-    pub size_of_heap_reserve: u64,
-    pub size_of_heap_commit: u64,
-    pub __loader_flags: u32, // obsolete
-    pub num_of_rva_and_sizes: u32,
-}
-
-impl OptionalHeader {
-    fn data_entries_start(&self) -> *const u8 {
-        unsafe { (self as *const _ as *const u8).offset(size_of::<OptionalHeader>() as _) }
-    }
-
-    fn data_entry<T>(&self, e: DirectoryEntry) -> &DataEntry<T> {
-        unsafe {
-            let ptr = (self.data_entries_start() as *const DataEntry<T>).offset(e as _);
-            &*ptr
-        }
-    }
-
-    pub(crate) fn get_import_descriptor(
-        &self,
-        base: u64,
-    ) -> Option<Pointer<*const ImportDescriptor>> {
-        let entry = self.data_entry::<ImportDescriptor>(DirectoryEntry::Import);
-        if entry.virtual_address.value == 0 || entry.size == 0 {
-            None
-        } else {
-            Some(entry.virtual_address.resolve(base).into())
-        }
-    }
-
-    pub(crate) fn get_relocation_entries(
-        &self,
-        base: u64,
-    ) -> Option<Pointer<*mut ImageBaseRelocation>> {
-        let entry = self.data_entry::<ImageBaseRelocation>(DirectoryEntry::Basereloc);
-        if entry.virtual_address.value == 0 {
-            None
-        } else {
-            let reloc = entry.virtual_address.resolve(base);
-            if reloc.size_of_block as usize <= size_of::<ImageBaseRelocation>() {
-                None
-            } else {
-                Some(reloc)
-            }
-        }
-    }
-
-    pub(crate) fn get_tls_entries(&self, base: u64) -> Option<Pointer<*const TlsDirectory>> {
-        let entry = self.data_entry::<TlsDirectory>(DirectoryEntry::Tls);
-        if entry.virtual_address.value == 0 {
-            None
-        } else {
-            Some(entry.virtual_address.resolve(base).into())
-        }
-    }
-
-    /*pub fn get_data_entries(&self) -> &[DataEntry] {
-        unsafe {
-            let self_ptr = self as *const _ as *const c_char;
-            slice::from_raw_parts(
-                self_ptr.offset(size_of::<OptionalHeader>() as _) as *const _,
-                self.num_of_rva_and_sizes as _,
-            )
-        }
-    }*/
-}
-
-#[repr(C)]
-pub struct FileHeader {
-    pub machine: Machine,
-    pub num_sections: u16,
-    pub time_date: u32,
-    pub p_symbol_table: u32,
-    pub num_symbols: u32,
-    pub size_optional_header: u16,
-    pub characteristics: u16,
-}
-
-impl FileHeader {
-    pub fn get_sections(&self) -> &[ImageSectionHeader] {
-        unsafe {
-            let self_ptr = self as *const _ as *const c_char;
-            slice::from_raw_parts(
-                self_ptr.offset((size_of::<FileHeader>() + self.size_optional_header as usize) as _)
-                    as *const _,
-                self.num_sections as _,
-            )
-        }
-    }
-}
-
-#[repr(C)]
-pub struct PeHeader {
-    pub signature: [c_char; 4], // PE\0\0
-    pub file_header: FileHeader,
-    pub optional_header: OptionalHeader,
-}
-
+/// IMAGE_DOS_HEADER, only `signature` and `offset_to_pe_header` matter for PE files.
 #[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DosHeader {
-    pub signature: [c_char; 2], // MZ
-    pub not_needed: [c_char; 58],
-    pub offset_to_pe_header: u32,
-}
-
-impl DosHeader {
-    pub fn get_pe_header(&self) -> &PeHeader {
-        unsafe {
-            let self_ptr = self as *const _ as *const c_char;
-            &*(self_ptr.offset(self.offset_to_pe_header as _) as *const _)
-        }
-    }
+    pub signature: u16,            // e_magic, MZ
+    pub bytes_on_last_page: u16,   // e_cblp
+    pub pages_in_file: u16,        // e_cp
+    pub relocations: u16,          // e_crlc
+    pub header_paragraphs: u16,    // e_cparhdr
+    pub min_extra_paragraphs: u16, // e_minalloc
+    pub max_extra_paragraphs: u16, // e_maxalloc
+    pub initial_ss: u16,           // e_ss
+    pub initial_sp: u16,           // e_sp
+    pub checksum: u16,             // e_csum
+    pub initial_ip: u16,           // e_ip
+    pub initial_cs: u16,           // e_cs
+    pub p_relocation_table: u16,   // e_lfarlc
+    pub overlay_number: u16,       // e_ovno
+    pub reserved: [u16; 4],        // e_res
+    pub oem_id: u16,               // e_oemid
+    pub oem_info: u16,             // e_oeminfo
+    pub reserved2: [u16; 10],      // e_res2
+    pub offset_to_pe_header: u32,  // e_lfanew
 }
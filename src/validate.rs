@@ -0,0 +1,140 @@
+// Sanity checks on the headers of an image.
+//
+// None of these keep the parser from working, and the Windows loader accepts images with most of
+// them, but they rarely show up in what compilers and linkers produce. Packers, crypters and hand
+// crafted images are a different story, which makes them useful signals when triaging samples.
+
+use crate::pe_file::{DataDirectory, PEFile, Section};
+use crate::structs::{DirectoryEntry, DIRECTORIES};
+
+const PAGE_SIZE: u32 = 0x1000;
+const MIN_FILE_ALIGNMENT: u32 = 0x200;
+const MAX_FILE_ALIGNMENT: u32 = 0x1_0000;
+const NUM_DATA_DIRECTORIES: u32 = 16;
+
+/// An anomaly found by `PEFile::validate`. Sections are referred to by their index in the section
+/// table.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Finding {
+    /// Two sections share part of their virtual address range.
+    OverlappingSections { first: usize, second: usize },
+    /// The section ends beyond `size_of_image`.
+    SectionBeyondImage { section: usize },
+    /// The entry point isn't inside an executable section.
+    EntryPointNotExecutable { rva: u32 },
+    /// The section alignment isn't a power of two or is smaller than the file alignment.
+    InvalidSectionAlignment(u32),
+    /// The file alignment isn't a power of two between 512 and 64K, or doesn't match a section
+    /// alignment below the page size.
+    InvalidFileAlignment(u32),
+    /// The directory points outside the headers and all sections.
+    DirectoryOutsideSections(DirectoryEntry),
+    /// `num_of_rva_and_sizes` is below 16.
+    FewDataDirectories(u32),
+    /// The section is both writable and executable.
+    WritableExecutableSection { section: usize },
+}
+
+impl<'a> PEFile<'a> {
+    /// Checks the headers for anomalies, an empty list means none were found.
+    pub fn validate(&self) -> Vec<Finding> {
+        let mut findings = vec![];
+        let sections = self.sections().collect::<Vec<_>>();
+
+        let section_alignment = self.section_alignment();
+        let file_alignment = self.file_alignment();
+        if !section_alignment.is_power_of_two() || section_alignment < file_alignment {
+            findings.push(Finding::InvalidSectionAlignment(section_alignment));
+        }
+        let file_alignment_valid = if section_alignment < PAGE_SIZE {
+            file_alignment == section_alignment
+        } else {
+            file_alignment.is_power_of_two()
+                && (MIN_FILE_ALIGNMENT..=MAX_FILE_ALIGNMENT).contains(&file_alignment)
+        };
+        if !file_alignment_valid {
+            findings.push(Finding::InvalidFileAlignment(file_alignment));
+        }
+
+        if self.num_of_rva_and_sizes() < NUM_DATA_DIRECTORIES {
+            findings.push(Finding::FewDataDirectories(self.num_of_rva_and_sizes()));
+        }
+
+        // Sorted by address, a section overlaps if it starts before the furthest end so far.
+        let mut ranges = sections
+            .iter()
+            .enumerate()
+            .filter(|&(_, s)| s.mapped_size() != 0)
+            .map(|(i, s)| {
+                (
+                    s.virtual_address as u64,
+                    s.virtual_address as u64 + s.mapped_size() as u64,
+                    i,
+                )
+            })
+            .collect::<Vec<_>>();
+        ranges.sort();
+        let mut furthest: Option<(u64, usize)> = None;
+        for &(start, end, index) in &ranges {
+            match furthest {
+                Some((furthest_end, other)) if furthest_end > start => {
+                    findings.push(Finding::OverlappingSections {
+                        first: other.min(index),
+                        second: other.max(index),
+                    });
+                    if end > furthest_end {
+                        furthest = Some((end, index));
+                    }
+                }
+                _ => furthest = Some((end, index)),
+            }
+        }
+
+        for (index, section) in sections.iter().enumerate() {
+            let end = section.virtual_address as u64 + section.mapped_size() as u64;
+            if end > self.size_of_image() as u64 {
+                findings.push(Finding::SectionBeyondImage { section: index });
+            }
+            if section.characteristics.is_writable() && section.characteristics.is_executable() {
+                findings.push(Finding::WritableExecutableSection { section: index });
+            }
+        }
+
+        // DLLs don't need an entry point.
+        let entry_point = self.address_of_entry_point();
+        if entry_point != 0 || !self.file_characteristics().is_dll() {
+            let executable = sections
+                .iter()
+                .any(|s| s.contains_rva(entry_point) && s.characteristics.is_executable());
+            if !executable {
+                findings.push(Finding::EntryPointNotExecutable { rva: entry_point });
+            }
+        }
+
+        // The security directory holds a file offset rather than an RVA. Bound imports usually
+        // live in the headers.
+        for &entry in DIRECTORIES
+            .iter()
+            .filter(|&&e| e != DirectoryEntry::Security)
+        {
+            if let Ok(Some(directory)) = self.data_directory(entry) {
+                if !self.contains_directory(&sections, directory) {
+                    findings.push(Finding::DirectoryOutsideSections(entry));
+                }
+            }
+        }
+
+        findings
+    }
+
+    fn contains_directory(&self, sections: &[Section], directory: DataDirectory) -> bool {
+        let start = directory.virtual_address as u64;
+        let end = start + directory.size as u64;
+        end <= self.size_of_headers() as u64
+            || sections.iter().any(|s| {
+                let section_start = s.virtual_address as u64;
+                start >= section_start && end <= section_start + s.mapped_size() as u64
+            })
+    }
+}
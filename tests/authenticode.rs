@@ -0,0 +1,196 @@
+extern crate pe_load;
+
+mod common;
+
+use common::*;
+use pe_load::*;
+
+// The certificates build.sh signs pe32plus_signed.dll with.
+const SIGNER: &str = "Fixture Signer";
+const SIGNER_CA: &str = "Fixture CA";
+
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    match contents.len() {
+        n if n < 0x80 => element.push(n as u8),
+        n => element.extend_from_slice(&[0x82, (n >> 8) as u8, n as u8]),
+    }
+    element.extend_from_slice(contents);
+    element
+}
+
+// A ContentInfo with a SHA-256 Authenticode digest of zeros, `certificates` and a SignerInfo
+// that stops after `signer_id`.
+fn content_info(certificates: &[&[u8]], version: u8, signer_id: &[u8]) -> Vec<u8> {
+    let sha256 = der(0x06, &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01]);
+    let digest_info = der(0x30, &[der(0x30, &sha256), der(0x04, &[0; 32])].concat());
+    let indirect_data = der(0x30, &[der(0x30, &[]), digest_info].concat());
+    let spc_indirect_data = [0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x04];
+    let content = der(
+        0x30,
+        &[der(0x06, &spc_indirect_data), der(0xa0, &indirect_data)].concat(),
+    );
+    let signer_info = der(0x30, &[der(0x02, &[version]), signer_id.to_vec()].concat());
+
+    let signed_data = [
+        der(0x02, &[version]),
+        der(0x31, &[]),
+        content,
+        der(0xa0, &certificates.concat()),
+        der(0x31, &signer_info),
+    ];
+    let signed_data_oid = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+    der(
+        0x30,
+        &[
+            der(0x06, &signed_data_oid),
+            der(0xa0, &der(0x30, &signed_data.concat())),
+        ]
+        .concat(),
+    )
+}
+
+#[test]
+fn certificate_table() {
+    let buffer = fixture("pe32plus_signed.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    let table = file.certificate_table().unwrap().unwrap();
+    assert_eq!(table.end, buffer.len());
+    assert_eq!(table.start % 8, 0);
+
+    let certificates = file.certificates().unwrap();
+    assert_eq!(certificates.len(), 1);
+    assert_eq!(certificates[0].offset, table.start);
+    assert_eq!(certificates[0].revision, CERT_REVISION_2_0);
+    assert_eq!(certificates[0].kind, CERT_TYPE_PKCS_SIGNED_DATA);
+    assert!(file.has_valid_checksum());
+
+    let buffer = fixture("pe32plus_dll.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.certificate_table(), Ok(None));
+    assert_eq!(file.certificates(), Ok(vec![]));
+}
+
+#[test]
+fn signed_data() {
+    let buffer = fixture("pe32plus_signed.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    let signed_data = file.certificates().unwrap()[0].signed_data().unwrap();
+    assert_eq!(signed_data.digest_algorithm, Some(DigestAlgorithm::Sha256));
+    assert_eq!(signed_data.digest.len(), 32);
+    assert_eq!(signed_data.certificates.len(), 2);
+    assert_eq!(signed_data.certificates[1].subject_name(), Some(SIGNER_CA));
+    assert_eq!(signed_data.signers.len(), 1);
+    assert!(matches!(
+        signed_data.signers[0],
+        Signer::IssuerAndSerial { .. }
+    ));
+
+    let signers = signed_data.signer_certificates();
+    assert_eq!(signers.len(), 1);
+    assert_eq!(signers[0].subject_name(), Some(SIGNER));
+    assert_eq!(signers[0].issuer_name(), Some(SIGNER_CA));
+    assert_eq!(signers[0].serial_number, [2]);
+}
+
+#[test]
+fn digest() {
+    let buffer = fixture("pe32plus_signed.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    let signed_data = file.certificates().unwrap()[0].signed_data().unwrap();
+    assert_eq!(signed_data.matches(&file), Ok(true));
+
+    // Signing only added the certificate table, which isn't part of the hash.
+    let unsigned = fixture("pe32plus_dll.dll");
+    let unsigned = PEFile::parse(&unsigned).unwrap();
+    for &algorithm in &[DigestAlgorithm::Sha1, DigestAlgorithm::Sha256] {
+        assert_eq!(
+            file.authenticode_hash(algorithm),
+            unsigned.authenticode_hash(algorithm)
+        );
+    }
+    assert_eq!(
+        file.authenticode_hash(DigestAlgorithm::Sha512)
+            .unwrap()
+            .len(),
+        64
+    );
+
+    // Neither is the checksum.
+    let mut patched = buffer.clone();
+    let checksum = read_u32(&buffer, 0x3c) as usize + 24 + 64;
+    patched[checksum] ^= 1;
+    assert_eq!(
+        signed_data.matches(&PEFile::parse(&patched).unwrap()),
+        Ok(true)
+    );
+
+    let mut patched = buffer.clone();
+    let text = file.sections().next().unwrap().p_raw_data as usize;
+    patched[text] ^= 1;
+    assert_eq!(
+        signed_data.matches(&PEFile::parse(&patched).unwrap()),
+        Ok(false)
+    );
+}
+
+#[test]
+fn malformed() {
+    let buffer = fixture("pe32plus_signed.dll");
+    let offset = PEFile::parse(&buffer)
+        .unwrap()
+        .certificate_table()
+        .unwrap()
+        .unwrap()
+        .start;
+
+    let mut patched = buffer.clone();
+    patched[offset..offset + 4].copy_from_slice(&4u32.to_le_bytes());
+    assert_eq!(
+        PEFile::parse(&patched).unwrap().certificates(),
+        Err(ParseError::InvalidSignature)
+    );
+
+    // Give the outer SEQUENCE of the SignedData a length that takes more than four bytes.
+    let mut patched = buffer.clone();
+    patched[offset + 8 + 2] = 0xff;
+    let file = PEFile::parse(&patched).unwrap();
+    assert_eq!(
+        file.certificates().unwrap()[0].signed_data(),
+        Err(ParseError::InvalidSignature)
+    );
+
+    // A version 3 SignerInfo identifies the signer by the subject key identifier.
+    let original = PEFile::parse(&buffer).unwrap().certificates().unwrap()[0]
+        .signed_data()
+        .unwrap();
+    let certificates = original
+        .certificates
+        .iter()
+        .map(|c| c.der)
+        .collect::<Vec<_>>();
+    let id = original.signer_certificates()[0].subject_key_id.unwrap();
+    let der_v3 = content_info(&certificates, 3, &der(0x80, id));
+    let v3 = SignedData::parse(&der_v3).unwrap();
+    assert_eq!(v3.signers, [Signer::SubjectKeyId(id)]);
+    assert_eq!(v3.signer_certificates()[0].subject_name(), Some(SIGNER));
+
+    let unknown = content_info(&certificates, 3, &der(0x80, &[1, 2, 3]));
+    let unknown = SignedData::parse(&unknown).unwrap();
+    assert_eq!(unknown.signer_certificates(), vec![]);
+
+    // Neither issuerAndSerialNumber nor subjectKeyIdentifier.
+    let bad_id = content_info(&certificates, 3, &der(0x81, id));
+    assert_eq!(
+        SignedData::parse(&bad_id),
+        Err(ParseError::InvalidSignature)
+    );
+
+    let mut patched = buffer.clone();
+    let length = patched.len();
+    patched.truncate(length - 8);
+    assert_eq!(
+        PEFile::parse(&patched).unwrap().certificates(),
+        Err(ParseError::OutOfBounds)
+    );
+}
@@ -0,0 +1,102 @@
+extern crate pe_load;
+
+mod common;
+
+use common::*;
+use pe_load::*;
+
+// Where pe32_managed.s puts the metadata root, and the token of `Main`.
+const METADATA_RVA: u32 = 0x1054;
+const ENTRY_POINT_TOKEN: u32 = 0x0600_0001;
+
+#[test]
+fn clr_header() {
+    let buffer = fixture("pe32_managed.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    let header = file.clr_header().unwrap().unwrap();
+    assert_eq!(header.cb, 72);
+    assert_eq!(
+        (header.major_runtime_version, header.minor_runtime_version),
+        (2, 5)
+    );
+    assert_eq!(header.metadata.virtual_address, METADATA_RVA);
+    assert_eq!(header.entry_point, ENTRY_POINT_TOKEN);
+    assert!(header.is_il_only());
+    assert_eq!(header.strong_name_signature.size, 0);
+
+    for &name in &["pe32_exe.exe", "pe32plus_dll.dll"] {
+        let buffer = fixture(name);
+        let file = PEFile::parse(&buffer).unwrap();
+        assert_eq!(file.clr_header(), Ok(None), "{}", name);
+        assert_eq!(file.clr_metadata(), Ok(None), "{}", name);
+    }
+}
+
+#[test]
+fn metadata_streams() {
+    let buffer = fixture("pe32_managed.exe");
+    let metadata = PEFile::parse(&buffer)
+        .unwrap()
+        .clr_metadata()
+        .unwrap()
+        .unwrap();
+    assert_eq!((metadata.major_version, metadata.minor_version), (1, 1));
+    assert_eq!(metadata.version, "v4.0.30319");
+    let names = metadata.streams.iter().map(|s| s.name).collect::<Vec<_>>();
+    assert_eq!(names, ["#~", "#Strings", "#US", "#GUID", "#Blob"]);
+
+    // Module, TypeDef, MethodDef and Assembly have a row each.
+    let tables = metadata.tables().unwrap().data;
+    assert_eq!(tables.len(), 0x64);
+    assert_eq!(read_u64(tables, 8), 1 | 1 << 2 | 1 << 6 | 1 << 0x20);
+    assert_eq!(
+        metadata.strings().unwrap().data,
+        b"\0<Module>\0managed.exe\0Main\0managed\0\0"
+    );
+    assert_eq!(metadata.user_strings().unwrap().data, &[0; 4]);
+    assert_eq!(&metadata.guids().unwrap().data[..4], &[0x6b, 0x1f, 0x57, 0x2d]);
+    assert_eq!(metadata.blobs().unwrap().data, &[0, 3, 0, 0, 1, 0, 0, 0]);
+    assert_eq!(metadata.stream("#Pdb"), None);
+
+    // Stream offsets are relative to the metadata root.
+    let guids = metadata.guids().unwrap();
+    let rva = METADATA_RVA + guids.offset;
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.read_rva(rva, 16), Ok(guids.data));
+}
+
+#[test]
+fn malformed_metadata() {
+    let mut buffer = fixture("pe32_managed.exe");
+    let metadata = PEFile::parse(&buffer)
+        .unwrap()
+        .rva_to_offset(METADATA_RVA)
+        .unwrap();
+
+    // A stream extending past the end of the metadata.
+    let mut bad_stream = buffer.clone();
+    bad_stream[metadata + 36..metadata + 40].copy_from_slice(&0x1000u32.to_le_bytes());
+    assert_eq!(
+        PEFile::parse(&bad_stream).unwrap().clr_metadata(),
+        Err(ParseError::InvalidMetadata)
+    );
+
+    buffer[metadata] = b'X';
+    let file = PEFile::parse(&buffer).unwrap();
+    assert!(file.clr_header().unwrap().is_some());
+    assert_eq!(file.clr_metadata(), Err(ParseError::InvalidMetadata));
+}
+
+#[test]
+fn load_il_only() {
+    match Loader::with_config(fixture("pe32_managed.exe"), config_at(0x40_0000)).load() {
+        Err(LoadError::IlOnlyImage) => {}
+        r => panic!("unexpected result {:?}", r.err()),
+    }
+
+    let config = config_at(0x40_0000).allow_il_only(true);
+    let loaded = Loader::with_config(fixture("pe32_managed.exe"), config)
+        .load()
+        .unwrap();
+    assert_eq!(loaded.base(), 0x40_0000);
+}
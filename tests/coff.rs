@@ -0,0 +1,330 @@
+extern crate pe_load;
+
+mod common;
+
+use common::*;
+use pe_load::*;
+
+const BASE: u64 = 0x1_8000_0000;
+const BASE_32: u64 = 0x1000_0000;
+const BEACON_PRINTF: u64 = 0xbeac_0000;
+
+// Where amd64.obj and i386.obj end up: every section on its own page, .drectve isn't loaded.
+const TEXT: u64 = 0;
+const DATA: u64 = 0x1000;
+const BSS: u64 = 0x2000;
+const COMMONS: u64 = 0x3000;
+const SLOTS: u64 = 0x4000;
+
+// Offsets of the relocated values in .data, see amd64.s.
+const GO_POINTER: usize = 16;
+const GO_RVA: usize = 24;
+const MESSAGE_OFFSET: usize = 28;
+const MESSAGE_SECTION: usize = 32;
+const HELPER_POINTER: usize = 40;
+
+fn object(is_64_bit: bool) -> Vec<u8> {
+    fixture(if is_64_bit { "amd64.obj" } else { "i386.obj" })
+}
+
+fn load_buffer(buffer: Vec<u8>, base: u64) -> Result<LoadedObject, LoadError> {
+    ObjectLoader::with_config(buffer, config_at(base))
+        .symbol("BeaconPrintf", BEACON_PRINTF)
+        .load()
+}
+
+fn load(is_64_bit: bool, base: u64) -> Result<LoadedObject, LoadError> {
+    load_buffer(object(is_64_bit), base)
+}
+
+fn read_i32(buffer: &[u8], offset: u64) -> i64 {
+    read_u32(buffer, offset as usize) as i32 as i64
+}
+
+#[test]
+fn symbol_table() {
+    let buffer = fixture("amd64.obj");
+    let file = CoffFile::parse(&buffer).unwrap();
+    assert_eq!(file.machine(), Machine::AMD64);
+    let names = file
+        .sections()
+        .map(|s| s.name_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".text", ".data", ".bss", ".drectve"]);
+
+    let table = file.symbol_table().unwrap();
+    assert_eq!(table.len(), 19);
+    let symbols = table.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(symbols.len(), 13);
+    assert_eq!(symbols[12].name, ".file");
+    assert_eq!(&symbols[12].aux[..5], b"bof.c");
+
+    // Names longer than 8 bytes are in the string table.
+    let import = table.symbol(10).unwrap();
+    assert_eq!(import.name, "__imp_KERNEL32$OutputDebugStringA");
+    assert!(import.is_external() && import.is_undefined() && !import.is_common());
+    let counter = table.symbol(12).unwrap();
+    assert_eq!((counter.name, counter.value), ("counter", 4));
+    assert!(counter.is_common());
+    assert_eq!(table.symbol(13).unwrap().num_aux_symbols(), 1);
+
+    let text = file.section(1).unwrap();
+    let relocations = file.relocations(&text).unwrap();
+    assert_eq!(relocations.len(), 5);
+    assert_eq!(
+        relocations[4],
+        CoffRelocation {
+            virtual_address: 26,
+            symbol_table_index: 12,
+            kind: IMAGE_REL_AMD64_REL32,
+        }
+    );
+    assert_eq!(file.section_data(&file.section(3).unwrap()), Ok(&[][..]));
+}
+
+#[test]
+fn load_amd64() {
+    let mut object = load(true, BASE).unwrap();
+    assert_eq!(object.base(), BASE);
+    assert_eq!(object.size(), 0x5000);
+    assert!(!object.is_runnable());
+
+    assert_eq!(object.symbol_address("go"), Some(BASE + TEXT));
+    assert_eq!(object.symbol_address("buffer"), Some(BASE + BSS + 8));
+    assert_eq!(object.symbol_address("counter"), Some(BASE + COMMONS));
+    assert_eq!(object.symbol_address("message"), None);
+
+    // Only referenced externals are resolved, BeaconPrintf was defined by us.
+    let imports = object.imports();
+    assert_eq!(imports.len(), 2);
+    let output_debug_string = StubResolver::address(
+        0,
+        &ImportName::Name {
+            hint: 0,
+            name: "OutputDebugStringA",
+        },
+    );
+    assert_eq!(imports[0].module.as_ref().map(|m| &m[..]), Some("KERNEL32"));
+    assert_eq!(imports[0].name, "OutputDebugStringA");
+    assert_eq!(imports[0].address, output_debug_string);
+    assert_eq!(imports[0].slot, BASE + SLOTS);
+    assert_eq!(imports[1].module, None);
+    assert_eq!(imports[1].address, BEACON_PRINTF);
+
+    let image = object.image().unwrap();
+    let image = &image[..];
+    assert_eq!(read_u64(image, SLOTS as usize), output_debug_string);
+    assert_eq!(read_u64(image, SLOTS as usize + 8), BEACON_PRINTF);
+
+    // Displacements are relative to the end of the instruction.
+    assert_eq!(read_i32(image, TEXT + 3), (DATA + 4 - 7) as i64);
+    assert_eq!(read_i32(image, TEXT + 9), (SLOTS - 13) as i64);
+    assert_eq!(read_i32(image, TEXT + 20), (COMMONS - 24) as i64);
+    // The store is followed by its immediate, the addend makes up for it.
+    assert_eq!(read_i32(image, TEXT + 26), (COMMONS - 34) as i64);
+
+    // The direct call goes through a stub that jumps through the slot.
+    let stub = TEXT as i64 + 18 + read_i32(image, TEXT + 14);
+    assert_eq!(stub as u64, SLOTS + 0x18);
+    assert_eq!(&image[stub as usize..stub as usize + 2], &[0xff, 0x25]);
+    assert_eq!(
+        stub + 6 + read_i32(image, stub as u64 + 2),
+        SLOTS as i64 + 8
+    );
+
+    let data = DATA as usize;
+    assert_eq!(read_u64(image, data + GO_POINTER), BASE);
+    assert_eq!(read_u32(image, data + GO_RVA), 1);
+    assert_eq!(read_u32(image, data + MESSAGE_OFFSET), 4);
+    assert_eq!(&image[data + MESSAGE_SECTION..][..2], &[2, 0]);
+    assert_eq!(read_u64(image, data + HELPER_POINTER), BASE);
+    assert_eq!(&image[data + 4..data + 10], b"hello\0");
+}
+
+#[test]
+fn load_i386() {
+    let mut object = load(false, BASE_32).unwrap();
+    assert_eq!(object.size(), 0x5000);
+    assert_eq!(object.symbol_address("_go"), Some(BASE_32));
+    assert_eq!(object.symbol_address("go"), Some(BASE_32));
+
+    let image = object.image().unwrap();
+    let image = &image[..];
+    let imports = object.imports();
+    assert_eq!(imports.len(), 2);
+    assert_eq!(imports[0].symbol, "__imp__KERNEL32$OutputDebugStringA@4");
+    assert_eq!(imports[0].name, "OutputDebugStringA");
+    assert_eq!(imports[1].symbol, "_BeaconPrintf");
+
+    assert_eq!(read_u32(image, SLOTS as usize), imports[0].address as u32);
+    assert_eq!(
+        read_u32(image, TEXT as usize + 1),
+        (BASE_32 + DATA + 4) as u32
+    );
+    assert_eq!(read_u32(image, TEXT as usize + 7), (BASE_32 + SLOTS) as u32);
+    // Without stubs the call goes straight to the function.
+    assert_eq!(
+        read_u32(image, TEXT as usize + 12),
+        BEACON_PRINTF.wrapping_sub(BASE_32 + TEXT + 16) as u32
+    );
+    assert_eq!(
+        read_u32(image, TEXT as usize + 17),
+        (BASE_32 + COMMONS) as u32
+    );
+
+    let data = DATA as usize;
+    assert_eq!(read_u32(image, data + GO_POINTER), BASE_32 as u32);
+    assert_eq!(
+        read_u32(image, data + HELPER_POINTER),
+        BASE_32 as u32
+    );
+}
+
+#[test]
+fn rel32_4() {
+    // MSVC emits REL32_4 for the store to `counter` rather than folding the immediate into the
+    // addend as llvm-mc does.
+    let buffer = object(true);
+    let (relocation, field) = {
+        let file = CoffFile::parse(&buffer).unwrap();
+        let text = file.section(1).unwrap();
+        (text.p_reloc as usize + 4 * 10, text.p_raw_data as usize + 26)
+    };
+    assert_eq!(read_u32(&buffer, field) as i32, -4);
+    let buffer = set_u16(buffer, relocation + 8, IMAGE_REL_AMD64_REL32_4);
+    let buffer = set_u32(buffer, field, 0);
+
+    let mut object = load_buffer(buffer, BASE).unwrap();
+    let image = object.image().unwrap();
+    assert_eq!(read_i32(&image[..], TEXT + 26), (COMMONS - 34) as i64);
+}
+
+#[test]
+fn resolve_externals() {
+    let error = ObjectLoader::with_config(object(true), config_at(BASE))
+        .load()
+        .err()
+        .unwrap();
+    match error {
+        LoadError::UnresolvedSymbol(ref name) => assert_eq!(name, "BeaconPrintf"),
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    let config = config_at(BASE).hook_import("kernel32", "OutputDebugStringA", |_| 0x1234);
+    let object = ObjectLoader::with_config(object(true), config)
+        .search_module("beacon")
+        .load()
+        .unwrap();
+    let imports = object.imports();
+    assert_eq!(imports[0].address, 0x1234);
+    assert_eq!(imports[1].module.as_ref().map(|m| &m[..]), Some("beacon"));
+    assert_eq!(
+        imports[1].address,
+        StubResolver::address(
+            1,
+            &ImportName::Name {
+                hint: 0,
+                name: "BeaconPrintf",
+            },
+        )
+    );
+}
+
+#[test]
+fn unsupported_machine() {
+    let mut buffer = object(true);
+    buffer[..2].copy_from_slice(&0xaa64u16.to_le_bytes());
+    match ObjectLoader::with_config(buffer, config_at(BASE)).load() {
+        Err(LoadError::UnsupportedMachine(Machine::ARM64)) => {}
+        r => panic!("unexpected result {:?}", r.err()),
+    }
+}
+
+#[test]
+fn aux_symbols() {
+    let buffer = fixture("amd64.obj");
+    let table = CoffFile::parse(&buffer).unwrap().symbol_table().unwrap();
+    let aux = |index| table.symbol(index).unwrap().aux_symbol().unwrap();
+
+    assert_eq!(aux(17), Some(AuxSymbol::File("bof.c")));
+    match aux(0) {
+        Some(AuxSymbol::SectionDefinition { length, .. }) => assert_eq!(length, 35),
+        a => panic!("unexpected aux symbol {:?}", a),
+    }
+    assert_eq!(aux(8), None);
+    assert_eq!(
+        aux(13),
+        Some(AuxSymbol::WeakExternal {
+            tag_index: 8,
+            characteristics: 3,
+        })
+    );
+}
+
+#[test]
+fn image_symbols() {
+    let buffer = fixture("pe32plus_mingw.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    let debug_info = file.sections().nth(4).unwrap();
+    assert_eq!(debug_info.name_str(), Some("/4"));
+    assert_eq!(debug_info.long_name_offset(), Some(4));
+    assert_eq!(file.section_name(&debug_info), Ok(".debug_info"));
+    let text = file.sections().next().unwrap();
+    assert_eq!(file.section_name(&text), Ok(".text"));
+
+    // lld only keeps the external symbols.
+    let table = file.symbol_table().unwrap().unwrap();
+    let symbols = table.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert!(symbols.iter().all(|s| s.aux_symbol() == Ok(None)));
+
+    let externals = symbols
+        .iter()
+        .filter(|s| s.is_external())
+        .map(|s| (s.name, s.section_number, s.value, s.is_function()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        externals,
+        [
+            ("mainCRTStartup", 1, 0, true),
+            ("main", 1, 0x10, true),
+            ("global_counter", 4, 8, false),
+        ]
+    );
+
+    let buffer = fixture("pe32plus_exe.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert!(file.symbol_table().unwrap().is_none());
+    assert!(file.sections().all(|s| file.section_name(&s).is_ok()));
+}
+
+#[test]
+fn long_section_names() {
+    let mut section = PEFile::parse(&fixture("pe32plus_exe.exe"))
+        .unwrap()
+        .sections()
+        .next()
+        .unwrap();
+    for &(name, offset) in &[
+        (b"/4\0\0\0\0\0\0", Some(4)),
+        (b"/1234567", Some(1_234_567)),
+        (b"//AAAAEA", Some(256)),
+        (b"//AAAAE!", None),
+        (b"/\0\0\0\0\0\0\0", None),
+        (b".text\0\0\0", None),
+    ] {
+        section.name = *name;
+        assert_eq!(section.long_name_offset(), offset);
+    }
+
+    // Without a string table there is nothing to look long names up in.
+    let buffer = fixture("pe32plus_exe.exe");
+    assert_eq!(
+        PEFile::parse(&buffer).unwrap().section_name(&section),
+        Ok(".text")
+    );
+    section.name = *b"/4\0\0\0\0\0\0";
+    assert_eq!(
+        PEFile::parse(&buffer).unwrap().section_name(&section),
+        Err(ParseError::InvalidName)
+    );
+}
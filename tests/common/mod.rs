@@ -0,0 +1,116 @@
+// Helpers shared by the integration tests. The fixtures in tests/fixtures are built from
+// tests/fixtures/src by build.sh, the constants below describe their layout.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+
+use pe_load::*;
+
+// pe32_exe.exe and pe32plus_exe.exe. The linker merges .bss into the end of .data.
+pub const TEXT_RVA: u32 = 0x1000;
+pub const RDATA_RVA: u32 = 0x2000;
+pub const DATA_RVA: u32 = 0x3000;
+pub const BSS_RVA: u32 = 0x3020;
+pub const TLS_CALLBACK_RVA: u32 = 0x1040;
+
+// Offsets of the pointers in .data, the first points to .text + 4, the second to .bss + 0x10.
+pub const TEXT_POINTER: u32 = 8;
+pub const BSS_POINTER: u32 = 16;
+
+// pe32_dll.dll and pe32plus_dll.dll, the first pointer in .data points to `fixture_sub`.
+pub const FIXTURE_ADD_RVA: u32 = 0x1010;
+pub const FIXTURE_SUB_RVA: u32 = 0x1020;
+
+// pe32plus_resources.exe.
+pub const RSRC_RVA: u32 = 0x3000;
+
+pub const FIXTURES: &[&str] = &[
+    "pe32_exe.exe",
+    "pe32plus_exe.exe",
+    "pe32_dll.dll",
+    "pe32plus_dll.dll",
+    "pe32plus_resources.exe",
+    "pe32plus_signed.dll",
+    "pe32plus_mingw.exe",
+    "pe32_managed.exe",
+    "arm64x.dll",
+];
+
+pub fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+pub fn fixture(name: &str) -> Vec<u8> {
+    fs::read(fixture_path(name)).unwrap_or_else(|e| panic!("couldn't read {}: {}", name, e))
+}
+
+pub fn set_u16(mut buffer: Vec<u8>, offset: usize, value: u16) -> Vec<u8> {
+    buffer[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    buffer
+}
+
+pub fn set_u32(mut buffer: Vec<u8>, offset: usize, value: u32) -> Vec<u8> {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    buffer
+}
+
+pub fn pe_header(buffer: &[u8]) -> usize {
+    u32::from_le_bytes([buffer[0x3c], buffer[0x3d], buffer[0x3e], buffer[0x3f]]) as usize
+}
+
+/// Resolves every import to `handle | ordinal` or `handle | name length`, where the handle of
+/// the n-th loaded module is `(n + 1) << 32`.
+#[derive(Default)]
+pub struct StubResolver {
+    pub modules: Vec<String>,
+}
+
+impl StubResolver {
+    pub fn address(module: usize, symbol: &ImportName) -> u64 {
+        ((module as u64 + 1) << 32)
+            | match *symbol {
+                ImportName::Ordinal(ordinal) => ordinal as u64,
+                ImportName::Name { name, .. } => 0x1000 + name.len() as u64,
+            }
+    }
+}
+
+impl ImportResolver for StubResolver {
+    fn load_module(&mut self, module: &str) -> Result<u64, LoadError> {
+        let name = module.to_ascii_lowercase();
+        let index = match self.modules.iter().position(|m| *m == name) {
+            Some(index) => index,
+            None => {
+                self.modules.push(name);
+                self.modules.len() - 1
+            }
+        };
+        Ok(index as u64)
+    }
+
+    fn resolve(&mut self, module: u64, symbol: &ImportName) -> u64 {
+        StubResolver::address(module as usize, symbol)
+    }
+}
+
+pub fn config_at(base: u64) -> LoaderConfig {
+    LoaderConfig::new()
+        .backend(InMemoryBackend::at(base))
+        .resolver(StubResolver::default())
+}
+
+pub fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    let mut b = [0; 4];
+    b.copy_from_slice(&buffer[offset..offset + 4]);
+    u32::from_le_bytes(b)
+}
+
+pub fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(&buffer[offset..offset + 8]);
+    u64::from_le_bytes(b)
+}
@@ -0,0 +1,90 @@
+extern crate pe_load;
+
+mod common;
+
+use common::*;
+use pe_load::*;
+
+#[test]
+fn imphash() {
+    // kernel32.exitprocess,kernel32.getprocaddress,user32.messageboxa,ws2_32.socket
+    for &name in &["pe32_exe.exe", "pe32plus_exe.exe"] {
+        let buffer = fixture(name);
+        assert_eq!(
+            PEFile::parse(&buffer).unwrap().imphash(),
+            Ok(Some("76ffa88d023f1b301207aa158e8cb51d".into())),
+            "{}",
+            name
+        );
+    }
+
+    let buffer = fixture("pe32plus_resources.exe");
+    assert_eq!(PEFile::parse(&buffer).unwrap().imphash(), Ok(None));
+
+    // msvcrt.ord5,helper.exe.exit
+    let mut b = PEBuilder::pe32();
+    b.section(".text", SECTION_CODE, vec![0xc3]);
+    b.import("MSVCRT.DLL", 5u16).import("Helper.exe", "Exit");
+    let buffer = b.build().unwrap();
+    assert_eq!(
+        PEFile::parse(&buffer).unwrap().imphash(),
+        Ok(Some("df4d87a855f99871f9c8b52afc77c335".into()))
+    );
+}
+
+#[test]
+fn ordinal_names() {
+    assert_eq!(ordinal_name("WS2_32.dll", 23), Some("socket"));
+    assert_eq!(ordinal_name("ws2_32.dll", 115), Some("WSAStartup"));
+    assert_eq!(ordinal_name("WSOCK32.DLL", 1141), Some("AcceptEx"));
+    assert_eq!(ordinal_name("OLEAUT32.dll", 2), Some("SysAllocString"));
+    assert_eq!(ordinal_name("ws2_32.dll", 100), None);
+    assert_eq!(ordinal_name("ws2_32", 23), None);
+    assert_eq!(ordinal_name("kernel32.dll", 1), None);
+}
+
+#[test]
+fn sections() {
+    let buffer = fixture("pe32plus_exe.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    let fingerprints = file.section_fingerprints();
+    assert_eq!(fingerprints.len(), file.num_sections());
+
+    let text = &fingerprints[0];
+    assert_eq!(text.section.name_str(), Some(".text"));
+    assert_eq!(file.section_data(&text.section).len(), 0x200);
+    // Mostly the zeros padding the code to the file alignment.
+    assert!((text.entropy - 1.0396).abs() < 1e-4);
+
+    // Without raw data, as .bss would be if the linker hadn't merged it into .data.
+    let header = pe_header(&buffer) + 24 + 240 + 2 * 40;
+    let buffer = set_u32(set_u32(buffer, header + 16, 0), header + 20, 0);
+    let fingerprints = PEFile::parse(&buffer).unwrap().section_fingerprints();
+    let data = &fingerprints[2];
+    assert_eq!(data.section.name_str(), Some(".data"));
+    assert_eq!(data.md5, "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(
+        data.sha256,
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(data.entropy, 0.0);
+}
+
+#[test]
+fn entropy_range() {
+    assert_eq!(entropy(&[]), 0.0);
+    assert_eq!(entropy(&[7; 100]), 0.0);
+    assert_eq!(entropy(&[0, 1, 0, 1]), 1.0);
+    assert_eq!(entropy(&(0..=255).collect::<Vec<u8>>()), 8.0);
+}
+
+#[test]
+fn rich_hash() {
+    let buffer = fixture("pe32plus_resources.exe");
+    let rich = PEFile::parse(&buffer)
+        .unwrap()
+        .rich_header()
+        .unwrap()
+        .unwrap();
+    assert_eq!(rich.hash(), "f95324348280db9f367f82e5f8a42353");
+}
@@ -0,0 +1,46 @@
+# amd64.obj, a beacon object file. i386.s is the same for i386.
+#
+# `go` passes `message` to OutputDebugStringA, imported the way beacon object files do, calls
+# BeaconPrintf directly and then reads and writes the common symbol `counter`. .data holds
+# relocated references to `go`, `message` and the weak external `helper`, which defaults to `go`.
+
+        .file   "bof.c"
+        .text
+        .globl  go
+        .def    go; .scl 2; .type 32; .endef
+go:
+        leaq    message(%rip), %rcx
+        callq   *__imp_KERNEL32$OutputDebugStringA(%rip)
+        callq   BeaconPrintf
+        movl    counter(%rip), %eax
+        movl    $42, counter(%rip)
+        retq
+
+        .data
+        .long   0
+message:
+        .asciz  "hello"
+        .balign 8, 0
+        .quad   go
+        .rva    go + 1
+        .secrel32 message
+        .secidx message
+        .balign 8, 0
+        .quad   helper
+
+        .bss
+        .zero   8
+        .globl  buffer
+buffer:
+        .zero   0x18
+
+        .comm   counter, 4, 2
+
+        # Declared but never referenced, so there is nothing to resolve.
+        .globl  Unused
+
+        .weak   helper
+        .set    helper, go
+
+        .section .drectve,"yn"
+        .ascii  "/DEFAULTLIB:\"LIBCMT\" "
@@ -0,0 +1,70 @@
+# The ARM64EC half of arm64x.dll. Its load config points at the CHPE metadata, whose tables the
+# linker fills in; the __os_arm64x_* pointers would be set by the OS loader.
+
+        .text
+        .globl  "#_DllMainCRTStartup"
+        .p2align 2
+"#_DllMainCRTStartup":
+        mov     w0, #1
+        ret
+        .weak_anti_dep _DllMainCRTStartup
+        .set    _DllMainCRTStartup, "#_DllMainCRTStartup"
+
+        .globl  "#fixture_add"
+        .p2align 2
+"#fixture_add":
+        add     w0, w0, w1
+        ret
+        .weak_anti_dep fixture_add
+        .set    fixture_add, "#fixture_add"
+
+        .section .rdata,"dr"
+        .globl  _load_config_used
+        .p2align 3
+_load_config_used:
+        .word   0x140
+        .fill   0xc4, 1, 0
+        .xword  __chpe_metadata
+        .fill   0x70, 1, 0
+
+        .globl  __chpe_metadata
+        .p2align 3
+__chpe_metadata:
+        .word   2
+        .rva    __hybrid_code_map
+        .word   __hybrid_code_map_count
+        .rva    __x64_code_ranges_to_entry_points
+        .rva    __arm64x_redirection_metadata
+        .rva    __os_arm64x_dispatch_call_no_redirect
+        .rva    __os_arm64x_dispatch_ret
+        .rva    __os_arm64x_check_call
+        .rva    __os_arm64x_check_icall
+        .rva    __os_arm64x_check_icall_cfg
+        .rva    __arm64x_native_entrypoint
+        .rva    __hybrid_auxiliary_iat
+        .word   __x64_code_ranges_to_entry_points_count
+        .word   __arm64x_redirection_metadata_count
+        .rva    __os_arm64x_get_x64_information
+        .rva    __os_arm64x_set_x64_information
+        .rva    __arm64x_extra_rfe_table
+        .word   __arm64x_extra_rfe_table_size
+        .rva    __os_arm64x_dispatch_fptr
+        .rva    __hybrid_auxiliary_iat_copy
+        .rva    __hybrid_auxiliary_delayload_iat
+        .rva    __hybrid_auxiliary_delayload_iat_copy
+        .word   __hybrid_image_info_bitfield
+
+        .data
+        .p2align 3
+__os_arm64x_dispatch_call_no_redirect:
+__os_arm64x_dispatch_ret:
+__os_arm64x_check_call:
+__os_arm64x_check_icall:
+__os_arm64x_check_icall_cfg:
+__os_arm64x_get_x64_information:
+__os_arm64x_set_x64_information:
+__os_arm64x_dispatch_fptr:
+        .xword  0
+
+        .section .drectve,"yn"
+        .ascii  " -export:fixture_add"
@@ -0,0 +1,30 @@
+# The native ARM64 half of arm64x.dll. arm64x_ec.s and arm64x_x64.s make up the ARM64EC view.
+
+        .text
+        .globl  _DllMainCRTStartup
+        .p2align 2
+_DllMainCRTStartup:
+        mov     w0, #1
+        ret
+
+        .globl  fixture_add
+        .p2align 2
+fixture_add:
+        add     w0, w0, w1
+        ret
+
+        .p2align 2
+allocate:
+        adrp    x16, __imp_HeapAlloc
+        ldr     x16, [x16, :lo12:__imp_HeapAlloc]
+        br      x16
+
+        .section .rdata,"dr"
+        .globl  _load_config_used
+        .p2align 3
+_load_config_used:
+        .word   0x140
+        .fill   0x13c, 1, 0
+
+        .section .drectve,"yn"
+        .ascii  " -export:fixture_add"
@@ -0,0 +1,11 @@
+# x64 code in the ARM64EC view of arm64x.dll.
+
+        .text
+        .globl  x64_sub
+x64_sub:
+        movl    %ecx, %eax
+        subl    %edx, %eax
+        retq
+
+        .section .drectve,"yn"
+        .ascii  " -export:x64_sub"
@@ -0,0 +1,114 @@
+#!/bin/sh
+# Rebuilds the fixtures in tests/fixtures from the sources next to this script.
+#
+# Needs llvm-mc and llvm-rc (LLVM 14 or later), rustc with the rust-lld it ships, a nightly rustc
+# with the aarch64-pc-windows-msvc and arm64ec-pc-windows-msvc targets for arm64x.dll, openssl and
+# python3 with the cryptography package. The output differs between runs only in the signing keys.
+
+set -eu
+
+src=$(cd "$(dirname "$0")" && pwd)
+out=$(dirname "$src")
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+lld="$(rustc --print sysroot)/lib/rustlib/$(rustc -vV | sed -n 's/^host: //p')/bin/rust-lld"
+
+# Import libraries of DLLs go to $tmp.
+link() {
+    "$lld" -flavor link /nologo /nodefaultlib /Brepro /release "/implib:$tmp/implib.lib" "$@"
+}
+
+lib() {
+    machine=$1
+    shift
+    for def in "$@"; do
+        "$lld" -flavor link /lib /nologo "/machine:$machine" "/def:$src/$def.def" \
+            "/out:$tmp/$def-$machine.lib"
+    done
+}
+
+mc() {
+    llvm-mc -triple "$1" -filetype obj "$src/$2" -o "$3"
+}
+
+# Assembles with rustc for targets llvm-mc doesn't know.
+global_asm() {
+    ASM="$src/$2" rustc +nightly --target "$1" --emit obj -C panic=abort \
+        "$src/global_asm.rs" -o "$3"
+}
+
+lib x64 kernel32 user32 ws2_32
+lib x86 kernel32 user32 ws2_32 mscoree
+"$lld" -flavor link /lib /nologo /machine:arm64x "/def:$src/kernel32.def" \
+    "/defArm64Native:$src/kernel32.def" "/out:$tmp/kernel32-arm64x.lib"
+
+# Executables and DLLs.
+mc x86_64-pc-windows-msvc pe32plus_exe.s "$tmp/pe32plus_exe.obj"
+link /subsystem:console /entry:mainCRTStartup "/out:$out/pe32plus_exe.exe" \
+    "$tmp/pe32plus_exe.obj" "$tmp/kernel32-x64.lib" "$tmp/user32-x64.lib" "$tmp/ws2_32-x64.lib"
+
+mc i686-pc-windows-msvc pe32_exe.s "$tmp/pe32_exe.obj"
+link /safeseh:no /subsystem:console /entry:mainCRTStartup "/out:$out/pe32_exe.exe" \
+    "$tmp/pe32_exe.obj" "$tmp/kernel32-x86.lib" "$tmp/user32-x86.lib" "$tmp/ws2_32-x86.lib"
+
+mc x86_64-pc-windows-msvc pe32plus_dll.s "$tmp/pe32plus_dll.obj"
+link /dll /entry:_DllMainCRTStartup "/def:$src/fixture.def" "/out:$out/pe32plus_dll.dll" \
+    "$tmp/pe32plus_dll.obj" "$tmp/kernel32-x64.lib"
+
+mc i686-pc-windows-msvc pe32_dll.s "$tmp/pe32_dll.obj"
+link /safeseh:no /dll /entry:_DllMainCRTStartup "/def:$src/fixture.def" \
+    "/out:$out/pe32_dll.dll" "$tmp/pe32_dll.obj" "$tmp/kernel32-x86.lib"
+
+# bad_*.exe, pe_header_out_of_bounds.exe and truncated_section_table.exe.
+python3 "$src/pe.py" malformed "$out/pe32plus_exe.exe" "$out"
+
+python3 "$src/pe.py" stub "$tmp/stub.bin"
+mc x86_64-pc-windows-msvc pe32plus_resources.s "$tmp/pe32plus_resources.obj"
+llvm-rc -no-preprocess -fo "$tmp/pe32plus_resources.res" "$src/pe32plus_resources.rc"
+link /subsystem:console /entry:mainCRTStartup /debug "/pdb:$tmp/fixture.pdb" \
+    /pdbaltpath:fixture.pdb "/stub:$tmp/stub.bin" "/out:$out/pe32plus_resources.exe" \
+    "$tmp/pe32plus_resources.obj" "$tmp/pe32plus_resources.res"
+
+llvm-mc -triple x86_64-w64-windows-gnu -filetype obj "$src/pe32plus_mingw.s" \
+    -o "$tmp/pe32plus_mingw.o"
+"$lld" -flavor gnu -m i386pep --entry mainCRTStartup --subsystem console \
+    --no-insert-timestamp -o "$out/pe32plus_mingw.exe" "$tmp/pe32plus_mingw.o"
+
+mc i686-pc-windows-msvc pe32_managed.s "$tmp/pe32_managed.obj"
+link /safeseh:no /subsystem:console /entry:_CorExeMain_stub "/out:$out/pe32_managed.exe" \
+    "$tmp/pe32_managed.obj" "$tmp/mscoree-x86.lib"
+python3 "$src/pe.py" clr "$out/pe32_managed.exe" 0x1008 72
+
+global_asm aarch64-pc-windows-msvc arm64x_native.s "$tmp/arm64x_native.obj"
+global_asm arm64ec-pc-windows-msvc arm64x_ec.s "$tmp/arm64x_ec.obj"
+mc x86_64-pc-windows-msvc arm64x_x64.s "$tmp/arm64x_x64.obj"
+link /machine:arm64x /dll "/out:$out/arm64x.dll" "$tmp/arm64x_native.obj" \
+    "$tmp/arm64x_ec.obj" "$tmp/arm64x_x64.obj" "$tmp/kernel32-arm64x.lib"
+
+# pe32plus_signed.dll is pe32plus_dll.dll signed by a throwaway certificate.
+openssl req -x509 -newkey rsa:2048 -nodes -keyout "$tmp/ca.key" -out "$tmp/ca.pem" \
+    -subj "/CN=Fixture CA" -days 36500 -set_serial 1 2>/dev/null
+openssl req -newkey rsa:2048 -nodes -keyout "$tmp/signer.key" -out "$tmp/signer.csr" \
+    -subj "/CN=Fixture Signer" 2>/dev/null
+printf 'extendedKeyUsage=codeSigning\nkeyUsage=digitalSignature\n' >"$tmp/signer.ext"
+openssl x509 -req -in "$tmp/signer.csr" -CA "$tmp/ca.pem" -CAkey "$tmp/ca.key" -set_serial 2 \
+    -days 36500 -extfile "$tmp/signer.ext" -out "$tmp/signer.pem" 2>/dev/null
+python3 "$src/pe.py" sign "$out/pe32plus_dll.dll" "$tmp/signer.key" "$tmp/signer.pem" \
+    "$tmp/ca.pem" "$out/pe32plus_signed.dll"
+
+# Checks the signature with openssl rather than with pe_load.
+python3 - "$out/pe32plus_signed.dll" "$tmp/signature.der" <<'EOF'
+import struct, sys
+image = open(sys.argv[1], "rb").read()
+pe = struct.unpack_from("<I", image, 0x3C)[0]
+offset, size = struct.unpack_from("<II", image, pe + 24 + 112 + 4 * 8)
+length = struct.unpack_from("<I", image, offset)[0]
+open(sys.argv[2], "wb").write(image[offset + 8 : offset + length])
+EOF
+openssl smime -verify -inform DER -in "$tmp/signature.der" -CAfile "$tmp/ca.pem" \
+    -purpose any -out /dev/null
+
+# Object files.
+mc x86_64-pc-windows-msvc amd64.s "$out/amd64.obj"
+mc i686-pc-windows-msvc i386.s "$out/i386.obj"
@@ -0,0 +1,18 @@
+// Assembles the file named by the ASM environment variable for targets llvm-mc doesn't know, such
+// as arm64ec-pc-windows-msvc, see build.sh.
+
+#![feature(no_core, rustc_attrs, decl_macro)]
+#![allow(internal_features)]
+#![crate_type = "lib"]
+#![no_core]
+
+#[rustc_builtin_macro]
+macro env($name:expr $(,)?) {}
+
+#[rustc_builtin_macro]
+macro include_str($file:expr $(,)?) {}
+
+#[rustc_builtin_macro]
+macro global_asm("assembly template", $(operands,)* $(options($(option),*))?) {}
+
+global_asm!(include_str!(env!("ASM")));
@@ -0,0 +1,42 @@
+# i386.obj, see amd64.s. Symbols are decorated with a leading underscore.
+
+        .file   "bof.c"
+        .text
+        .globl  _go
+        .def    _go; .scl 2; .type 32; .endef
+_go:
+        pushl   $message
+        calll   *__imp__KERNEL32$OutputDebugStringA@4
+        calll   _BeaconPrintf
+        movl    _counter, %eax
+        retl
+
+        .data
+        .long   0
+message:
+        .asciz  "hello"
+        .balign 8, 0
+        .long   _go
+        .long   0
+        .rva    _go + 1
+        .secrel32 message
+        .secidx message
+        .balign 8, 0
+        .long   _helper
+
+        .bss
+        .zero   8
+        .globl  _buffer
+_buffer:
+        .zero   0x18
+
+        .comm   _counter, 4, 2
+
+        # Declared but never referenced, so there is nothing to resolve.
+        .globl  _Unused
+
+        .weak   _helper
+        .set    _helper, _go
+
+        .section .drectve,"yn"
+        .ascii  "/DEFAULTLIB:\"LIBCMT\" "
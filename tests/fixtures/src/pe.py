@@ -0,0 +1,245 @@
+#!/usr/bin/env python3
+"""The steps of build.sh the linker can't do. Independent of pe_load on purpose.
+
+    pe.py stub OUT                           a DOS stub with a Rich header, for /stub
+    pe.py clr IMAGE RVA SIZE                 point the CLR header directory at RVA
+    pe.py malformed IMAGE DIR                write copies of IMAGE with broken headers to DIR
+    pe.py sign IMAGE KEY CERT CA_CERT OUT    append an Authenticode signature
+
+Layouts are those of the PE format specification and of "Windows Authenticode Portable
+Executable Signature Format". The Rich header isn't documented by Microsoft, the layout and the
+checksum follow the descriptions everyone else uses.
+"""
+
+import hashlib
+import os
+import struct
+import sys
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import padding
+
+# The DOS program MSVC links in: prints the message and exits.
+DOS_PROGRAM = (
+    bytes.fromhex("0e1fba0e00b409cd21b8014ccd21")
+    + b"This program cannot be run in DOS mode.\r\r\n$"
+    + bytes(7)
+)
+
+# (product id, build, count) as in a small C program linked by Visual Studio 2019 16.11.
+RICH_ENTRIES = [(0x104, 30133, 12), (0x1, 0, 40), (0x102, 30133, 1)]
+
+
+def rol32(value, count):
+    count %= 32
+    return ((value << count) | (value >> (32 - count))) & 0xFFFFFFFF
+
+
+def stub():
+    header = bytearray(64)
+    # e_magic, e_cblp, e_cp, e_crlc, e_cparhdr, e_minalloc, e_maxalloc, e_ss, e_sp
+    struct.pack_into("<2s8H", header, 0, b"MZ", 0x90, 3, 0, 4, 0, 0xFFFF, 0, 0xB8)
+    # e_lfarlc
+    struct.pack_into("<H", header, 0x18, 0x40)
+    dos = bytes(header) + DOS_PROGRAM
+    assert len(dos) == 0x80
+
+    # The checksum covers the DOS header without e_lfanew, which the linker fills in.
+    checksum = len(dos)
+    for i, byte in enumerate(dos):
+        if not 0x3C <= i < 0x40:
+            checksum += rol32(byte, i)
+    for product, build, count in RICH_ENTRIES:
+        checksum += rol32(product << 16 | build, count)
+    checksum &= 0xFFFFFFFF
+
+    values = [0x536E6144, 0, 0, 0]  # "DanS" and padding
+    for product, build, count in RICH_ENTRIES:
+        values += [product << 16 | build, count]
+    rich = b"".join(struct.pack("<I", v ^ checksum) for v in values)
+    return dos + rich + b"Rich" + struct.pack("<I", checksum)
+
+
+def headers(image):
+    pe = struct.unpack_from("<I", image, 0x3C)[0]
+    magic = struct.unpack_from("<H", image, pe + 24)[0]
+    directories = pe + 24 + (96 if magic == 0x10B else 112)
+    return pe + 24 + 64, directories
+
+
+def update_checksum(image):
+    checksum_offset, _ = headers(image)
+    struct.pack_into("<I", image, checksum_offset, 0)
+    padded = bytes(image) + bytes(-len(image) % 2)
+    total = 0
+    for (word,) in struct.iter_unpack("<H", padded):
+        total += word
+        total = (total & 0xFFFF) + (total >> 16)
+    struct.pack_into("<I", image, checksum_offset, (total & 0xFFFF) + len(image))
+
+
+def clr(image, rva, size):
+    _, directories = headers(image)
+    struct.pack_into("<II", image, directories + 14 * 8, rva, size)
+    update_checksum(image)
+
+
+def malformed(image):
+    pe = struct.unpack_from("<I", image, 0x3C)[0]
+    optional_header_size = struct.unpack_from("<H", image, pe + 20)[0]
+    section_table = pe + 24 + optional_header_size
+
+    def patched(offset, layout, value):
+        copy = bytearray(image)
+        struct.pack_into(layout, copy, offset, value)
+        return copy
+
+    return {
+        "bad_dos_signature.exe": patched(0, "<H", 0x5A5A),
+        "bad_pe_signature.exe": patched(pe, "<I", 0x4545),
+        # The magic of ROM images, which don't have an optional header the parser knows.
+        "bad_optional_magic.exe": patched(pe + 24, "<H", 0x107),
+        "pe_header_out_of_bounds.exe": patched(0x3C, "<I", 0x100000),
+        # Ends in the middle of the first section header.
+        "truncated_section_table.exe": image[: section_table + 20],
+    }
+
+
+def der(tag, *parts):
+    contents = b"".join(parts)
+    length = len(contents)
+    if length < 0x80:
+        encoded = bytes([length])
+    else:
+        encoded = length.to_bytes((length.bit_length() + 7) // 8, "big")
+        encoded = bytes([0x80 | len(encoded)]) + encoded
+    return bytes([tag]) + encoded + contents
+
+
+def der_contents(encoded):
+    length = encoded[1]
+    return encoded[2 + (length & 0x7F if length & 0x80 else 0) :]
+
+
+def oid(dotted):
+    numbers = [int(n) for n in dotted.split(".")]
+    encoded = bytes([numbers[0] * 40 + numbers[1]])
+    for n in numbers[2:]:
+        chunk = [n & 0x7F]
+        while n > 0x7F:
+            n >>= 7
+            chunk.insert(0, 0x80 | (n & 0x7F))
+        encoded += bytes(chunk)
+    return der(0x06, encoded)
+
+
+def integer(value):
+    return der(0x02, value.to_bytes(value.bit_length() // 8 + 1, "big", signed=True))
+
+
+SHA256 = der(0x30, oid("2.16.840.1.101.3.4.2.1"), der(0x05))
+SPC_INDIRECT_DATA = oid("1.3.6.1.4.1.311.2.1.4")
+
+
+def authenticode_hash(image):
+    checksum, directories = headers(image)
+    security = directories + 4 * 8
+    table, size = struct.unpack_from("<II", image, security)
+    end = table if size else len(image)
+    digest = hashlib.sha256()
+    digest.update(image[:checksum])
+    digest.update(image[checksum + 4 : security])
+    digest.update(image[security + 8 : end])
+    return digest.digest()
+
+
+def sign(image, key, certificate, ca_certificate):
+    # SpcIndirectDataContent with SpcPeImageData, whose file is the customary "<<<Obsolete>>>".
+    obsolete = der(0x80, "<<<Obsolete>>>".encode("utf-16-be"))
+    image_data = der(0x30, der(0x03, b"\0"), der(0xA0, der(0xA2, obsolete)))
+    indirect_data = der(
+        0x30,
+        der(0x30, oid("1.3.6.1.4.1.311.2.1.15"), image_data),
+        der(0x30, SHA256, der(0x04, authenticode_hash(image))),
+    )
+
+    # The message digest covers the contents of the SpcIndirectDataContent, without its tag and
+    # length, and the signature covers the authenticated attributes as a SET OF.
+    message_digest = der(0x04, hashlib.sha256(der_contents(indirect_data)).digest())
+    attributes = sorted(
+        [
+            der(0x30, oid("1.2.840.113549.1.9.3"), der(0x31, SPC_INDIRECT_DATA)),
+            der(0x30, oid("1.3.6.1.4.1.311.2.1.12"), der(0x31, der(0x30))),
+            der(0x30, oid("1.2.840.113549.1.9.4"), der(0x31, message_digest)),
+        ]
+    )
+    signature = key.sign(der(0x31, *attributes), padding.PKCS1v15(), hashes.SHA256())
+
+    signer_info = der(
+        0x30,
+        integer(1),
+        der(0x30, certificate.issuer.public_bytes(), integer(certificate.serial_number)),
+        SHA256,
+        der(0xA0, *attributes),
+        der(0x30, oid("1.2.840.113549.1.1.1"), der(0x05)),
+        der(0x04, signature),
+    )
+    der_certificates = [
+        c.public_bytes(serialization.Encoding.DER) for c in (certificate, ca_certificate)
+    ]
+    signed_data = der(
+        0x30,
+        integer(1),
+        der(0x31, SHA256),
+        der(0x30, SPC_INDIRECT_DATA, der(0xA0, indirect_data)),
+        der(0xA0, *der_certificates),
+        der(0x31, signer_info),
+    )
+    content_info = der(0x30, oid("1.2.840.113549.1.7.2"), der(0xA0, signed_data))
+
+    # WIN_CERTIFICATE, revision 2.0 holding PKCS#7 SignedData, padded to eight bytes.
+    offset = len(image)
+    assert offset % 8 == 0
+    entry = struct.pack("<IHH", 8 + len(content_info), 0x200, 2) + content_info
+    entry += bytes(-len(entry) % 8)
+    image += entry
+    _, directories = headers(image)
+    struct.pack_into("<II", image, directories + 4 * 8, offset, len(entry))
+    update_checksum(image)
+
+
+def main(args):
+    if args[0] == "stub":
+        with open(args[1], "wb") as f:
+            f.write(stub())
+    elif args[0] == "clr":
+        with open(args[1], "rb") as f:
+            image = bytearray(f.read())
+        clr(image, int(args[2], 0), int(args[3], 0))
+        with open(args[1], "wb") as f:
+            f.write(image)
+    elif args[0] == "malformed":
+        with open(args[1], "rb") as f:
+            image = f.read()
+        for name, contents in malformed(image).items():
+            with open(os.path.join(args[2], name), "wb") as f:
+                f.write(contents)
+    elif args[0] == "sign":
+        with open(args[1], "rb") as f:
+            image = bytearray(f.read())
+        with open(args[2], "rb") as f:
+            key = serialization.load_pem_private_key(f.read(), None)
+        with open(args[3], "rb") as f:
+            certificate = x509.load_pem_x509_certificate(f.read())
+        with open(args[4], "rb") as f:
+            ca_certificate = x509.load_pem_x509_certificate(f.read())
+        sign(image, key, certificate, ca_certificate)
+        with open(args[5], "wb") as f:
+            f.write(image)
+    else:
+        sys.exit(__doc__)
+
+
+if __name__ == "__main__":
+    main(sys.argv[1:])
@@ -0,0 +1,36 @@
+# pe32_dll.dll, see pe32plus_dll.s.
+
+        .text
+        .globl  __DllMainCRTStartup
+        .p2align 4
+__DllMainCRTStartup:
+        movl    $1, %eax
+        retl    $12
+
+        .globl  _fixture_add
+        .p2align 4
+_fixture_add:
+        movl    4(%esp), %eax
+        addl    8(%esp), %eax
+        retl
+
+        .globl  _fixture_sub
+        .p2align 4
+_fixture_sub:
+        movl    4(%esp), %eax
+        subl    8(%esp), %eax
+        retl
+
+        .p2align 4
+allocate:
+        jmpl    *__imp__HeapAlloc
+
+        .data
+        .p2align 3
+        .long   _fixture_sub
+        .long   0
+        .quad   0
+
+        .bss
+        .p2align 4
+        .zero   0x2ff0
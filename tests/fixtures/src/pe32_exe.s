@@ -0,0 +1,61 @@
+# pe32_exe.exe, see pe32plus_exe.s.
+
+        .text
+        .globl  _mainCRTStartup
+        .p2align 4
+_mainCRTStartup:
+        pushl   $exit_process
+        pushl   $0
+        calll   *__imp__GetProcAddress
+        pushl   $0
+        pushl   $1
+        pushl   $2
+        calll   *__imp__socket
+        pushl   $0
+        pushl   $0
+        pushl   $0
+        pushl   $0
+        calll   *__imp__MessageBoxA
+        pushl   $0
+        calll   *__imp__ExitProcess
+        int3
+
+        .p2align 6
+tls_callback:
+        retl    $12
+
+        .section .rdata,"dr"
+exit_process:
+        .asciz  "ExitProcess"
+
+        .p2align 2
+        .globl  __tls_used
+__tls_used:
+        .long   tls_start
+        .long   tls_end
+        .long   __tls_index
+        .long   tls_callbacks
+        .long   0
+        .long   0
+tls_callbacks:
+        .long   tls_callback
+        .long   0
+
+        .section .tls$,"dw"
+tls_start:
+        .byte   1, 2, 3, 4
+tls_end:
+
+        .data
+        .p2align 3
+__tls_index:
+        .long   0
+        .p2align 3
+        .long   _mainCRTStartup + 4
+        .long   0
+        .long   buffer + 0x10
+
+        .bss
+        .p2align 4
+buffer:
+        .zero   0x1800
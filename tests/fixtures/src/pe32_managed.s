@@ -0,0 +1,111 @@
+# pe32_managed.exe: an IL-only assembly whose only method is the global `Main`, which returns.
+# The CLR header and the metadata follow ECMA-335 partition II, chapters 24 and 25. build.sh
+# points the CLR header directory at `clr_header`, lld doesn't know about it.
+
+        .text
+        .globl  __CorExeMain_stub
+__CorExeMain_stub:
+        jmpl    *__imp___CorExeMain
+
+        # IMAGE_COR20_HEADER, II.25.3.3, at .text + 8.
+        .balign 8, 0
+clr_header:
+        .long   72                      # cb
+        .short  2, 5                    # MajorRuntimeVersion, MinorRuntimeVersion
+        .rva    metadata                # MetaData
+        .long   metadata_end - metadata
+        .long   1                       # Flags, COMIMAGE_FLAGS_ILONLY
+        .long   0x06000001              # EntryPointToken, MethodDef 1
+        .zero   6 * 8                   # Resources up to ManagedNativeHeader
+
+        # The body of `Main`, a tiny header (II.25.4.2) and `ret`.
+main:
+        .byte   1 << 2 | 2, 0x2a
+
+        # The metadata root, II.24.2.1.
+        .balign 4, 0
+metadata:
+        .long   0x424a5342              # Signature, "BSJB"
+        .short  1, 1                    # MajorVersion, MinorVersion
+        .long   0                       # Reserved
+        .long   12                      # Length
+        .asciz  "v4.0.30319"
+        .balign 4, 0
+        .short  0                       # Flags
+        .short  5                       # Streams
+
+        # Stream headers, II.24.2.2.
+        .long   tables - metadata, tables_end - tables
+        .asciz  "#~"
+        .balign 4, 0
+        .long   strings - metadata, strings_end - strings
+        .asciz  "#Strings"
+        .balign 4, 0
+        .long   user_strings - metadata, user_strings_end - user_strings
+        .asciz  "#US"
+        .balign 4, 0
+        .long   guids - metadata, guids_end - guids
+        .asciz  "#GUID"
+        .balign 4, 0
+        .long   blobs - metadata, blobs_end - blobs
+        .asciz  "#Blob"
+        .balign 4, 0
+
+        # The #~ stream, II.24.2.6, with every heap index and table index two bytes wide.
+tables:
+        .long   0                       # Reserved
+        .byte   2, 0                    # MajorVersion, MinorVersion
+        .byte   0                       # HeapSizes
+        .byte   1                       # Reserved
+        .quad   1 << 0x00 | 1 << 0x02 | 1 << 0x06 | 1 << 0x20   # Valid
+        .quad   0                       # Sorted
+        .long   1, 1, 1, 1              # Rows
+
+        # Module, II.22.30: Generation, Name, Mvid, EncId, EncBaseId.
+        .short  0, module_name - strings, 1, 0, 0
+        # TypeDef, II.22.37: Flags, TypeName, TypeNamespace, Extends, FieldList, MethodList.
+        .long   0
+        .short  type_name - strings, 0, 0, 1, 1
+        # MethodDef, II.22.26: RVA, ImplFlags, Flags (public static), Name, Signature, ParamList.
+        .rva    main
+        .short  0, 0x16, main_name - strings, main_signature - blobs, 1
+        # Assembly, II.22.2: HashAlgId, version, Flags, PublicKey, Name, Culture.
+        .long   0x8004
+        .short  1, 0, 0, 0
+        .long   0
+        .short  0, assembly_name - strings, 0
+        .balign 4, 0
+tables_end:
+
+        # II.24.2.3 to II.24.2.5.
+strings:
+        .byte   0
+type_name:
+        .asciz  "<Module>"
+module_name:
+        .asciz  "managed.exe"
+main_name:
+        .asciz  "Main"
+assembly_name:
+        .asciz  "managed"
+        .balign 4, 0
+strings_end:
+
+user_strings:
+        .byte   0
+        .balign 4, 0
+user_strings_end:
+
+guids:
+        .byte   0x6b, 0x1f, 0x57, 0x2d, 0x4a, 0x10, 0x4e, 0x4b
+        .byte   0x9c, 0x2e, 0x60, 0x2f, 0x4d, 0x4b, 0x31, 0x8a
+guids_end:
+
+blobs:
+        .byte   0
+main_signature:
+        # DEFAULT, no parameters, returns VOID, II.23.2.1.
+        .byte   3, 0x00, 0, 0x01
+        .balign 4, 0
+blobs_end:
+metadata_end:
@@ -0,0 +1,35 @@
+# pe32plus_dll.dll: exports two functions and a forwarder, see fixture.def. .bss is merged into
+# .data, which makes .data 0x3000 bytes in memory. pe32_dll.s is the same for i386.
+
+        .text
+        .globl  _DllMainCRTStartup
+        .p2align 4
+_DllMainCRTStartup:
+        movl    $1, %eax
+        retq
+
+        .globl  fixture_add
+        .p2align 4
+fixture_add:
+        leal    (%rcx,%rdx), %eax
+        retq
+
+        .globl  fixture_sub
+        .p2align 4
+fixture_sub:
+        movl    %ecx, %eax
+        subl    %edx, %eax
+        retq
+
+        .p2align 4
+allocate:
+        jmpq    *__imp_HeapAlloc(%rip)
+
+        .data
+        .p2align 3
+        .quad   fixture_sub
+        .quad   0
+
+        .bss
+        .p2align 4
+        .zero   0x2ff0
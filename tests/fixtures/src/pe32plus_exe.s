@@ -0,0 +1,63 @@
+# pe32plus_exe.exe: imports by name and by ordinal, a TLS callback and base relocations. .bss is
+# merged into .data, which makes .data larger in memory than on disk. pe32_exe.s is the same for
+# i386.
+
+        .text
+        .globl  mainCRTStartup
+        .p2align 4
+mainCRTStartup:
+        subq    $40, %rsp
+        xorl    %ecx, %ecx
+        leaq    exit_process(%rip), %rdx
+        callq   *__imp_GetProcAddress(%rip)
+        movl    $2, %ecx
+        movl    $1, %edx
+        xorl    %r8d, %r8d
+        callq   *__imp_socket(%rip)
+        xorl    %ecx, %ecx
+        xorl    %edx, %edx
+        xorl    %r8d, %r8d
+        xorl    %r9d, %r9d
+        callq   *__imp_MessageBoxA(%rip)
+        xorl    %ecx, %ecx
+        callq   *__imp_ExitProcess(%rip)
+        int3
+
+        .p2align 6
+tls_callback:
+        retq
+
+        .section .rdata,"dr"
+exit_process:
+        .asciz  "ExitProcess"
+
+        .p2align 3
+        .globl  _tls_used
+_tls_used:
+        .quad   tls_start
+        .quad   tls_end
+        .quad   _tls_index
+        .quad   tls_callbacks
+        .long   0
+        .long   0
+tls_callbacks:
+        .quad   tls_callback
+        .quad   0
+
+        .section .tls$,"dw"
+tls_start:
+        .byte   1, 2, 3, 4
+tls_end:
+
+        .data
+        .p2align 3
+_tls_index:
+        .long   0
+        .p2align 3
+        .quad   mainCRTStartup + 4
+        .quad   buffer + 0x10
+
+        .bss
+        .p2align 4
+buffer:
+        .zero   0x1800
@@ -0,0 +1,27 @@
+# pe32plus_mingw.exe, linked the way MinGW does: with a COFF symbol table and a .debug_info section
+# whose name is too long for the section header.
+
+        .text
+        .globl  mainCRTStartup
+        .def    mainCRTStartup; .scl 2; .type 32; .endef
+mainCRTStartup:
+        jmp     main
+
+        .globl  main
+        .def    main; .scl 2; .type 32; .endef
+        .p2align 4
+main:
+        movl    global_counter(%rip), %eax
+        retq
+
+        .data
+        .globl  global_counter
+        .p2align 3
+        .quad   0
+global_counter:
+        .long   0
+
+        .section .debug_info,"dr"
+        .long   0x2c
+        .short  4
+        .zero   0x2a
@@ -0,0 +1,9 @@
+# pe32plus_resources.exe: the resources of pe32plus_resources.rc, CodeView debug information and
+# a DOS stub with a Rich header. Nothing to relocate and nothing imported.
+
+        .text
+        .globl  mainCRTStartup
+        .p2align 4
+mainCRTStartup:
+        xorl    %eax, %eax
+        retq
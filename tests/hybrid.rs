@@ -0,0 +1,386 @@
+extern crate pe_load;
+
+mod common;
+
+use common::*;
+use pe_load::*;
+
+const BASE: u64 = 0x1_8000_0000;
+
+// Layout of arm64x.dll. The linker puts the ARM64 code first, then the ARM64EC code, the x64 code
+// and the thunks x64 callers enter the ARM64EC functions through.
+const NATIVE_ADD: u32 = 0x1008;
+const EC_CODE: u32 = 0x2000;
+const X64_CODE: u32 = 0x3000;
+const EC_ENTRY_POINT: u32 = 0x4000;
+const EC_ADD: u32 = 0x4010;
+const NATIVE_LOAD_CONFIG: u32 = 0x6000;
+const EC_LOAD_CONFIG: u32 = 0x6140;
+const CHPE_METADATA: u32 = 0x6280;
+const CODE_MAP: u32 = 0x62f8;
+const EC_EXPORTS: u32 = 0x6371;
+const EC_EXPORTS_SIZE: u32 = 0x5b;
+const IMPORTS: u32 = 0x63cc;
+// The dynamic value relocation table follows the base relocations in .reloc.
+const DVRT: u32 = 0xa00c;
+
+#[test]
+fn load_config() {
+    let buffer = fixture("arm64x.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    let directory = file.data_directory(DirectoryEntry::LoadConfig).unwrap();
+    assert_eq!(directory.unwrap().virtual_address, NATIVE_LOAD_CONFIG);
+
+    let config = file.load_config().unwrap().unwrap();
+    assert_eq!(config.size, 0x140);
+    assert_eq!(config.chpe_metadata_pointer, BASE + CHPE_METADATA as u64);
+    assert_eq!(config.dynamic_value_reloc_table_offset, 0xc);
+    assert_eq!(config.dynamic_value_reloc_table_section, 6);
+    assert_eq!(config.security_cookie, 0);
+
+    let buffer = fixture("pe32plus_dll.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.load_config(), Ok(None));
+    assert_eq!(file.dynamic_relocations(), Ok(vec![]));
+}
+
+#[test]
+fn chpe_metadata() {
+    let buffer = fixture("arm64x.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.hybrid_machine(), Ok(Some(Machine::ARM64X)));
+    let metadata = file.chpe_metadata().unwrap().unwrap();
+    assert_eq!(metadata.version, 2);
+    assert_eq!(metadata.code_map, CODE_MAP);
+    assert_eq!(metadata.code_map_count, 3);
+    assert_eq!(metadata.code_ranges_to_entry_points_count, 2);
+    // The ARM64EC entry point, the EC view gets the native one.
+    assert_eq!(metadata.alternate_entry_point, EC_CODE);
+    assert_eq!(metadata.hybrid_image_info, 0);
+
+    let ranges = file.code_ranges().unwrap();
+    assert_eq!(
+        ranges,
+        [
+            CodeRange {
+                start: TEXT_RVA,
+                length: 0x1c,
+                kind: CodeRangeKind::Arm64,
+            },
+            CodeRange {
+                start: EC_CODE,
+                length: 0x10,
+                kind: CodeRangeKind::Arm64EC,
+            },
+            CodeRange {
+                start: X64_CODE,
+                length: 0x1020,
+                kind: CodeRangeKind::Amd64,
+            },
+        ]
+    );
+    assert!(ranges[1].contains(EC_CODE + 0xf));
+    assert!(!ranges[1].contains(X64_CODE));
+
+    for &name in &["pe32_dll.dll", "pe32plus_dll.dll"] {
+        let buffer = fixture(name);
+        let file = PEFile::parse(&buffer).unwrap();
+        assert_eq!(file.chpe_metadata(), Ok(None), "{}", name);
+        assert_eq!(file.hybrid_machine(), Ok(None), "{}", name);
+        assert_eq!(file.code_ranges(), Ok(vec![]), "{}", name);
+        assert_eq!(file.ec_view(), Ok(None), "{}", name);
+    }
+}
+
+#[test]
+fn arm64x_relocations() {
+    let buffer = fixture("arm64x.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    let relocations = file.dynamic_relocations().unwrap();
+    assert_eq!(relocations.len(), 1);
+    assert_eq!(relocations[0].symbol, DYNAMIC_RELOCATION_ARM64X);
+
+    let pe = pe_header(&buffer) as u32;
+    let directories = pe + 24 + 112;
+    let relocation = |rva, fixup| Arm64XRelocation { rva, fixup };
+    assert_eq!(
+        file.arm64x_relocations().unwrap(),
+        [
+            relocation(pe + 4, Arm64XFixup::Value(&[0x64, 0x86])),
+            relocation(
+                pe + 24 + 16,
+                Arm64XFixup::Value(&EC_ENTRY_POINT.to_le_bytes())
+            ),
+            relocation(directories, Arm64XFixup::Value(&EC_EXPORTS.to_le_bytes())),
+            relocation(
+                directories + 4,
+                Arm64XFixup::Value(&EC_EXPORTS_SIZE.to_le_bytes())
+            ),
+            relocation(
+                directories + 10 * 8,
+                Arm64XFixup::Value(&EC_LOAD_CONFIG.to_le_bytes())
+            ),
+            relocation(
+                directories + 10 * 8 + 4,
+                Arm64XFixup::Value(&0x140u32.to_le_bytes())
+            ),
+            // The alternate entry point in the CHPE metadata.
+            relocation(
+                CHPE_METADATA + 40,
+                Arm64XFixup::Value(&TEXT_RVA.to_le_bytes())
+            ),
+            // The lookup table and the IAT of the import descriptor.
+            relocation(IMPORTS, Arm64XFixup::Delta(8)),
+            relocation(IMPORTS + 16, Arm64XFixup::Delta(8)),
+        ]
+    );
+}
+
+#[test]
+fn ec_view() {
+    let buffer = fixture("arm64x.dll");
+    let native = PEFile::parse(&buffer).unwrap();
+    let view = native.ec_view().unwrap().unwrap();
+    let ec = PEFile::parse(&view).unwrap();
+
+    assert_eq!(ec.machine(), Machine::AMD64);
+    assert_eq!(ec.hybrid_machine(), Ok(Some(Machine::ARM64EC)));
+    assert_eq!(ec.address_of_entry_point(), EC_ENTRY_POINT);
+    assert_eq!(ec.chpe_metadata().unwrap().unwrap().alternate_entry_point, TEXT_RVA);
+    // The view is what x64 processes see, it doesn't have another one.
+    assert_eq!(ec.ec_view(), Ok(None));
+
+    let exports = |file: &PEFile| {
+        let exports = file.exports().unwrap().unwrap();
+        let add = exports.by_name("fixture_add").unwrap().unwrap();
+        let sub = exports.by_name("x64_sub").unwrap().map(|e| e.rva);
+        (exports.name.to_owned(), add.rva, sub)
+    };
+    assert_eq!(
+        exports(&native),
+        ("arm64x.dll".to_owned(), NATIVE_ADD, None)
+    );
+    assert_eq!(
+        exports(&ec),
+        ("arm64x.dll".to_owned(), EC_ADD, Some(X64_CODE))
+    );
+
+    // Both views share the import directory, only the ARM64 code imports anything.
+    assert_eq!(
+        ec.data_directory(DirectoryEntry::Import),
+        native.data_directory(DirectoryEntry::Import)
+    );
+    let entries = |file: &PEFile| {
+        let module = file.imports().unwrap().next().unwrap().unwrap();
+        assert_eq!(module.name, "KERNEL32.dll");
+        module.entries().count()
+    };
+    assert_eq!(entries(&native), 1);
+    assert_eq!(entries(&ec), 0);
+}
+
+#[test]
+fn load_either_view() {
+    let load = |base, ec_view| {
+        let config = config_at(base).ec_view(ec_view);
+        let loaded = Loader::with_config(fixture("arm64x.dll"), config)
+            .load()
+            .unwrap();
+        assert!(!loaded.is_runnable());
+        loaded
+    };
+
+    let native = load(BASE, false);
+    assert_eq!(
+        native.export_address("fixture_add"),
+        Some(BASE + NATIVE_ADD as u64)
+    );
+    assert_eq!(native.export_address("x64_sub"), None);
+
+    // Relocations still apply to the view.
+    let base = 0x2_0000_0000;
+    let mut ec = load(base, true);
+    assert_eq!(
+        ec.export_address("fixture_add"),
+        Some(base + EC_ADD as u64)
+    );
+    assert_eq!(ec.export_address("x64_sub"), Some(base + X64_CODE as u64));
+    let dump = ec.dump().unwrap();
+    let file = PEFile::parse(&dump).unwrap();
+    assert_eq!(file.machine(), Machine::AMD64);
+    let chpe_pointer = read_u64(file.read_rva(EC_LOAD_CONFIG + 200, 8).unwrap(), 0);
+    assert_eq!(chpe_pointer, base + CHPE_METADATA as u64);
+
+    // Other images load as they are.
+    let config = config_at(BASE).ec_view(true);
+    let dll = Loader::with_config(fixture("pe32plus_dll.dll"), config)
+        .load()
+        .unwrap();
+    assert_eq!(
+        dll.export_address("fixture_add"),
+        Some(BASE + FIXTURE_ADD_RVA as u64)
+    );
+}
+
+#[test]
+fn malformed_dynamic_relocations() {
+    let buffer = fixture("arm64x.dll");
+    let dvrt = PEFile::parse(&buffer).unwrap().rva_to_offset(DVRT).unwrap();
+
+    // Only version 1 tables are understood.
+    let version_2 = set_u32(buffer.clone(), dvrt, 2);
+    let file = PEFile::parse(&version_2).unwrap();
+    assert_eq!(file.dynamic_relocations(), Ok(vec![]));
+    assert_eq!(file.ec_view(), Ok(None));
+
+    // The table extends past the section.
+    let too_large = set_u32(buffer.clone(), dvrt + 4, 0x10_0000);
+    assert_eq!(
+        PEFile::parse(&too_large).unwrap().dynamic_relocations(),
+        Err(ParseError::InvalidLoadConfig)
+    );
+
+    // The first record of the first block with a type of 3.
+    let record = dvrt + 8 + 12 + 8;
+    let bad_type = set_u16(buffer.clone(), record, buffer[record] as u16 | 0x3000);
+    assert_eq!(
+        PEFile::parse(&bad_type).unwrap().arm64x_relocations(),
+        Err(ParseError::InvalidLoadConfig)
+    );
+    match Loader::with_config(bad_type, config_at(BASE).ec_view(true)).load() {
+        Err(LoadError::Parse(ParseError::InvalidLoadConfig)) => {}
+        r => panic!("unexpected result {:?}", r.err()),
+    }
+
+    // The value of the first record, which patches the machine, leaving it at ARM64.
+    let same_machine = set_u16(buffer.clone(), record + 2, Machine::ARM64.0);
+    assert_eq!(
+        PEFile::parse(&same_machine).unwrap().ec_view(),
+        Err(ParseError::InvalidLoadConfig)
+    );
+    match Loader::with_config(same_machine, config_at(BASE).ec_view(true)).load() {
+        Err(LoadError::Parse(ParseError::InvalidLoadConfig)) => {}
+        r => panic!("unexpected result {:?}", r.err()),
+    }
+}
+
+// A block of ARM64X fixups, records are (offset, type, argument, data).
+fn arm64x_block(page_rva: u32, records: &[(u32, u16, u16, &[u16])]) -> Vec<u8> {
+    let mut words = vec![];
+    for &(offset, kind, argument, data) in records {
+        words.push(offset as u16 | kind << 12 | argument << 14);
+        words.extend_from_slice(data);
+    }
+    if words.len() % 2 != 0 {
+        words.push(0);
+    }
+    let mut block = vec![];
+    block.extend_from_slice(&page_rva.to_le_bytes());
+    block.extend_from_slice(&(8 + words.len() as u32 * 2).to_le_bytes());
+    for word in words {
+        block.extend_from_slice(&word.to_le_bytes());
+    }
+    block
+}
+
+// A delta fixup as (argument, data).
+fn arm64x_delta(delta: i32) -> (u16, u16) {
+    let (sign, delta) = ((delta < 0) as u16, delta.unsigned_abs());
+    if delta % 8 == 0 {
+        (sign | 2, (delta / 8) as u16)
+    } else {
+        (sign, (delta / 4) as u16)
+    }
+}
+
+// An ARM64 image whose dynamic value relocation table is `dvrt`, placed in .rdata after a load
+// config of 0x100 bytes. .data holds eight bytes of 0x11 followed by two u32s of 0x100.
+fn image_with_dvrt(dvrt: &[u8]) -> Vec<u8> {
+    let mut rdata = vec![];
+    rdata.extend_from_slice(&0x100u32.to_le_bytes());
+    rdata.resize(224, 0);
+    rdata.extend_from_slice(&0x100u32.to_le_bytes());
+    rdata.extend_from_slice(&1u32.to_le_bytes());
+    rdata.resize(0x100, 0);
+    rdata.extend_from_slice(dvrt);
+
+    let mut data = vec![0x11; 8];
+    data.extend_from_slice(&0x100u32.to_le_bytes());
+    data.extend_from_slice(&0x100u32.to_le_bytes());
+    data.resize(0x20, 0);
+
+    let mut b = PEBuilder::pe32_plus();
+    b.machine(Machine::ARM64).dll();
+    let rdata = b.section(".rdata", SECTION_READ_ONLY_DATA, rdata);
+    b.section(".data", SECTION_DATA, data);
+    b.directory(DirectoryEntry::LoadConfig, rdata.at(0), 0x100);
+    b.build().unwrap()
+}
+
+#[test]
+fn fixup_kinds() {
+    // arm64x.dll only has values and positive deltas that are multiples of eight.
+    let value = 0x1122_3344_5566_7788u64;
+    let records = |pe: u32, data: u32| {
+        let (plus, plus_data) = arm64x_delta(0x10);
+        let (minus, minus_data) = arm64x_delta(-4);
+        let mut fixups = arm64x_block(0, &[(pe + 4, 1, 1, &[Machine::AMD64.0])]);
+        fixups.extend_from_slice(&arm64x_block(
+            data,
+            &[
+                (0, 0, 3, &[]),
+                (8, 2, plus, &[plus_data]),
+                (12, 2, minus, &[minus_data]),
+                (
+                    16,
+                    1,
+                    3,
+                    &[
+                        value as u16,
+                        (value >> 16) as u16,
+                        (value >> 32) as u16,
+                        (value >> 48) as u16,
+                    ],
+                ),
+            ],
+        ));
+        let mut dvrt = vec![];
+        dvrt.extend_from_slice(&1u32.to_le_bytes());
+        dvrt.extend_from_slice(&(12 + fixups.len() as u32).to_le_bytes());
+        dvrt.extend_from_slice(&DYNAMIC_RELOCATION_ARM64X.to_le_bytes());
+        dvrt.extend_from_slice(&(fixups.len() as u32).to_le_bytes());
+        dvrt.extend_from_slice(&fixups);
+        dvrt
+    };
+
+    // Where the headers and .data end up doesn't depend on the contents of the table.
+    let probe = image_with_dvrt(&records(0, 0));
+    let pe = pe_header(&probe) as u32;
+    let data = PEFile::parse(&probe)
+        .unwrap()
+        .sections()
+        .find(|s| s.name_str() == Some(".data"))
+        .unwrap()
+        .virtual_address;
+    let buffer = image_with_dvrt(&records(pe, data));
+
+    let file = PEFile::parse(&buffer).unwrap();
+    let relocation = |rva, fixup| Arm64XRelocation { rva, fixup };
+    assert_eq!(
+        file.arm64x_relocations().unwrap()[1..],
+        [
+            relocation(data, Arm64XFixup::ZeroFill(8)),
+            relocation(data + 8, Arm64XFixup::Delta(0x10)),
+            relocation(data + 12, Arm64XFixup::Delta(-4)),
+            relocation(data + 16, Arm64XFixup::Value(&value.to_le_bytes())),
+        ]
+    );
+
+    let view = file.ec_view().unwrap().unwrap();
+    let ec = PEFile::parse(&view).unwrap();
+    let contents = ec.read_rva(data, 24).unwrap();
+    assert_eq!(&contents[..8], &[0; 8]);
+    assert_eq!(read_u32(contents, 8), 0x110);
+    assert_eq!(read_u32(contents, 12), 0xfc);
+    assert_eq!(read_u64(contents, 16), value);
+}
@@ -0,0 +1,138 @@
+// Runs pe-inspect over the fixtures.
+
+extern crate pe_load;
+
+mod common;
+
+use std::process::{Command, Output};
+
+use common::*;
+
+fn inspect(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pe-inspect"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = inspect(args);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn prints_every_part() {
+    let path = fixture_path("pe32plus_dll.dll");
+    let text = stdout(&[path.to_str().unwrap()]);
+
+    assert!(text.contains("PE32+ DLL, AMD64"));
+    assert!(text.contains("  .data     00003000  00003000"));
+    assert!(text.contains("  Basereloc      00006000"));
+    assert!(text.contains("HeapAlloc (hint 0)"));
+    assert!(text.contains("fixture_alloc -> NTDLL.RtlAllocateHeap"));
+    assert!(text.contains("1 blocks, 2 relocations: ABSOLUTE 1, DIR64 1"));
+    assert!(text
+        .lines()
+        .any(|line| line.starts_with("  Checksum") && line.ends_with("(valid)")));
+
+    let path = fixture_path("pe32_exe.exe");
+    let text = stdout(&[path.to_str().unwrap()]);
+    assert!(text.contains("PE32 executable, i386"));
+    assert!(text.contains("#23"));
+    assert!(text.contains("Callback   0x401040"));
+    assert!(text.contains("Imphash      76ffa88d023f1b301207aa158e8cb51d"));
+
+    let path = fixture_path("pe32plus_resources.exe");
+    let text = stdout(&[path.to_str().unwrap()]);
+    assert!(text.contains("CONFIG/1/1033  000030b0  10 bytes, code page 0"));
+    assert!(text.contains("VERSION/1/1033  000030c0  352 bytes"));
+    assert!(text.contains("Rich header\n  48 bytes at 0x80, key 0xd2af6f9b (valid)"));
+    assert!(text.contains("Product 0x0104  build 30133  12 objects"));
+    assert!(text
+        .lines()
+        .any(|line| line.starts_with("  PDB          fixture.pdb {") && line.ends_with("} age 1")));
+
+    let path = fixture_path("arm64x.dll");
+    let text = stdout(&[path.to_str().unwrap()]);
+    assert!(text.contains("PE32+ DLL, ARM64"));
+    assert!(text.contains("Hybrid\n  Machine         ARM64X"));
+    assert!(text.contains("    00003000  00001020  AMD64"));
+    assert!(text.contains("EC exports\n    00004010  fixture_add\n    00003000  x64_sub"));
+}
+
+#[test]
+fn certificates() {
+    let path = fixture_path("pe32plus_signed.dll");
+    let text = stdout(&[path.to_str().unwrap()]);
+    assert!(text.contains("PKCS#7       revision 0x0200"));
+    assert!(text.contains("(matches)"));
+    assert!(text.contains("Signer       Fixture Signer (issued by Fixture CA)"));
+
+    let json = stdout(&["--json", path.to_str().unwrap()]);
+    assert!(json.contains("\"digest_matches\": true"));
+}
+
+#[test]
+fn json() {
+    for name in &[
+        "pe32_exe.exe",
+        "pe32plus_exe.exe",
+        "pe32_dll.dll",
+        "pe32plus_dll.dll",
+        "pe32plus_resources.exe",
+        "arm64x.dll",
+    ] {
+        let path = fixture_path(name);
+        let json = stdout(&["--json", path.to_str().unwrap()]);
+
+        assert!(json.starts_with('{') && json.trim_end().ends_with('}'));
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json.matches('[').count(), json.matches(']').count());
+        for key in &[
+            "\"headers\"",
+            "\"findings\"",
+            "\"rich_header\"",
+            "\"sections\"",
+            "\"data_directories\"",
+            "\"imports\"",
+            "\"imphash\"",
+            "\"exports\"",
+            "\"relocations\"",
+            "\"tls\"",
+            "\"resources\"",
+            "\"debug\"",
+            "\"certificates\"",
+            "\"clr\"",
+            "\"hybrid\"",
+            "\"symbols\"",
+            "\"overlay\"",
+        ] {
+            assert!(json.contains(key), "{} is missing {}", name, key);
+        }
+    }
+
+    let path = fixture_path("pe32plus_dll.dll");
+    let json = stdout(&[path.to_str().unwrap(), "--json"]);
+    assert!(json.contains("\"forwarder\": \"NTDLL.RtlAllocateHeap\""));
+    assert!(json.contains("\"image_base\": 6442450944"));
+
+    let path = fixture_path("arm64x.dll");
+    let json = stdout(&["--json", path.to_str().unwrap()]);
+    assert!(json.contains("\"machine\": \"ARM64X\""));
+    assert!(json.contains("\"kind\": \"ARM64EC\""));
+}
+
+#[test]
+fn errors() {
+    let path = fixture_path("bad_pe_signature.exe");
+    let output = inspect(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("InvalidPeSignature"));
+
+    let path = fixture_path("missing.exe");
+    assert_eq!(inspect(&[path.to_str().unwrap()]).status.code(), Some(1));
+
+    assert_eq!(inspect(&[]).status.code(), Some(2));
+    assert_eq!(inspect(&["--yaml", "a.exe"]).status.code(), Some(2));
+}
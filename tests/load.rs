@@ -0,0 +1,635 @@
+extern crate pe_load;
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::rc::Rc;
+
+use common::*;
+use pe_load::*;
+
+const BASES_64: [u64; 3] = [0x1_4000_0000, 0x7ff6_1234_0000, 0x1_0000];
+const BASES_32: [u64; 3] = [0x40_0000, 0x7000_0000, 0x1_0000];
+
+fn load(name: &str, base: u64) -> LoadedPEFile {
+    Loader::with_config(fixture(name), config_at(base))
+        .load()
+        .unwrap()
+}
+
+// The image as it is mapped, read back through a dump so the layout on disk is used.
+fn mapped_pointer(image: &mut LoadedPEFile, rva: u32, is_64_bit: bool) -> u64 {
+    let dump = image.dump().unwrap();
+    let file = PEFile::parse(&dump).unwrap();
+    if is_64_bit {
+        read_u64(file.read_rva(rva, 8).unwrap(), 0)
+    } else {
+        read_u32(file.read_rva(rva, 4).unwrap(), 0) as u64
+    }
+}
+
+#[test]
+fn relocates_to_arbitrary_bases() {
+    for &(name, is_64_bit, bases) in &[
+        ("pe32plus_exe.exe", true, &BASES_64),
+        ("pe32_exe.exe", false, &BASES_32),
+    ] {
+        for &base in bases.iter() {
+            let mut image = load(name, base);
+            assert_eq!(image.base(), base);
+            assert!(image.contains(base + BSS_RVA as u64 + 0x17ff));
+            assert!(!image.contains(base - 1));
+
+            // Images can't be run unless they are mapped into this process.
+            assert!(image.entry_point.is_none());
+
+            assert_eq!(
+                mapped_pointer(&mut image, DATA_RVA + TEXT_POINTER, is_64_bit),
+                base + TEXT_RVA as u64 + 4,
+                "{} at {:x}",
+                name,
+                base
+            );
+            assert_eq!(
+                mapped_pointer(&mut image, DATA_RVA + BSS_POINTER, is_64_bit),
+                base + BSS_RVA as u64 + 0x10
+            );
+        }
+    }
+}
+
+#[test]
+fn relocates_tls_callbacks() {
+    for &(name, is_64_bit, base) in &[
+        ("pe32plus_exe.exe", true, 0x7ff6_1234_0000),
+        ("pe32_exe.exe", false, 0x7000_0000),
+    ] {
+        let mut image = load(name, base);
+        let dump = image.dump().unwrap();
+        let file = PEFile::parse(&dump).unwrap();
+        assert_eq!(file.image_base(), base);
+
+        let tls = file.data_directory(DirectoryEntry::Tls).unwrap().unwrap();
+        let directory = file
+            .read_rva(tls.virtual_address, tls.size as usize)
+            .unwrap();
+        let (callbacks, callback) = if is_64_bit {
+            let callbacks = read_u64(directory, 24) - base;
+            (
+                callbacks,
+                read_u64(file.read_rva(callbacks as u32, 8).unwrap(), 0),
+            )
+        } else {
+            let callbacks = read_u32(directory, 12) as u64 - base;
+            let callback = read_u32(file.read_rva(callbacks as u32, 4).unwrap(), 0);
+            (callbacks, callback as u64)
+        };
+        assert!(callbacks < file.size_of_image() as u64);
+        assert_eq!(callback, base + TLS_CALLBACK_RVA as u64);
+    }
+}
+
+#[test]
+fn preferred_base_policies() {
+    let config = |preferred_base| {
+        LoaderConfig::new()
+            .resolver(StubResolver::default())
+            .preferred_base(preferred_base)
+    };
+    let load = |preferred_base| {
+        Loader::with_config(fixture("pe32plus_exe.exe"), config(preferred_base)).load()
+    };
+
+    assert_eq!(load(PreferredBase::Default).unwrap().base(), 0x1_4000_0000);
+    assert_eq!(
+        load(PreferredBase::DefaultExact).unwrap().base(),
+        0x1_4000_0000
+    );
+    assert_eq!(
+        load(PreferredBase::Exact(0x7000_0000)).unwrap().base(),
+        0x7000_0000
+    );
+    assert_eq!(
+        load(PreferredBase::TryExact(0x7000_0000)).unwrap().base(),
+        0x7000_0000
+    );
+
+    // A backend that always maps at the same address only satisfies exact requests for it.
+    let load_at = |preferred_base| {
+        let config = config(preferred_base).backend(InMemoryBackend::at(0x2_0000_0000));
+        Loader::with_config(fixture("pe32plus_exe.exe"), config).load()
+    };
+    assert_eq!(
+        load_at(PreferredBase::Default).unwrap().base(),
+        0x2_0000_0000
+    );
+    assert_eq!(
+        load_at(PreferredBase::TryExact(0x7000_0000))
+            .unwrap()
+            .base(),
+        0x2_0000_0000
+    );
+    assert_eq!(
+        load_at(PreferredBase::Exact(0x2_0000_0000)).unwrap().base(),
+        0x2_0000_0000
+    );
+    match load_at(PreferredBase::DefaultExact) {
+        Err(LoadError::BaseUnavailable(0x1_4000_0000)) => {}
+        _ => panic!("mapped somewhere other than the image base"),
+    }
+    match load_at(PreferredBase::Exact(0x7000_0000)) {
+        Err(LoadError::BaseUnavailable(0x7000_0000)) => {}
+        _ => panic!("mapped somewhere other than the requested base"),
+    }
+}
+
+#[test]
+fn resolves_imports_through_resolver() {
+    for &(name, base, mask) in &[
+        ("pe32plus_exe.exe", 0x1_4000_0000, u64::MAX),
+        ("pe32_exe.exe", 0x40_0000, u32::MAX as u64),
+    ] {
+        let image = load(name, base);
+        let imports = image.imports().collect::<Vec<_>>();
+        assert_eq!(imports.len(), 4);
+
+        let exit_process = image.find_import("kernel32.dll", "ExitProcess").unwrap();
+        assert_eq!(exit_process.module, "KERNEL32.dll");
+        assert!(image.contains(exit_process.slot));
+        let expected = StubResolver::address(
+            0,
+            &ImportName::Name {
+                hint: 0,
+                name: "ExitProcess",
+            },
+        );
+        assert_eq!(exit_process.target, expected & mask);
+
+        let by_ordinal = image.find_import("WS2_32.dll", 23u16).unwrap();
+        assert_eq!(by_ordinal.symbol, &ImportSymbol::Ordinal(23));
+        assert_eq!(
+            by_ordinal.target,
+            StubResolver::address(2, &ImportName::Ordinal(23)) & mask
+        );
+
+        assert!(image.find_import("KERNEL32.dll", "LoadLibraryA").is_none());
+    }
+}
+
+#[test]
+fn import_hooks_and_patching() {
+    let calls = Rc::new(Cell::new(0));
+    let seen = calls.clone();
+    let config = config_at(0x1_4000_0000)
+        .hook_import("kernel32.DLL", "ExitProcess", move |original| {
+            seen.set(seen.get() + 1);
+            original + 1
+        })
+        .hook_import("WS2_32.dll", 23u16, |_| 0x1234);
+
+    let mut image = Loader::with_config(fixture("pe32plus_exe.exe"), config)
+        .load()
+        .unwrap();
+    assert_eq!(calls.get(), 1);
+
+    let exit_process = image.find_import("KERNEL32.dll", "ExitProcess").unwrap();
+    let original = StubResolver::address(
+        0,
+        &ImportName::Name {
+            hint: 0,
+            name: "ExitProcess",
+        },
+    );
+    assert_eq!(exit_process.target, original + 1);
+    assert_eq!(
+        image.find_import("WS2_32.dll", 23u16).unwrap().target,
+        0x1234
+    );
+
+    let slot = exit_process.slot;
+    assert_eq!(image.set_import_target(slot, 0xdead).unwrap(), original + 1);
+    assert_eq!(
+        image
+            .find_import("KERNEL32.dll", "ExitProcess")
+            .unwrap()
+            .target,
+        0xdead
+    );
+
+    match image.set_import_target(slot + 1, 0) {
+        Err(LoadError::UnknownImportSlot(s)) => assert_eq!(s, slot + 1),
+        _ => panic!("patched an unknown slot"),
+    }
+}
+
+#[test]
+fn exports_are_relative_to_the_base() {
+    for &(name, base) in &[
+        ("pe32plus_dll.dll", 0x2_0000_0000),
+        ("pe32_dll.dll", 0x2000_0000),
+    ] {
+        let image = load(name, base);
+
+        assert_eq!(
+            image.export_address("fixture_add"),
+            Some(base + FIXTURE_ADD_RVA as u64)
+        );
+        assert_eq!(
+            image.export_address(2u16),
+            Some(base + FIXTURE_SUB_RVA as u64)
+        );
+        // Forwarded exports don't have an address.
+        assert_eq!(image.export_address("fixture_alloc"), None);
+
+        let forwarder = image
+            .exports()
+            .iter()
+            .find(|e| e.name.as_deref() == Some("fixture_alloc"))
+            .unwrap();
+        assert_eq!(
+            forwarder.target,
+            ExportTarget::Forwarder("NTDLL.RtlAllocateHeap".into())
+        );
+    }
+}
+
+#[test]
+fn registry_resolves_between_modules() {
+    let registry = ModuleRegistry::new();
+    let dll = registry
+        .load(
+            "C:\\Windows\\FIXTURE",
+            fixture("pe32plus_dll.dll"),
+            config_at(0x2_0000_0000),
+        )
+        .unwrap();
+    assert_eq!(dll.name, "fixture.dll");
+    assert_eq!(dll.base, 0x2_0000_0000);
+    assert_eq!(dll.ref_count, 1);
+
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0xc3; 0x10]);
+    b.entry_point(text.at(0))
+        .import("FIXTURE.dll", "fixture_add")
+        .import("fixture.dll", 2u16)
+        .import("fixture.dll", "fixture_alloc");
+    let exe = b.build().unwrap();
+
+    let image = Loader::with_config(exe, config_at(0x1_4000_0000).registry(registry.clone()))
+        .load()
+        .unwrap();
+
+    let add = image.find_import("FIXTURE.dll", "fixture_add").unwrap();
+    assert_eq!(add.target, dll.base + FIXTURE_ADD_RVA as u64);
+    assert_eq!(registry.module_at(add.target), Some(dll.clone()));
+
+    let sub = image.find_import("fixture.dll", 2u16).unwrap();
+    assert_eq!(sub.target, dll.base + FIXTURE_SUB_RVA as u64);
+
+    // The forwarder isn't registered, so it goes to the resolver.
+    let alloc = image.find_import("fixture.dll", "fixture_alloc").unwrap();
+    assert_eq!(
+        alloc.target,
+        StubResolver::address(
+            0,
+            &ImportName::Name {
+                hint: 0,
+                name: "RtlAllocateHeap",
+            }
+        )
+    );
+
+    assert_eq!(registry.module_at(0x1_4000_0000), None);
+    assert_eq!(
+        registry.export_address("fixture", "fixture_sub"),
+        Some(dll.base + FIXTURE_SUB_RVA as u64)
+    );
+
+    let again = registry
+        .load("fixture.dll", fixture("pe32plus_dll.dll"), config_at(0))
+        .unwrap();
+    assert_eq!(again.base, dll.base);
+    assert_eq!(again.ref_count, 2);
+
+    assert_eq!(registry.free("FIXTURE.DLL"), Some(1));
+    assert!(registry.contains("fixture"));
+    assert_eq!(registry.free("fixture.dll"), Some(0));
+    assert!(!registry.contains("fixture"));
+    assert_eq!(registry.free("fixture.dll"), None);
+    assert!(registry.modules().is_empty());
+}
+
+#[test]
+fn registry_keeps_dependencies_loaded() {
+    let registry = ModuleRegistry::new();
+    registry
+        .load("fixture", fixture("pe32plus_dll.dll"), config_at(0x2_0000_0000))
+        .unwrap();
+
+    let mut b = PEBuilder::pe32_plus();
+    b.dll();
+    let text = b.section(".text", SECTION_CODE, vec![0xc3; 0x10]);
+    b.entry_point(text.at(0)).import("fixture.dll", "fixture_add");
+    let dependent = registry
+        .load("dependent", b.build().unwrap(), config_at(0x3_0000_0000))
+        .unwrap();
+    assert_eq!(dependent.ref_count, 1);
+    assert_eq!(registry.get("fixture").unwrap().ref_count, 2);
+
+    // Still imported from, so it stays mapped.
+    assert_eq!(registry.free("fixture"), Some(1));
+    assert!(registry.contains("fixture"));
+
+    assert_eq!(registry.free("dependent"), Some(0));
+    assert!(registry.modules().is_empty());
+}
+
+#[test]
+fn loads_from_files() {
+    let path = fixture_path("pe32plus_exe.exe");
+    let base = 0x5_0000_0000;
+
+    let mut from_path = Loader::from_path_with_config(&path, config_at(base))
+        .unwrap()
+        .load()
+        .unwrap();
+    let reader = File::open(&path).unwrap();
+    let mut from_reader = Loader::from_reader_with_config(reader, config_at(base))
+        .unwrap()
+        .load()
+        .unwrap();
+    let mut from_buffer = load("pe32plus_exe.exe", base);
+
+    assert_eq!(from_path.dump().unwrap(), from_buffer.dump().unwrap());
+    assert_eq!(from_reader.dump().unwrap(), from_buffer.dump().unwrap());
+
+    match Loader::from_path(fixture_path("missing.exe")) {
+        Err(LoadError::Io(_)) => {}
+        _ => panic!("loaded a missing file"),
+    }
+}
+
+#[test]
+fn dump_round_trips() {
+    for &(name, base) in &[
+        ("pe32plus_exe.exe", 0x7ff6_0000_0000),
+        ("pe32_exe.exe", 0x7000_0000),
+    ] {
+        let original = fixture(name);
+        let mut image = load(name, base);
+
+        let options = DumpOptions::new().rebase(true).rebuild_imports(true);
+        let dump = image.dump_with_options(&options).unwrap();
+        let original_file = PEFile::parse(&original).unwrap();
+        let file = PEFile::parse(&dump).unwrap();
+        assert_eq!(file.image_base(), original_file.image_base());
+        assert!(file.has_valid_checksum());
+
+        // Sections get as much raw data as they take up in memory, but what the file had must be
+        // unchanged once relocations and imports are undone.
+        for (a, b) in file.sections().zip(original_file.sections()) {
+            let len = b.size_of_raw_data as usize;
+            assert!(a.size_of_raw_data as usize >= len);
+            assert_eq!(
+                &dump[a.p_raw_data as usize..][..len],
+                &original[b.p_raw_data as usize..][..len],
+                "{} {:?}",
+                name,
+                a.name_str()
+            );
+        }
+
+        // The dump can be loaded again.
+        Loader::with_config(dump, config_at(base)).load().unwrap();
+    }
+}
+
+#[test]
+fn malformed_images_are_rejected() {
+    for name in &[
+        "bad_dos_signature.exe",
+        "bad_pe_signature.exe",
+        "bad_optional_magic.exe",
+        "pe_header_out_of_bounds.exe",
+        "truncated_section_table.exe",
+    ] {
+        match Loader::with_config(fixture(name), config_at(0)).load() {
+            Err(LoadError::Parse(_)) => {}
+            _ => panic!("{} was loaded", name),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+#[test]
+fn default_config_has_no_os_loader() {
+    match Loader::new(fixture("pe32plus_exe.exe")).load() {
+        Err(LoadError::LoadLibraryFailed) => {}
+        _ => panic!("imports were resolved without a resolver"),
+    }
+}
+
+// Overwrites the u32 at `rva` in the file.
+fn patch_u32(buffer: &mut [u8], rva: u32, value: u32) {
+    let offset = PEFile::parse(buffer).unwrap().rva_to_offset(rva).unwrap();
+    buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn directory(buffer: &[u8], entry: DirectoryEntry) -> u32 {
+    let file = PEFile::parse(buffer).unwrap();
+    file.data_directory(entry).unwrap().unwrap().virtual_address
+}
+
+#[test]
+fn malformed_directories_are_rejected() {
+    let original = fixture("pe32plus_exe.exe");
+    let relocations = directory(&original, DirectoryEntry::Basereloc);
+    let imports = directory(&original, DirectoryEntry::Import);
+
+    let load = |patches: &[(u32, u32)]| {
+        let mut buffer = original.clone();
+        for &(rva, value) in patches {
+            patch_u32(&mut buffer, rva, value);
+        }
+        Loader::with_config(buffer, config_at(0x7000_0000)).load()
+    };
+
+    // Relocation blocks that aren't aligned or run past the directory.
+    for &size in &[0x0e, 0x1000] {
+        match load(&[(relocations + 4, size)]) {
+            Err(LoadError::Parse(ParseError::OutOfBounds)) => {}
+            _ => panic!("loaded with a relocation block of size {:x}", size),
+        }
+    }
+
+    // A first block too small to hold its own header.
+    for &size in &[0, 4] {
+        match load(&[(relocations + 4, size)]) {
+            Err(LoadError::Parse(ParseError::OutOfBounds)) => {}
+            _ => panic!("loaded with a first relocation block of size {:x}", size),
+        }
+    }
+
+    // A relocation that points past the end of the image.
+    match load(&[(relocations, 0x7fff_f000)]) {
+        Err(LoadError::Parse(ParseError::InvalidRva(_))) => {}
+        _ => panic!("relocated outside of the image"),
+    }
+
+    // Module names and lookup tables outside of the image.
+    for &(field, value) in &[(12, 0x7fff_ffff), (0, 0x7fff_fff0)] {
+        match load(&[(imports + field, value)]) {
+            Err(LoadError::Parse(ParseError::InvalidRva(_)))
+            | Err(LoadError::Parse(ParseError::OutOfBounds)) => {}
+            _ => panic!("imported through {:x}", value),
+        }
+    }
+}
+
+// Pretends to map images into this process, which is when the machine has to match the host.
+// Nothing may be run from it, the heap isn't executable.
+struct NativeBackend(InMemoryBackend);
+
+struct NativeMemory(Box<dyn Memory>);
+
+unsafe impl Memory for NativeMemory {
+    fn base(&self) -> u64 {
+        self.0.as_ptr() as u64
+    }
+
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.0.as_ptr()
+    }
+
+    fn is_native(&self) -> bool {
+        true
+    }
+
+    fn protect(
+        &mut self,
+        offset: usize,
+        size: usize,
+        protection: Protection,
+    ) -> Result<Protection, LoadError> {
+        self.0.protect(offset, size, protection)
+    }
+}
+
+unsafe impl Backend for NativeBackend {
+    fn allocate(&mut self, preferred_base: u64, size: usize) -> Result<Box<dyn Memory>, LoadError> {
+        let memory = self.0.allocate(preferred_base, size)?;
+        Ok(Box::new(NativeMemory(memory)))
+    }
+
+    fn page_size(&self) -> usize {
+        self.0.page_size()
+    }
+}
+
+// Records every protection change.
+struct RecordingBackend(InMemoryBackend, Rc<RefCell<Vec<(usize, Protection)>>>);
+
+struct RecordingMemory(Box<dyn Memory>, Rc<RefCell<Vec<(usize, Protection)>>>);
+
+unsafe impl Memory for RecordingMemory {
+    fn base(&self) -> u64 {
+        self.0.base()
+    }
+
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.0.as_ptr()
+    }
+
+    fn is_native(&self) -> bool {
+        false
+    }
+
+    fn protect(
+        &mut self,
+        offset: usize,
+        size: usize,
+        protection: Protection,
+    ) -> Result<Protection, LoadError> {
+        self.1.borrow_mut().push((offset, protection));
+        self.0.protect(offset, size, protection)
+    }
+}
+
+unsafe impl Backend for RecordingBackend {
+    fn allocate(&mut self, preferred_base: u64, size: usize) -> Result<Box<dyn Memory>, LoadError> {
+        let memory = self.0.allocate(preferred_base, size)?;
+        Ok(Box::new(RecordingMemory(memory, self.1.clone())))
+    }
+
+    fn page_size(&self) -> usize {
+        self.0.page_size()
+    }
+}
+
+#[test]
+fn dump_lifts_protections() {
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0xc3; 0x10]);
+    b.section(".hidden", SectionCharacteristics::CNT_INITIALIZED_DATA, vec![7; 0x10]);
+    b.entry_point(text.at(0));
+    let buffer = b.build().unwrap();
+    let rva = PEFile::parse(&buffer)
+        .unwrap()
+        .sections()
+        .find(|s| s.name_str() == Some(".hidden"))
+        .unwrap()
+        .virtual_address;
+
+    let log = Rc::new(RefCell::new(vec![]));
+    let backend = RecordingBackend(InMemoryBackend::at(0x1_4000_0000), log.clone());
+    let mut image = Loader::with_config(buffer, config_at(0).backend(backend))
+        .load()
+        .unwrap();
+    let offset = rva as usize;
+    assert_eq!(log.borrow().last(), Some(&(offset, Protection::NoAccess)));
+
+    log.borrow_mut().clear();
+    let dump = image.dump().unwrap();
+    assert_eq!(
+        *log.borrow(),
+        [
+            (offset, Protection::ReadOnly),
+            (offset, Protection::NoAccess)
+        ]
+    );
+    let file = PEFile::parse(&dump).unwrap();
+    assert_eq!(file.read_rva(rva, 0x10).unwrap(), &[7; 0x10]);
+}
+
+#[test]
+fn foreign_machines_are_refused() {
+    // No host is an Itanium.
+    let mut buffer = fixture("pe32plus_exe.exe");
+    let machine = pe_header(&buffer) + 4;
+    buffer[machine..machine + 2].copy_from_slice(&Machine::IA64.0.to_le_bytes());
+    let native = || config_at(0).backend(NativeBackend(InMemoryBackend::new()));
+
+    match Loader::with_config(buffer.clone(), native()).load() {
+        Err(LoadError::UnsupportedMachine(Machine::IA64)) => {}
+        r => panic!("unexpected result {:?}", r.err()),
+    }
+    let image = Loader::with_config(buffer.clone(), native().allow_foreign_machine(true))
+        .load()
+        .unwrap();
+    assert!(!image.is_runnable());
+
+    // Nothing runs in heap memory, so any machine goes.
+    let image = Loader::with_config(buffer, config_at(0x1_4000_0000))
+        .load()
+        .unwrap();
+    assert_eq!(image.base(), 0x1_4000_0000);
+}
@@ -0,0 +1,745 @@
+extern crate pe_load;
+
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+
+use common::*;
+use pe_load::*;
+
+fn named_sections(file: &PEFile) -> Vec<(String, Section)> {
+    file.sections()
+        .map(|s| (s.name_str().unwrap().to_owned(), s))
+        .collect()
+}
+
+#[test]
+fn headers() {
+    for &(name, is_64_bit, image_base) in &[
+        ("pe32_exe.exe", false, 0x40_0000),
+        ("pe32plus_exe.exe", true, 0x1_4000_0000),
+    ] {
+        let buffer = fixture(name);
+        let file = PEFile::parse(&buffer).unwrap();
+
+        assert_eq!(file.is_64_bit(), is_64_bit, "{}", name);
+        assert_eq!(file.thunk_size(), if is_64_bit { 8 } else { 4 });
+        assert_eq!(
+            file.machine(),
+            if is_64_bit { Machine::AMD64 } else { Machine::I386 }
+        );
+        assert_eq!(file.image_base(), image_base);
+        assert_eq!(file.address_of_entry_point(), TEXT_RVA);
+        assert_eq!(file.section_alignment(), 0x1000);
+        assert_eq!(file.file_alignment(), 0x200);
+        assert_eq!(file.num_of_rva_and_sizes(), 16);
+        assert_eq!(file.size_of_headers(), 0x400);
+        assert_eq!(file.num_sections(), 5);
+    }
+}
+
+#[test]
+fn sections_without_raw_data() {
+    let buffer = fixture("pe32plus_exe.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    let names = named_sections(&file)
+        .into_iter()
+        .map(|s| s.0)
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".text", ".rdata", ".data", ".tls", ".reloc"]);
+
+    // The linker merged .bss into .data, drop the raw data of .data instead.
+    let header = pe_header(&buffer) + 24 + 240 + 2 * 40;
+    let buffer = set_u32(set_u32(buffer, header + 16, 0), header + 20, 0);
+    let file = PEFile::parse(&buffer).unwrap();
+    let data = named_sections(&file)[2].1;
+    assert_eq!(data.virtual_address, DATA_RVA);
+    assert_eq!(data.size_of_raw_data, 0);
+    assert_eq!(data.mapped_size(), 0x1820);
+    assert!(data.contains_rva(DATA_RVA + 0x181f));
+    assert!(!data.contains_rva(DATA_RVA + 0x1820));
+    assert_eq!(file.rva_to_offset(BSS_RVA), None);
+
+    // The tail of a section that is larger in memory than on disk isn't backed by the file.
+    let buffer = fixture("pe32plus_dll.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    let data = named_sections(&file)[2].1;
+    assert_eq!(data.mapped_size(), 0x3000);
+    assert!(file.rva_to_offset(data.virtual_address + 0x10).is_some());
+    assert_eq!(file.rva_to_offset(data.virtual_address + 0x2000), None);
+    assert_eq!(
+        file.read_rva(data.virtual_address + 0x2000, 1),
+        Err(ParseError::InvalidRva(data.virtual_address + 0x2000))
+    );
+}
+
+#[test]
+fn imports() {
+    for name in &["pe32_exe.exe", "pe32plus_exe.exe"] {
+        let buffer = fixture(name);
+        let file = PEFile::parse(&buffer).unwrap();
+
+        let modules = file.imports()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let names = modules.iter().map(|m| m.name).collect::<Vec<_>>();
+        assert_eq!(names, ["KERNEL32.dll", "USER32.dll", "WS2_32.dll"]);
+
+        let kernel32 = modules[0]
+            .entries()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(!modules[0].is_bound());
+        assert_eq!(kernel32.len(), 2);
+        assert_eq!(
+            kernel32[0].name,
+            ImportName::Name {
+                hint: 0,
+                name: "ExitProcess",
+            }
+        );
+        assert_eq!(kernel32[1].iat_rva, kernel32[0].iat_rva + file.thunk_size() as u32);
+        assert_eq!(kernel32[0].original_thunk, kernel32[0].bound_thunk);
+
+        let ws2_32 = modules[2].entries().next().unwrap().unwrap();
+        assert_eq!(ws2_32.name, ImportName::Ordinal(23));
+    }
+}
+
+#[test]
+fn exports() {
+    for name in &["pe32_dll.dll", "pe32plus_dll.dll"] {
+        let buffer = fixture(name);
+        let file = PEFile::parse(&buffer).unwrap();
+        let exports = file.exports().unwrap().unwrap();
+
+        assert_eq!(exports.name, *name);
+        assert_eq!(exports.ordinal_base, 1);
+
+        let all = exports.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(all.len(), 3);
+
+        let add = exports.by_name("fixture_add").unwrap().unwrap();
+        assert_eq!(add.ordinal, 1);
+        assert_eq!(add.rva, FIXTURE_ADD_RVA);
+        assert_eq!(add.forwarder, None);
+
+        let sub = exports.by_ordinal(2).unwrap().unwrap();
+        assert_eq!(sub.name, Some("fixture_sub"));
+        assert_eq!(sub.rva, FIXTURE_SUB_RVA);
+
+        let alloc = exports.by_name("fixture_alloc").unwrap().unwrap();
+        assert_eq!(alloc.forwarder, Some("NTDLL.RtlAllocateHeap"));
+        assert_eq!(
+            split_forwarder(alloc.forwarder.unwrap()),
+            Some((
+                "NTDLL",
+                ImportName::Name {
+                    hint: 0,
+                    name: "RtlAllocateHeap",
+                }
+            ))
+        );
+
+        assert_eq!(exports.by_name("missing").unwrap(), None);
+        assert_eq!(exports.by_ordinal(4).unwrap(), None);
+    }
+}
+
+#[test]
+fn data_directories() {
+    let buffer = fixture("pe32plus_exe.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+
+    assert_eq!(file.data_directory(DirectoryEntry::Export).unwrap(), None);
+    assert!(file.data_directory(DirectoryEntry::Import).unwrap().is_some());
+    assert!(file.data_directory(DirectoryEntry::Iat).unwrap().is_some());
+    assert!(file.data_directory(DirectoryEntry::Basereloc).unwrap().is_some());
+
+    let tls = file.data_directory(DirectoryEntry::Tls).unwrap().unwrap();
+    assert_eq!(tls.size, 40);
+    let directory = file.read_rva(tls.virtual_address, 40).unwrap();
+    let callbacks = read_u64(directory, 24) - file.image_base();
+    let callback = file.read_rva(callbacks as u32, 8).unwrap();
+    assert_eq!(read_u64(callback, 0), file.image_base() + TLS_CALLBACK_RVA as u64);
+}
+
+#[test]
+fn forwarders() {
+    assert_eq!(
+        split_forwarder("NTDLL.#12"),
+        Some(("NTDLL", ImportName::Ordinal(12)))
+    );
+    assert_eq!(split_forwarder("api-ms-win-core.Sleep").map(|f| f.0), Some("api-ms-win-core"));
+    assert_eq!(split_forwarder("NoDot"), None);
+    assert_eq!(split_forwarder("NTDLL."), None);
+    assert_eq!(split_forwarder("NTDLL.#x"), None);
+}
+
+#[test]
+fn malformed_headers() {
+    let cases = [
+        ("bad_dos_signature.exe", ParseError::InvalidDosSignature),
+        ("bad_pe_signature.exe", ParseError::InvalidPeSignature),
+        (
+            "bad_optional_magic.exe",
+            ParseError::UnknownOptionalHeader(OptionalHeaderMagic::ROM),
+        ),
+        ("pe_header_out_of_bounds.exe", ParseError::OutOfBounds),
+        ("truncated_section_table.exe", ParseError::OutOfBounds),
+    ];
+
+    for &(name, ref error) in &cases {
+        let buffer = fixture(name);
+        assert_eq!(PEFile::parse(&buffer).err().as_ref(), Some(error), "{}", name);
+    }
+
+    assert_eq!(PEFile::parse(&[]).err(), Some(ParseError::OutOfBounds));
+}
+
+// The fuzz targets start from tests/fixtures, the malformed images among the seeds have to be
+// refused the same way when read from a file.
+#[test]
+fn fuzz_seeds() {
+    let mut refused = vec![];
+    for entry in fs::read_dir(fixture_path("")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        if !path.is_file() || name.ends_with(".obj") {
+            continue;
+        }
+        let buffer = fs::read(&path).unwrap();
+        let parsed = PEFile::parse(&buffer).is_ok();
+        assert_eq!(Loader::from_reader(Cursor::new(&buffer)).is_ok(), parsed, "{}", name);
+        if !parsed {
+            refused.push(name);
+        }
+    }
+
+    refused.sort();
+    assert_eq!(
+        refused,
+        [
+            "bad_dos_signature.exe",
+            "bad_optional_magic.exe",
+            "bad_pe_signature.exe",
+            "pe_header_out_of_bounds.exe",
+            "truncated_section_table.exe",
+        ]
+    );
+}
+
+#[test]
+fn builder_errors() {
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0; 4]);
+    b.symbol("main", text.at(0x10));
+    assert_eq!(b.build(), Err(BuildError::InvalidLocation(text.at(0x10))));
+
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0; 4]);
+    b.pointer(text.at(0), text.at(0));
+    assert_eq!(b.build(), Err(BuildError::InvalidPointer(text.at(0))));
+
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0; 4]);
+    b.entry_point(text.at(5));
+    assert_eq!(b.build(), Err(BuildError::InvalidLocation(text.at(5))));
+
+    // One past the end is only fine for pointers and directories.
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0; 8]);
+    b.pointer(text.at(0), text.at(8));
+    b.directory(DirectoryEntry::Debug, text.at(8), 0);
+    assert!(b.build().is_ok());
+    b.entry_point(text.at(8));
+    assert_eq!(b.build(), Err(BuildError::InvalidLocation(text.at(8))));
+    for i in 0..3 {
+        let mut b = PEBuilder::pe32_plus();
+        let text = b.section(".text", SECTION_CODE, vec![0; 4]);
+        match i {
+            0 => b.export("f", text.at(4)),
+            1 => b.tls_callback(text.at(4)),
+            _ => b.symbol("f", text.at(4)),
+        };
+        assert_eq!(b.build(), Err(BuildError::InvalidLocation(text.at(4))));
+    }
+
+    let mut b = PEBuilder::pe32_plus();
+    b.file_alignment(0x300);
+    assert_eq!(b.build(), Err(BuildError::InvalidAlignment));
+
+    let mut b = PEBuilder::pe32_plus();
+    b.section_with_virtual_size(".bss", SECTION_UNINITIALIZED_DATA, vec![], 0xffff_f000);
+    assert_eq!(b.build(), Err(BuildError::TooLarge));
+
+    // Fits without the .reloc section the pointer needs.
+    let mut b = PEBuilder::pe32_plus();
+    let data = b.section_with_virtual_size(".data", SECTION_DATA, vec![0; 8], 0xffff_e000);
+    assert!(b.build().is_ok());
+    b.pointer(data.at(0), data.at(0));
+    assert_eq!(b.build(), Err(BuildError::TooLarge));
+
+    // As many sections as the file header can count, until .reloc makes one more.
+    let mut b = PEBuilder::pe32_plus();
+    let mut data = vec![];
+    for _ in 0..u16::MAX {
+        data.push(b.section(".data", SECTION_DATA, vec![0; 8]));
+    }
+    let buffer = b.build().unwrap();
+    assert_eq!(PEFile::parse(&buffer).unwrap().sections().count(), u16::MAX as usize);
+    b.pointer(data[0].at(0), data[0].at(0));
+    assert_eq!(b.build(), Err(BuildError::TooLarge));
+
+    // One more export than the name ordinals can index.
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0; 4]);
+    for i in 0..=1 << 16 {
+        b.export(&format!("f{}", i), text.at(0));
+    }
+    assert_eq!(b.build(), Err(BuildError::TooLarge));
+}
+
+#[test]
+fn builder_round_trip() {
+    for &is_64_bit in &[true, false] {
+        let mut b = if is_64_bit {
+            PEBuilder::pe32_plus()
+        } else {
+            PEBuilder::pe32()
+        };
+        let rich = vec![RichEntry {
+            product_id: 0x104,
+            build: 30133,
+            count: 3,
+        }];
+        let text = b.section(".text", SECTION_CODE, vec![0xc3; 0x20]);
+        b.dll()
+            .export_name("built.dll")
+            .export("second", text.at(0x10))
+            .export_ordinal(text.at(0x18))
+            .export_forwarder("alloc", "NTDLL.RtlAllocateHeap")
+            .export("first", text.at(0))
+            .tls_data(b"tls template".to_vec())
+            .tls_callback(text.at(8))
+            .rich_header(rich.clone())
+            .symbol("a_long_symbol_name", text.at(0x10));
+        let buffer = b.build().unwrap();
+        let file = PEFile::parse(&buffer).unwrap();
+        let text_rva = file.sections().next().unwrap().virtual_address;
+
+        let exports = file.exports().unwrap().unwrap();
+        assert_eq!(exports.name, "built.dll");
+        let exports = exports.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        let summary = exports
+            .iter()
+            .map(|e| (e.ordinal, e.name, e.forwarder))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (1, Some("second"), None),
+                (2, None, None),
+                (3, Some("alloc"), Some("NTDLL.RtlAllocateHeap")),
+                (4, Some("first"), None),
+            ]
+        );
+        assert_eq!(exports[0].rva, text_rva + 0x10);
+        assert_eq!(exports[1].rva, text_rva + 0x18);
+        assert_eq!(exports[3].rva, text_rva);
+
+        let tls = file.tls().unwrap().unwrap();
+        assert_eq!(tls.callbacks, [file.image_base() + text_rva as u64 + 8]);
+        let start = (tls.start_address_of_raw_data - file.image_base()) as u32;
+        assert_eq!(file.read_rva(start, 12), Ok(&b"tls template"[..]));
+        assert_eq!(tls.end_address_of_raw_data - tls.start_address_of_raw_data, 12);
+
+        let header = file.rich_header().unwrap().unwrap();
+        assert_eq!(header.entries, rich);
+        assert!(header.is_valid());
+
+        let table = file.symbol_table().unwrap().unwrap();
+        let symbols = table.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let symbol = symbols.iter().find(|s| s.is_external()).unwrap();
+        assert_eq!(symbol.name, "a_long_symbol_name");
+        assert_eq!((symbol.section_number, symbol.value), (1, 0x10));
+        assert!(symbol.is_function());
+    }
+}
+
+#[test]
+fn relocations() {
+    for &is_64_bit in &[true, false] {
+        let buffer = fixture(if is_64_bit {
+            "pe32plus_exe.exe"
+        } else {
+            "pe32_exe.exe"
+        });
+        let file = PEFile::parse(&buffer).unwrap();
+        let kind = if is_64_bit { 10 } else { 3 };
+
+        let relocations = file
+            .relocations()
+            .unwrap()
+            .map(|b| b.unwrap())
+            .flat_map(|b| b.relocations().collect::<Vec<_>>())
+            .filter(|r| r.kind != 0)
+            .collect::<Vec<_>>();
+        assert!(relocations.contains(&Relocation {
+            rva: DATA_RVA + TEXT_POINTER,
+            kind,
+        }));
+        assert!(relocations.contains(&Relocation {
+            rva: DATA_RVA + BSS_POINTER,
+            kind,
+        }));
+        assert!(relocations.iter().all(|r| r.kind == kind));
+    }
+
+    let buffer = fixture("pe32plus_resources.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.relocations().unwrap().count(), 0);
+}
+
+#[test]
+fn tls() {
+    for &(name, base) in &[
+        ("pe32plus_exe.exe", 0x1_4000_0000),
+        ("pe32_exe.exe", 0x40_0000),
+    ] {
+        let buffer = fixture(name);
+        let file = PEFile::parse(&buffer).unwrap();
+        let tls = file.tls().unwrap().unwrap();
+
+        assert_eq!(tls.callbacks, [base + TLS_CALLBACK_RVA as u64]);
+        assert_eq!(
+            tls.end_address_of_raw_data - tls.start_address_of_raw_data,
+            4
+        );
+        assert!(tls.address_of_index > base);
+    }
+
+    let buffer = fixture("pe32plus_dll.dll");
+    assert_eq!(PEFile::parse(&buffer).unwrap().tls().unwrap(), None);
+
+    // The callback array ends where the raw data of .data does, the null entry is zero fill.
+    let mut b = PEBuilder::pe32_plus();
+    let text = b.section(".text", SECTION_CODE, vec![0xc3]);
+    let data = b.section_with_virtual_size(".data", SECTION_DATA, vec![0; 0x200], 0x1000);
+    b.directory(DirectoryEntry::Tls, data.at(0), 40);
+    b.pointer(data.at(24), data.at(0x1f8));
+    b.pointer(data.at(0x1f8), text.at(0));
+    let buffer = b.build().unwrap();
+    let file = PEFile::parse(&buffer).unwrap();
+    let text_rva = file.sections().next().unwrap().virtual_address;
+    let tls = file.tls().unwrap().unwrap();
+    assert_eq!(tls.callbacks, [file.image_base() + text_rva as u64]);
+}
+
+#[test]
+fn debug_directories() {
+    let buffer = fixture("pe32plus_resources.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+
+    let entries = file.debug_directories().unwrap();
+    assert_eq!(
+        entries.iter().map(|e| e.kind).collect::<Vec<_>>(),
+        [DEBUG_TYPE_CODEVIEW, DEBUG_TYPE_REPRO]
+    );
+    assert_eq!(file.debug_data(&entries[1]).unwrap(), &[][..]);
+
+    let codeview = file.codeview().unwrap().unwrap();
+    assert_eq!(codeview.path, "fixture.pdb");
+    assert_eq!(codeview.age, 1);
+
+    // Only the reproducible build entry.
+    let buffer = fixture("pe32plus_exe.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    let entries = file.debug_directories().unwrap();
+    assert_eq!(
+        entries.iter().map(|e| e.kind).collect::<Vec<_>>(),
+        [DEBUG_TYPE_REPRO]
+    );
+    assert_eq!(file.codeview().unwrap(), None);
+}
+
+#[test]
+fn resources() {
+    let buffer = fixture("pe32plus_resources.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+
+    let resources = file.resources().unwrap();
+    assert_eq!(
+        resources,
+        [
+            Resource {
+                kind: ResourceId::Name("CONFIG".into()),
+                name: ResourceId::Id(1),
+                language: ResourceId::Id(0x409),
+                rva: RSRC_RVA + 0xb0,
+                size: 10,
+                code_page: 0,
+            },
+            Resource {
+                kind: ResourceId::Id(16),
+                name: ResourceId::Id(1),
+                language: ResourceId::Id(0x409),
+                rva: RSRC_RVA + 0xc0,
+                size: 352,
+                code_page: 0,
+            },
+        ]
+    );
+    assert_eq!(file.read_rva(resources[0].rva, 10).unwrap(), b"key=value\0");
+
+    // A subdirectory that refers back to the root.
+    let mut looped = buffer.clone();
+    let root = file.rva_to_offset(RSRC_RVA).unwrap();
+    looped[root + 0x14..root + 0x18].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    let file = PEFile::parse(&looped).unwrap();
+    assert_eq!(file.resources(), Err(ParseError::InvalidRva(RSRC_RVA)));
+
+    // Language directories at every entry of a run of `(1, 4)` entries: each entry's data doubles
+    // as the next directory's count of four named entries.
+    let names = 16;
+    let run = 0x28 + names * 8;
+    let mut rsrc = vec![0; run as usize + (names as usize + 8) * 8];
+    let mut entry = |offset: u32, id: u32, data: u32| {
+        let offset = offset as usize;
+        rsrc[offset..offset + 4].copy_from_slice(&id.to_le_bytes());
+        rsrc[offset + 4..offset + 8].copy_from_slice(&data.to_le_bytes());
+    };
+    entry(0x08, 0, 1 << 16);
+    entry(0x10, 1, 0x8000_0018);
+    entry(0x20, 0, names << 16);
+    for i in 0..names {
+        entry(0x28 + i * 8, i, 0x8000_0000 | (run + i * 8));
+    }
+    for i in 0..names + 8 {
+        entry(run + i * 8, 1, 4);
+    }
+
+    let mut b = PEBuilder::pe32_plus();
+    let len = rsrc.len() as u32;
+    let section = b.section(".rsrc", SECTION_READ_ONLY_DATA, rsrc);
+    b.directory(DirectoryEntry::Resource, section.at(0), len);
+    let overlapping = b.build().unwrap();
+    let file = PEFile::parse(&overlapping).unwrap();
+    let rva = file.sections().next().unwrap().virtual_address;
+    assert_eq!(file.resources(), Err(ParseError::InvalidRva(rva + run + 8)));
+}
+
+#[test]
+fn dos_header() {
+    // The stub MSVC links in, see pe.py.
+    let buffer = fixture("pe32plus_resources.exe");
+    let header = PEFile::parse(&buffer).unwrap().dos_header();
+    assert_eq!(header.signature, 0x5a4d);
+    assert_eq!(header.bytes_on_last_page, 0x90);
+    assert_eq!(header.pages_in_file, 3);
+    assert_eq!(header.max_extra_paragraphs, 0xffff);
+    assert_eq!(header.initial_sp, 0xb8);
+    assert_eq!(header.p_relocation_table, 0x40);
+    assert_eq!(header.reserved2, [0; 10]);
+    assert_eq!(header.offset_to_pe_header, 0xb0);
+
+    // The shorter one of lld.
+    let buffer = fixture("pe32plus_exe.exe");
+    let header = PEFile::parse(&buffer).unwrap().dos_header();
+    assert_eq!(header.pages_in_file, 1);
+    assert_eq!(header.offset_to_pe_header, 0x78);
+}
+
+#[test]
+fn rich_header() {
+    // The entries pe.py writes.
+    let entries = [
+        RichEntry {
+            product_id: 0x104,
+            build: 30133,
+            count: 12,
+        },
+        RichEntry {
+            product_id: 0x1,
+            build: 0,
+            count: 40,
+        },
+        RichEntry {
+            product_id: 0x102,
+            build: 30133,
+            count: 1,
+        },
+    ];
+
+    let buffer = fixture("pe32plus_resources.exe");
+    let file = PEFile::parse(&buffer).unwrap();
+    let rich = file.rich_header().unwrap().unwrap();
+    assert_eq!(rich.offset, 0x80);
+    assert_eq!(rich.size, 16 + 3 * 8 + 8);
+    assert_eq!(rich.entries, entries);
+    assert_eq!(rich.entries[0].comp_id(), 0x0104_75b5);
+    assert!(rich.is_valid());
+    assert_eq!(&rich.decoded()[..4], b"DanS");
+    assert_eq!(file.dos_header().offset_to_pe_header, 0x80 + 0x30);
+
+    let buffer = fixture("pe32plus_exe.exe");
+    assert_eq!(PEFile::parse(&buffer).unwrap().rich_header(), Ok(None));
+
+    // The checksum covers the DOS stub, but not the offset of the PE header.
+    let mut patched = fixture("pe32plus_resources.exe");
+    patched[0x50] ^= 1;
+    let rich = PEFile::parse(&patched).unwrap().rich_header().unwrap();
+    assert!(!rich.unwrap().is_valid());
+    let mut patched = fixture("pe32plus_resources.exe");
+    patched[0x3c] = 0xb8;
+    patched.splice(0xb0..0xb0, vec![0; 8]);
+    let rich = PEFile::parse(&patched).unwrap().rich_header().unwrap();
+    assert!(rich.unwrap().is_valid());
+
+    let mut patched = fixture("pe32plus_resources.exe");
+    patched[0x80] ^= 1;
+    assert_eq!(
+        PEFile::parse(&patched).unwrap().rich_header(),
+        Err(ParseError::InvalidRichHeader)
+    );
+}
+
+#[test]
+fn checksum() {
+    for &name in &[
+        "pe32_exe.exe",
+        "pe32plus_exe.exe",
+        "pe32_dll.dll",
+        "pe32plus_dll.dll",
+        "pe32plus_resources.exe",
+    ] {
+        let buffer = fixture(name);
+        assert!(PEFile::parse(&buffer).unwrap().has_valid_checksum(), "{}", name);
+    }
+
+    let mut buffer = fixture("pe32plus_dll.dll");
+    let checksum = PEFile::parse(&buffer).unwrap().checksum();
+    let offset = buffer.len() - 1;
+    buffer[offset] ^= 0xff;
+    let file = PEFile::parse(&buffer).unwrap();
+    assert!(!file.has_valid_checksum());
+    let computed = file.compute_checksum();
+    assert_ne!(computed, checksum);
+
+    assert_eq!(update_checksum(&mut buffer), Ok(computed));
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.checksum(), computed);
+    assert!(file.has_valid_checksum());
+
+    assert_eq!(
+        update_checksum(&mut fixture("bad_pe_signature.exe")),
+        Err(ParseError::InvalidPeSignature)
+    );
+
+    // The field is left out wherever it is, even at an odd offset.
+    let buffer = fixture("pe32plus_dll.dll");
+    let pe = pe_header(&buffer);
+    let mut odd = buffer[..pe].to_vec();
+    odd.push(0);
+    odd.extend_from_slice(&buffer[pe..]);
+    let odd = set_u32(odd, 0x3c, pe as u32 + 1);
+    let field = pe + 1 + 24 + 64;
+    let computed = PEFile::parse(&odd).unwrap().compute_checksum();
+    for &value in &[0, 1, 0xffff, 0xdead_beef] {
+        let patched = set_u32(odd.clone(), field, value);
+        assert_eq!(PEFile::parse(&patched).unwrap().compute_checksum(), computed);
+    }
+}
+
+#[test]
+fn overlay() {
+    let mut buffer = fixture("pe32plus_exe.exe");
+    assert_eq!(PEFile::parse(&buffer).unwrap().overlay(), Ok(None));
+
+    let end = buffer.len();
+    buffer.extend_from_slice(b"archive");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.overlay_range(), Ok(Some(end..end + 7)));
+    assert_eq!(file.overlay(), Ok(Some(&b"archive"[..])));
+
+    // Sections running past the end of a truncated file don't leave an overlay either.
+    let file = PEFile::parse(&buffer[..0x500]).unwrap();
+    assert_eq!(file.overlay_range(), Ok(None));
+}
+
+#[test]
+fn overlay_around_certificates() {
+    let signed = fixture("pe32plus_signed.dll");
+    let file = PEFile::parse(&signed).unwrap();
+    let table = file.certificate_table().unwrap().unwrap();
+    assert_eq!(file.overlay(), Ok(None));
+
+    // Appended after signing.
+    let mut buffer = signed.clone();
+    buffer.extend_from_slice(&[0xcc; 8]);
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.overlay_range(), Ok(Some(table.end..table.end + 8)));
+
+    // Signed with the overlay already there.
+    let mut buffer = signed.clone();
+    buffer.splice(table.start..table.start, vec![0xcc; 16]);
+    let security = read_u32(&buffer, 0x3c) as usize + 24 + 112 + 4 * 8;
+    let offset = table.start as u32 + 16;
+    buffer[security..security + 4].copy_from_slice(&offset.to_le_bytes());
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(file.overlay(), Ok(Some(&[0xcc; 16][..])));
+}
+
+#[test]
+fn machines() {
+    assert_eq!(Machine::AMD64, Machine(0x8664));
+    assert_eq!(Machine::from(0xaa64), Machine::ARM64);
+    assert_eq!(u16::from(Machine::I386), 0x14c);
+    for &(machine, name) in &[
+        (Machine::ARMNT, "ARMNT"),
+        (Machine::ARM64EC, "ARM64EC"),
+        (Machine::ARM64X, "ARM64X"),
+        (Machine::RISCV64, "RISCV64"),
+        (Machine::UNKNOWN, "unknown"),
+        (Machine(0x1234), "unknown"),
+    ] {
+        assert_eq!(machine.name(), name);
+        assert_eq!(machine.is_known(), name != "unknown");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    assert_eq!(Machine::host(), Some(Machine::AMD64));
+    #[cfg(target_arch = "aarch64")]
+    assert_eq!(Machine::host(), Some(Machine::ARM64));
+}
+
+#[test]
+fn flags() {
+    let text = SectionCharacteristics::from_bits_retain(0x6050_0020);
+    assert!(text.contains(SECTION_CODE));
+    assert!(text.is_executable() && text.is_readable() && !text.is_writable());
+    assert_eq!(text.alignment(), Some(16));
+    assert_eq!(
+        format!("{:?}", SECTION_DATA),
+        "SectionCharacteristics(CNT_INITIALIZED_DATA | MEM_READ | MEM_WRITE)"
+    );
+
+    // Bits without a name survive a round trip.
+    let unknown = DllCharacteristics::from_bits_retain(0x8141);
+    assert_eq!(unknown.bits(), 0x8141);
+    assert!(unknown.is_dynamic_base() && unknown.is_nx_compat());
+    assert!(!unknown.is_guard_cf());
+
+    let buffer = fixture("pe32plus_dll.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert!(file.file_characteristics().is_dll());
+    assert!(file.file_characteristics().is_executable());
+    assert_eq!(file.magic(), OptionalHeaderMagic::PE32_PLUS);
+    assert!(file.magic().is_64_bit());
+    assert_eq!(file.subsystem(), Subsystem::WINDOWS_GUI);
+    assert_eq!(file.subsystem().name(), "Windows GUI");
+    assert!(Subsystem::EFI_ROM.is_efi());
+    assert!(!Subsystem::WINDOWS_CUI.is_efi());
+}
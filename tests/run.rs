@@ -0,0 +1,82 @@
+// Runs pe-load over the fixtures. Nothing can be run on these hosts, so only mapping is covered.
+
+extern crate pe_load;
+
+mod common;
+
+use std::process::{Command, Output};
+
+use common::*;
+
+fn pe_load(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pe-load"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = pe_load(args);
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn stderr(args: &[&str], code: i32) -> String {
+    let output = pe_load(args);
+    assert_eq!(output.status.code(), Some(code), "{:?}", output);
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn prints_base_and_imports() {
+    let path = fixture_path("pe32plus_dll.dll");
+    let path = path.to_str().unwrap();
+
+    let text = stdout(&["--unresolved", path]);
+    assert!(text.contains("mapped at 0x180000000, 0x7000 bytes"));
+    assert!(!text.contains("relocated"));
+    assert!(text.contains("KERNEL32.dll!HeapAlloc  slot 0x1800020e8 -> unresolved"));
+
+    let text = stdout(&["--unresolved", "--base", "0x7000000000", path]);
+    assert!(text.contains("mapped at 0x7000000000"));
+    assert!(text.contains("relocated from 0x180000000"));
+    assert!(text.contains("slot 0x70000020e8"));
+
+    let exe = fixture_path("pe32_exe.exe");
+    let text = stdout(&[
+        "--unresolved",
+        "--any-machine",
+        "--with",
+        path,
+        exe.to_str().unwrap(),
+    ]);
+    assert!(text.contains("pe32plus_dll.dll: mapped at 0x180000000"));
+    assert!(text.contains("WS2_32.dll!#23"));
+
+    // Only the ARM64 code imports anything.
+    let hybrid = fixture_path("arm64x.dll");
+    let text = stdout(&["--unresolved", hybrid.to_str().unwrap()]);
+    assert!(text.contains("arm64x.dll: mapped at 0x180000000"));
+    assert!(text.contains("KERNEL32.dll!HeapAlloc"));
+    let text = stdout(&["--unresolved", "--ec-view", hybrid.to_str().unwrap()]);
+    assert!(text.contains("arm64x.dll: mapped at 0x180000000"));
+    assert!(text.contains("Imports\n  none"));
+}
+
+#[test]
+fn errors() {
+    let path = fixture_path("pe32plus_dll.dll");
+    let path = path.to_str().unwrap();
+
+    assert!(stderr(&[path], 1).contains("couldn't load: LoadLibraryFailed"));
+    assert!(stderr(&["--unresolved", "--call", "nope", path], 1).contains("doesn't export nope"));
+    #[cfg(not(windows))]
+    assert!(stderr(&["--unresolved", "--call", "fixture_add", path], 1)
+        .contains("can't be run in this process"));
+
+    let path = fixture_path("bad_pe_signature.exe");
+    assert!(stderr(&[path.to_str().unwrap()], 1).contains("not a PE file"));
+
+    stderr(&[], 2);
+    stderr(&["--base", "somewhere", "file"], 2);
+}
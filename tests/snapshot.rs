@@ -0,0 +1,177 @@
+extern crate pe_load;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+mod common;
+
+use common::*;
+use pe_load::*;
+
+fn snapshot(name: &str) -> Snapshot {
+    PEFile::parse(&fixture(name)).unwrap().snapshot().unwrap()
+}
+
+#[test]
+fn headers() {
+    let exe = snapshot("pe32_exe.exe");
+    assert_eq!(exe.file_header.machine, 0x14c);
+    assert_eq!(
+        exe.file_header.number_of_sections as usize,
+        exe.sections.len()
+    );
+    assert_eq!(exe.optional_header.magic, 0x10b);
+    assert_eq!(exe.optional_header.image_base, 0x40_0000);
+    assert!(exe.optional_header.base_of_data.is_some());
+    assert_eq!(exe.optional_header.number_of_rva_and_sizes, 16);
+
+    let dll = snapshot("pe32plus_dll.dll");
+    assert_eq!(dll.file_header.machine, 0x8664);
+    assert_eq!(dll.optional_header.magic, 0x20b);
+    assert_eq!(dll.optional_header.image_base, 0x1_8000_0000);
+    assert_eq!(dll.optional_header.base_of_data, None);
+    assert_eq!(dll.optional_header.number_of_rva_and_sizes, 16);
+    assert_eq!(dll.optional_header.section_alignment, 0x1000);
+
+    let buffer = fixture("pe32plus_dll.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    assert_eq!(dll.optional_header.size_of_image, file.size_of_image());
+    assert_eq!(
+        dll.optional_header.dll_characteristics,
+        file.dll_characteristics().bits()
+    );
+}
+
+#[test]
+fn directories() {
+    let exe = snapshot("pe32plus_exe.exe");
+    let modules = exe.imports.iter().map(|m| &m.name[..]).collect::<Vec<_>>();
+    assert_eq!(modules, ["KERNEL32.dll", "USER32.dll", "WS2_32.dll"]);
+    assert_eq!(exe.imports[2].entries[0].symbol, ImportSymbol::Ordinal(23));
+    assert_eq!(exe.tls.as_ref().unwrap().callbacks.len(), 1);
+    assert!(!exe.relocations.is_empty());
+    assert!(exe
+        .data_directories
+        .iter()
+        .any(|d| d.entry == DirectoryEntry::Tls));
+
+    let dll = snapshot("pe32plus_dll.dll");
+    let exports = dll.exports.unwrap();
+    assert_eq!(exports.name, "pe32plus_dll.dll");
+    assert_eq!(
+        exports.exports[2].forwarder.as_ref().unwrap(),
+        "NTDLL.RtlAllocateHeap"
+    );
+
+    let resources = snapshot("pe32plus_resources.exe");
+    assert_eq!(resources.resources.len(), 2);
+    assert_eq!(resources.debug.len(), 2);
+    assert_eq!(resources.codeview.unwrap().path, "fixture.pdb");
+}
+
+#[test]
+fn certificates() {
+    let buffer = fixture("pe32plus_signed.dll");
+    let certificates = PEFile::parse(&buffer).unwrap().certificates().unwrap();
+    let signed = snapshot("pe32plus_signed.dll");
+    assert_eq!(signed.certificates.len(), 1);
+    assert_eq!(signed.certificates[0].kind, CERT_TYPE_PKCS_SIGNED_DATA);
+    assert_eq!(signed.certificates[0].data, certificates[0].data);
+    assert!(snapshot("pe32plus_dll.dll").certificates.is_empty());
+}
+
+#[test]
+fn overlay() {
+    let mut buffer = fixture("pe32plus_exe.exe");
+    assert_eq!(snapshot("pe32plus_exe.exe").overlay, None);
+    let end = buffer.len();
+    buffer.extend_from_slice(b"archive");
+    let overlay = PEFile::parse(&buffer).unwrap().snapshot().unwrap().overlay.unwrap();
+    assert_eq!(overlay.offset, end);
+    assert_eq!(overlay.data, b"archive");
+}
+
+#[test]
+fn symbols() {
+    let mingw = snapshot("pe32plus_mingw.exe");
+    let main = mingw.symbols.iter().find(|s| s.name == "main").unwrap();
+    assert_eq!((main.section_number, main.value), (1, 0x10));
+    assert_eq!(
+        mingw.file_header.number_of_symbols as usize,
+        mingw.symbols.len()
+    );
+    assert!(snapshot("pe32plus_exe.exe").symbols.is_empty());
+}
+
+#[test]
+fn clr() {
+    let managed = snapshot("pe32_managed.exe");
+    assert!(managed.clr_header.unwrap().is_il_only());
+    let metadata = managed.clr_metadata.unwrap();
+    assert_eq!(metadata.version, "v4.0.30319");
+    assert_eq!(metadata.streams[1].name, "#Strings");
+    assert_eq!(
+        metadata.streams[1].data,
+        b"\0<Module>\0managed.exe\0Main\0managed\0\0"
+    );
+    assert_eq!(snapshot("pe32_exe.exe").clr_metadata, None);
+}
+
+#[test]
+fn hybrid() {
+    let buffer = fixture("arm64x.dll");
+    let file = PEFile::parse(&buffer).unwrap();
+    let arm64x = snapshot("arm64x.dll");
+    assert_eq!(arm64x.load_config, file.load_config().unwrap());
+    assert_eq!(arm64x.dynamic_relocations[0].symbol, DYNAMIC_RELOCATION_ARM64X);
+    assert_eq!(arm64x.chpe_metadata.unwrap().code_map_count, 3);
+    assert_eq!(arm64x.code_ranges, file.code_ranges().unwrap());
+    assert_eq!(snapshot("pe32plus_dll.dll").load_config, None);
+}
+
+#[test]
+fn malformed_parts_fail() {
+    let mut buffer = fixture("pe32plus_resources.exe");
+    let offset = PEFile::parse(&buffer)
+        .unwrap()
+        .rva_to_offset(RSRC_RVA)
+        .unwrap();
+    // Point the first resource type back at the root directory.
+    buffer[offset + 20..offset + 24].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    assert_eq!(
+        PEFile::parse(&buffer).unwrap().snapshot(),
+        Err(ParseError::InvalidRva(RSRC_RVA))
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn round_trips_through_json() {
+    for &name in &[
+        "pe32_exe.exe",
+        "pe32plus_exe.exe",
+        "pe32_dll.dll",
+        "pe32plus_dll.dll",
+        "pe32plus_resources.exe",
+        "pe32plus_signed.dll",
+        "pe32plus_mingw.exe",
+        "pe32_managed.exe",
+        "arm64x.dll",
+    ] {
+        let snapshot = snapshot(name);
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Snapshot>(&json).unwrap(),
+            snapshot,
+            "{}",
+            name
+        );
+    }
+
+    let json = serde_json::to_value(snapshot("pe32plus_dll.dll")).unwrap();
+    assert_eq!(json["exports"]["exports"][0]["name"], "fixture_add");
+    assert_eq!(json["data_directories"][0]["entry"], "Export");
+    assert_eq!(
+        json["imports"][0]["entries"][0]["symbol"]["Name"],
+        "HeapAlloc"
+    );
+}
@@ -0,0 +1,134 @@
+extern crate pe_load;
+
+mod common;
+
+use common::*;
+use pe_load::*;
+
+// Offsets into the headers of pe32plus_exe.exe.
+fn optional_header(buffer: &[u8]) -> usize {
+    pe_header(buffer) + 24
+}
+
+fn section_header(buffer: &[u8], index: usize) -> usize {
+    optional_header(buffer) + 240 + index * 40
+}
+
+fn validate(buffer: &[u8]) -> Vec<Finding> {
+    PEFile::parse(buffer).unwrap().validate()
+}
+
+#[test]
+fn clean_images() {
+    for &name in FIXTURES {
+        assert_eq!(validate(&fixture(name)), [], "{}", name);
+    }
+}
+
+#[test]
+fn section_findings() {
+    let exe = fixture("pe32plus_exe.exe");
+
+    // .tls starts inside the part of .data that isn't in the file.
+    let tls_rva = set_u32(exe.clone(), section_header(&exe, 3) + 12, 0x4800);
+    let findings = validate(&tls_rva);
+    assert!(findings.contains(&Finding::OverlappingSections {
+        first: 2,
+        second: 3,
+    }));
+
+    let size_of_image = set_u32(exe.clone(), optional_header(&exe) + 56, 0x6000);
+    assert_eq!(
+        validate(&size_of_image),
+        [Finding::SectionBeyondImage { section: 4 }]
+    );
+
+    let characteristics = set_u32(
+        exe.clone(),
+        section_header(&exe, 0) + 36,
+        (SECTION_CODE | SectionCharacteristics::MEM_WRITE).bits(),
+    );
+    assert_eq!(
+        validate(&characteristics),
+        [Finding::WritableExecutableSection { section: 0 }]
+    );
+}
+
+#[test]
+fn entry_point() {
+    let exe = fixture("pe32plus_exe.exe");
+    let entry_point = optional_header(&exe) + 16;
+    assert_eq!(
+        validate(&set_u32(exe.clone(), entry_point, DATA_RVA)),
+        [Finding::EntryPointNotExecutable { rva: DATA_RVA }]
+    );
+    assert_eq!(
+        validate(&set_u32(exe.clone(), entry_point, 0)),
+        [Finding::EntryPointNotExecutable { rva: 0 }]
+    );
+
+    // DLLs without an entry point are fine.
+    let dll = fixture("pe32plus_dll.dll");
+    assert_eq!(
+        validate(&set_u32(dll.clone(), optional_header(&dll) + 16, 0)),
+        []
+    );
+}
+
+#[test]
+fn header_findings() {
+    let exe = fixture("pe32plus_exe.exe");
+    let optional_header = optional_header(&exe);
+
+    let file_alignment = set_u32(exe.clone(), optional_header + 36, 0x300);
+    assert_eq!(
+        validate(&file_alignment),
+        [Finding::InvalidFileAlignment(0x300)]
+    );
+    let section_alignment = set_u32(exe.clone(), optional_header + 32, 0x100);
+    assert_eq!(
+        validate(&section_alignment),
+        [
+            Finding::InvalidSectionAlignment(0x100),
+            Finding::InvalidFileAlignment(0x200),
+        ]
+    );
+
+    // Only the export, import and resource directories are left.
+    let num_of_rva_and_sizes = set_u32(exe.clone(), optional_header + 108, 3);
+    assert_eq!(
+        validate(&num_of_rva_and_sizes),
+        [Finding::FewDataDirectories(3)]
+    );
+
+    let exception = optional_header + 112 + DirectoryEntry::Exception as usize * 8;
+    let directory = set_u32(exe.clone(), exception, 0x10_0000);
+    let directory = set_u32(directory, exception + 4, 0x10);
+    assert_eq!(
+        validate(&directory),
+        [Finding::DirectoryOutsideSections(DirectoryEntry::Exception)]
+    );
+}
+
+#[test]
+fn refuse_invalid() {
+    let exe = fixture("pe32plus_exe.exe");
+    let buffer = set_u32(exe.clone(), optional_header(&exe) + 16, DATA_RVA);
+    assert!(
+        Loader::with_config(buffer.clone(), config_at(0x1_4000_0000))
+            .load()
+            .is_ok()
+    );
+
+    let config = config_at(0x1_4000_0000).refuse_invalid(true);
+    match Loader::with_config(buffer, config).load() {
+        Err(LoadError::InvalidImage(findings)) => assert_eq!(
+            findings,
+            [Finding::EntryPointNotExecutable { rva: DATA_RVA }]
+        ),
+        r => panic!("unexpected result {:?}", r.err()),
+    }
+
+    let config = config_at(0x1_4000_0000).refuse_invalid(true);
+    assert!(Loader::with_config(exe, config).load().is_ok());
+}